@@ -0,0 +1,299 @@
+//! Kotlin and Swift bindings to a zingolib wallet, generated with UniFFI.
+//!
+//! A [`ZingoClient`] wraps a [`LightClient`]: it is created from a new seed, restored from an
+//! existing one, or loaded from disk or from bytes the app kept itself, and from there it syncs,
+//! reports balances and transactions, and sends. Calls block until they're done, so apps make
+//! them off their UI thread.
+//!
+//! Generate the bindings from the built library with the `uniffi-bindgen` binary of this crate:
+//! `cargo run -p zingo-ffi --bin uniffi-bindgen generate --library
+//! target/release/libzingo_ffi.so --language kotlin --out-dir out`.
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::runtime::Runtime;
+use zingoconfig::{ChainType, ZingoConfig};
+use zingolib::lightclient::LightClient;
+use zingolib::wallet::keys::is_shielded_address;
+use zingolib::wallet::utils::interpret_memo_string;
+use zingolib::wallet::WalletBase;
+
+mod records;
+pub use records::{
+    Address, Balance, OutgoingPayment, Payment, SyncProgress, SyncSummary, Transaction,
+};
+
+uniffi::setup_scaffolding!();
+
+lazy_static! {
+    static ref RT: Runtime = tokio::runtime::Runtime::new().unwrap();
+}
+
+/// How often a sync reports its progress to the listener
+const SYNC_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum Chain {
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl From<Chain> for ChainType {
+    fn from(chain: Chain) -> Self {
+        match chain {
+            Chain::Mainnet => ChainType::Mainnet,
+            Chain::Testnet => ChainType::Testnet,
+            Chain::Regtest => ChainType::Regtest,
+        }
+    }
+}
+
+/// Where a wallet lives and which server it talks to
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct WalletConfig {
+    /// The lightwalletd server, e.g. `https://mainnet.lightwalletd.com:9067`. Without a port,
+    /// 9067 is used.
+    pub server_uri: String,
+    pub chain: Chain,
+    /// The directory holding the wallet file
+    pub data_dir: String,
+}
+
+impl WalletConfig {
+    fn load(&self) -> Result<ZingoConfig, ZingoError> {
+        // Checked here, as zingoconfig panics on a server it can't parse
+        if self.server_uri.parse::<http::Uri>().is_err() {
+            return Err(ZingoError::InvalidArgument {
+                message: format!("'{}' is not a server URI", self.server_uri),
+            });
+        }
+        let uri = zingoconfig::construct_lightwalletd_uri(Some(self.server_uri.clone()));
+        zingolib::load_clientconfig(
+            uri,
+            Some(self.data_dir.clone().into()),
+            self.chain.into(),
+            true,
+        )
+        .map_err(|e| ZingoError::Server {
+            message: e.to_string(),
+        })
+    }
+}
+
+/// Why a call failed. The categories are those of the CLI's error codes.
+#[derive(Debug, PartialEq, Eq, uniffi::Error)]
+pub enum ZingoError {
+    /// The call was given something it can't use, such as a malformed memo
+    InvalidArgument { message: String },
+    /// The wallet couldn't do it, for example for lack of funds or a wallet file
+    Wallet { message: String },
+    /// The server couldn't be reached, or refused
+    Server { message: String },
+}
+
+impl fmt::Display for ZingoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZingoError::InvalidArgument { message }
+            | ZingoError::Wallet { message }
+            | ZingoError::Server { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ZingoError {}
+
+fn wallet_error(e: impl ToString) -> ZingoError {
+    ZingoError::Wallet {
+        message: e.to_string(),
+    }
+}
+
+/// Called from the syncing thread as a sync makes progress
+#[uniffi::export(callback_interface)]
+pub trait SyncListener: Send + Sync {
+    fn on_progress(&self, progress: SyncProgress);
+}
+
+#[derive(uniffi::Object)]
+pub struct ZingoClient {
+    lightclient: Arc<LightClient>,
+}
+
+#[uniffi::export]
+impl ZingoClient {
+    /// Creates a wallet from a fresh seed, born at the server's current height, and saves it.
+    /// Fails if the data dir already holds a wallet.
+    #[uniffi::constructor]
+    pub fn create(config: WalletConfig) -> Result<Arc<Self>, ZingoError> {
+        let config = config.load()?;
+        let latest_block =
+            zingolib::get_latest_block_height(&config).map_err(|e| ZingoError::Server {
+                message: e.to_string(),
+            })?;
+        let lightclient = LightClient::new(&config, latest_block).map_err(wallet_error)?;
+        Ok(Self::wrap(lightclient))
+    }
+
+    /// Restores the wallet of a seed phrase, scanning from `birthday` on its first sync.
+    /// Replaces any wallet in the data dir once saved.
+    #[uniffi::constructor]
+    pub fn restore(
+        config: WalletConfig,
+        seed: String,
+        birthday: u64,
+    ) -> Result<Arc<Self>, ZingoError> {
+        let config = config.load()?;
+        let lightclient = LightClient::create_from_wallet_base(
+            WalletBase::MnemonicPhrase(seed),
+            &config,
+            birthday,
+            true,
+        )
+        .map_err(|e| ZingoError::InvalidArgument {
+            message: e.to_string(),
+        })?;
+        Ok(Self::wrap(lightclient))
+    }
+
+    /// Loads the wallet saved in the data dir
+    #[uniffi::constructor]
+    pub fn load(config: WalletConfig) -> Result<Arc<Self>, ZingoError> {
+        let config = config.load()?;
+        let lightclient = LightClient::read_wallet_from_disk(&config).map_err(wallet_error)?;
+        Ok(Self::wrap(lightclient))
+    }
+
+    /// Loads a wallet from the bytes of [`ZingoClient::save_to_bytes`], for apps that keep the
+    /// wallet in storage of their own
+    #[uniffi::constructor]
+    pub fn load_from_bytes(config: WalletConfig, bytes: Vec<u8>) -> Result<Arc<Self>, ZingoError> {
+        let config = config.load()?;
+        let lightclient =
+            LightClient::read_wallet_from_buffer(&config, &bytes[..]).map_err(wallet_error)?;
+        Ok(Self::wrap(lightclient))
+    }
+
+    /// Writes the wallet to the data dir
+    pub fn save(&self) -> Result<(), ZingoError> {
+        // Not `do_save`, which leaves saving to the app on Android and iOS
+        let bytes = self.save_to_bytes()?;
+        let config = self.lightclient.config();
+        config.write_wallet(&bytes).map_err(|e| {
+            wallet_error(format!(
+                "Couldn't write {}: {}",
+                config.wallet_location(),
+                e
+            ))
+        })
+    }
+
+    /// The wallet, serialized
+    pub fn save_to_bytes(&self) -> Result<Vec<u8>, ZingoError> {
+        RT.block_on(self.lightclient.do_save_to_buffer())
+            .map_err(wallet_error)
+    }
+
+    /// Syncs to the chain tip, reporting progress to `listener` along the way
+    pub fn sync(&self, listener: Box<dyn SyncListener>) -> Result<SyncSummary, ZingoError> {
+        let lightclient = &self.lightclient;
+        let result = RT.block_on(async move {
+            let sync = lightclient.do_sync(false);
+            tokio::pin!(sync);
+            let mut interval = tokio::time::interval(SYNC_PROGRESS_INTERVAL);
+            loop {
+                tokio::select! {
+                    result = &mut sync => break result,
+                    _ = interval.tick() => {
+                        let status = lightclient.do_sync_status().await;
+                        if status.in_progress {
+                            listener.on_progress(SyncProgress::from(&status));
+                        }
+                    }
+                }
+            }
+        });
+        result
+            .map(SyncSummary::from)
+            .map_err(|message| ZingoError::Server { message })
+    }
+
+    /// Stops a running sync once its current batch is done
+    pub fn interrupt_sync(&self) {
+        RT.block_on(self.lightclient.interrupt_sync_after_batch(true))
+    }
+
+    pub fn balance(&self) -> Balance {
+        RT.block_on(self.lightclient.do_balance()).into()
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        RT.block_on(self.lightclient.list_addresses())
+            .into_iter()
+            .map(Address::from)
+            .collect()
+    }
+
+    /// The wallet's transactions, oldest first
+    pub fn transactions(&self) -> Vec<Transaction> {
+        RT.block_on(self.lightclient.list_transactions())
+            .into_iter()
+            .map(Transaction::from)
+            .collect()
+    }
+
+    /// The seed phrase, or an error for a watch-only wallet
+    pub fn seed_phrase(&self) -> Result<String, ZingoError> {
+        RT.block_on(self.lightclient.seed_phrase())
+            .map(|seed| seed.seed)
+            .map_err(wallet_error)
+    }
+
+    pub fn birthday(&self) -> u64 {
+        RT.block_on(self.lightclient.wallet.get_birthday())
+    }
+
+    /// Sends to one or more recipients, returning the txid once the transaction is broadcast
+    pub fn send(&self, payments: Vec<Payment>) -> Result<String, ZingoError> {
+        if payments.is_empty() {
+            return Err(ZingoError::InvalidArgument {
+                message: "Nothing to send".to_string(),
+            });
+        }
+        let mut tos = Vec::with_capacity(payments.len());
+        for payment in &payments {
+            let memo = match &payment.memo {
+                Some(memo) => {
+                    if !is_shielded_address(&payment.address, self.lightclient.config()) {
+                        return Err(ZingoError::InvalidArgument {
+                            message: format!(
+                                "Can't send a memo to the non-shielded address {}",
+                                payment.address
+                            ),
+                        });
+                    }
+                    Some(interpret_memo_string(memo.clone()).map_err(|e| {
+                        ZingoError::InvalidArgument {
+                            message: format!("Couldn't interpret memo: {}", e),
+                        }
+                    })?)
+                }
+                None => None,
+            };
+            tos.push((payment.address.as_str(), payment.amount, memo));
+        }
+        RT.block_on(self.lightclient.do_send(tos))
+            .map_err(wallet_error)
+    }
+}
+
+impl ZingoClient {
+    fn wrap(lightclient: LightClient) -> Arc<Self> {
+        Arc::new(ZingoClient {
+            lightclient: Arc::new(lightclient),
+        })
+    }
+}
@@ -0,0 +1,178 @@
+//! The records passed across the bindings, converted from zingolib's own types. Amounts are in
+//! zatoshis.
+use zingolib::blaze::sync_status::BatchSyncStatus;
+use zingolib::lightclient::records::{AddressRecord, OutgoingRecord, TransactionRecord};
+use zingolib::lightclient::{PoolBalances, SyncResult};
+
+/// Balances per pool. A pool the wallet has no key for has no balance, rather than zero.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct Balance {
+    pub sapling_balance: Option<u64>,
+    pub verified_sapling_balance: Option<u64>,
+    pub spendable_sapling_balance: Option<u64>,
+    pub unverified_sapling_balance: Option<u64>,
+    pub pending_incoming_sapling_balance: Option<u64>,
+    pub pending_outgoing_sapling_balance: Option<u64>,
+
+    pub orchard_balance: Option<u64>,
+    pub verified_orchard_balance: Option<u64>,
+    pub spendable_orchard_balance: Option<u64>,
+    pub unverified_orchard_balance: Option<u64>,
+    pub pending_incoming_orchard_balance: Option<u64>,
+    pub pending_outgoing_orchard_balance: Option<u64>,
+
+    pub transparent_balance: Option<u64>,
+    pub pending_incoming_transparent_balance: Option<u64>,
+    pub pending_outgoing_transparent_balance: Option<u64>,
+}
+
+impl From<PoolBalances> for Balance {
+    fn from(balances: PoolBalances) -> Self {
+        Balance {
+            sapling_balance: balances.sapling_balance,
+            verified_sapling_balance: balances.verified_sapling_balance,
+            spendable_sapling_balance: balances.spendable_sapling_balance,
+            unverified_sapling_balance: balances.unverified_sapling_balance,
+            pending_incoming_sapling_balance: balances.pending_incoming_sapling_balance,
+            pending_outgoing_sapling_balance: balances.pending_outgoing_sapling_balance,
+            orchard_balance: balances.orchard_balance,
+            verified_orchard_balance: balances.verified_orchard_balance,
+            spendable_orchard_balance: balances.spendable_orchard_balance,
+            unverified_orchard_balance: balances.unverified_orchard_balance,
+            pending_incoming_orchard_balance: balances.pending_incoming_orchard_balance,
+            pending_outgoing_orchard_balance: balances.pending_outgoing_orchard_balance,
+            transparent_balance: balances.transparent_balance,
+            pending_incoming_transparent_balance: balances.pending_incoming_transparent_balance,
+            pending_outgoing_transparent_balance: balances.pending_outgoing_transparent_balance,
+        }
+    }
+}
+
+/// A unified address of the wallet, and the receivers it contains
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct Address {
+    pub address: String,
+    pub transparent: Option<String>,
+    pub sapling: Option<String>,
+    pub has_orchard: bool,
+}
+
+impl From<AddressRecord> for Address {
+    fn from(address: AddressRecord) -> Self {
+        Address {
+            address: address.address,
+            transparent: address.receivers.transparent,
+            sapling: address.receivers.sapling,
+            has_orchard: address.receivers.orchard_exists,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct Payment {
+    pub address: String,
+    pub amount: u64,
+    /// Text, or hex prefixed with 0x. Only shielded addresses take a memo.
+    pub memo: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct OutgoingPayment {
+    pub address: String,
+    pub value: u64,
+    pub memo: Option<String>,
+}
+
+impl From<OutgoingRecord> for OutgoingPayment {
+    fn from(outgoing: OutgoingRecord) -> Self {
+        OutgoingPayment {
+            address: outgoing.address,
+            value: outgoing.value,
+            memo: outgoing.memo,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, uniffi::Record)]
+pub struct Transaction {
+    pub txid: String,
+    pub block_height: u32,
+    pub unconfirmed: bool,
+    pub datetime: u64,
+    /// Net change to the wallet's balance
+    pub amount: i64,
+    pub zec_price: Option<f64>,
+    /// Where the value was received, for received value
+    pub address: Option<String>,
+    pub memo: Option<String>,
+    /// What was paid, for transactions the wallet sent
+    pub outgoing: Vec<OutgoingPayment>,
+}
+
+impl From<TransactionRecord> for Transaction {
+    fn from(transaction: TransactionRecord) -> Self {
+        Transaction {
+            txid: transaction.txid,
+            block_height: transaction.block_height,
+            unconfirmed: transaction.unconfirmed,
+            datetime: transaction.datetime,
+            amount: transaction.amount,
+            zec_price: transaction.zec_price,
+            address: transaction.address,
+            memo: transaction.memo,
+            outgoing: transaction
+                .outgoing_metadata
+                .unwrap_or_default()
+                .into_iter()
+                .map(OutgoingPayment::from)
+                .collect(),
+        }
+    }
+}
+
+/// Where a running sync is at
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct SyncProgress {
+    pub sync_id: u64,
+    pub start_block: u64,
+    pub end_block: u64,
+    pub synced_blocks: u64,
+    pub trial_decryptions_blocks: u64,
+    pub txn_scan_blocks: u64,
+    pub total_blocks: u64,
+    pub batch_num: u64,
+    pub batch_total: u64,
+}
+
+impl From<&BatchSyncStatus> for SyncProgress {
+    fn from(status: &BatchSyncStatus) -> Self {
+        SyncProgress {
+            sync_id: status.sync_id,
+            start_block: status.start_block,
+            end_block: status.end_block,
+            synced_blocks: status.blocks_done,
+            trial_decryptions_blocks: status.trial_dec_done,
+            txn_scan_blocks: status.txn_scan_done,
+            total_blocks: status.blocks_total,
+            batch_num: status.batch_num as u64,
+            batch_total: status.batch_total as u64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Record)]
+pub struct SyncSummary {
+    pub success: bool,
+    pub latest_block: u64,
+    pub total_blocks_synced: u64,
+}
+
+impl From<SyncResult> for SyncSummary {
+    fn from(result: SyncResult) -> Self {
+        SyncSummary {
+            success: result.success,
+            latest_block: result.latest_block,
+            total_blocks_synced: result.total_blocks_synced,
+        }
+    }
+}
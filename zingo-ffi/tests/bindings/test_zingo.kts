@@ -0,0 +1,51 @@
+import uniffi.zingo_ffi.*
+
+// Everything here runs offline: a regtest wallet born at height 1 needs no tree state from the
+// server, so nothing listens on the server address
+val seed = "chimney better bulb horror rebuild whisper improve intact letter giraffe brave rib appear bulk aim burst snap salt hill sad merge tennis phrase raise"
+val dataDir = java.nio.file.Files.createTempDirectory("zingo-ffi").toString()
+val config = WalletConfig(serverUri = "http://127.0.0.1:9067", chain = Chain.REGTEST, dataDir = dataDir)
+
+val client = ZingoClient.restore(config, seed, 1uL)
+assert(client.seedPhrase() == seed)
+assert(client.birthday() == 1uL)
+assert(client.transactions().isEmpty())
+
+val balance = client.balance()
+assert(balance.orchardBalance == 0uL)
+assert(balance.saplingBalance == 0uL)
+assert(balance.transparentBalance == 0uL)
+
+val addresses = client.addresses()
+assert(addresses.size == 1)
+assert(addresses[0].hasOrchard)
+
+// Saved wallets come back with the same keys, from disk or from bytes
+client.save()
+assert(ZingoClient.load(config).addresses() == addresses)
+assert(ZingoClient.loadFromBytes(config, client.saveToBytes()).addresses() == addresses)
+
+try {
+    client.send(listOf())
+    throw AssertionError("Sent nothing")
+} catch (e: ZingoException.InvalidArgument) {
+}
+
+try {
+    client.send(listOf(Payment(addresses[0].transparent!!, 1000uL, "hello")))
+    throw AssertionError("Sent a memo to a transparent address")
+} catch (e: ZingoException.InvalidArgument) {
+}
+
+try {
+    ZingoClient.load(config.copy(serverUri = "not a server"))
+    throw AssertionError("Loaded with a bad server")
+} catch (e: ZingoException.InvalidArgument) {
+}
+
+val emptyDir = java.nio.file.Files.createTempDirectory("zingo-ffi").toString()
+try {
+    ZingoClient.load(config.copy(dataDir = emptyDir))
+    throw AssertionError("Loaded a wallet that doesn't exist")
+} catch (e: ZingoException.Wallet) {
+}
@@ -0,0 +1,61 @@
+import Foundation
+import zingo_ffi
+
+// Everything here runs offline: a regtest wallet born at height 1 needs no tree state from the
+// server, so nothing listens on the server address
+let seed = "chimney better bulb horror rebuild whisper improve intact letter giraffe brave rib appear bulk aim burst snap salt hill sad merge tennis phrase raise"
+
+func tempDir() -> String {
+    let dir = FileManager.default.temporaryDirectory.appendingPathComponent(UUID().uuidString)
+    try! FileManager.default.createDirectory(at: dir, withIntermediateDirectories: true)
+    return dir.path
+}
+
+let config = WalletConfig(serverUri: "http://127.0.0.1:9067", chain: .regtest, dataDir: tempDir())
+
+let client = try! ZingoClient.restore(config: config, seed: seed, birthday: 1)
+assert(try! client.seedPhrase() == seed)
+assert(client.birthday() == 1)
+assert(client.transactions().isEmpty)
+
+let balance = client.balance()
+assert(balance.orchardBalance == 0)
+assert(balance.saplingBalance == 0)
+assert(balance.transparentBalance == 0)
+
+let addresses = client.addresses()
+assert(addresses.count == 1)
+assert(addresses[0].hasOrchard)
+
+// Saved wallets come back with the same keys, from disk or from bytes
+try! client.save()
+assert(try! ZingoClient.load(config: config).addresses() == addresses)
+assert(try! ZingoClient.loadFromBytes(config: config, bytes: client.saveToBytes()).addresses() == addresses)
+
+do {
+    _ = try client.send(payments: [])
+    fatalError("Sent nothing")
+} catch ZingoError.InvalidArgument {
+}
+
+do {
+    _ = try client.send(payments: [Payment(address: addresses[0].transparent!, amount: 1000, memo: "hello")])
+    fatalError("Sent a memo to a transparent address")
+} catch ZingoError.InvalidArgument {
+}
+
+do {
+    var badServer = config
+    badServer.serverUri = "not a server"
+    _ = try ZingoClient.load(config: badServer)
+    fatalError("Loaded with a bad server")
+} catch ZingoError.InvalidArgument {
+}
+
+do {
+    var empty = config
+    empty.dataDir = tempDir()
+    _ = try ZingoClient.load(config: empty)
+    fatalError("Loaded a wallet that doesn't exist")
+} catch ZingoError.Wallet {
+}
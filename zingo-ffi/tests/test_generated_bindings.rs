@@ -0,0 +1,6 @@
+#![cfg(feature = "host_language_tests")]
+
+uniffi::build_foreign_language_testcases!(
+    "tests/bindings/test_zingo.kts",
+    "tests/bindings/test_zingo.swift",
+);
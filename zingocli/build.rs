@@ -3,10 +3,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         &[
             "../zingolib/proto/compact_formats.proto",
             "proto/darkside.proto",
+            "proto/wallet.proto",
             "../zingolib/proto/service.proto",
         ],
         &["proto", "../zingolib/proto"],
     )?;
     println!("cargo:rerun-if-changed=proto/darkside.proto");
+    println!("cargo:rerun-if-changed=proto/wallet.proto");
     Ok(())
 }
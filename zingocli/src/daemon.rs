@@ -0,0 +1,973 @@
+//! `zingo-cli --daemon`: the wallet served over HTTP JSON-RPC instead of the interactive prompt.
+//!
+//! Only a zcashd-compatible subset of methods is answered, so that tooling written against
+//! zcashd's wallet RPC can drive a light wallet. Amounts are in ZEC and errors carry zcashd's
+//! error codes. Like zcashd, `z_sendmany` returns an operation id straight away and the send
+//! runs in the background, to be polled with `z_getoperationstatus`.
+//!
+//! Every method is answered by running commands through the same command loop the prompt
+//! uses, one command at a time. The gRPC service shares the loop and the credentials.
+//!
+//! Besides zcashd's methods, every command but those in [`COMMANDS_NOT_SERVED`] can be called
+//! as `zingo_<command>`, with its arguments as params: strictly in order in an array, or by
+//! name in an object, as declared by the command. Its result is the command's, unchanged.
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http_body::{LengthLimitError, Limited};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use json::{object, JsonValue};
+use log::info;
+use rand::RngCore;
+use zingolib::commands::{self, args, CommandError, CommandResult};
+
+use crate::CommandChannel;
+
+/// The ports zcashd listens on for RPC
+pub const MAINNET_RPC_PORT: u16 = 8232;
+pub const TESTNET_RPC_PORT: u16 = 18232;
+
+/// Where the generated credentials go, in the data dir, when no token is given
+const COOKIE_FILE: &str = ".cookie";
+const COOKIE_USER: &str = "__cookie__";
+
+const ZATS_PER_ZEC: u64 = 100_000_000;
+
+/// How many finished operations `z_getoperationstatus` can still report. Older ones are
+/// forgotten as new sends start, while those still executing are always kept.
+const MAX_FINISHED_OPERATIONS: usize = 100;
+
+/// The largest request body read, in bytes. Larger ones are turned away before they are parsed.
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Methods that run a command, e.g. `zingo_balance`
+const COMMAND_METHOD_PREFIX: &str = "zingo_";
+
+/// Commands not served as methods: stopping the wallet, destroying its data and reading files
+/// of the daemon's host are left to whoever runs the daemon
+pub(crate) const COMMANDS_NOT_SERVED: &[&str] = &["quit", "delete", "clear", "sendmany"];
+
+// zcashd's RPC error codes
+const RPC_MISC_ERROR: i32 = -1;
+const RPC_WALLET_ERROR: i32 = -4;
+const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+const RPC_INVALID_PARAMETER: i32 = -8;
+const RPC_INVALID_REQUEST: i32 = -32600;
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+const RPC_PARSE_ERROR: i32 = -32700;
+
+#[derive(Debug)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        object! {
+            "code" => self.code,
+            "message" => self.message.clone(),
+        }
+    }
+}
+
+enum OperationStatus {
+    Executing,
+    Success(String),
+    Failed(String),
+}
+
+/// A background send, as reported by `z_getoperationstatus`
+struct Operation {
+    id: String,
+    method: &'static str,
+    params: JsonValue,
+    creation_time: u64,
+    status: OperationStatus,
+}
+
+impl Operation {
+    fn is_finished(&self) -> bool {
+        !matches!(self.status, OperationStatus::Executing)
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut json = object! {
+            "id" => self.id.clone(),
+            "creation_time" => self.creation_time,
+            "method" => self.method,
+            "params" => self.params.clone(),
+        };
+        match &self.status {
+            OperationStatus::Executing => json["status"] = "executing".into(),
+            OperationStatus::Success(txid) => {
+                json["status"] = "success".into();
+                json["result"] = object! { "txid" => txid.clone() };
+            }
+            OperationStatus::Failed(message) => {
+                json["status"] = "failed".into();
+                json["error"] = RpcError::new(RPC_WALLET_ERROR, message.clone()).to_json();
+            }
+        }
+        json
+    }
+}
+
+struct Daemon {
+    commands: Arc<CommandChannel>,
+    token: String,
+    operations: Mutex<Vec<Operation>>,
+}
+
+/// Serves JSON-RPC on `bind` until `shutdown` completes.
+pub(crate) async fn serve(
+    bind: SocketAddr,
+    token: String,
+    commands: Arc<CommandChannel>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let daemon = Arc::new(Daemon {
+        commands,
+        token,
+        operations: Mutex::new(vec![]),
+    });
+    let make_service = make_service_fn(move |_| {
+        let daemon = daemon.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(daemon.clone(), request))) }
+    });
+
+    let server =
+        Server::try_bind(&bind).map_err(|e| io::Error::new(io::ErrorKind::AddrInUse, e))?;
+    println!("Serving JSON-RPC on {}", bind);
+    info!("Serving JSON-RPC on {}", bind);
+    server
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+/// Generates a token and writes it to a cookie file in `data_dir`, for clients on the same
+/// machine to read. The file should be removed again once the servers stop.
+pub(crate) fn generate_cookie(data_dir: &Path) -> io::Result<(String, PathBuf)> {
+    let cookie_path = data_dir.join(COOKIE_FILE);
+    let token = random_hex(32);
+    write_cookie(&cookie_path, &token)?;
+    println!("RPC credentials written to {}", cookie_path.display());
+    Ok((token, cookie_path))
+}
+
+fn write_cookie(path: &Path, token: &str) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    write!(file, "{}:{}", COOKIE_USER, token)
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// zcashd's operation ids are `opid-` followed by a random UUID
+fn new_operation_id() -> String {
+    let hex = random_hex(16);
+    format!(
+        "opid-{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn zats_to_zec(zats: u64) -> JsonValue {
+    JsonValue::Number(json::number::Number::from_parts(true, zats, -8))
+}
+
+fn format_zec(zats: u64) -> String {
+    format!("{}.{:08}", zats / ZATS_PER_ZEC, zats % ZATS_PER_ZEC)
+}
+
+/// Amounts come in ZEC, as numbers or strings, and can't be more precise than a zat
+fn zec_to_zats(amount: &JsonValue) -> Result<u64, RpcError> {
+    let invalid = || RpcError::new(RPC_INVALID_PARAMETER, "Invalid amount");
+    let amount = match amount.as_str() {
+        Some(s) => json::parse(s).unwrap_or(JsonValue::Null),
+        None => amount.clone(),
+    };
+    // as_fixed_point_u64 would round away any digits past the eighth decimal
+    if let JsonValue::Number(number) = &amount {
+        let (_, mantissa, exponent) = number.as_parts();
+        if exponent < -8 {
+            let excess_digits = (-8 - i32::from(exponent)) as u32;
+            let exact = mantissa == 0
+                || 10u64
+                    .checked_pow(excess_digits)
+                    .map_or(false, |scale| mantissa % scale == 0);
+            if !exact {
+                return Err(invalid());
+            }
+        }
+    }
+    amount.as_fixed_point_u64(8).ok_or_else(invalid)
+}
+
+fn string_param<'a>(params: &'a JsonValue, index: usize, name: &str) -> Result<&'a str, RpcError> {
+    params[index].as_str().ok_or_else(|| {
+        RpcError::new(
+            RPC_INVALID_PARAMETER,
+            format!("Invalid parameter, {} must be a string", name),
+        )
+    })
+}
+
+fn u64_param(params: &JsonValue, index: usize, name: &str, default: u64) -> Result<u64, RpcError> {
+    if params[index].is_null() {
+        return Ok(default);
+    }
+    params[index].as_u64().ok_or_else(|| {
+        RpcError::new(
+            RPC_INVALID_PARAMETER,
+            format!("Invalid parameter, {} must be a non-negative integer", name),
+        )
+    })
+}
+
+/// Checks an `Authorization` header against `token`. The token can be presented as a bearer
+/// token, or as the password of basic auth (with any user name, which is what zcashd tooling
+/// configured with an rpcuser and rpcpassword sends).
+pub(crate) fn authorized(header: Option<&str>, token: &str) -> bool {
+    let header = match header {
+        Some(header) => header,
+        None => return false,
+    };
+    let presented = if let Some(bearer) = header.strip_prefix("Bearer ") {
+        bearer.trim().to_string()
+    } else if let Some(credentials) = header.strip_prefix("Basic ") {
+        match base64::decode(credentials.trim())
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| decoded.split_once(':').map(|(_, p)| p.to_string()))
+        {
+            Some(password) => password,
+            None => return false,
+        }
+    } else {
+        return false;
+    };
+
+    // Compare in constant time
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn handle(daemon: Arc<Daemon>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if !daemon.authorized(&request) {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, "Basic realm=\"jsonrpc\"")
+            .body(Body::empty())
+            .unwrap());
+    }
+    if request.method() != Method::POST {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("JSON-RPC uses POST"))
+            .unwrap());
+    }
+
+    let body = hyper::body::to_bytes(Limited::new(request.into_body(), MAX_REQUEST_BYTES)).await;
+    if matches!(&body, Err(e) if e.is::<LengthLimitError>()) {
+        return Ok(Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body(Body::from("Request too large"))
+            .unwrap());
+    }
+    let call = body
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes.to_vec()).ok())
+        .and_then(|body| json::parse(&body).ok());
+    let (status, reply) = match call {
+        Some(JsonValue::Array(calls)) => {
+            let mut replies = vec![];
+            for call in calls {
+                replies.push(run_call(daemon.clone(), call).await.1);
+            }
+            (StatusCode::OK, JsonValue::Array(replies))
+        }
+        Some(call) => run_call(daemon, call).await,
+        None => (
+            StatusCode::BAD_REQUEST,
+            reply(
+                JsonValue::Null,
+                Err(RpcError::new(RPC_PARSE_ERROR, "Parse error")),
+            ),
+        ),
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(reply.dump()))
+        .unwrap())
+}
+
+async fn run_call(daemon: Arc<Daemon>, call: JsonValue) -> (StatusCode, JsonValue) {
+    let id = call["id"].clone();
+    let method = match call["method"].as_str() {
+        Some(method) => method.to_string(),
+        None => {
+            let error = RpcError::new(RPC_INVALID_REQUEST, "Method must be a string");
+            return (StatusCode::BAD_REQUEST, reply(id, Err(error)));
+        }
+    };
+    let params = match &call["params"] {
+        JsonValue::Null => JsonValue::new_array(),
+        params if params.is_array() => params.clone(),
+        // Named params are only for commands, zcashd's methods take theirs in order
+        params if params.is_object() && method.starts_with(COMMAND_METHOD_PREFIX) => params.clone(),
+        _ => {
+            let error = RpcError::new(RPC_INVALID_REQUEST, "Params must be an array");
+            return (StatusCode::BAD_REQUEST, reply(id, Err(error)));
+        }
+    };
+
+    // Commands block until the command loop answers, so keep them off the async workers
+    let result = tokio::task::spawn_blocking(move || daemon.call(&method, &params))
+        .await
+        .unwrap_or_else(|e| Err(RpcError::new(RPC_MISC_ERROR, e.to_string())));
+    let status = match &result {
+        Ok(_) => StatusCode::OK,
+        Err(e) if e.code == RPC_METHOD_NOT_FOUND => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, reply(id, result))
+}
+
+/// Adds an operation, forgetting the oldest finished ones beyond [`MAX_FINISHED_OPERATIONS`]
+fn push_operation(operations: &mut Vec<Operation>, operation: Operation) {
+    operations.push(operation);
+    let mut excess = operations
+        .iter()
+        .filter(|operation| operation.is_finished())
+        .count()
+        .saturating_sub(MAX_FINISHED_OPERATIONS);
+    // Operations are kept in the order they started
+    operations.retain(|operation| {
+        if excess > 0 && operation.is_finished() {
+            excess -= 1;
+            false
+        } else {
+            true
+        }
+    });
+}
+
+fn reply(id: JsonValue, result: Result<JsonValue, RpcError>) -> JsonValue {
+    match result {
+        Ok(result) => object! { "result" => result, "error" => JsonValue::Null, "id" => id },
+        Err(e) => object! { "result" => JsonValue::Null, "error" => e.to_json(), "id" => id },
+    }
+}
+
+impl Daemon {
+    fn authorized(&self, request: &Request<Body>) -> bool {
+        let header = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok());
+        authorized(header, &self.token)
+    }
+
+    /// Runs a command through the command loop, blocking until it answers
+    fn command(&self, command: &str, args: Vec<String>) -> Result<CommandResult, RpcError> {
+        self.commands
+            .run(command, args)
+            .map_err(|e| RpcError::new(RPC_MISC_ERROR, e))
+    }
+
+    /// Runs a command and returns its result, turning a failure into the closest zcashd error
+    fn json_command(&self, command: &str, args: Vec<String>) -> Result<JsonValue, RpcError> {
+        match self.command(command, args)? {
+            Ok(output) => Ok(output.to_json()),
+            Err(failure) => {
+                let code = match failure.error {
+                    CommandError::InvalidArguments(_) => RPC_INVALID_PARAMETER,
+                    CommandError::Wallet(_) => RPC_WALLET_ERROR,
+                    CommandError::UnknownCommand(_) | CommandError::Server(_) => RPC_MISC_ERROR,
+                };
+                Err(RpcError::new(code, failure.error.to_string()))
+            }
+        }
+    }
+
+    fn call(self: &Arc<Self>, method: &str, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        match method {
+            "z_getbalance" => self.z_getbalance(params),
+            "z_gettotalbalance" => self.z_gettotalbalance(params),
+            "z_listunspent" => self.z_listunspent(params),
+            "z_getnewaddress" => self.z_getnewaddress(params),
+            "z_sendmany" => self.z_sendmany(params),
+            "z_getoperationstatus" => self.z_getoperationstatus(params),
+            _ => match method.strip_prefix(COMMAND_METHOD_PREFIX) {
+                Some(command) => self.command_method(command, params),
+                None => Err(RpcError::new(RPC_METHOD_NOT_FOUND, "Method not found")),
+            },
+        }
+    }
+
+    /// `zingo_<command>`: runs the command with the params as its arguments
+    fn command_method(&self, command: &str, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        let specs = match commands::get_commands().get(command) {
+            Some(c) if !COMMANDS_NOT_SERVED.contains(&command) => c.args(),
+            _ => return Err(RpcError::new(RPC_METHOD_NOT_FOUND, "Method not found")),
+        };
+        let args = args::from_json_params(specs, params)
+            .map_err(|e| RpcError::new(RPC_INVALID_PARAMETER, e))?;
+        self.json_command(command, args)
+    }
+
+    /// Every address and receiver of the wallet, mapped to the unified address it belongs to
+    /// and, for a receiver, the pool it receives into
+    fn wallet_addresses(
+        &self,
+    ) -> Result<HashMap<String, (String, Option<&'static str>)>, RpcError> {
+        let mut addresses = HashMap::new();
+        for address in self.json_command("addresses", vec![])?.members() {
+            let ua = match address["address"].as_str() {
+                Some(ua) => ua.to_string(),
+                None => continue,
+            };
+            for pool in ["transparent", "sapling"] {
+                if let Some(receiver) = address["receivers"][pool].as_str() {
+                    addresses.insert(receiver.to_string(), (ua.clone(), Some(pool)));
+                }
+            }
+            addresses.insert(ua.clone(), (ua, None));
+        }
+        Ok(addresses)
+    }
+
+    /// The wallet's unspent notes and utxos, in `z_listunspent`'s format
+    fn unspent_outputs(&self) -> Result<Vec<JsonValue>, RpcError> {
+        let height = self.json_command("height", vec!["false".to_string()])?["height"]
+            .as_u64()
+            .unwrap_or(0);
+        let notes = self.json_command("notes", vec![])?;
+
+        let mut outputs = vec![];
+        for (list, pool) in [
+            ("unspent_sapling_notes", "sapling"),
+            ("unspent_orchard_notes", "orchard"),
+            ("utxos", "transparent"),
+        ] {
+            for note in notes[list].members() {
+                let confirmations = match note["created_in_block"].as_u64() {
+                    Some(_) if note["unconfirmed"].as_bool().unwrap_or(false) => 0,
+                    Some(created) => (height + 1).saturating_sub(created),
+                    None => 0,
+                };
+                let zats = note["value"].as_u64().unwrap_or(0);
+                outputs.push(object! {
+                    "txid" => note["created_in_txid"].clone(),
+                    "pool" => pool,
+                    "confirmations" => confirmations,
+                    "spendable" => note["spendable"].as_bool().unwrap_or(confirmations > 0),
+                    "address" => note["address"].clone(),
+                    "amount" => zats_to_zec(zats),
+                    "amountZat" => zats,
+                    "change" => note["is_change"].as_bool().unwrap_or(false),
+                });
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// z_getbalance "address" ( minconf )
+    fn z_getbalance(&self, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        let address = string_param(params, 0, "address")?;
+        let minconf = u64_param(params, 1, "minconf", 1)?;
+        let (ua, pool) = self.wallet_addresses()?.remove(address).ok_or_else(|| {
+            RpcError::new(
+                RPC_INVALID_ADDRESS_OR_KEY,
+                "From address does not belong to this wallet",
+            )
+        })?;
+
+        let zats = self
+            .unspent_outputs()?
+            .iter()
+            .filter(|output| output["address"].as_str() == Some(&ua))
+            .filter(|output| pool.map_or(true, |pool| output["pool"] == pool))
+            .filter(|output| output["confirmations"].as_u64().unwrap_or(0) >= minconf)
+            .map(|output| output["amountZat"].as_u64().unwrap_or(0))
+            .sum();
+        Ok(zats_to_zec(zats))
+    }
+
+    /// z_gettotalbalance ( minconf includeWatchonly )
+    fn z_gettotalbalance(&self, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        let minconf = u64_param(params, 0, "minconf", 1)?;
+        let (mut transparent, mut private) = (0, 0);
+        for output in self.unspent_outputs()? {
+            if output["confirmations"].as_u64().unwrap_or(0) < minconf {
+                continue;
+            }
+            let zats = output["amountZat"].as_u64().unwrap_or(0);
+            if output["pool"] == "transparent" {
+                transparent += zats;
+            } else {
+                private += zats;
+            }
+        }
+        Ok(object! {
+            "transparent" => format_zec(transparent),
+            "private" => format_zec(private),
+            "total" => format_zec(transparent + private),
+        })
+    }
+
+    /// z_listunspent ( minconf maxconf includeWatchonly ["address",...] )
+    fn z_listunspent(&self, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        let minconf = u64_param(params, 0, "minconf", 1)?;
+        let maxconf = u64_param(params, 1, "maxconf", 9_999_999)?;
+        let addresses = if params[3].is_null() {
+            None
+        } else {
+            let wallet_addresses = self.wallet_addresses()?;
+            let selected = params[3]
+                .members()
+                .map(|address| {
+                    address
+                        .as_str()
+                        .and_then(|address| wallet_addresses.get(address))
+                        .cloned()
+                        .ok_or_else(|| {
+                            RpcError::new(
+                                RPC_INVALID_PARAMETER,
+                                format!(
+                                    "Invalid parameter, address not in this wallet: {}",
+                                    address
+                                ),
+                            )
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Some(selected)
+        };
+
+        let outputs = self
+            .unspent_outputs()?
+            .into_iter()
+            .filter(|output| {
+                let confirmations = output["confirmations"].as_u64().unwrap_or(0);
+                minconf <= confirmations && confirmations <= maxconf
+            })
+            .filter(|output| match &addresses {
+                None => true,
+                Some(addresses) => addresses.iter().any(|(ua, pool)| {
+                    output["address"].as_str() == Some(ua)
+                        && pool.map_or(true, |pool| output["pool"] == pool)
+                }),
+            })
+            .collect();
+        Ok(JsonValue::Array(outputs))
+    }
+
+    /// z_getnewaddress ( "type" ). Only Sapling addresses, as with current zcashd.
+    fn z_getnewaddress(&self, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        let address_type = params[0].as_str().unwrap_or("sapling");
+        if address_type != "sapling" {
+            return Err(RpcError::new(
+                RPC_INVALID_PARAMETER,
+                format!("Invalid address type: {}", address_type),
+            ));
+        }
+
+        let new_address = self.json_command("new", vec!["z".to_string()])?;
+        let ua = new_address[0].as_str().unwrap_or_default().to_string();
+        self.wallet_addresses()?
+            .into_iter()
+            .find(|(_, (owner, pool))| *owner == ua && *pool == Some("sapling"))
+            .map(|(sapling, _)| sapling.into())
+            .ok_or_else(|| RpcError::new(RPC_WALLET_ERROR, "Couldn't create a Sapling address"))
+    }
+
+    /// z_sendmany "fromaddress" [{"address":...,"amount":...,"memo":...},...] ( minconf fee )
+    ///
+    /// The wallet chooses which notes to spend and what fee to pay, so `fromaddress` only has to
+    /// belong to the wallet, and `fee` has to be left out.
+    fn z_sendmany(self: &Arc<Self>, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        let from = string_param(params, 0, "fromaddress")?;
+        if !self.wallet_addresses()?.contains_key(from) {
+            return Err(RpcError::new(
+                RPC_INVALID_ADDRESS_OR_KEY,
+                "From address does not belong to this wallet",
+            ));
+        }
+        if !params[1].is_array() || params[1].is_empty() {
+            return Err(RpcError::new(
+                RPC_INVALID_PARAMETER,
+                "Invalid parameter, amounts array is empty",
+            ));
+        }
+        if !params[3].is_null() {
+            return Err(RpcError::new(
+                RPC_INVALID_PARAMETER,
+                "Invalid parameter, the fee is chosen by the wallet and can't be set",
+            ));
+        }
+
+        let mut recipients = JsonValue::new_array();
+        for recipient in params[1].members() {
+            let address = recipient["address"].as_str().ok_or_else(|| {
+                RpcError::new(RPC_INVALID_PARAMETER, "Invalid parameter, missing address")
+            })?;
+            // zcashd memos are hex, which the send command takes with a 0x prefix
+            let memo = recipient["memo"].as_str().map(|memo| format!("0x{}", memo));
+            recipients
+                .push(object! {
+                    "address" => address,
+                    "amount" => zec_to_zats(&recipient["amount"])?,
+                    "memo" => memo,
+                })
+                .unwrap();
+        }
+
+        let id = new_operation_id();
+        push_operation(
+            &mut self.operations.lock().unwrap(),
+            Operation {
+                id: id.clone(),
+                method: "z_sendmany",
+                params: params.clone(),
+                creation_time: now(),
+                status: OperationStatus::Executing,
+            },
+        );
+
+        let daemon = self.clone();
+        let operation_id = id.clone();
+        std::thread::spawn(move || {
+            let status = match daemon.json_command("send", vec![recipients.dump()]) {
+                Ok(sent) => match sent["txid"].as_str() {
+                    Some(txid) => OperationStatus::Success(txid.to_string()),
+                    None => OperationStatus::Failed(sent.dump()),
+                },
+                Err(e) => OperationStatus::Failed(e.message),
+            };
+            if let Some(operation) = daemon
+                .operations
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .find(|operation| operation.id == operation_id)
+            {
+                operation.status = status;
+            }
+        });
+
+        Ok(id.into())
+    }
+
+    /// z_getoperationstatus ( ["operationid", ...] )
+    fn z_getoperationstatus(&self, params: &JsonValue) -> Result<JsonValue, RpcError> {
+        let wanted = params[0]
+            .members()
+            .filter_map(|id| id.as_str())
+            .collect::<Vec<_>>();
+        let statuses = self
+            .operations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|operation| wanted.is_empty() || wanted.contains(&operation.id.as_str()))
+            .map(Operation::to_json)
+            .collect();
+        Ok(JsonValue::Array(statuses))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use zingolib::commands::CommandOutput;
+
+    /// A daemon whose command loop answers every command with `answer`
+    fn daemon_answering(answer: fn(&str, &[String]) -> CommandResult) -> Arc<Daemon> {
+        let (command_transmitter, command_receiver) = channel::<crate::CommandRequest>();
+        let (resp_transmitter, resp_receiver) = channel();
+        std::thread::spawn(move || {
+            while let Ok((command, args)) = command_receiver.recv() {
+                if resp_transmitter.send(answer(&command, &args)).is_err() {
+                    break;
+                }
+            }
+        });
+        Arc::new(Daemon {
+            commands: Arc::new(CommandChannel::new(command_transmitter, resp_receiver)),
+            token: "0123abcd".to_string(),
+            operations: Mutex::new(vec![]),
+        })
+    }
+
+    /// Answers with the command and arguments it was asked to run
+    fn echo(command: &str, args: &[String]) -> CommandResult {
+        Ok(CommandOutput::json(
+            object! { "command" => command, "args" => args.to_vec() },
+        ))
+    }
+
+    async fn post(daemon: &Arc<Daemon>, body: &str) -> (StatusCode, JsonValue) {
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(AUTHORIZATION, "Bearer 0123abcd")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = handle(daemon.clone(), request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (
+            status,
+            json::parse(std::str::from_utf8(&body).unwrap()).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn command_methods_take_params_in_order_or_by_name() {
+        let daemon = daemon_answering(echo);
+        let (status, reply) = post(
+            &daemon,
+            r#"{"jsonrpc": "1.0", "id": 7, "method": "zingo_height", "params": [true]}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reply["id"], 7);
+        assert!(reply["error"].is_null());
+        assert_eq!(reply["result"]["command"], "height");
+        assert_eq!(reply["result"]["args"], json::array!["--", "true"]);
+
+        let (_, reply) = post(
+            &daemon,
+            r#"{"id": 8, "method": "zingo_height", "params": {"sync": false}}"#,
+        )
+        .await;
+        assert_eq!(reply["result"]["args"], json::array!["sync=false"]);
+
+        let (status, reply) = post(
+            &daemon,
+            r#"{"id": 9, "method": "zingo_height", "params": {"when": 1}}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(reply["error"]["code"], RPC_INVALID_PARAMETER);
+    }
+
+    #[tokio::test]
+    async fn bad_requests_get_zcashd_errors() {
+        let daemon = daemon_answering(echo);
+        for (body, status, code) in [
+            ("{", StatusCode::BAD_REQUEST, RPC_PARSE_ERROR),
+            (r#"{"id": 1}"#, StatusCode::BAD_REQUEST, RPC_INVALID_REQUEST),
+            (
+                r#"{"id": 1, "method": "getinfo"}"#,
+                StatusCode::NOT_FOUND,
+                RPC_METHOD_NOT_FOUND,
+            ),
+            (
+                r#"{"id": 1, "method": "zingo_quit"}"#,
+                StatusCode::NOT_FOUND,
+                RPC_METHOD_NOT_FOUND,
+            ),
+            (
+                r#"{"id": 1, "method": "z_getbalance", "params": {"address": "u1"}}"#,
+                StatusCode::BAD_REQUEST,
+                RPC_INVALID_REQUEST,
+            ),
+        ] {
+            let (got_status, reply) = post(&daemon, body).await;
+            assert_eq!(got_status, status, "{}", body);
+            assert_eq!(reply["error"]["code"], code, "{}", body);
+            assert!(reply["result"].is_null());
+        }
+
+        let unauthorized = Request::builder()
+            .method(Method::POST)
+            .header(AUTHORIZATION, "Bearer 0123abce")
+            .body(Body::from(r#"{"id": 1, "method": "zingo_balance"}"#))
+            .unwrap();
+        let response = handle(daemon, unauthorized).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn command_failures_map_to_zcashd_error_codes() {
+        let daemon = daemon_answering(|_, _| {
+            Err(CommandError::Wallet("Insufficient funds".to_string()).into())
+        });
+        let (status, reply) = post(&daemon, r#"{"id": 1, "method": "zingo_balance"}"#).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(reply["error"]["code"], RPC_WALLET_ERROR);
+        assert_eq!(reply["error"]["message"], "Insufficient funds");
+    }
+
+    #[tokio::test]
+    async fn batches_are_answered_in_order() {
+        let daemon = daemon_answering(echo);
+        let (status, replies) = post(
+            &daemon,
+            r#"[{"id": 1, "method": "zingo_balance"}, {"id": 2, "method": "nothing"}]"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["id"], 1);
+        assert_eq!(replies[0]["result"]["command"], "balance");
+        assert_eq!(replies[1]["id"], 2);
+        assert_eq!(replies[1]["error"]["code"], RPC_METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn total_balance_is_summed_from_the_notes() {
+        let daemon = daemon_answering(|command, _| match command {
+            "height" => Ok(CommandOutput::json(object! { "height" => 100 })),
+            "notes" => Ok(CommandOutput::json(object! {
+                "unspent_sapling_notes" => json::array![
+                    object! { "created_in_block" => 90, "value" => 150_000_000, "address" => "u1" },
+                    // Not confirmed enough for the default minconf
+                    object! { "created_in_block" => 101, "value" => 1, "address" => "u1" },
+                ],
+                "unspent_orchard_notes" => json::array![],
+                "utxos" => json::array![
+                    object! { "created_in_block" => 50, "value" => 2_500, "address" => "u1" },
+                ],
+            })),
+            _ => Err(CommandError::UnknownCommand(command.to_string()).into()),
+        });
+        let (status, reply) = post(&daemon, r#"{"id": 1, "method": "z_gettotalbalance"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            reply["result"],
+            object! {
+                "transparent" => "0.00002500",
+                "private" => "1.50000000",
+                "total" => "1.50002500",
+            }
+        );
+    }
+
+    #[test]
+    fn only_the_latest_finished_operations_are_kept() {
+        let operation = |id: usize, status: OperationStatus| Operation {
+            id: id.to_string(),
+            method: "z_sendmany",
+            params: JsonValue::new_array(),
+            creation_time: 0,
+            status,
+        };
+        let mut operations = vec![operation(0, OperationStatus::Executing)];
+        for id in 1..=MAX_FINISHED_OPERATIONS + 5 {
+            push_operation(
+                &mut operations,
+                operation(id, OperationStatus::Success("txid".to_string())),
+            );
+        }
+        assert_eq!(operations.len(), MAX_FINISHED_OPERATIONS + 1);
+        // The one still executing is kept, however old
+        assert_eq!(operations[0].id, "0");
+        assert_eq!(operations[1].id, "6");
+    }
+
+    #[test]
+    fn amounts_convert_between_zec_and_zats() {
+        assert_eq!(format_zec(123_456_789), "1.23456789");
+        assert_eq!(format_zec(5_000), "0.00005000");
+        assert_eq!(
+            zec_to_zats(&json::parse("1.5").unwrap()).unwrap(),
+            150_000_000
+        );
+        assert_eq!(zec_to_zats(&"0.0001".into()).unwrap(), 10_000);
+        assert!(zec_to_zats(&"lots".into()).is_err());
+        // Nothing finer than a zat
+        assert!(zec_to_zats(&"0.000000001".into()).is_err());
+        assert!(zec_to_zats(&json::parse("1.123456789").unwrap()).is_err());
+        assert_eq!(zec_to_zats(&"1.000000000".into()).unwrap(), 100_000_000);
+        assert!(zec_to_zats(&"-1".into()).is_err());
+        assert_eq!(zec_to_zats(&zats_to_zec(123_456_789)).unwrap(), 123_456_789);
+        assert!(new_operation_id().starts_with("opid-"));
+        assert_eq!(new_operation_id().len(), 41);
+    }
+
+    #[test]
+    fn commands_not_served_are_commands() {
+        let commands = commands::get_commands();
+        for command in COMMANDS_NOT_SERVED {
+            assert!(commands.contains_key(command), "{}", command);
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_requests_are_turned_away_unread() {
+        let daemon = daemon_answering(echo);
+        let call = r#"{"id": 1, "method": "zingo_height", "params": []}"#;
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(AUTHORIZATION, "Bearer 0123abcd")
+            .body(Body::from(format!(
+                "{}{}",
+                " ".repeat(MAX_REQUEST_BYTES),
+                call
+            )))
+            .unwrap();
+        let response = handle(daemon.clone(), request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, reply) = post(&daemon, &format!("{}{}", " ".repeat(1000), call)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(reply["result"]["command"], "height");
+    }
+
+    #[test]
+    fn tokens_are_accepted_as_bearer_or_basic_password() {
+        let token = "0123abcd";
+        assert!(authorized(Some("Bearer 0123abcd"), token));
+        // "zcash:0123abcd"
+        assert!(authorized(Some("Basic emNhc2g6MDEyM2FiY2Q="), token));
+        assert!(!authorized(Some("Bearer 0123abce"), token));
+        assert!(!authorized(Some("Bearer 0123abcd0"), token));
+        assert!(!authorized(Some("Basic 0123abcd"), token));
+        assert!(!authorized(None, token));
+    }
+}
@@ -0,0 +1,515 @@
+//! `zingo-cli --grpc-bind`: the wallet served over gRPC, as the `Wallet` service of
+//! `proto/wallet.proto`.
+//!
+//! Like JSON-RPC, requests run through the command loop, and clients present the same token,
+//! here as `authorization` metadata. Syncing is the exception: it takes a while, so it runs on
+//! the light client beside the command loop rather than holding it, and `SyncStatus` reads
+//! progress from the light client directly.
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use json::{object, JsonValue};
+use log::info;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use zingolib::commands::{self, results::Sent, CommandError, CommandOutput};
+use zingolib::lightclient::records::{AddressRecord, TransactionRecord};
+use zingolib::lightclient::{LightClient, PoolBalances};
+
+use crate::CommandChannel;
+
+pub mod proto {
+    tonic::include_proto!("zingo.wallet");
+}
+use proto::wallet_server::{Wallet, WalletServer};
+use proto::{
+    Address, AddressList, BalanceReply, CommandReply, CommandRequest, Empty, NewAddressRequest,
+    OutgoingPayment, Payment, SendReply, SendRequest, SyncReply, SyncStatusReply,
+    SyncStatusRequest, Transaction,
+};
+
+/// How often `SyncStatus` reports progress
+const SYNC_STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Serves the `Wallet` service on `bind` until `shutdown` completes.
+pub(crate) async fn serve(
+    bind: SocketAddr,
+    token: String,
+    lightclient: Arc<LightClient>,
+    commands: Arc<CommandChannel>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let service = WalletService {
+        lightclient,
+        commands,
+    };
+    let check_token = move |request: Request<()>| {
+        let header = request
+            .metadata()
+            .get("authorization")
+            .and_then(|h| h.to_str().ok());
+        if crate::daemon::authorized(header, &token) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("Missing or wrong token"))
+        }
+    };
+
+    println!("Serving gRPC on {}", bind);
+    info!("Serving gRPC on {}", bind);
+    tonic::transport::Server::builder()
+        .add_service(WalletServer::with_interceptor(service, check_token))
+        .serve_with_shutdown(bind, shutdown)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+struct WalletService {
+    lightclient: Arc<LightClient>,
+    commands: Arc<CommandChannel>,
+}
+
+impl WalletService {
+    /// Runs a command off the async workers, as it blocks until the command loop answers,
+    /// turning a failure into a status
+    async fn run_command(&self, command: &str, args: Vec<String>) -> Result<CommandOutput, Status> {
+        if command == "sync" {
+            return self.sync_beside_command_loop().await;
+        }
+        let commands = self.commands.clone();
+        let command = command.to_string();
+        tokio::task::spawn_blocking(move || commands.run(&command, args))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(Status::unavailable)?
+            .map_err(|failure| status_from_error(failure.error))
+    }
+
+    /// Syncs on the light client directly, so that the command loop stays free to answer
+    /// other requests while the sync runs
+    async fn sync_beside_command_loop(&self) -> Result<CommandOutput, Status> {
+        let lightclient = self.lightclient.clone();
+        tokio::task::spawn_blocking(move || {
+            commands::run_user_command("sync", &[], lightclient.as_ref())
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|failure| status_from_error(failure.error))
+    }
+}
+
+/// A command answered with something other than its result type
+fn unexpected_result(e: CommandError) -> Status {
+    Status::internal(e.to_string())
+}
+
+fn status_from_error(e: CommandError) -> Status {
+    match e {
+        CommandError::UnknownCommand(_) => Status::unimplemented(e.to_string()),
+        CommandError::InvalidArguments(message) => Status::invalid_argument(message),
+        CommandError::Wallet(message) => Status::failed_precondition(message),
+        CommandError::Server(message) => Status::unavailable(message),
+    }
+}
+
+fn address_from_record(address: &AddressRecord) -> Address {
+    Address {
+        address: address.address.clone(),
+        transparent: address.receivers.transparent.clone().unwrap_or_default(),
+        sapling: address.receivers.sapling.clone().unwrap_or_default(),
+        has_orchard: address.receivers.orchard_exists,
+    }
+}
+
+fn balance_from_pools(balance: &PoolBalances) -> BalanceReply {
+    BalanceReply {
+        sapling_balance: balance.sapling_balance.unwrap_or(0),
+        verified_sapling_balance: balance.verified_sapling_balance.unwrap_or(0),
+        spendable_sapling_balance: balance.spendable_sapling_balance.unwrap_or(0),
+        unverified_sapling_balance: balance.unverified_sapling_balance.unwrap_or(0),
+        pending_incoming_sapling_balance: balance.pending_incoming_sapling_balance.unwrap_or(0),
+        pending_outgoing_sapling_balance: balance.pending_outgoing_sapling_balance.unwrap_or(0),
+        orchard_balance: balance.orchard_balance.unwrap_or(0),
+        verified_orchard_balance: balance.verified_orchard_balance.unwrap_or(0),
+        spendable_orchard_balance: balance.spendable_orchard_balance.unwrap_or(0),
+        unverified_orchard_balance: balance.unverified_orchard_balance.unwrap_or(0),
+        pending_incoming_orchard_balance: balance.pending_incoming_orchard_balance.unwrap_or(0),
+        pending_outgoing_orchard_balance: balance.pending_outgoing_orchard_balance.unwrap_or(0),
+        transparent_balance: balance.transparent_balance.unwrap_or(0),
+        pending_incoming_transparent_balance: balance
+            .pending_incoming_transparent_balance
+            .unwrap_or(0),
+        pending_outgoing_transparent_balance: balance
+            .pending_outgoing_transparent_balance
+            .unwrap_or(0),
+    }
+}
+
+/// The `send` command's argument for these payments. An empty memo is no memo.
+fn payments_to_json(payments: Vec<Payment>) -> JsonValue {
+    JsonValue::Array(
+        payments
+            .into_iter()
+            .map(|payment| {
+                object! {
+                    "address" => payment.address,
+                    "amount" => payment.amount,
+                    "memo" => Some(payment.memo).filter(|memo| !memo.is_empty()),
+                }
+            })
+            .collect(),
+    )
+}
+
+fn sync_reply_from_json(synced: &JsonValue) -> SyncReply {
+    SyncReply {
+        success: synced["result"] == "success",
+        latest_block: synced["latest_block"].as_u64().unwrap_or(0),
+        total_blocks_synced: synced["total_blocks_synced"].as_u64().unwrap_or(0),
+    }
+}
+
+fn transaction_from_record(transaction: &TransactionRecord) -> Transaction {
+    Transaction {
+        txid: transaction.txid.clone(),
+        block_height: transaction.block_height,
+        unconfirmed: transaction.unconfirmed,
+        datetime: transaction.datetime,
+        amount: transaction.amount,
+        address: transaction.address.clone().unwrap_or_default(),
+        memo: transaction.memo.clone().unwrap_or_default(),
+        outgoing: transaction
+            .outgoing_metadata
+            .iter()
+            .flatten()
+            .map(|payment| OutgoingPayment {
+                address: payment.address.clone(),
+                value: payment.value,
+                memo: payment.memo.clone().unwrap_or_default(),
+            })
+            .collect(),
+    }
+}
+
+#[tonic::async_trait]
+impl Wallet for WalletService {
+    async fn balance(&self, _request: Request<Empty>) -> Result<Response<BalanceReply>, Status> {
+        let balance = self
+            .run_command("balance", vec![])
+            .await?
+            .result_as::<PoolBalances>()
+            .map_err(unexpected_result)?;
+        Ok(Response::new(balance_from_pools(&balance)))
+    }
+
+    async fn addresses(&self, _request: Request<Empty>) -> Result<Response<AddressList>, Status> {
+        let addresses = self
+            .run_command("addresses", vec![])
+            .await?
+            .result_as::<Vec<AddressRecord>>()
+            .map_err(unexpected_result)?;
+        Ok(Response::new(AddressList {
+            addresses: addresses.iter().map(address_from_record).collect(),
+        }))
+    }
+
+    async fn new_address(
+        &self,
+        request: Request<NewAddressRequest>,
+    ) -> Result<Response<Address>, Status> {
+        let request = request.into_inner();
+        let receivers = [
+            (request.orchard, "o"),
+            (request.sapling, "z"),
+            (request.transparent, "t"),
+        ]
+        .iter()
+        .filter(|(wanted, _)| *wanted)
+        .map(|(_, receiver)| *receiver)
+        .collect::<String>();
+        if receivers.is_empty() {
+            return Err(Status::invalid_argument("Ask for at least one receiver"));
+        }
+
+        let ua = self
+            .run_command("new", vec![receivers])
+            .await?
+            .result_as::<Vec<String>>()
+            .map_err(unexpected_result)?
+            .into_iter()
+            .next()
+            .unwrap_or_default();
+        let addresses = self
+            .run_command("addresses", vec![])
+            .await?
+            .result_as::<Vec<AddressRecord>>()
+            .map_err(unexpected_result)?;
+        Ok(Response::new(
+            addresses
+                .iter()
+                .find(|address| address.address == ua)
+                .map(address_from_record)
+                .unwrap_or_else(|| Address {
+                    address: ua,
+                    ..Default::default()
+                }),
+        ))
+    }
+
+    async fn send(&self, request: Request<SendRequest>) -> Result<Response<SendReply>, Status> {
+        let payments = request.into_inner().payments;
+        if payments.is_empty() {
+            return Err(Status::invalid_argument("Nothing to send"));
+        }
+        let sent = self
+            .run_command("send", vec![payments_to_json(payments).dump()])
+            .await?
+            .result_as::<Sent>()
+            .map_err(unexpected_result)?;
+        Ok(Response::new(SendReply { txid: sent.txid }))
+    }
+
+    async fn sync(&self, _request: Request<Empty>) -> Result<Response<SyncReply>, Status> {
+        let synced = self.sync_beside_command_loop().await?;
+        Ok(Response::new(sync_reply_from_json(&synced.to_json())))
+    }
+
+    type SyncStatusStream = ReceiverStream<Result<SyncStatusReply, Status>>;
+
+    async fn sync_status(
+        &self,
+        request: Request<SyncStatusRequest>,
+    ) -> Result<Response<Self::SyncStatusStream>, Status> {
+        // Until a requested sync has finished, keep streaming even when the wallet doesn't
+        // report one in progress yet
+        let requested_sync_running = Arc::new(AtomicBool::new(false));
+        if request.into_inner().start_sync {
+            requested_sync_running.store(true, Ordering::SeqCst);
+            let lightclient = self.lightclient.clone();
+            let running = requested_sync_running.clone();
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = commands::run_user_command("sync", &[], lightclient.as_ref()) {
+                    log::error!("Sync failed: {}", e);
+                }
+                running.store(false, Ordering::SeqCst);
+            });
+        }
+
+        let lightclient = self.lightclient.clone();
+        let (transmitter, receiver) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SYNC_STATUS_INTERVAL);
+            loop {
+                interval.tick().await;
+                let status = lightclient.do_sync_status().await;
+                let reply = SyncStatusReply {
+                    in_progress: status.in_progress,
+                    sync_id: status.sync_id,
+                    start_block: status.start_block,
+                    end_block: status.end_block,
+                    synced_blocks: status.blocks_done,
+                    trial_decryptions_blocks: status.trial_dec_done,
+                    txn_scan_blocks: status.txn_scan_done,
+                    total_blocks: status.blocks_total,
+                    batch_num: status.batch_num as u64,
+                    batch_total: status.batch_total as u64,
+                    last_error: status.last_error.clone().unwrap_or_default(),
+                };
+                let running = status.in_progress || requested_sync_running.load(Ordering::SeqCst);
+                // Stop when the client has gone away, or once the last status is sent
+                if transmitter.send(Ok(reply)).await.is_err() || !running {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    type TransactionsStream = tokio_stream::Iter<std::vec::IntoIter<Result<Transaction, Status>>>;
+
+    async fn transactions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::TransactionsStream>, Status> {
+        let transactions = self
+            .run_command("list", vec![])
+            .await?
+            .result_as::<Vec<TransactionRecord>>()
+            .map_err(unexpected_result)?;
+        Ok(Response::new(tokio_stream::iter(
+            transactions
+                .iter()
+                .map(|transaction| Ok(transaction_from_record(transaction)))
+                .collect::<Vec<_>>(),
+        )))
+    }
+
+    async fn command(
+        &self,
+        request: Request<CommandRequest>,
+    ) -> Result<Response<CommandReply>, Status> {
+        let request = request.into_inner();
+        // As over JSON-RPC, quitting, destroying the wallet's data and reading the host's files
+        // are left to whoever runs zingo-cli
+        if crate::daemon::COMMANDS_NOT_SERVED.contains(&request.command.as_str()) {
+            return Err(Status::permission_denied(format!(
+                "{} isn't served, run it from zingo-cli itself",
+                request.command
+            )));
+        }
+        let output = self.run_command(&request.command, request.args).await?;
+        Ok(Response::new(CommandReply {
+            output: output.text,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tonic::Code;
+    use zingolib::lightclient::records::{OutgoingRecord, ReceiverRecord};
+
+    #[test]
+    fn addresses_map_to_their_receivers() {
+        let address = AddressRecord {
+            address: "u1abc".to_string(),
+            receivers: ReceiverRecord {
+                transparent: Some("t1abc".to_string()),
+                sapling: None,
+                orchard_exists: true,
+            },
+        };
+        assert_eq!(
+            address_from_record(&address),
+            Address {
+                address: "u1abc".to_string(),
+                transparent: "t1abc".to_string(),
+                sapling: String::new(),
+                has_orchard: true,
+            }
+        );
+    }
+
+    #[test]
+    fn balances_map_pool_by_pool() {
+        // A wallet that can't view a pool reports its balances as none
+        let balance = PoolBalances {
+            sapling_balance: Some(5_000),
+            orchard_balance: Some(7_000),
+            pending_outgoing_orchard_balance: Some(1_000),
+            transparent_balance: None,
+            ..Default::default()
+        };
+        let reply = balance_from_pools(&balance);
+        assert_eq!(reply.sapling_balance, 5_000);
+        assert_eq!(reply.orchard_balance, 7_000);
+        assert_eq!(reply.pending_outgoing_orchard_balance, 1_000);
+        assert_eq!(reply.transparent_balance, 0);
+        assert_eq!(reply.verified_sapling_balance, 0);
+    }
+
+    #[test]
+    fn transactions_map_with_their_outgoing_payments() {
+        let transaction = TransactionRecord {
+            block_height: 100,
+            unconfirmed: false,
+            datetime: 1_700_000_000,
+            txid: "abcd".to_string(),
+            amount: -12_000,
+            zec_price: None,
+            position: None,
+            address: None,
+            memo: None,
+            outgoing_metadata: Some(vec![OutgoingRecord {
+                address: "u1xyz".to_string(),
+                value: 10_000,
+                memo: Some("thanks".to_string()),
+            }]),
+        };
+        assert_eq!(
+            transaction_from_record(&transaction),
+            Transaction {
+                txid: "abcd".to_string(),
+                block_height: 100,
+                unconfirmed: false,
+                datetime: 1_700_000_000,
+                amount: -12_000,
+                address: String::new(),
+                memo: String::new(),
+                outgoing: vec![OutgoingPayment {
+                    address: "u1xyz".to_string(),
+                    value: 10_000,
+                    memo: "thanks".to_string(),
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn payments_map_to_the_send_argument() {
+        let payments = vec![
+            Payment {
+                address: "u1abc".to_string(),
+                amount: 10_000,
+                memo: "hello".to_string(),
+            },
+            Payment {
+                address: "t1abc".to_string(),
+                amount: 20_000,
+                memo: String::new(),
+            },
+        ];
+        assert_eq!(
+            payments_to_json(payments),
+            json::array![
+                object! { "address" => "u1abc", "amount" => 10_000, "memo" => "hello" },
+                object! { "address" => "t1abc", "amount" => 20_000, "memo" => JsonValue::Null },
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_results_map_to_replies() {
+        let synced = object! {
+            "result" => "success",
+            "latest_block" => 2_000_000,
+            "total_blocks_synced" => 150,
+        };
+        assert_eq!(
+            sync_reply_from_json(&synced),
+            SyncReply {
+                success: true,
+                latest_block: 2_000_000,
+                total_blocks_synced: 150,
+            }
+        );
+        assert!(!sync_reply_from_json(&object! { "result" => "failure" }).success);
+    }
+
+    #[test]
+    fn command_errors_map_to_status_codes() {
+        for (error, code) in [
+            (
+                CommandError::UnknownCommand("x".to_string()),
+                Code::Unimplemented,
+            ),
+            (
+                CommandError::InvalidArguments("x".to_string()),
+                Code::InvalidArgument,
+            ),
+            (
+                CommandError::Wallet("x".to_string()),
+                Code::FailedPrecondition,
+            ),
+            (CommandError::Server("x".to_string()), Code::Unavailable),
+        ] {
+            assert_eq!(status_from_error(error).code(), code);
+        }
+    }
+}
@@ -1,16 +1,27 @@
 #![forbid(unsafe_code)]
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use log::{error, info};
+use log::{error, info, warn};
 
 use clap::{self, Arg};
+use rustyline::history::DefaultHistory;
 use zingo_testutils::regtest;
-use zingoconfig::ChainType;
+use zingoconfig::{
+    ChainType, GrpcTransport, MempoolMonitorMode, ProxyConfig, RateLimits, TlsConfig, ZingoConfig,
+};
+use zingolib::commands::{self, results, CommandError, CommandResult, OutputFormat};
+use zingolib::lightclient::records::{AddressRecord, ServerInfo, TransactionRecord};
 use zingolib::wallet::WalletBase;
-use zingolib::{commands, lightclient::LightClient, load_clientconfig};
+use zingolib::{lightclient::LightClient, load_clientconfig_with_proxy};
 
+use repl::ReplHelper;
+
+pub mod daemon;
+pub mod grpc_service;
+pub mod repl;
 pub mod version;
 
 pub fn build_clap_app() -> clap::App<'static> {
@@ -44,7 +55,7 @@ pub fn build_clap_app() -> clap::App<'static> {
             .arg(Arg::new("birthday")
                 .long("birthday")
                 .value_name("birthday")
-                .help("Specify wallet birthday when restoring from seed. This is the earlist block height where the wallet has a transaction.")
+                .help("Specify wallet birthday when restoring from seed. This is the earliest block height where the wallet has a transaction, or the date (YYYY-MM-DD) of it, which is turned into a height.")
                 .takes_value(true))
             .arg(Arg::new("server")
                 .long("server")
@@ -53,6 +64,70 @@ pub fn build_clap_app() -> clap::App<'static> {
                 .takes_value(true)
                 .default_value(zingoconfig::DEFAULT_LIGHTWALLETD_SERVER)
                 .takes_value(true))
+            .arg(Arg::new("backup-server")
+                .long("backup-server")
+                .value_name("backup-server")
+                .help("Additional lightwalletd server to fail over to if the main one is unreachable or disagrees with the others about the chain tip. May be given more than once.")
+                .takes_value(true)
+                .multiple_occurrences(true))
+            .arg(Arg::new("proxy")
+                .long("proxy")
+                .value_name("proxy")
+                .help("SOCKS5 proxy (e.g. a local Tor daemon at 127.0.0.1:9050) to make all outgoing connections through. A connection that can't be made through it fails, unless --proxy-allow-direct is given.")
+                .takes_value(true))
+            .arg(Arg::new("proxy-allow-direct")
+                .long("proxy-allow-direct")
+                .help("Connect directly, revealing this machine's address to the server, if a connection can't be made through the proxy.")
+                .requires("proxy")
+                .takes_value(false))
+            .arg(Arg::new("tls-root")
+                .long("tls-root")
+                .value_name("tls-root")
+                .help("PEM file of an extra CA certificate to trust for the server's TLS certificate. May be given more than once.")
+                .takes_value(true)
+                .multiple_occurrences(true))
+            .arg(Arg::new("tls-pin")
+                .long("tls-pin")
+                .value_name("tls-pin")
+                .help("Hex SHA-256 hash of the server's certificate or SubjectPublicKeyInfo. If given, the server must match one of the pins, and is trusted on that alone, so a self-signed certificate can be pinned. May be given more than once.")
+                .takes_value(true)
+                .multiple_occurrences(true))
+            .arg(Arg::new("tls-client-cert")
+                .long("tls-client-cert")
+                .value_name("tls-client-cert")
+                .help("PEM certificate chain to present to the server (mTLS).")
+                .requires("tls-client-key")
+                .takes_value(true))
+            .arg(Arg::new("tls-client-key")
+                .long("tls-client-key")
+                .value_name("tls-client-key")
+                .help("PEM private key for --tls-client-cert.")
+                .requires("tls-client-cert")
+                .takes_value(true))
+            .arg(Arg::new("grpc-web")
+                .long("grpc-web")
+                .help("Talk to the server with gRPC-web over HTTP/1.1, for a server behind a gRPC-web proxy such as Envoy. Can't be used with the --tls options.")
+                .conflicts_with_all(&["tls-root", "tls-pin", "tls-client-cert", "tls-client-key"])
+                .takes_value(false))
+            .arg(Arg::new("block-requests-per-minute")
+                .long("block-requests-per-minute")
+                .value_name("block-requests-per-minute")
+                .help("At most this many compact block range requests per minute while syncing. Unlimited by default.")
+                .takes_value(true))
+            .arg(Arg::new("max-transaction-fetches")
+                .long("max-transaction-fetches")
+                .value_name("max-transaction-fetches")
+                .help("How many full transactions to fetch at once while syncing. Defaults to 16.")
+                .takes_value(true))
+            .arg(Arg::new("compact-mempool")
+                .long("compact-mempool")
+                .help("Watch the mempool by polling compact transactions and downloading only the ones that involve this wallet, instead of streaming every transaction. Uses less bandwidth, but incoming transparent funds only show up once mined.")
+                .takes_value(false))
+            .arg(Arg::new("block-file")
+                .long("block-file")
+                .value_name("block-file")
+                .help("Sync from a file of length-prefixed compact blocks (with tree states in a sibling .treestates file) instead of a lightwalletd server. Compact blocks only hold what is needed to find the wallet's notes, so memos, outgoing transaction metadata and transparent history are not rebuilt: memos aren't downloaded, and full transaction and transparent address lookups fail or come back empty.")
+                .takes_value(true))
             .arg(Arg::new("data-dir")
                 .long("data-dir")
                 .value_name("data-dir")
@@ -68,6 +143,34 @@ pub fn build_clap_app() -> clap::App<'static> {
                 .value_name("no-clean")
                 .help("Don't clean regtest state before running. Regtest mode only")
                 .takes_value(false))
+            .arg(Arg::new("daemon")
+                .long("daemon")
+                .help("Serve a zcashd-compatible subset of the wallet RPC over HTTP JSON-RPC instead of starting in interactive mode.")
+                .conflicts_with("COMMAND")
+                .takes_value(false))
+            .arg(Arg::new("rpc-bind")
+                .long("rpc-bind")
+                .value_name("rpc-bind")
+                .help("Address for --daemon to listen on. Defaults to 127.0.0.1 on zcashd's RPC port for the chain (8232 on mainnet, 18232 otherwise).")
+                .requires("daemon")
+                .takes_value(true))
+            .arg(Arg::new("rpc-token")
+                .long("rpc-token")
+                .value_name("rpc-token")
+                .help("Token clients of --daemon and --grpc-bind must present, as a bearer token or as the basic auth password. If not given, one is generated and written to a .cookie file in the data dir.")
+                .takes_value(true))
+            .arg(Arg::new("grpc-bind")
+                .long("grpc-bind")
+                .value_name("grpc-bind")
+                .help("Serve the Wallet gRPC service (zingocli/proto/wallet.proto) on this address instead of starting in interactive mode. Can be combined with --daemon.")
+                .conflicts_with("COMMAND")
+                .takes_value(true))
+            .arg(Arg::new("output")
+                .long("output")
+                .value_name("output")
+                .help(r#"How to print command results. "text" (default) is for reading. "json" prints one line per result, either {"status": "ok", "result": ...} or {"status": "error", "error": {"code": ..., "message": ...}}, A command that fails exits with status 1."#)
+                .possible_values(["text", "json"])
+                .takes_value(true))
             .arg(Arg::new("COMMAND")
                 .help("Command to execute. If a command is not specified, zingo-cli will start in interactive mode.")
                 .required(false)
@@ -76,6 +179,7 @@ pub fn build_clap_app() -> clap::App<'static> {
                 .help("Params to execute command with. Run the 'help' command to get usage help.")
                 .required(false)
                 .multiple(true)
+                .allow_hyphen_values(true)
                 .index(2))
 }
 
@@ -117,43 +221,61 @@ fn regtest_config_check(regtest_manager: &Option<regtest::RegtestManager>, chain
 /// TODO: start_interactive does not explicitly reference a wallet, do we need
 /// to expose new/more/higher-layer abstractions to facilitate wallet reuse from
 /// the CLI?
+///
+/// Results are printed in `output`.
+/// History is kept in `data_dir`, except for lines starting with a space.
 fn start_interactive(
     command_transmitter: Sender<(String, Vec<String>)>,
-    resp_receiver: Receiver<String>,
+    resp_receiver: Receiver<CommandResponse>,
+    output: OutputFormat,
+    data_dir: &Path,
 ) {
-    // `()` can be used when no completer is required
-    let mut rl = rustyline::DefaultEditor::new().expect("Default rustyline Editor not creatable!");
+    let editor_config = rustyline::Config::builder()
+        .max_history_size(repl::HISTORY_SIZE)
+        .expect("A valid history size")
+        .history_ignore_dups(true)
+        .expect("A valid history setting")
+        .history_ignore_space(true)
+        .completion_type(rustyline::CompletionType::List)
+        .build();
+    let mut rl = rustyline::Editor::<ReplHelper, DefaultHistory>::with_config(editor_config)
+        .expect("rustyline Editor not creatable!");
+    rl.set_helper(Some(ReplHelper::new()));
+    let history_path = repl::history_path(data_dir);
+    // There is no history before the first session
+    let _ = rl.load_history(&history_path);
 
     log::debug!("Ready!");
 
-    let send_command = |cmd: String, args: Vec<String>| -> String {
+    let send_command = |cmd: String, args: Vec<String>| -> CommandResult {
         command_transmitter.send((cmd.clone(), args)).unwrap();
-        match resp_receiver.recv() {
-            Ok(s) => s,
-            Err(e) => {
-                let e = format!("Error executing command {}: {}", cmd, e);
-                eprintln!("{}", e);
-                error!("{}", e);
-                "".to_string()
-            }
+        receive_result(&resp_receiver, &cmd)
+    };
+    let refresh_addresses = |rl: &mut rustyline::Editor<ReplHelper, DefaultHistory>| {
+        let addresses = send_command("addresses".to_string(), vec![])
+            .ok()
+            .and_then(|output| output.result_as::<Vec<AddressRecord>>().ok())
+            .unwrap_or_default();
+        let transactions = send_command("list".to_string(), vec![])
+            .ok()
+            .and_then(|output| output.result_as::<Vec<TransactionRecord>>().ok())
+            .unwrap_or_default();
+        if let Some(helper) = rl.helper_mut() {
+            helper.set_addresses(repl::known_addresses(&addresses, &transactions));
         }
     };
+    refresh_addresses(&mut rl);
 
-    let info = send_command("info".to_string(), vec![]);
-    let chain_name = json::parse(&info).unwrap()["chain_name"]
-        .as_str()
-        .unwrap()
-        .to_string();
+    let info = send_command("info".to_string(), vec![]).unwrap();
+    let chain_name = info.result_as::<ServerInfo>().unwrap().chain_name;
 
     loop {
         // Read the height first
-        let height = json::parse(&send_command(
-            "height".to_string(),
-            vec!["false".to_string()],
-        ))
-        .unwrap()["height"]
-            .as_i64()
-            .unwrap();
+        let height = send_command("height".to_string(), vec!["false".to_string()])
+            .unwrap()
+            .result_as::<results::Height>()
+            .unwrap()
+            .height;
 
         let readline = rl.readline(&format!(
             "({}) Block:{} (type 'help') >> ",
@@ -178,8 +300,16 @@ fn start_interactive(
 
                 let cmd = cmd_args.remove(0);
                 let args: Vec<String> = cmd_args;
+                let changes_addresses =
+                    repl::ADDRESS_CHANGING_COMMANDS.contains(&cmd.to_ascii_lowercase().as_str());
 
-                println!("{}", send_command(cmd, args));
+                println!("{}", commands::render(&send_command(cmd, args), output));
+                if let Err(e) = rl.save_history(&history_path) {
+                    warn!("Couldn't save the prompt history: {}", e);
+                }
+                if changes_addresses {
+                    refresh_addresses(&mut rl);
+                }
 
                 // Special check for Quit command.
                 if line == "quit" {
@@ -189,13 +319,15 @@ fn start_interactive(
             Err(rustyline::error::ReadlineError::Interrupted) => {
                 println!("CTRL-C");
                 info!("CTRL-C");
-                println!("{}", send_command("save".to_string(), vec![]));
+                let saved = send_command("save".to_string(), vec![]);
+                println!("{}", commands::render(&saved, output));
                 break;
             }
             Err(rustyline::error::ReadlineError::Eof) => {
                 println!("CTRL-D");
                 info!("CTRL-D");
-                println!("{}", send_command("save".to_string(), vec![]));
+                let saved = send_command("save".to_string(), vec![]);
+                println!("{}", commands::render(&saved, output));
                 break;
             }
             Err(err) => {
@@ -206,11 +338,25 @@ fn start_interactive(
     }
 }
 
+/// Reads the answer to `cmd` from the command loop
+fn receive_result(resp_receiver: &Receiver<CommandResponse>, cmd: &str) -> CommandResult {
+    match resp_receiver.recv() {
+        Ok(result) => result,
+        Err(e) => {
+            let e = format!("Error executing command {}: {}", cmd, e);
+            eprintln!("{}", e);
+            error!("{}", e);
+            Err(CommandError::Wallet(e).into())
+        }
+    }
+}
+
+/// Runs commands on their own thread, answering each with its result
 pub fn command_loop(
     lightclient: Arc<LightClient>,
-) -> (Sender<(String, Vec<String>)>, Receiver<String>) {
-    let (command_transmitter, command_receiver) = channel::<(String, Vec<String>)>();
-    let (resp_transmitter, resp_receiver) = channel::<String>();
+) -> (Sender<CommandRequest>, Receiver<CommandResponse>) {
+    let (command_transmitter, command_receiver) = channel::<CommandRequest>();
+    let (resp_transmitter, resp_receiver) = channel::<CommandResponse>();
 
     std::thread::spawn(move || {
         LightClient::start_mempool_monitor(lightclient.clone());
@@ -218,7 +364,7 @@ pub fn command_loop(
         while let Ok((cmd, args)) = command_receiver.recv() {
             let args: Vec<_> = args.iter().map(|s| s.as_ref()).collect();
 
-            let cmd_response = commands::do_user_command(&cmd, &args[..], lightclient.as_ref());
+            let cmd_response = commands::run_user_command(&cmd, &args[..], lightclient.as_ref());
             resp_transmitter.send(cmd_response).unwrap();
 
             if cmd == "quit" {
@@ -231,14 +377,57 @@ pub fn command_loop(
     (command_transmitter, resp_receiver)
 }
 
+/// The command loop's channels behind a lock, so that several servers can share one loop and
+/// still each get the answer to their own command
+pub struct CommandChannel {
+    channel: Mutex<(Sender<CommandRequest>, Receiver<CommandResponse>)>,
+}
+
+impl CommandChannel {
+    pub fn new(
+        command_transmitter: Sender<CommandRequest>,
+        resp_receiver: Receiver<CommandResponse>,
+    ) -> Self {
+        Self {
+            channel: Mutex::new((command_transmitter, resp_receiver)),
+        }
+    }
+
+    /// Runs a command, blocking until the command loop answers
+    pub fn run(&self, command: &str, args: Vec<String>) -> Result<CommandResponse, String> {
+        let channel = self.channel.lock().unwrap();
+        channel
+            .0
+            .send((command.to_string(), args))
+            .map_err(|e| format!("Command loop stopped: {}", e))?;
+        channel
+            .1
+            .recv()
+            .map_err(|e| format!("Command loop stopped: {}", e))
+    }
+}
+
 pub struct ConfigTemplate {
     params: Vec<String>,
     server: http::Uri,
+    backup_servers: Vec<http::Uri>,
+    proxy: Option<ProxyConfig>,
+    tls: TlsConfig,
+    grpc_transport: GrpcTransport,
+    rate_limits: RateLimits,
+    mempool_monitor_mode: MempoolMonitorMode,
     from: Option<String>,
     birthday: u64,
+    /// Set when the birthday was given as a date, so that `birthday` is only an estimate
+    birthday_date: Option<i64>,
     data_dir: PathBuf,
+    block_file: Option<PathBuf>,
     sync: bool,
     command: Option<String>,
+    output: OutputFormat,
+    rpc_bind: Option<std::net::SocketAddr>,
+    grpc_bind: Option<std::net::SocketAddr>,
+    rpc_token: Option<String>,
     regtest_manager: Option<regtest::RegtestManager>,
     #[allow(dead_code)] // This field is defined so that it can be used in Drop::drop
     child_process_handler: Option<regtest::ChildProcessHandler>,
@@ -260,6 +449,8 @@ enum TemplateFillError {
     ChildLaunchError(regtest::LaunchChildProcessError),
     InvalidChain(String),
     RegtestAndChainSpecified(String),
+    InvalidRateLimit(String),
+    InvalidBindAddress(String),
 }
 
 impl From<regtest::LaunchChildProcessError> for TemplateFillError {
@@ -301,11 +492,12 @@ impl ConfigTemplate {
         if from.is_some() && maybe_birthday.is_none() {
             eprintln!("ERROR!");
             eprintln!(
-                "Please specify the wallet birthday (eg. '--birthday 600000') to restore a wallet. (If you want to load the entire blockchain instead, you can use birthday 0. /this would require extensive time and computational resources)"
+                "Please specify the wallet birthday (eg. '--birthday 600000' or '--birthday 2022-06-01') to restore a wallet. (If you want to load the entire blockchain instead, you can use birthday 0. /this would require extensive time and computational resources)"
             );
             return Err(TemplateFillError::BirthdaylessSeed(
                 "This should be the block height where the wallet was created.\
-If you don't remember the block height, you can pass '--birthday 0'\
+If you don't remember the block height, you can pass the date the wallet was created \
+(eg. '--birthday 2022-06-01'), or '--birthday 0'\
 to scan from the start of the blockchain."
                     .to_string(),
             ));
@@ -315,14 +507,18 @@ to scan from the start of the blockchain."
                 "regtest mode incompatible with custom chain selection".to_string(),
             ));
         }
-        let birthday = match maybe_birthday.unwrap_or("0").parse::<u64>() {
-            Ok(b) => b,
-            Err(e) => {
-                return Err(TemplateFillError::InvalidBirthday(format!(
-                    "Couldn't parse birthday. This should be a block number. Error={}",
-                    e
-                )));
-            }
+        let maybe_birthday = maybe_birthday.unwrap_or("0");
+        let (birthday, birthday_date) = match maybe_birthday.parse::<u64>() {
+            Ok(b) => (b, None),
+            Err(e) => match zingolib::wallet::birthday::parse_date(maybe_birthday) {
+                Ok(date) => (0, Some(date)),
+                Err(_) => {
+                    return Err(TemplateFillError::InvalidBirthday(format!(
+                        "Couldn't parse birthday. This should be a block number or a date (YYYY-MM-DD). Error={}",
+                        e
+                    )));
+                }
+            },
         };
 
         let clean_regtest_data = !matches.is_present("no-clean");
@@ -334,6 +530,9 @@ to scan from the start of the blockchain."
             PathBuf::from("wallets")
         };
         log::info!("data_dir: {}", &data_dir.to_str().unwrap());
+        let block_file = matches
+            .get_one::<String>("block-file")
+            .map(|file| PathBuf::from(file.clone()));
         let mut maybe_server = matches.value_of("server").map(|s| s.to_string());
         let mut child_process_handler = None;
         // Regtest specific launch:
@@ -348,6 +547,65 @@ to scan from the start of the blockchain."
             None
         };
         let server = zingoconfig::construct_lightwalletd_uri(maybe_server);
+        let backup_servers = matches
+            .values_of("backup-server")
+            .into_iter()
+            .flatten()
+            .map(|s| zingoconfig::construct_lightwalletd_uri(Some(s.to_string())))
+            .collect::<Vec<_>>();
+        let proxy = matches
+            .get_one::<String>("proxy")
+            .map(|socks5_address| ProxyConfig {
+                socks5_address: socks5_address.clone(),
+                strict: !matches.is_present("proxy-allow-direct"),
+            });
+        let tls = TlsConfig {
+            extra_root_pems: matches
+                .values_of("tls-root")
+                .into_iter()
+                .flatten()
+                .map(PathBuf::from)
+                .collect(),
+            pinned_sha256: matches
+                .values_of("tls-pin")
+                .into_iter()
+                .flatten()
+                .map(|pin| pin.to_string())
+                .collect(),
+            client_cert: matches
+                .value_of("tls-client-cert")
+                .zip(matches.value_of("tls-client-key"))
+                .map(|(cert, key)| (PathBuf::from(cert), PathBuf::from(key))),
+        };
+        let grpc_transport = if matches.is_present("grpc-web") {
+            GrpcTransport::Web
+        } else {
+            GrpcTransport::Native
+        };
+        let mut rate_limits = RateLimits::default();
+        if let Some(per_minute) = matches.value_of("block-requests-per-minute") {
+            rate_limits.block_range_requests_per_minute =
+                Some(per_minute.parse::<u32>().map_err(|e| {
+                    TemplateFillError::InvalidRateLimit(format!(
+                        "Couldn't parse --block-requests-per-minute: {}",
+                        e
+                    ))
+                })?);
+        }
+        if let Some(max_fetches) = matches.value_of("max-transaction-fetches") {
+            rate_limits.max_concurrent_transaction_fetches =
+                max_fetches.parse::<usize>().map_err(|e| {
+                    TemplateFillError::InvalidRateLimit(format!(
+                        "Couldn't parse --max-transaction-fetches: {}",
+                        e
+                    ))
+                })?;
+        }
+        let mempool_monitor_mode = if matches.is_present("compact-mempool") {
+            MempoolMonitorMode::Compact
+        } else {
+            MempoolMonitorMode::FullStream
+        };
         let chaintype = if let Some(chain) = matches.get_one::<String>("chain") {
             match chain.as_str() {
                 "mainnet" => ChainType::Mainnet,
@@ -368,15 +626,66 @@ to scan from the start of the blockchain."
                 server )));
         }
 
+        let rpc_bind = if matches.is_present("daemon") {
+            Some(match matches.value_of("rpc-bind") {
+                Some(bind) => bind.parse().map_err(|e| {
+                    TemplateFillError::InvalidBindAddress(format!(
+                        "Couldn't parse --rpc-bind as ip:port: {}",
+                        e
+                    ))
+                })?,
+                None => std::net::SocketAddr::from((
+                    [127, 0, 0, 1],
+                    match chaintype {
+                        ChainType::Mainnet => daemon::MAINNET_RPC_PORT,
+                        _ => daemon::TESTNET_RPC_PORT,
+                    },
+                )),
+            })
+        } else {
+            None
+        };
+        let grpc_bind = matches
+            .value_of("grpc-bind")
+            .map(|bind| {
+                bind.parse().map_err(|e| {
+                    TemplateFillError::InvalidBindAddress(format!(
+                        "Couldn't parse --grpc-bind as ip:port: {}",
+                        e
+                    ))
+                })
+            })
+            .transpose()?;
+        let rpc_token = matches.value_of("rpc-token").map(|t| t.to_string());
+
+        let output = matches
+            .value_of("output")
+            .map(|output| output.parse().unwrap())
+            .unwrap_or_default();
+
         let sync = !matches.is_present("nosync");
         Ok(Self {
             params,
             server,
+            backup_servers,
+            proxy,
+            tls,
+            grpc_transport,
+            rate_limits,
+            mempool_monitor_mode,
             from,
-            birthday,
+            birthday: birthday_date
+                .map(|date| zingolib::wallet::birthday::estimate_height_at(chaintype, date))
+                .unwrap_or(birthday),
+            birthday_date,
             data_dir,
+            block_file,
             sync,
             command,
+            output,
+            rpc_bind,
+            grpc_bind,
+            rpc_token,
             regtest_manager,
             child_process_handler,
             chaintype,
@@ -387,8 +696,30 @@ to scan from the start of the blockchain."
 /// A (command, args) request
 pub type CommandRequest = (String, Vec<String>);
 
-/// Command responses are strings
-pub type CommandResponse = String;
+/// Command responses are the commands' results, rendered by whoever asked
+pub type CommandResponse = CommandResult;
+
+/// The birthday to restore with. A birthday given as a date was estimated from bundled
+/// checkpoints; when there is a server, look the date up in its block times instead.
+fn birthday_height(filled_template: &ConfigTemplate, config: &ZingoConfig) -> u64 {
+    let date = match filled_template.birthday_date {
+        Some(date) if config.block_source_file.is_none() => date,
+        _ => return filled_template.birthday,
+    };
+    match zingolib::get_birthday_height_at(config, date) {
+        Ok(height) => {
+            info!("Birthday date is at height {}", height);
+            height
+        }
+        Err(e) => {
+            error!(
+                "Couldn't find the birthday date on the server, using the estimate {}: {}",
+                filled_template.birthday, e
+            );
+            filled_template.birthday
+        }
+    }
+}
 
 /// Used by the zingocli crate, and the zingo-mobile application:
 /// <https://github.com/zingolabs/zingolib/tree/dev/cli>
@@ -396,21 +727,48 @@ pub type CommandResponse = String;
 pub fn startup(
     filled_template: &ConfigTemplate,
 ) -> std::io::Result<(Sender<CommandRequest>, Receiver<CommandResponse>)> {
+    Ok(command_loop(start_lightclient(filled_template)?))
+}
+
+/// Opens or creates the wallet and, unless told not to, syncs it
+pub fn start_lightclient(filled_template: &ConfigTemplate) -> std::io::Result<Arc<LightClient>> {
     // Try to get the configuration
-    let config = load_clientconfig(
-        filled_template.server.clone(),
-        Some(filled_template.data_dir.clone()),
-        filled_template.chaintype,
-        true,
-    )
-    .unwrap();
+    let mut config = match &filled_template.block_file {
+        // A local block file needs no server, so don't try to resolve one
+        Some(block_file) => {
+            let mut config = ZingoConfig::create_unconnected(
+                filled_template.chaintype,
+                Some(filled_template.data_dir.clone()),
+            );
+            config.block_source_file = Some(block_file.clone());
+            config
+        }
+        None => {
+            let mut config = load_clientconfig_with_proxy(
+                filled_template.server.clone(),
+                Some(filled_template.data_dir.clone()),
+                filled_template.chaintype,
+                true,
+                filled_template.proxy.clone(),
+            )
+            .unwrap();
+            config
+                .server_uris
+                .extend(filled_template.backup_servers.iter().cloned());
+            config.tls = filled_template.tls.clone();
+            config.grpc_transport = filled_template.grpc_transport;
+            config
+        }
+    };
+    config.rate_limits = filled_template.rate_limits;
+    config.mempool_monitor_mode = filled_template.mempool_monitor_mode;
     regtest_config_check(&filled_template.regtest_manager, &config.chain);
 
     let lightclient = match filled_template.from.clone() {
         Some(phrase) => Arc::new(LightClient::create_from_wallet_base(
             WalletBase::from_string(phrase),
             &config,
-            filled_template.birthday,
+            birthday_height(filled_template, &config),
             false,
         )?),
         None => {
@@ -420,8 +778,10 @@ pub fn startup(
                 println!("Creating a new wallet");
                 // Call the lightwalletd server to get the current block-height
                 // Do a getinfo first, before opening the wallet
-                let server_uri = config.get_lightwalletd_uri();
-                let block_height = zingolib::get_latest_block_height(server_uri);
+                let block_height = match &config.block_source_file {
+                    Some(block_file) => zingolib::get_latest_block_height_from_file(block_file),
+                    None => zingolib::get_latest_block_height(&config),
+                };
                 // Create a wallet with height - 100, to protect against reorgs
                 Arc::new(LightClient::new(
                     &config,
@@ -445,19 +805,14 @@ pub fn startup(
 
     // At startup, run a sync.
     if filled_template.sync {
-        let update = commands::do_user_command("sync", &[], lightclient.as_ref());
-        println!("{}", update);
+        let update = commands::run_user_command("sync", &[], lightclient.as_ref());
+        println!("{}", commands::render(&update, filled_template.output));
     }
 
-    // Start the command loop
-    let (command_transmitter, resp_receiver) = command_loop(lightclient);
-
-    Ok((command_transmitter, resp_receiver))
+    Ok(lightclient)
 }
-fn start_cli_service(
-    cli_config: &ConfigTemplate,
-) -> (Sender<(String, Vec<String>)>, Receiver<String>) {
-    match startup(cli_config) {
+fn start_cli_service(cli_config: &ConfigTemplate) -> Arc<LightClient> {
+    match start_lightclient(cli_config) {
         Ok(c) => c,
         Err(e) => {
             let emsg = format!("Error during startup:\n{}\nIf you repeatedly run into this issue, you might have to restore your wallet from your seed phrase.", e);
@@ -472,10 +827,131 @@ fn start_cli_service(
         }
     }
 }
-fn dispatch_command_or_start_interactive(cli_config: &ConfigTemplate) {
-    let (command_transmitter, resp_receiver) = start_cli_service(cli_config);
-    if cli_config.command.is_none() {
-        start_interactive(command_transmitter, resp_receiver);
+/// How often the wallet syncs while being served: one target block interval
+const SERVE_SYNC_INTERVAL: Duration = Duration::from_secs(75);
+
+/// Serves the wallet over JSON-RPC and/or gRPC until interrupted, syncing it and paying the
+/// scheduled payments that are due every block interval, and saves it on the way out.
+fn serve(
+    cli_config: &ConfigTemplate,
+    lightclient: Arc<LightClient>,
+    commands: Arc<CommandChannel>,
+) -> std::io::Result<()> {
+    let (token, cookie_path) = match &cli_config.rpc_token {
+        Some(token) => (token.clone(), None),
+        None => {
+            let (token, cookie_path) = daemon::generate_cookie(&cli_config.data_dir)?;
+            (token, Some(cookie_path))
+        }
+    };
+
+    let served = tokio::runtime::Runtime::new()?.block_on(async {
+        let (stop, stopped) = tokio::sync::watch::channel(false);
+        tokio::spawn(async move {
+            tokio::signal::ctrl_c().await.ok();
+            info!("CTRL-C");
+            stop.send(true).ok();
+        });
+        let shutdown = || {
+            let mut stopped = stopped.clone();
+            async move {
+                stopped.changed().await.ok();
+            }
+        };
+
+        let syncing = commands.clone();
+        let syncing_lightclient = lightclient.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SERVE_SYNC_INTERVAL);
+            // The first tick is immediate, and startup has just synced
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let commands = syncing.clone();
+                let lightclient = syncing_lightclient.clone();
+                match tokio::task::spawn_blocking(move || {
+                    // Syncing takes a while, so it runs beside the command loop rather than in
+                    // it, leaving the loop and its channel free to answer the servers
+                    let synced = commands::run_user_command("sync", &[], lightclient.as_ref());
+                    // Scheduled payments fall due against the height just synced to
+                    (synced, commands.run("schedule", vec!["run".to_string()]))
+                })
+                .await
+                {
+                    Ok((synced, scheduled)) => {
+                        if let Err(e) = synced {
+                            error!("Sync failed: {}", e);
+                        }
+                        match scheduled {
+                            Ok(Ok(runs)) => {
+                                for run in runs.to_json().members() {
+                                    info!("Scheduled payment run: {}", run)
+                                }
+                            }
+                            Ok(Err(e)) => error!("Scheduled payments failed: {}", e),
+                            Err(e) => error!("Scheduled payments failed: {}", e),
+                        }
+                    }
+                    Err(e) => error!("Sync failed: {}", e),
+                }
+            }
+        });
+
+        let json_rpc = async {
+            match cli_config.rpc_bind {
+                Some(bind) => {
+                    daemon::serve(bind, token.clone(), commands.clone(), shutdown()).await
+                }
+                None => Ok(()),
+            }
+        };
+        let grpc = async {
+            match cli_config.grpc_bind {
+                Some(bind) => {
+                    grpc_service::serve(
+                        bind,
+                        token.clone(),
+                        lightclient.clone(),
+                        commands.clone(),
+                        shutdown(),
+                    )
+                    .await
+                }
+                None => Ok(()),
+            }
+        };
+        tokio::try_join!(json_rpc, grpc).map(|_| ())
+    });
+
+    match commands.run("save", vec![]) {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => error!("Couldn't save the wallet: {}", e),
+        Err(e) => error!("Couldn't save the wallet: {}", e),
+    }
+    if let Some(cookie_path) = cookie_path {
+        let _ = std::fs::remove_file(cookie_path);
+    }
+    served
+}
+
+/// Returns false if a command given on the command line failed
+fn dispatch_command_or_start_interactive(cli_config: &ConfigTemplate) -> bool {
+    let lightclient = start_cli_service(cli_config);
+    let (command_transmitter, resp_receiver) = command_loop(lightclient.clone());
+    if cli_config.rpc_bind.is_some() || cli_config.grpc_bind.is_some() {
+        let commands = Arc::new(CommandChannel::new(command_transmitter, resp_receiver));
+        if let Err(e) = serve(cli_config, lightclient, commands) {
+            let e = format!("Error serving the wallet: {}", e);
+            eprintln!("{}", e);
+            error!("{}", e);
+        }
+    } else if cli_config.command.is_none() {
+        start_interactive(
+            command_transmitter,
+            resp_receiver,
+            cli_config.output,
+            &cli_config.data_dir,
+        );
     } else {
         command_transmitter
             .send((
@@ -488,25 +964,18 @@ fn dispatch_command_or_start_interactive(cli_config: &ConfigTemplate) {
             ))
             .unwrap();
 
-        match resp_receiver.recv() {
-            Ok(s) => println!("{}", s),
-            Err(e) => {
-                let e = format!(
-                    "Error executing command {}: {}",
-                    cli_config.command.clone().unwrap(),
-                    e
-                );
-                eprintln!("{}", e);
-                error!("{}", e);
-            }
-        }
+        let result = receive_result(&resp_receiver, cli_config.command.as_ref().unwrap());
+        println!("{}", commands::render(&result, cli_config.output));
 
         // Save before exit
         command_transmitter
             .send(("save".to_string(), vec![]))
             .unwrap();
         resp_receiver.recv().unwrap();
+
+        return result.is_ok();
     }
+    true
 }
 pub fn run_cli() {
     // Initialize logging
@@ -514,5 +983,10 @@ pub fn run_cli() {
         eprintln!("Could not initialize logging: {e}")
     };
     let cli_config = ConfigTemplate::fill(build_clap_app()).unwrap();
-    dispatch_command_or_start_interactive(&cli_config);
+    let succeeded = dispatch_command_or_start_interactive(&cli_config);
+    // Stop any regtest children before exiting
+    drop(cli_config);
+    if !succeeded {
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,265 @@
+//! What the interactive prompt knows beyond reading lines: completion of command names and of
+//! addresses, hints from each command's short help, and history kept in the data dir.
+//!
+//! The addresses completed are the wallet's own, with their receivers, and those it has sent
+//! to. The prompt reads them through the command loop, at startup and after the commands that
+//! can add to them.
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use zingolib::commands;
+use zingolib::lightclient::records::{AddressRecord, TransactionRecord};
+
+/// The history file, in the data dir
+pub const HISTORY_FILE_NAME: &str = "zingo-cli.history";
+
+/// How many lines of history are kept
+pub const HISTORY_SIZE: usize = 1_000;
+
+/// After these, the wallet may have addresses the prompt doesn't know yet
+pub const ADDRESS_CHANGING_COMMANDS: [&str; 6] =
+    ["new", "send", "sendmany", "shield", "sync", "rescan"];
+
+pub fn history_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(HISTORY_FILE_NAME)
+}
+
+/// The addresses in the answers to the `addresses` and `list` commands
+pub fn known_addresses(
+    addresses: &[AddressRecord],
+    transactions: &[TransactionRecord],
+) -> Vec<String> {
+    let mut known = vec![];
+    for address in addresses {
+        known.push(address.address.clone());
+        known.extend(address.receivers.transparent.clone());
+        known.extend(address.receivers.sapling.clone());
+    }
+    for transaction in transactions {
+        for outgoing in transaction.outgoing_metadata.iter().flatten() {
+            known.push(outgoing.address.clone());
+        }
+    }
+    known.sort();
+    known.dedup();
+    known
+}
+
+pub struct ReplHelper {
+    /// Command names and their short help, by name
+    commands: Vec<(&'static str, &'static str)>,
+    addresses: Vec<String>,
+}
+
+impl ReplHelper {
+    pub fn new() -> Self {
+        let mut commands = commands::get_commands()
+            .into_iter()
+            .map(|(name, command)| (name, command.short_help()))
+            .collect::<Vec<_>>();
+        commands.sort();
+        Self {
+            commands,
+            addresses: vec![],
+        }
+    }
+
+    pub fn set_addresses(&mut self, addresses: Vec<String>) {
+        self.addresses = addresses;
+    }
+}
+
+impl Default for ReplHelper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shown dimmed after the cursor. Only the rest of a command name is taken by the right
+/// arrow key, never the help text.
+pub struct CommandHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for CommandHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let start = before
+            .rfind(char::is_whitespace)
+            .map_or(0, |space| space + 1);
+        let word = &before[start..];
+        let candidates = if before[..start].trim().is_empty() {
+            self.commands
+                .iter()
+                .map(|(name, _)| *name)
+                .filter(|name| name.starts_with(word))
+                .map(String::from)
+                .collect::<Vec<_>>()
+        } else {
+            self.addresses
+                .iter()
+                .filter(|address| address.starts_with(word))
+                .cloned()
+                .collect()
+        };
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .map(|candidate| Pair {
+                    display: candidate.clone(),
+                    replacement: candidate,
+                })
+                .collect(),
+        ))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = CommandHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<CommandHint> {
+        if pos < line.len() {
+            return None;
+        }
+        let typed = line.trim_start();
+        if typed.is_empty() {
+            return None;
+        }
+        match typed.split_once(char::is_whitespace) {
+            // A command being typed: the rest of its name, and what it does
+            None => self
+                .commands
+                .iter()
+                .find(|(name, _)| name.starts_with(typed))
+                .map(|(name, short_help)| CommandHint {
+                    display: format!("{}  {}", &name[typed.len()..], short_help),
+                    completion: Some(name[typed.len()..].to_string()),
+                }),
+            // A command typed in full, with no arguments yet: what it does
+            Some((command, arguments)) if arguments.trim().is_empty() => self
+                .commands
+                .iter()
+                .find(|(name, _)| *name == command)
+                .map(|(_, short_help)| CommandHint {
+                    display: format!(" {}", short_help),
+                    completion: None,
+                }),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        // Dimmed
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+    use zingolib::lightclient::records::{OutgoingRecord, ReceiverRecord};
+
+    #[test]
+    fn commands_and_addresses_complete() {
+        let mut helper = ReplHelper::new();
+        helper.set_addresses(known_addresses(
+            &[AddressRecord {
+                address: "u1abc".to_string(),
+                receivers: ReceiverRecord {
+                    transparent: Some("t1xyz".to_string()),
+                    sapling: None,
+                    orchard_exists: true,
+                },
+            }],
+            &[TransactionRecord {
+                block_height: 1,
+                unconfirmed: false,
+                datetime: 0,
+                txid: "abc".to_string(),
+                amount: -1,
+                zec_price: None,
+                position: None,
+                address: None,
+                memo: None,
+                outgoing_metadata: Some(vec![OutgoingRecord {
+                    address: "t1abc".to_string(),
+                    value: 1,
+                    memo: None,
+                }]),
+            }],
+        ));
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+        let replacements = |line: &str| {
+            let (start, pairs) = helper.complete(line, line.len(), &ctx).unwrap();
+            (
+                start,
+                pairs.into_iter().map(|p| p.replacement).collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(
+            replacements("sen"),
+            (
+                0,
+                vec![
+                    "send".to_string(),
+                    "sendmany".to_string(),
+                    "sendprogress".to_string(),
+                    "sends_to_address".to_string()
+                ]
+            )
+        );
+        assert_eq!(
+            replacements("send t1"),
+            (5, vec!["t1abc".to_string(), "t1xyz".to_string()])
+        );
+        assert_eq!(replacements("send u"), (5, vec!["u1abc".to_string()]));
+    }
+
+    #[test]
+    fn hints_come_from_short_help() {
+        let helper = ReplHelper::new();
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+
+        let hint = helper.hint("balan", 5, &ctx).unwrap();
+        assert_eq!(hint.completion(), Some("ce"));
+        assert!(hint.display().starts_with("ce  "));
+
+        let hint = helper.hint("balance ", 8, &ctx).unwrap();
+        assert_eq!(hint.completion(), None);
+        assert!(helper.hint("balance 1", 9, &ctx).is_none());
+    }
+}
@@ -240,11 +240,17 @@ async fn simple_sync() {
             verified_sapling_balance: Some(0),
             spendable_sapling_balance: Some(0),
             unverified_sapling_balance: Some(0),
+            pending_incoming_sapling_balance: Some(0),
+            pending_outgoing_sapling_balance: Some(0),
             orchard_balance: Some(100000000),
             verified_orchard_balance: Some(100000000),
             spendable_orchard_balance: Some(100000000),
             unverified_orchard_balance: Some(0),
-            transparent_balance: Some(0)
+            pending_incoming_orchard_balance: Some(0),
+            pending_outgoing_orchard_balance: Some(0),
+            transparent_balance: Some(0),
+            pending_incoming_transparent_balance: Some(0),
+            pending_outgoing_transparent_balance: Some(0)
         }
     );
 }
@@ -277,11 +283,17 @@ async fn reorg_away_receipt() {
             verified_sapling_balance: Some(0),
             spendable_sapling_balance: Some(0),
             unverified_sapling_balance: Some(0),
+            pending_incoming_sapling_balance: Some(0),
+            pending_outgoing_sapling_balance: Some(0),
             orchard_balance: Some(100000000),
             verified_orchard_balance: Some(100000000),
             spendable_orchard_balance: Some(100000000),
             unverified_orchard_balance: Some(0),
-            transparent_balance: Some(0)
+            pending_incoming_orchard_balance: Some(0),
+            pending_outgoing_orchard_balance: Some(0),
+            transparent_balance: Some(0),
+            pending_incoming_transparent_balance: Some(0),
+            pending_outgoing_transparent_balance: Some(0)
         }
     );
     prepare_darksidewalletd(server_id.clone(), false)
@@ -295,11 +307,17 @@ async fn reorg_away_receipt() {
             verified_sapling_balance: Some(0),
             spendable_sapling_balance: Some(0),
             unverified_sapling_balance: Some(0),
+            pending_incoming_sapling_balance: Some(0),
+            pending_outgoing_sapling_balance: Some(0),
             orchard_balance: Some(0),
             verified_orchard_balance: Some(0),
             spendable_orchard_balance: Some(0),
             unverified_orchard_balance: Some(0),
-            transparent_balance: Some(0)
+            pending_incoming_orchard_balance: Some(0),
+            pending_outgoing_orchard_balance: Some(0),
+            transparent_balance: Some(0),
+            pending_incoming_transparent_balance: Some(0),
+            pending_outgoing_transparent_balance: Some(0)
         }
     );
 }
@@ -338,11 +356,17 @@ async fn sent_transaction_reorged_into_mempool() {
             verified_sapling_balance: Some(0),
             spendable_sapling_balance: Some(0),
             unverified_sapling_balance: Some(0),
+            pending_incoming_sapling_balance: Some(0),
+            pending_outgoing_sapling_balance: Some(0),
             orchard_balance: Some(100000000),
             verified_orchard_balance: Some(100000000),
             spendable_orchard_balance: Some(100000000),
             unverified_orchard_balance: Some(0),
-            transparent_balance: Some(0)
+            pending_incoming_orchard_balance: Some(0),
+            pending_outgoing_orchard_balance: Some(0),
+            transparent_balance: Some(0),
+            pending_incoming_transparent_balance: Some(0),
+            pending_outgoing_transparent_balance: Some(0)
         }
     );
     let txid = light_client
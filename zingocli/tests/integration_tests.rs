@@ -62,11 +62,17 @@ async fn dont_write_unconfirmed() {
             verified_sapling_balance: Some(0),
             spendable_sapling_balance: Some(0),
             unverified_sapling_balance: Some(0),
+            pending_incoming_sapling_balance: Some(0),
+            pending_outgoing_sapling_balance: Some(0),
             orchard_balance: Some(100000),
             verified_orchard_balance: Some(100000),
             spendable_orchard_balance: Some(100000),
             unverified_orchard_balance: Some(0),
-            transparent_balance: Some(0)
+            pending_incoming_orchard_balance: Some(0),
+            pending_outgoing_orchard_balance: Some(0),
+            transparent_balance: Some(0),
+            pending_incoming_transparent_balance: Some(0),
+            pending_outgoing_transparent_balance: Some(0)
         }
     );
     recipient
@@ -982,11 +988,17 @@ async fn diversified_addresses_receive_funds_in_best_pool() {
             verified_sapling_balance: Some(5000),
             spendable_sapling_balance: Some(5000),
             unverified_sapling_balance: Some(0),
+            pending_incoming_sapling_balance: Some(0),
+            pending_outgoing_sapling_balance: Some(0),
             orchard_balance: Some(15000),
             verified_orchard_balance: Some(15000),
             spendable_orchard_balance: Some(15000),
             unverified_orchard_balance: Some(0),
-            transparent_balance: Some(0)
+            pending_incoming_orchard_balance: Some(0),
+            pending_outgoing_orchard_balance: Some(0),
+            transparent_balance: Some(0),
+            pending_incoming_transparent_balance: Some(0),
+            pending_outgoing_transparent_balance: Some(0)
         }
     );
     // Unneeded, but more explicit than having _cph be an
@@ -1613,6 +1625,9 @@ async fn mempool_and_balance() {
     assert_eq!(bal.orchard_balance.unwrap(), new_bal);
     assert_eq!(bal.verified_orchard_balance.unwrap(), 0);
     assert_eq!(bal.unverified_orchard_balance.unwrap(), new_bal);
+    // The note being spent is pending outgoing, and the change coming back is pending incoming
+    assert_eq!(bal.pending_outgoing_orchard_balance.unwrap(), value);
+    assert_eq!(bal.pending_incoming_orchard_balance.unwrap(), new_bal);
 
     // 5. Mine the pending block, making the funds verified and spendable.
     zingo_testutils::increase_height_and_sync_client(&regtest_manager, &recipient, 10)
@@ -1624,6 +1639,57 @@ async fn mempool_and_balance() {
     assert_eq!(bal.orchard_balance.unwrap(), new_bal);
     assert_eq!(bal.verified_orchard_balance.unwrap(), new_bal);
     assert_eq!(bal.unverified_orchard_balance.unwrap(), 0);
+    assert_eq!(bal.pending_outgoing_orchard_balance.unwrap(), 0);
+    assert_eq!(bal.pending_incoming_orchard_balance.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn compact_mempool_monitor_sees_pending_incoming() {
+    let (regtest_manager, _cph, mut client_builder) = scenarios::custom_clients().await;
+    let faucet = client_builder.build_new_faucet(0, false).await;
+    let mut recipient_config = client_builder.make_unique_data_dir_and_load_config();
+    recipient_config.mempool_monitor_mode = zingoconfig::MempoolMonitorMode::Compact;
+    let recipient = std::sync::Arc::new(
+        LightClient::create_from_wallet_base_async(
+            zingolib::wallet::WalletBase::MnemonicPhrase(HOSPITAL_MUSEUM_SEED.to_string()),
+            &recipient_config,
+            0,
+            false,
+        )
+        .await
+        .unwrap(),
+    );
+    zingo_testutils::increase_height_and_sync_client(&regtest_manager, &faucet, 1)
+        .await
+        .unwrap();
+    recipient.do_sync(false).await.unwrap();
+    LightClient::start_mempool_monitor(recipient.clone());
+
+    let sent_value = 10_000;
+    faucet
+        .do_send(vec![(
+            &get_base_address!(recipient, "unified"),
+            sent_value,
+            None,
+        )])
+        .await
+        .unwrap();
+
+    // The monitor only polls every so often, and only fetches the transaction once it has
+    // found it paying to us among the compact ones
+    let mut pending_incoming = 0;
+    for _ in 0..60 {
+        pending_incoming = recipient
+            .do_balance()
+            .await
+            .pending_incoming_orchard_balance
+            .unwrap();
+        if pending_incoming > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+    assert_eq!(pending_incoming, sent_value);
 }
 
 #[tokio::test]
@@ -2025,12 +2091,10 @@ async fn mempool_clearing_and_full_batch_syncs_correct_trees() {
         .witness_tree_orchard
         .max_leaf_position(0)
         .unwrap();
-    let server_trees = zingolib::grpc_connector::GrpcConnector::get_trees(
-        recipient.get_server_uri(),
-        recipient.wallet.last_synced_height().await,
-    )
-    .await
-    .unwrap();
+    let server_trees = zingolib::grpc_connector::GrpcConnector::new(recipient.get_server_uri())
+        .get_trees(recipient.wallet.last_synced_height().await)
+        .await
+        .unwrap();
     let server_orchard_front = zcash_primitives::merkle_tree::read_commitment_tree::<
         MerkleHashOrchard,
         &[u8],
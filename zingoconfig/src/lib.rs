@@ -23,11 +23,15 @@ use zcash_primitives::{
     constants,
 };
 
+pub mod storage;
+use storage::{FileStorage, WalletStorage};
+
 pub const DEFAULT_LIGHTWALLETD_SERVER: &str = "https://mainnet.lightwalletd.com:9067";
 pub const MAX_REORG: usize = 100;
 pub const DEFAULT_WALLET_NAME: &str = "zingo-wallet.dat";
 pub const DEFAULT_LOGFILE_NAME: &str = "zingo-wallet.debug.log";
 pub const REORG_BUFFER_OFFSET: u32 = 0;
+pub const MAX_CONCURRENT_TRANSACTION_FETCHES_DEFAULT: usize = 16;
 
 #[cfg(any(target_os = "ios", target_os = "android"))]
 pub const GAP_RULE_UNUSED_ADDRESSES: usize = 0;
@@ -55,19 +59,99 @@ pub fn construct_lightwalletd_uri(server: Option<String>) -> http::Uri {
     .unwrap()
 }
 
+/// A SOCKS5 proxy (for example a local Tor daemon) that all outgoing connections go through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// The proxy's `host:port`, e.g. `127.0.0.1:9050`.
+    pub socks5_address: String,
+    /// In strict mode a connection that can't be made through the proxy fails, rather than
+    /// falling back to connecting directly.
+    pub strict: bool,
+}
+
+/// Extra TLS settings for talking to a lightwalletd that doesn't use a publicly trusted certificate.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM files of CA certificates to trust, on top of the webpki roots.
+    pub extra_root_pems: Vec<PathBuf>,
+    /// Hex SHA-256 hashes of the server's certificate or of its SubjectPublicKeyInfo.
+    /// If any are given, the server's certificate must match one of them, and need not chain to
+    /// a trusted root.
+    pub pinned_sha256: Vec<String>,
+    /// A PEM certificate chain and a PEM private key to present to the server (mTLS).
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+/// Limits on how hard sync leans on the network, for users on metered connections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+    /// At most this many block-range requests per minute. `None` means as fast as the server answers.
+    pub block_range_requests_per_minute: Option<u32>,
+    /// How many full transactions may be in flight at once.
+    pub max_concurrent_transaction_fetches: usize,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            block_range_requests_per_minute: None,
+            max_concurrent_transaction_fetches: MAX_CONCURRENT_TRANSACTION_FETCHES_DEFAULT,
+        }
+    }
+}
+
+/// How gRPC calls reach lightwalletd.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GrpcTransport {
+    /// gRPC over HTTP/2
+    #[default]
+    Native,
+    /// gRPC-web over HTTP/1.1, for a server behind a gRPC-web proxy such as Envoy. Browsers can
+    /// only make gRPC-web calls.
+    Web,
+}
+
+/// How the mempool monitor finds out about transactions that haven't been mined yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MempoolMonitorMode {
+    /// Stream every raw transaction in the mempool (GetMempoolStream).
+    #[default]
+    FullStream,
+    /// Poll for compact transactions (GetMempoolTx), skipping ones already seen, and download
+    /// only those that pay to or spend from the wallet. Far less bandwidth, but compact
+    /// transactions carry no transparent data, so transparent receipts wait for a block.
+    Compact,
+}
+
 /// Configuration data that is necessary? and sufficient? for the creation of a LightClient.
 #[derive(Clone, Debug)]
 pub struct ZingoConfig {
     pub lightwalletd_uri: Arc<RwLock<http::Uri>>,
+    /// Every lightwalletd server we may use, in order of preference. `lightwalletd_uri` is the one currently in use.
+    pub server_uris: Vec<http::Uri>,
     pub chain: ChainType,
     pub reorg_buffer_offset: u32,
     pub monitor_mempool: bool,
+    pub mempool_monitor_mode: MempoolMonitorMode,
     /// The directory where the wallet and logfiles will be created. By default, this will be in ~/.zcash on Linux and %APPDATA%\Zcash on Windows.
     pub wallet_dir: Option<PathBuf>,
     /// The filename of the wallet. This will be created in the `wallet_dir`.
     pub wallet_name: PathBuf,
     /// The filename of the logfile. This will be created in the `wallet_dir`.
     pub logfile_name: PathBuf,
+    /// If set, sync reads compact blocks (and tree states) from this file instead of lightwalletd.
+    pub block_source_file: Option<PathBuf>,
+    /// If set, gRPC and HTTP connections are made through this SOCKS5 proxy.
+    pub proxy: Option<ProxyConfig>,
+    /// Extra trust roots, pins and client certificate for TLS connections to lightwalletd.
+    pub tls: TlsConfig,
+    /// How fast sync may request blocks and transactions from lightwalletd.
+    pub rate_limits: RateLimits,
+    /// How gRPC calls reach lightwalletd.
+    pub grpc_transport: GrpcTransport,
+    /// Where the wallet file and sync checkpoint are kept. `None` keeps them as files in the
+    /// wallet dir.
+    pub storage: Option<Arc<dyn WalletStorage>>,
 }
 
 impl ZingoConfig {
@@ -75,12 +159,20 @@ impl ZingoConfig {
     pub fn create_unconnected(chain: ChainType, dir: Option<PathBuf>) -> ZingoConfig {
         ZingoConfig {
             lightwalletd_uri: Arc::new(RwLock::new(http::Uri::default())),
+            server_uris: vec![],
             chain,
             monitor_mempool: false,
+            mempool_monitor_mode: MempoolMonitorMode::default(),
             reorg_buffer_offset: REORG_BUFFER_OFFSET,
             wallet_dir: dir,
             wallet_name: DEFAULT_WALLET_NAME.into(),
             logfile_name: DEFAULT_LOGFILE_NAME.into(),
+            block_source_file: None,
+            proxy: None,
+            tls: TlsConfig::default(),
+            rate_limits: RateLimits::default(),
+            grpc_transport: GrpcTransport::default(),
+            storage: None,
         }
     }
 
@@ -226,6 +318,15 @@ impl ZingoConfig {
             .expect("Couldn't read configured server URI!")
             .clone()
     }
+    /// The configured servers, falling back to the current one if no list was given.
+    pub fn get_server_uris(&self) -> Vec<http::Uri> {
+        if self.server_uris.is_empty() {
+            vec![self.get_lightwalletd_uri()]
+        } else {
+            self.server_uris.clone()
+        }
+    }
+    /// Where the wallet file is, when the wallet is kept in files
     pub fn get_wallet_path(&self) -> Box<Path> {
         let mut wallet_location = self.get_zingo_wallet_dir().into_path_buf();
         wallet_location.push(&self.wallet_name);
@@ -233,32 +334,82 @@ impl ZingoConfig {
         wallet_location.into_boxed_path()
     }
 
+    pub fn get_storage(&self) -> Arc<dyn WalletStorage> {
+        match &self.storage {
+            Some(storage) => storage.clone(),
+            None => Arc::new(FileStorage::new(
+                self.get_zingo_wallet_dir().into_path_buf(),
+            )),
+        }
+    }
+
+    /// The name the wallet is stored under
+    pub fn wallet_storage_name(&self) -> String {
+        self.wallet_name.to_string_lossy().into_owned()
+    }
+
+    /// The name an interrupted sync keeps its progress under, next to the wallet
+    pub fn sync_checkpoint_storage_name(&self) -> String {
+        self.wallet_name
+            .with_extension("checkpoint")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The name the wallet's scheduled payments and their journal are kept under
+    pub fn payment_schedule_storage_name(&self) -> String {
+        self.wallet_name
+            .with_extension("schedule")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Where the wallet is kept, for messages to the user
+    pub fn wallet_location(&self) -> String {
+        self.get_storage().describe(&self.wallet_storage_name())
+    }
+
     pub fn wallet_exists(&self) -> bool {
-        self.get_wallet_path().exists()
+        self.get_storage().exists(&self.wallet_storage_name())
+    }
+
+    pub fn read_wallet(&self) -> io::Result<Option<Vec<u8>>> {
+        self.get_storage().read(&self.wallet_storage_name())
+    }
+
+    pub fn write_wallet(&self, bytes: &[u8]) -> io::Result<()> {
+        self.get_storage().write(&self.wallet_storage_name(), bytes)
+    }
+
+    pub fn remove_wallet(&self) -> io::Result<()> {
+        self.get_storage().remove(&self.wallet_storage_name())
     }
 
     pub fn backup_existing_wallet(&self) -> Result<String, String> {
-        if !self.wallet_exists() {
-            return Err(format!(
-                "Couldn't find existing wallet to backup. Looked in {:?}",
-                self.get_wallet_path().to_str()
-            ));
-        }
+        let wallet_bytes = match self.read_wallet().map_err(|e| format!("{}", e))? {
+            Some(bytes) => bytes,
+            None => {
+                return Err(format!(
+                    "Couldn't find existing wallet to backup. Looked in {}",
+                    self.wallet_location()
+                ))
+            }
+        };
         use std::time::{SystemTime, UNIX_EPOCH};
 
-        let mut backup_file_path = self.get_zingo_wallet_dir().into_path_buf();
-        backup_file_path.push(&format!(
+        let backup_name = format!(
             "zingo-wallet.backup.{}.dat",
             SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_secs()
-        ));
-
-        let backup_file_str = backup_file_path.to_string_lossy().to_string();
-        std::fs::copy(self.get_wallet_path(), backup_file_path).map_err(|e| format!("{}", e))?;
+        );
+        let storage = self.get_storage();
+        storage
+            .write(&backup_name, &wallet_bytes)
+            .map_err(|e| format!("{}", e))?;
 
-        Ok(backup_file_str)
+        Ok(storage.describe(&backup_name))
     }
 
     pub fn get_log_path(&self) -> Box<Path> {
@@ -0,0 +1,110 @@
+//! Where a wallet keeps its files: the wallet itself, its backups and its sync checkpoint.
+//!
+//! By default they are files in the wallet dir. Environments without a filesystem, such as a
+//! browser extension, provide a [`WalletStorage`] of their own (e.g. over IndexedDB), or start
+//! from a [`MemoryStorage`] and persist the wallet bytes themselves.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// A flat namespace of named blobs
+pub trait WalletStorage: Send + Sync + fmt::Debug {
+    /// The contents of `name`, or `None` if nothing is stored under it
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Replaces the contents of `name`. Implementations shouldn't leave a partial write behind.
+    fn write(&self, name: &str, bytes: &[u8]) -> io::Result<()>;
+
+    /// Removes `name`. Removing something that isn't there is not an error.
+    fn remove(&self, name: &str) -> io::Result<()>;
+
+    fn exists(&self, name: &str) -> bool {
+        matches!(self.read(name), Ok(Some(_)))
+    }
+
+    /// Where `name` is kept, for messages to the user
+    fn describe(&self, name: &str) -> String;
+}
+
+/// Files in a directory, created when first written to
+#[derive(Clone, Debug)]
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl WalletStorage for FileStorage {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path(name)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes through a temporary file, so that a crash mid-write leaves the old contents
+    fn write(&self, name: &str, bytes: &[u8]) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(name);
+        let tmp_path = self.path(&format!("{}.tmp", name));
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        std::fs::rename(tmp_path, path)
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.path(name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.path(name).exists()
+    }
+
+    fn describe(&self, name: &str) -> String {
+        self.path(name).display().to_string()
+    }
+}
+
+/// Blobs held in memory, gone when the process ends
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    blobs: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl WalletStorage for MemoryStorage {
+    fn read(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.blobs.read().unwrap().get(name).cloned())
+    }
+
+    fn write(&self, name: &str, bytes: &[u8]) -> io::Result<()> {
+        self.blobs
+            .write()
+            .unwrap()
+            .insert(name.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> io::Result<()> {
+        self.blobs.write().unwrap().remove(name);
+        Ok(())
+    }
+
+    fn describe(&self, name: &str) -> String {
+        format!("{} (in memory)", name)
+    }
+}
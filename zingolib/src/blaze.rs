@@ -1,8 +1,10 @@
+pub mod block_source;
 pub(super) mod block_witness_data;
 pub(super) mod fetch_compact_blocks;
 pub(super) mod fetch_full_transaction;
 pub(super) mod fetch_taddr_transactions;
-pub(super) mod sync_status;
+pub(super) mod sync_checkpoint;
+pub mod sync_status;
 pub(super) mod syncdata;
 pub(super) mod trial_decryptions;
 pub(super) mod update_notes;
@@ -0,0 +1,374 @@
+//! Where the sync pipeline gets its chain data from.
+//!
+//! Normally this is a lightwalletd server, but for forensics and testing a wallet can
+//! be rebuilt from a local file of compact blocks, with no network access at all.
+//! A block file is a sequence of `CompactBlock` protobufs (as produced by
+//! `FakeCompactBlock::as_bytes`), each preceded by its length as a little-endian u32.
+//! The matching tree states live next to it, in a file with the same name and a
+//! `treestates` extension, in the same length-prefixed format.
+//!
+//! A block file has no full transactions, so a wallet synced from one finds its notes, but
+//! not their memos, its outgoing transaction metadata or its transparent history.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use prost::Message;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, OnceCell};
+use zcash_primitives::consensus::Parameters;
+use zcash_primitives::transaction::{Transaction, TxId};
+use zingoconfig::ZingoConfig;
+
+use crate::compact_formats::{BlockId, CompactBlock, RawTransaction, TreeState};
+use crate::executor::{self, JoinHandle};
+use crate::grpc_connector::{BytesDownloaded, GrpcConnector};
+
+#[derive(Clone, Debug)]
+pub enum BlockSource {
+    Lightwalletd(GrpcConnector),
+    LocalFile {
+        blocks: PathBuf,
+        tree_states: PathBuf,
+        /// Shared by clones, so that a sync reads through each file only once
+        index: Arc<LocalFileIndex>,
+    },
+}
+
+/// What has been learnt from reading through a block file and its tree states, each built
+/// on first use.
+#[derive(Debug, Default)]
+pub struct LocalFileIndex {
+    /// Where each block starts in the block file, by height
+    block_offsets: OnceCell<BTreeMap<u64, u64>>,
+    tree_states: OnceCell<BTreeMap<u64, TreeState>>,
+}
+
+impl BlockSource {
+    pub fn from_config(config: &ZingoConfig) -> Self {
+        match &config.block_source_file {
+            Some(path) => Self::local_file(path),
+            None => Self::Lightwalletd(GrpcConnector::from_config(config)),
+        }
+    }
+
+    /// A local source reading blocks from `path` and tree states from its `treestates` sibling.
+    pub fn local_file(path: &Path) -> Self {
+        Self::LocalFile {
+            blocks: path.to_path_buf(),
+            tree_states: path.with_extension("treestates"),
+            index: Arc::new(LocalFileIndex::default()),
+        }
+    }
+
+    /// The server behind this source, if it is one
+    pub fn connector(&self) -> Option<&GrpcConnector> {
+        match self {
+            Self::Lightwalletd(connector) => Some(connector),
+            Self::LocalFile { .. } => None,
+        }
+    }
+
+    /// What has been downloaded through this source and its clones. Nothing, for a local file.
+    pub fn bytes_downloaded(&self) -> BytesDownloaded {
+        self.connector()
+            .map(GrpcConnector::bytes_downloaded)
+            .unwrap_or_default()
+    }
+
+    /// Sends blocks from `start_height` down to `end_height` (inclusive) to both senders, highest first.
+    pub async fn get_block_range(
+        &self,
+        start_height: u64,
+        end_height: u64,
+        senders: &[UnboundedSender<CompactBlock>; 2],
+    ) -> Result<(), String> {
+        match self {
+            Self::Lightwalletd(connector) => {
+                connector
+                    .get_block_range(start_height, end_height, senders)
+                    .await
+            }
+            Self::LocalFile { blocks, index, .. } => {
+                if end_height > start_height {
+                    return Err(format!(
+                        "Bad block range {}-{}: blocks are read from the highest down",
+                        start_height, end_height
+                    ));
+                }
+                let offsets = index
+                    .block_offsets(blocks)
+                    .await?
+                    .range(end_height..=start_height)
+                    .rev()
+                    .map(|(_, offset)| *offset)
+                    .collect::<Vec<_>>();
+
+                let expected = start_height - end_height + 1;
+                if offsets.len() as u64 != expected {
+                    return Err(format!(
+                        "Block file {} has {} of the {} blocks in {}-{}",
+                        blocks.display(),
+                        offsets.len(),
+                        expected,
+                        start_height,
+                        end_height
+                    ));
+                }
+
+                let mut reader = File::open(blocks)
+                    .map(BufReader::new)
+                    .map_err(|e| format!("Error reading block file {}: {}", blocks.display(), e))?;
+                for offset in offsets {
+                    let block = read_block_at(&mut reader, offset).map_err(|e| {
+                        format!("Error reading block file {}: {}", blocks.display(), e)
+                    })?;
+                    senders[0]
+                        .send(block.clone())
+                        .map_err(|e| format!("{}", e))?;
+                    senders[1].send(block).map_err(|e| format!("{}", e))?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn get_trees(&self, height: u64) -> Result<TreeState, String> {
+        match self {
+            Self::Lightwalletd(connector) => connector.get_trees(height).await,
+            Self::LocalFile {
+                tree_states, index, ..
+            } => index
+                .tree_states(tree_states)
+                .await?
+                .get(&height)
+                .cloned()
+                .ok_or(format!(
+                    "No tree state for height {} in {}",
+                    height,
+                    tree_states.display()
+                )),
+        }
+    }
+
+    pub async fn get_latest_block(&self) -> Result<BlockId, String> {
+        match self {
+            Self::Lightwalletd(connector) => connector.get_latest_block().await,
+            Self::LocalFile { blocks, index, .. } => {
+                let offset = *index
+                    .block_offsets(blocks)
+                    .await?
+                    .values()
+                    .next_back()
+                    .ok_or(format!("Block file {} is empty", blocks.display()))?;
+                let latest = File::open(blocks)
+                    .map(BufReader::new)
+                    .and_then(|mut reader| read_block_at(&mut reader, offset))
+                    .map_err(|e| format!("Error reading block file {}: {}", blocks.display(), e))?;
+
+                // lightwalletd reports the latest block hash in the reverse byte order of the block itself
+                Ok(BlockId {
+                    height: latest.height,
+                    hash: latest.hash.into_iter().rev().collect(),
+                })
+            }
+        }
+    }
+
+    /// Fetches full transactions from the server. A block file only has compact blocks, so
+    /// from one every request fails.
+    pub async fn start_full_transaction_fetcher(
+        &self,
+        network: impl Parameters + Send + Copy + 'static,
+    ) -> (
+        JoinHandle<()>,
+        UnboundedSender<(TxId, oneshot::Sender<Result<Transaction, String>>)>,
+    ) {
+        let blocks = match self {
+            Self::Lightwalletd(connector) => {
+                return connector.start_full_transaction_fetcher(network).await
+            }
+            Self::LocalFile { blocks, .. } => blocks.clone(),
+        };
+
+        let (transmitter, mut receiver) =
+            unbounded_channel::<(TxId, oneshot::Sender<Result<Transaction, String>>)>();
+        let h = executor::spawn(async move {
+            while let Some((transaction_id, result_transmitter)) = receiver.recv().await {
+                let _ = result_transmitter.send(Err(format!(
+                    "Block file {} has no full transaction {}",
+                    blocks.display(),
+                    transaction_id
+                )));
+            }
+        });
+
+        (h, transmitter)
+    }
+
+    /// Fetches the transactions of transparent addresses from the server. A block file has
+    /// none to offer, so from one every address's history is empty.
+    pub async fn start_taddr_transaction_fetcher(
+        &self,
+    ) -> (
+        JoinHandle<()>,
+        oneshot::Sender<(
+            (Vec<String>, u64, u64),
+            oneshot::Sender<Vec<UnboundedReceiver<Result<RawTransaction, String>>>>,
+        )>,
+    ) {
+        if let Self::Lightwalletd(connector) = self {
+            return connector.start_taddr_transaction_fetcher().await;
+        }
+
+        let (transmitter, receiver) = oneshot::channel::<(
+            (Vec<String>, u64, u64),
+            oneshot::Sender<Vec<UnboundedReceiver<Result<RawTransaction, String>>>>,
+        )>();
+        let h = executor::spawn(async move {
+            if let Ok(((taddrs, _, _), result_transmitter)) = receiver.await {
+                // Each receiver's sender is dropped straight away, so it ends without a transaction
+                let _ =
+                    result_transmitter.send(taddrs.iter().map(|_| unbounded_channel().1).collect());
+            }
+        });
+
+        (h, transmitter)
+    }
+}
+
+impl LocalFileIndex {
+    async fn block_offsets(&self, blocks: &Path) -> Result<&BTreeMap<u64, u64>, String> {
+        self.block_offsets
+            .get_or_try_init(|| async {
+                let mut reader = BufReader::new(File::open(blocks)?);
+                let mut offsets = BTreeMap::new();
+                let mut offset = 0;
+                while let Some((block, len)) = read_next::<CompactBlock, _>(&mut reader)? {
+                    offsets.insert(block.height, offset);
+                    offset += 4 + len as u64;
+                }
+                Ok::<_, io::Error>(offsets)
+            })
+            .await
+            .map_err(|e| format!("Error reading block file {}: {}", blocks.display(), e))
+    }
+
+    async fn tree_states(&self, tree_states: &Path) -> Result<&BTreeMap<u64, TreeState>, String> {
+        self.tree_states
+            .get_or_try_init(|| async {
+                read_length_prefixed::<TreeState>(tree_states).map(|tree_states| {
+                    tree_states
+                        .into_iter()
+                        .map(|tree_state| (tree_state.height, tree_state))
+                        .collect()
+                })
+            })
+            .await
+            .map_err(|e| {
+                format!(
+                    "Error reading tree state file {}: {}",
+                    tree_states.display(),
+                    e
+                )
+            })
+    }
+}
+
+/// Reads the next message and the length of its encoding, or `None` at the end of the file.
+fn read_next<M: Message + Default, R: Read>(reader: &mut R) -> io::Result<Option<(M, u32)>> {
+    let len = match reader.read_u32::<LittleEndian>() {
+        Ok(len) => len,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact(&mut buf)?;
+    let message = M::decode(&buf[..]).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+
+    Ok(Some((message, len)))
+}
+
+fn read_block_at<R: Read + Seek>(reader: &mut R, offset: u64) -> io::Result<CompactBlock> {
+    reader.seek(SeekFrom::Start(offset))?;
+    read_next(reader)?
+        .map(|(block, _)| block)
+        .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))
+}
+
+pub fn read_length_prefixed<M: Message + Default>(path: &Path) -> io::Result<Vec<M>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut messages = vec![];
+    while let Some((message, _)) = read_next(&mut reader)? {
+        messages.push(message);
+    }
+
+    Ok(messages)
+}
+
+pub fn write_length_prefixed<M: Message>(path: &Path, messages: &[M]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for message in messages {
+        let bytes = message.encode_to_vec();
+        writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+        writer.write_all(&bytes)?;
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blaze::test_utils::FakeCompactBlock;
+    use zcash_primitives::block::BlockHash;
+
+    #[tokio::test]
+    async fn local_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blocks");
+
+        let fake_blocks = (1..=5)
+            .map(|height| FakeCompactBlock::new(height, BlockHash([0; 32])))
+            .collect::<Vec<_>>();
+        let mut file = File::create(&path).unwrap();
+        for fake_block in &fake_blocks {
+            let bytes = fake_block.as_bytes();
+            file.write_u32::<LittleEndian>(bytes.len() as u32).unwrap();
+            file.write_all(&bytes).unwrap();
+        }
+        drop(file);
+
+        let tree_state = TreeState {
+            height: 3,
+            ..Default::default()
+        };
+        write_length_prefixed(&path.with_extension("treestates"), &[tree_state]).unwrap();
+
+        let source = BlockSource::local_file(&path);
+        assert_eq!(source.get_latest_block().await.unwrap().height, 5);
+        assert_eq!(source.get_trees(3).await.unwrap().height, 3);
+        assert!(source.get_trees(4).await.is_err());
+
+        let (s0, mut r0) = unbounded_channel();
+        let (s1, mut r1) = unbounded_channel();
+        source.get_block_range(4, 2, &[s0, s1]).await.unwrap();
+        for height in [4, 3, 2] {
+            assert_eq!(r0.recv().await.unwrap().height, height);
+            assert_eq!(r1.recv().await.unwrap().height, height);
+        }
+        assert!(r0.recv().await.is_none());
+
+        let (s0, _r0) = unbounded_channel();
+        let (s1, _r1) = unbounded_channel();
+        assert!(source.get_block_range(7, 5, &[s0, s1]).await.is_err());
+
+        let (s0, _r0) = unbounded_channel();
+        let (s1, _r1) = unbounded_channel();
+        assert!(source.get_block_range(2, 4, &[s0, s1]).await.is_err());
+    }
+}
@@ -1,8 +1,8 @@
 use crate::{
     compact_formats::{CompactBlock, CompactTx, TreeState},
-    grpc_connector::GrpcConnector,
+    executor::{self, JoinHandle},
     wallet::{
-        data::{BlockData, PoolNullifier},
+        data::{BlockData, PoolNullifier, TransactionMetadata},
         traits::{DomainWalletExt, FromCommitment, ReceivedNoteAndMetadata},
         transactions::TransactionMetadataSet,
     },
@@ -15,23 +15,23 @@ use zcash_note_encryption::Domain;
 use zingoconfig::ChainType;
 
 use futures::future::join_all;
-use http::Uri;
 use std::{sync::Arc, time::Duration};
 use tokio::{
     sync::{
         mpsc::{self, UnboundedSender},
         RwLock,
     },
-    task::{yield_now, JoinHandle},
+    task::yield_now,
     time::sleep,
 };
 use zcash_primitives::{
     consensus::BlockHeight,
     merkle_tree::{read_commitment_tree, write_commitment_tree, HashSer},
     sapling::note_encryption::SaplingDomain,
+    transaction::TxId,
 };
 
-use super::sync_status::BatchSyncStatus;
+use super::{block_source::BlockSource, sync_status::BatchSyncStatus};
 
 type Node<D> = <<D as DomainWalletExt>::WalletNote as ReceivedNoteAndMetadata>::Node;
 
@@ -257,7 +257,7 @@ impl BlockAndWitnessData {
         closest_lower_verified_tree: TreeState,
     ) -> JoinHandle<bool> {
         assert!(closest_lower_verified_tree.height <= unverified_tree.height);
-        tokio::spawn(async move {
+        executor::spawn(async move {
             if closest_lower_verified_tree.height == unverified_tree.height {
                 return true;
             }
@@ -360,7 +360,7 @@ impl BlockAndWitnessData {
         // using the Sapling tree fetched
         // from the server temporarily, but we verify it before we return it
 
-        let h0: JoinHandle<Result<u64, String>> = tokio::spawn(async move {
+        let h0: JoinHandle<Result<u64, String>> = executor::spawn(async move {
             // Temporary holding place for blocks while we process them.
             let mut blks = vec![];
             let mut earliest_block_height = 0;
@@ -421,7 +421,7 @@ impl BlockAndWitnessData {
 
         // Handle: Final
         // Join all the handles
-        let h = tokio::spawn(async move {
+        let h = executor::spawn(async move {
             let earliest_block = h0
                 .await
                 .map_err(|e| format!("Error processing blocks: {}", e))??;
@@ -524,12 +524,31 @@ impl BlockAndWitnessData {
             blocks.get(pos as usize).unwrap().cb().time
         }
     }
+
+    /// The txids of every transaction in the block at `height`.
+    pub(crate) async fn get_block_txids(&self, height: &BlockHeight) -> Vec<TxId> {
+        let height = u64::from(*height);
+        self.wait_for_block(height).await;
+
+        {
+            let blocks = self.blocks_in_current_batch.read().await;
+            let pos = blocks.first().unwrap().height - height;
+            blocks
+                .get(pos as usize)
+                .unwrap()
+                .cb()
+                .vtx
+                .iter()
+                .map(|compact_transaction| TransactionMetadata::new_txid(&compact_transaction.hash))
+                .collect()
+        }
+    }
     /// This function handles Orchard and Sapling domains.
     /// This function takes data from the Untrusted Malicious Proxy, and uses it to construct a witness locally.  I am
     /// currently of the opinion that this function should be factored into separate concerns.
     pub(crate) async fn get_note_witness<D>(
         &self,
-        uri: Uri,
+        block_source: &BlockSource,
         height: BlockHeight,
         transaction_num: usize,
         output_num: usize,
@@ -550,7 +569,7 @@ impl BlockAndWitnessData {
             let tree = if prev_height < activation_height {
                 CommitmentTree::<<D::WalletNote as ReceivedNoteAndMetadata>::Node, 32>::empty()
             } else {
-                let tree_state = GrpcConnector::get_trees(uri, prev_height).await?;
+                let tree_state = block_source.get_trees(prev_height).await?;
                 let tree = hex::decode(D::get_tree(&tree_state)).unwrap();
                 self.unverified_treestates.write().await.push(tree_state);
                 read_commitment_tree(&tree[..]).map_err(|e| format!("{}", e))?
@@ -797,7 +816,7 @@ mod test {
             )
             .await;
 
-        let send_h: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+        let send_h: JoinHandle<Result<(), String>> = executor::spawn(async move {
             for block in blocks {
                 cb_sender
                     .send(block.cb())
@@ -846,7 +865,7 @@ mod test {
             )
             .await;
 
-        let send_h: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+        let send_h: JoinHandle<Result<(), String>> = executor::spawn(async move {
             for block in blocks {
                 cb_sender
                     .send(block.cb())
@@ -942,7 +961,7 @@ mod test {
             )
             .await;
 
-        let send_h: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+        let send_h: JoinHandle<Result<(), String>> = executor::spawn(async move {
             // Send the normal blocks
             for block in blocks {
                 cb_sender
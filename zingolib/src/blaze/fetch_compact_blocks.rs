@@ -1,18 +1,16 @@
-use std::{cmp::max, sync::Arc};
+use std::cmp::max;
 
-use crate::{compact_formats::CompactBlock, grpc_connector::GrpcConnector};
+use super::block_source::BlockSource;
+use crate::compact_formats::CompactBlock;
 use log::debug;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use zingoconfig::ZingoConfig;
 pub struct FetchCompactBlocks {
-    config: ZingoConfig,
+    block_source: BlockSource,
 }
 
 impl FetchCompactBlocks {
-    pub fn new(config: &ZingoConfig) -> Self {
-        Self {
-            config: config.clone(),
-        }
+    pub fn new(block_source: BlockSource) -> Self {
+        Self { block_source }
     }
 
     async fn fetch_blocks_range(
@@ -21,7 +19,6 @@ impl FetchCompactBlocks {
         start_block: u64,
         end_block: u64,
     ) -> Result<(), String> {
-        let grpc_client = Arc::new(GrpcConnector::new(self.config.get_lightwalletd_uri()));
         const STEP: u64 = 10_000;
 
         // We need the `rev()` here because rust ranges can only go up
@@ -34,13 +31,15 @@ impl FetchCompactBlocks {
 
             debug!("Fetching blocks {}-{}", start, end);
 
-            grpc_client.get_block_range(start, end, senders).await?;
+            self.block_source
+                .get_block_range(start, end, senders)
+                .await?;
         }
 
         Ok(())
     }
 
-    // Load all the blocks from LightwalletD
+    // Load all the blocks from LightwalletD, or the configured local block file
     pub async fn start(
         &self,
         senders: [UnboundedSender<CompactBlock>; 2],
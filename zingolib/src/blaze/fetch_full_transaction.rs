@@ -1,3 +1,4 @@
+use crate::executor::{self, JoinHandle};
 use crate::wallet::{
     data::OutgoingTxData,
     keys::{address_from_pubkeyhash, unified::WalletCapability},
@@ -11,9 +12,11 @@ use crate::wallet::{
 use zingo_memo::{parse_zingo_memo, ParsedMemo};
 
 use super::syncdata::BlazeSyncData;
+use crate::wallet::{TransactionFetchPrivacy, WalletOptions};
 use futures::{future::join_all, stream::FuturesUnordered, StreamExt};
 use log::info;
 use orchard::note_encryption::OrchardDomain;
+use rand::{seq::SliceRandom, Rng};
 use std::{
     collections::HashSet,
     convert::TryInto,
@@ -22,12 +25,9 @@ use std::{
         Arc,
     },
 };
-use tokio::{
-    sync::{
-        mpsc::{unbounded_channel, UnboundedSender},
-        oneshot, RwLock,
-    },
-    task::JoinHandle,
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedSender},
+    oneshot, RwLock,
 };
 use zcash_client_backend::address::{RecipientAddress, UnifiedAddress};
 use zcash_note_encryption::try_output_recovery_with_ovk;
@@ -178,6 +178,11 @@ impl TransactionContext {
         self.update_outgoing_txdatas_with_uas(txid_indexed_zingo_memos)
             .await;
 
+        self.transaction_metadata_set
+            .write()
+            .await
+            .set_expiry_height(&transaction.txid(), transaction.expiry_height());
+
         // Update price if available
         if price.is_some() {
             self.transaction_metadata_set
@@ -593,6 +598,68 @@ impl TransactionContext {
     }
 }
 
+/// Picks the other transactions from `transaction_id`'s block to fetch alongside it, so that
+/// the server can't tell which of the requested transactions is the one we care about.
+/// Transactions already in `requested`, whether fetched for us or as decoys, are never picked
+/// again, and the picked ones are added to it.
+fn select_decoys(
+    transaction_id: TxId,
+    block_txids: Vec<TxId>,
+    wallet_options: &WalletOptions,
+    requested: &mut HashSet<TxId>,
+) -> Vec<TxId> {
+    let candidates = block_txids
+        .into_iter()
+        .filter(|txid| *txid != transaction_id && !requested.contains(txid))
+        .collect::<Vec<_>>();
+    let decoys = match wallet_options.fetch_privacy {
+        TransactionFetchPrivacy::Off => vec![],
+        TransactionFetchPrivacy::WholeBlock => candidates,
+        TransactionFetchPrivacy::Decoys => candidates
+            .choose_multiple(
+                &mut rand::thread_rng(),
+                wallet_options.decoys_per_transaction as usize,
+            )
+            .copied()
+            .collect(),
+    };
+    requested.extend(decoys.iter().copied());
+
+    decoys
+}
+
+/// The txids requested from the server, for us or as decoys, so that none is requested twice
+#[derive(Default)]
+struct RequestedTxids {
+    txids: HashSet<TxId>,
+    /// Decoys still being fetched
+    decoys: HashSet<TxId>,
+    /// Decoys still being fetched that turned out to be ours
+    wanted: HashSet<TxId>,
+}
+
+impl RequestedTxids {
+    /// Notes that `txid` is ours, returning whether it still has to be requested
+    fn request(&mut self, txid: TxId) -> bool {
+        if self.decoys.contains(&txid) {
+            self.wanted.insert(txid);
+        }
+        self.txids.insert(txid)
+    }
+
+    /// Settles the fetch of a decoy, returning whether it has to be fetched again as ours.
+    /// A decoy that couldn't be fetched and isn't ours is forgotten, so that it is still
+    /// requested if it turns out to be ours later.
+    fn decoy_fetched(&mut self, txid: TxId, fetched: bool) -> bool {
+        self.decoys.remove(&txid);
+        let wanted = self.wanted.remove(&txid);
+        if !fetched && !wanted {
+            self.txids.remove(&txid);
+        }
+        !fetched && wanted
+    }
+}
+
 pub async fn start(
     transaction_context: TransactionContext,
     fulltx_fetcher: UnboundedSender<(TxId, oneshot::Sender<Result<Transaction, String>>)>,
@@ -611,51 +678,149 @@ pub async fn start(
 
     let (transaction_id_transmitter, mut transaction_id_receiver) =
         unbounded_channel::<(TxId, BlockHeight)>();
-    let h1: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+    let h1: JoinHandle<Result<(), String>> = executor::spawn(async move {
         let last_progress = Arc::new(AtomicU64::new(0));
         let mut workers = FuturesUnordered::new();
+        let wallet_options = bsync_data_i.read().await.wallet_options;
+        let decoy_bytes = Arc::new(AtomicU64::new(0));
+        let requested = Arc::new(std::sync::Mutex::new(RequestedTxids::default()));
+        let mut decoys_counted = FuturesUnordered::new();
 
         while let Some((transaction_id, height)) = transaction_id_receiver.recv().await {
-            let per_txid_iter_context = local_transaction_context.clone();
+            let newly_requested = requested.lock().unwrap().request(transaction_id);
+            if !newly_requested
+                || bsync_data_i
+                    .read()
+                    .await
+                    .sync_status
+                    .read()
+                    .await
+                    .fetched_txids
+                    .contains(&transaction_id)
+            {
+                // Already fetched, or on its way as a decoy, which gets scanned all the same
+                continue;
+            }
             let block_time = bsync_data_i
                 .read()
                 .await
                 .block_data
                 .get_block_timestamp(&height)
                 .await;
-            let full_transaction_fetcher = fulltx_fetcher.clone();
-            let bsync_data = bsync_data_i.clone();
-            let last_progress = last_progress.clone();
-
-            workers.push(tokio::spawn(async move {
-                // It is possible that we receive the same txid multiple times, so we keep track of all the txids that were fetched
-                let transaction = {
-                    // Fetch the TxId from LightwalletD and process all the parts of it.
-                    let (transmitter, receiver) = oneshot::channel();
-                    full_transaction_fetcher
-                        .send((transaction_id, transmitter))
-                        .unwrap();
-                    receiver.await.unwrap()?
+
+            // The budget is checked against bytes actually downloaded, so wait for the decoys
+            // already requested to be counted first.
+            if wallet_options.privacy_bandwidth_budget.is_some() {
+                while decoys_counted.next().await.is_some() {}
+            }
+            let decoys = if wallet_options.fetch_privacy == TransactionFetchPrivacy::Off
+                || wallet_options
+                    .privacy_bandwidth_budget
+                    .map_or(false, |budget| decoy_bytes.load(Ordering::SeqCst) >= budget)
+            {
+                vec![]
+            } else {
+                let block_txids = bsync_data_i
+                    .read()
+                    .await
+                    .block_data
+                    .get_block_txids(&height)
+                    .await;
+                let mut requested = requested.lock().unwrap();
+                let decoys = select_decoys(
+                    transaction_id,
+                    block_txids,
+                    &wallet_options,
+                    &mut requested.txids,
+                );
+                requested.decoys.extend(decoys.iter().copied());
+                decoys
+            };
+
+            // Request the real transaction at a random position among the decoys, so that
+            // the order of requests doesn't give it away.
+            let real_position = rand::thread_rng().gen_range(0..=decoys.len());
+            for (position, requested_id) in decoys
+                .iter()
+                .take(real_position)
+                .chain(std::iter::once(&transaction_id))
+                .chain(decoys.iter().skip(real_position))
+                .copied()
+                .enumerate()
+            {
+                let (transmitter, receiver) = oneshot::channel();
+                fulltx_fetcher.send((requested_id, transmitter)).unwrap();
+                let decoy_counter = if position == real_position {
+                    None
+                } else {
+                    let (counted_transmitter, counted_receiver) = oneshot::channel::<()>();
+                    decoys_counted.push(counted_receiver);
+                    Some((decoy_bytes.clone(), counted_transmitter))
                 };
+                let per_txid_iter_context = local_transaction_context.clone();
+                let bsync_data = bsync_data_i.clone();
+                let last_progress = last_progress.clone();
+                let requested = requested.clone();
+                let fulltx_fetcher = fulltx_fetcher.clone();
+
+                workers.push(executor::spawn(async move {
+                    // Wait for the TxId to arrive from LightwalletD, and process all the parts of it.
+                    let transaction = match (
+                        receiver.await.unwrap_or_else(|e| Err(e.to_string())),
+                        decoy_counter,
+                    ) {
+                        (Ok(transaction), None) => transaction,
+                        (Err(e), None) => return Err(e),
+                        (Err(_), Some((_, counted))) => {
+                            drop(counted);
+                            // A decoy that couldn't be fetched doesn't matter, unless it
+                            // turned out to be ours while it was being fetched
+                            if !requested.lock().unwrap().decoy_fetched(requested_id, false) {
+                                return Ok(());
+                            }
+                            let (transmitter, receiver) = oneshot::channel();
+                            fulltx_fetcher
+                                .send((requested_id, transmitter))
+                                .map_err(|e| e.to_string())?;
+                            receiver.await.unwrap_or_else(|e| Err(e.to_string()))?
+                        }
+                        (Ok(transaction), Some((decoy_bytes, counted))) => {
+                            let mut bytes = vec![];
+                            if transaction.write(&mut bytes).is_ok() {
+                                decoy_bytes.fetch_add(bytes.len() as u64, Ordering::SeqCst);
+                            }
+                            let _ = counted.send(());
+                            requested.lock().unwrap().decoy_fetched(requested_id, true);
+                            transaction
+                        }
+                    };
 
-                let progress = start_height - u64::from(height);
-                if progress > last_progress.load(Ordering::SeqCst) {
-                    bsync_data
-                        .read()
-                        .await
-                        .sync_status
-                        .write()
-                        .await
-                        .txn_scan_done = progress;
-                    last_progress.store(progress, Ordering::SeqCst);
-                }
+                    let progress = start_height - u64::from(height);
+                    if progress > last_progress.load(Ordering::SeqCst) {
+                        bsync_data
+                            .read()
+                            .await
+                            .sync_status
+                            .write()
+                            .await
+                            .txn_scan_done = progress;
+                        last_progress.store(progress, Ordering::SeqCst);
+                    }
 
-                per_txid_iter_context
-                    .scan_full_tx(transaction, height, false, block_time, None)
-                    .await;
+                    // Decoys are scanned too: one of them may turn out to be a transaction we
+                    // asked for after it was already requested as a decoy.
+                    per_txid_iter_context
+                        .scan_full_tx(transaction, height, false, block_time, None)
+                        .await;
+
+                    // It is possible that we receive the same txid multiple times, so we keep track of all the txids that were fetched
+                    let sync_status = bsync_data.read().await.sync_status.clone();
+                    let mut sync_status = sync_status.write().await;
+                    sync_status.fetched_txids.insert(requested_id);
 
-                Ok::<_, String>(())
-            }));
+                    Ok::<_, String>(())
+                }));
+            }
         }
 
         while let Some(r) = workers.next().await {
@@ -678,7 +843,7 @@ pub async fn start(
     let (transaction_transmitter, mut transaction_receiver) =
         unbounded_channel::<(Transaction, BlockHeight)>();
 
-    let h2: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+    let h2: JoinHandle<Result<(), String>> = executor::spawn(async move {
         let bsync_data = bsync_data.clone();
 
         while let Some((transaction, height)) = transaction_receiver.recv().await {
@@ -697,7 +862,7 @@ pub async fn start(
         Ok(())
     });
 
-    let h = tokio::spawn(async move {
+    let h = executor::spawn(async move {
         join_all(vec![h1, h2])
             .await
             .into_iter()
@@ -706,3 +871,125 @@ pub async fn start(
 
     (h, transaction_id_transmitter, transaction_transmitter)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn txids(range: std::ops::Range<u8>) -> Vec<TxId> {
+        range.map(|byte| TxId::from_bytes([byte; 32])).collect()
+    }
+
+    fn options(fetch_privacy: TransactionFetchPrivacy) -> WalletOptions {
+        WalletOptions {
+            fetch_privacy,
+            decoys_per_transaction: 3,
+            ..WalletOptions::default()
+        }
+    }
+
+    #[test]
+    fn no_decoys_when_privacy_is_off() {
+        let mut requested = HashSet::new();
+        let decoys = select_decoys(
+            TxId::from_bytes([0; 32]),
+            txids(0..10),
+            &options(TransactionFetchPrivacy::Off),
+            &mut requested,
+        );
+        assert!(decoys.is_empty());
+        assert!(requested.is_empty());
+    }
+
+    #[test]
+    fn decoys_are_other_unrequested_transactions_of_the_block() {
+        let real = TxId::from_bytes([0; 32]);
+        let already_requested = TxId::from_bytes([1; 32]);
+        let mut requested = HashSet::from([real, already_requested]);
+        let decoys = select_decoys(
+            real,
+            txids(0..10),
+            &options(TransactionFetchPrivacy::Decoys),
+            &mut requested,
+        );
+        assert_eq!(decoys.len(), 3);
+        assert!(decoys
+            .iter()
+            .all(|txid| *txid != real && *txid != already_requested));
+        assert!(decoys.iter().all(|txid| requested.contains(txid)));
+        assert_eq!(requested.len(), 5);
+
+        // Only one transaction of a block of four is left to pick
+        let real = TxId::from_bytes([2; 32]);
+        requested.insert(real);
+        let decoys = select_decoys(
+            real,
+            txids(0..4),
+            &options(TransactionFetchPrivacy::Decoys),
+            &mut requested,
+        );
+        assert!(decoys.iter().all(|txid| *txid == TxId::from_bytes([3; 32])));
+    }
+
+    #[test]
+    fn whole_block_skips_what_was_already_requested() {
+        let real = TxId::from_bytes([0; 32]);
+        let mut requested = HashSet::from([real, TxId::from_bytes([2; 32])]);
+        let decoys = select_decoys(
+            real,
+            txids(0..5),
+            &options(TransactionFetchPrivacy::WholeBlock),
+            &mut requested,
+        );
+        assert_eq!(
+            decoys,
+            [1, 3, 4].map(|byte| TxId::from_bytes([byte; 32])).to_vec()
+        );
+
+        // Every other transaction of the block is requested now, so there are no more decoys
+        let real = TxId::from_bytes([3; 32]);
+        let decoys = select_decoys(
+            real,
+            txids(0..5),
+            &options(TransactionFetchPrivacy::WholeBlock),
+            &mut requested,
+        );
+        assert!(decoys.is_empty());
+    }
+
+    #[test]
+    fn a_decoy_that_failed_can_be_requested_again() {
+        let decoy = TxId::from_bytes([1; 32]);
+        let mut requested = RequestedTxids::default();
+        requested.request(TxId::from_bytes([0; 32]));
+        requested.txids.insert(decoy);
+        requested.decoys.insert(decoy);
+
+        assert!(!requested.decoy_fetched(decoy, false));
+        assert!(requested.request(decoy));
+    }
+
+    #[test]
+    fn a_failed_decoy_that_turned_out_ours_is_fetched_again() {
+        let decoy = TxId::from_bytes([1; 32]);
+        let mut requested = RequestedTxids::default();
+        requested.txids.insert(decoy);
+        requested.decoys.insert(decoy);
+
+        // Ours, but already on its way as a decoy
+        assert!(!requested.request(decoy));
+        assert!(requested.decoy_fetched(decoy, false));
+        assert!(!requested.request(decoy));
+    }
+
+    #[test]
+    fn a_fetched_decoy_is_not_requested_again() {
+        let decoy = TxId::from_bytes([1; 32]);
+        let mut requested = RequestedTxids::default();
+        requested.txids.insert(decoy);
+        requested.decoys.insert(decoy);
+
+        assert!(!requested.decoy_fetched(decoy, true));
+        assert!(!requested.request(decoy));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::compact_formats::RawTransaction;
+use crate::executor::{self, JoinHandle};
 use crate::wallet::keys::address_from_pubkeyhash;
 use crate::wallet::keys::unified::WalletCapability;
 
@@ -6,8 +7,8 @@ use std::sync::Arc;
 use tokio::join;
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot;
-use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
 
 use zcash_primitives::consensus::BlockHeight;
 use zcash_primitives::consensus::BranchId;
@@ -44,7 +45,7 @@ impl FetchTaddrTransactions {
     ) -> JoinHandle<Result<(), String>> {
         let wc = self.wc.clone();
         let config = self.config.clone();
-        tokio::spawn(async move {
+        executor::spawn(async move {
             let taddrs = wc
                 .addresses()
                 .iter()
@@ -62,7 +63,7 @@ impl FetchTaddrTransactions {
                 unbounded_channel();
 
             // Process every transparent address transaction, in order of height
-            let h1: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+            let h1: JoinHandle<Result<(), String>> = executor::spawn(async move {
                 // Now, read the transactions one-at-a-time, and then dispatch them in height order
                 let mut transactions_top = vec![];
 
@@ -117,7 +118,7 @@ impl FetchTaddrTransactions {
                 Ok(())
             });
 
-            let h2: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+            let h2: JoinHandle<Result<(), String>> = executor::spawn(async move {
                 let mut prev_height = 0;
 
                 while let Some(raw_transaction) = ordered_raw_transaction_receiver.recv().await {
@@ -0,0 +1,184 @@
+//! Progress of a sync run, kept on disk so that an interrupted sync can carry on where it
+//! stopped instead of starting over.
+//!
+//! The wallet file is saved at the end of every batch, so everything a finished batch found
+//! (notes, witnesses, full transactions) is already durable. What the wallet file doesn't know
+//! is the shape of the sync run itself: which tip it was heading for, which ranges it has
+//! covered, and which transactions it has already downloaded. That is what lives here, in a
+//! small file next to the wallet, rewritten after every batch and removed once the run reaches
+//! its target.
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use zcash_encoding::Vector;
+use zcash_primitives::transaction::TxId;
+use zingoconfig::storage::WalletStorage;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncCheckpoint {
+    /// The chain tip this sync run was started against
+    pub sync_target: u64,
+
+    /// Inclusive (lowest, highest) block ranges that have been scanned and saved, lowest first
+    pub scanned_ranges: Vec<(u64, u64)>,
+
+    /// Height every witness has been advanced to. Checkpoints are only taken between batches,
+    /// so no witness update above this height is ever half done.
+    pub witnesses_updated_to: u64,
+
+    /// Transactions whose full data was downloaded and scanned by this sync run
+    pub fetched_txids: HashSet<TxId>,
+}
+
+impl SyncCheckpoint {
+    pub const fn serialized_version() -> u64 {
+        1
+    }
+
+    pub fn new(sync_target: u64) -> Self {
+        Self {
+            sync_target,
+            ..Default::default()
+        }
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let version = reader.read_u64::<LittleEndian>()?;
+        if version > Self::serialized_version() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Don't know how to read sync checkpoint version {}", version),
+            ));
+        }
+
+        let sync_target = reader.read_u64::<LittleEndian>()?;
+        let scanned_ranges = Vector::read(&mut reader, |r| {
+            Ok((r.read_u64::<LittleEndian>()?, r.read_u64::<LittleEndian>()?))
+        })?;
+        let witnesses_updated_to = reader.read_u64::<LittleEndian>()?;
+        let fetched_txids = Vector::read(&mut reader, |r| {
+            let mut txid_bytes = [0u8; 32];
+            r.read_exact(&mut txid_bytes)?;
+            Ok(TxId::from_bytes(txid_bytes))
+        })?
+        .into_iter()
+        .collect();
+
+        Ok(Self {
+            sync_target,
+            scanned_ranges,
+            witnesses_updated_to,
+            fetched_txids,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(Self::serialized_version())?;
+
+        writer.write_u64::<LittleEndian>(self.sync_target)?;
+        Vector::write(&mut writer, &self.scanned_ranges, |w, (lowest, highest)| {
+            w.write_u64::<LittleEndian>(*lowest)?;
+            w.write_u64::<LittleEndian>(*highest)
+        })?;
+        writer.write_u64::<LittleEndian>(self.witnesses_updated_to)?;
+        let fetched_txids = self.fetched_txids.iter().collect::<Vec<_>>();
+        Vector::write(&mut writer, &fetched_txids, |w, txid| {
+            w.write_all(txid.as_ref())
+        })
+    }
+
+    /// Reads the checkpoint stored as `name`. A missing or unreadable checkpoint just means
+    /// there is nothing to resume, so that is not an error.
+    pub fn load(storage: &dyn WalletStorage, name: &str) -> Option<Self> {
+        let bytes = storage.read(name).ok()??;
+        match Self::read(&bytes[..]) {
+            Ok(checkpoint) => Some(checkpoint),
+            Err(e) => {
+                log::warn!(
+                    "Ignoring unreadable sync checkpoint {}: {}",
+                    storage.describe(name),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Stores the checkpoint as `name`. The storage sees to it that a crash mid-write can't
+    /// leave a truncated checkpoint behind.
+    pub fn save(&self, storage: &dyn WalletStorage, name: &str) -> io::Result<()> {
+        let mut bytes = vec![];
+        self.write(&mut bytes)?;
+        storage.write(name, &bytes)
+    }
+
+    pub fn remove(storage: &dyn WalletStorage, name: &str) {
+        if let Err(e) = storage.remove(name) {
+            log::warn!(
+                "Couldn't remove sync checkpoint {}: {}",
+                storage.describe(name),
+                e
+            );
+        }
+    }
+
+    /// Highest block this sync run has scanned, if any
+    pub fn scanned_to(&self) -> Option<u64> {
+        self.scanned_ranges.last().map(|(_, highest)| *highest)
+    }
+
+    /// Records a finished batch, merging it into the previous range when they touch.
+    pub fn record_batch(&mut self, lowest: u64, highest: u64, fetched_txids: &HashSet<TxId>) {
+        match self.scanned_ranges.last_mut() {
+            Some((_, prev_highest)) if *prev_highest + 1 == lowest => *prev_highest = highest,
+            _ => self.scanned_ranges.push((lowest, highest)),
+        }
+        self.witnesses_updated_to = highest;
+        self.fetched_txids.extend(fetched_txids);
+    }
+
+    /// Forgets any progress above `height`. The wallet file may be older than the checkpoint
+    /// (e.g. when the app was killed before it saved), and only what the wallet has can be resumed.
+    pub fn truncate_to(&mut self, height: u64) {
+        self.scanned_ranges.retain(|(lowest, _)| *lowest <= height);
+        if let Some((_, highest)) = self.scanned_ranges.last_mut() {
+            *highest = (*highest).min(height);
+        }
+        self.witnesses_updated_to = self.witnesses_updated_to.min(height);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zingoconfig::storage::FileStorage;
+
+    #[test]
+    fn record_truncate_and_round_trip() {
+        let mut checkpoint = SyncCheckpoint::new(1_000);
+        let txids = |bytes: u8| HashSet::from([TxId::from_bytes([bytes; 32])]);
+        checkpoint.record_batch(1, 100, &txids(1));
+        checkpoint.record_batch(101, 200, &txids(1));
+        checkpoint.record_batch(301, 400, &txids(2));
+        assert_eq!(checkpoint.scanned_ranges, vec![(1, 200), (301, 400)]);
+        assert_eq!(checkpoint.fetched_txids.len(), 2);
+        assert_eq!(checkpoint.scanned_to(), Some(400));
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf());
+        let name = "wallet.checkpoint";
+        checkpoint.save(&storage, name).unwrap();
+        assert_eq!(
+            SyncCheckpoint::load(&storage, name),
+            Some(checkpoint.clone())
+        );
+
+        checkpoint.truncate_to(150);
+        assert_eq!(checkpoint.scanned_ranges, vec![(1, 150)]);
+        assert_eq!(checkpoint.witnesses_updated_to, 150);
+
+        SyncCheckpoint::remove(&storage, name);
+        assert_eq!(SyncCheckpoint::load(&storage, name), None);
+    }
+}
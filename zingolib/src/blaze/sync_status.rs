@@ -1,6 +1,9 @@
 use core::fmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use zcash_primitives::transaction::TxId;
+
+use crate::grpc_connector::BytesDownloaded;
 use crate::wallet::data::PoolNullifier;
 
 #[derive(Clone, Debug, Default)]
@@ -22,6 +25,16 @@ pub struct BatchSyncStatus {
 
     pub batch_num: usize,
     pub batch_total: usize,
+
+    /// Ranges scanned by an earlier, interrupted run that this sync picked up from
+    pub resumed_ranges: Vec<(u64, u64)>,
+
+    /// Full transactions downloaded and scanned by this sync, or by the interrupted run it
+    /// resumed. They aren't fetched again.
+    pub fetched_txids: HashSet<TxId>,
+
+    /// What this sync has downloaded so far, updated after every batch
+    pub bytes_downloaded: BytesDownloaded,
 }
 
 impl BatchSyncStatus {
@@ -37,6 +50,9 @@ impl BatchSyncStatus {
         self.witnesses_updated = HashMap::new();
         self.batch_num = 0;
         self.batch_total = batch_total;
+        self.resumed_ranges = vec![];
+        self.fetched_txids = HashSet::new();
+        self.bytes_downloaded = BytesDownloaded::default();
     }
 
     /// Setup a new sync status in prep for an upcoming sync
@@ -88,6 +104,15 @@ impl fmt::Display for BatchSyncStatus {
                 self.in_progress,
                 self.last_error.as_ref().unwrap_or(&"None".to_string())
             )
+        }?;
+        if !self.resumed_ranges.is_empty() {
+            let ranges = self
+                .resumed_ranges
+                .iter()
+                .map(|(lowest, highest)| format!("{}-{}", lowest, highest))
+                .collect::<Vec<_>>();
+            write!(f, " (resumed after {})", ranges.join(", "))?;
         }
+        Ok(())
     }
 }
@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
-use http::Uri;
 use tokio::sync::RwLock;
 
-use super::{block_witness_data::BlockAndWitnessData, sync_status::BatchSyncStatus};
+use super::{
+    block_source::BlockSource, block_witness_data::BlockAndWitnessData,
+    sync_status::BatchSyncStatus,
+};
 use crate::compact_formats::TreeState;
 use crate::wallet::data::BlockData;
 use crate::wallet::WalletOptions;
@@ -12,7 +14,8 @@ use zingoconfig::ZingoConfig;
 pub struct BlazeSyncData {
     pub(crate) sync_status: Arc<RwLock<BatchSyncStatus>>,
     pub(crate) block_data: BlockAndWitnessData,
-    uri: Arc<std::sync::RwLock<Uri>>,
+    /// Where the current sync gets its chain data
+    block_source: BlockSource,
     pub(crate) wallet_options: WalletOptions,
 }
 
@@ -22,18 +25,20 @@ impl BlazeSyncData {
 
         Self {
             sync_status: sync_status.clone(),
-            uri: config.lightwalletd_uri.clone(),
+            block_source: BlockSource::from_config(config),
             block_data: BlockAndWitnessData::new(sync_status),
             wallet_options: WalletOptions::default(),
         }
     }
 
-    pub fn uri(&self) -> Uri {
-        self.uri.read().unwrap().clone()
+    pub fn block_source(&self) -> BlockSource {
+        self.block_source.clone()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn setup_nth_batch(
         &mut self,
+        block_source: BlockSource,
         start_block: u64,
         end_block: u64,
         batch_num: usize,
@@ -54,6 +59,7 @@ impl BlazeSyncData {
             .await
             .new_sync_batch(start_block, end_block, batch_num);
 
+        self.block_source = block_source;
         self.wallet_options = wallet_options;
 
         self.block_data
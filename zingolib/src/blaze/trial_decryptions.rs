@@ -5,6 +5,7 @@
 
 use crate::{
     compact_formats::{CompactBlock, CompactTx},
+    executor::{self, JoinHandle},
     wallet::{
         data::{PoolNullifier, TransactionMetadata},
         keys::unified::WalletCapability,
@@ -20,12 +21,9 @@ use incrementalmerkletree::{Position, Retention};
 use log::debug;
 use orchard::{keys::IncomingViewingKey as OrchardIvk, note_encryption::OrchardDomain};
 use std::sync::Arc;
-use tokio::{
-    sync::{
-        mpsc::{unbounded_channel, UnboundedSender},
-        oneshot, RwLock,
-    },
-    task::JoinHandle,
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedSender},
+    oneshot, RwLock,
 };
 use zcash_note_encryption::Domain;
 use zcash_primitives::{
@@ -82,7 +80,7 @@ impl TrialDecryptions {
         let transaction_metadata_set = self.transaction_metadata_set.clone();
 
         let config = self.config.clone();
-        let management_thread_handle = tokio::spawn(async move {
+        let management_thread_handle = executor::spawn(async move {
             let mut workers = FuturesUnordered::new();
             let mut cbs = vec![];
 
@@ -103,7 +101,7 @@ impl TrialDecryptions {
                     let detected_transaction_id_sender = detected_transaction_id_sender.clone();
                     let config = config.clone();
 
-                    workers.push(tokio::spawn(Self::trial_decrypt_batch(
+                    workers.push(executor::spawn(Self::trial_decrypt_batch(
                         config,
                         cbs.split_off(0), // This allocates all received cbs to the spawn.
                         wc,
@@ -118,7 +116,7 @@ impl TrialDecryptions {
                 }
             }
             // Finish off the remaining < 1000 cbs
-            workers.push(tokio::spawn(Self::trial_decrypt_batch(
+            workers.push(executor::spawn(Self::trial_decrypt_batch(
                 config,
                 cbs,
                 wc,
@@ -245,7 +243,7 @@ impl TrialDecryptions {
                         .send((transaction_id, transmitter))
                         .unwrap();
 
-                    workers.push(tokio::spawn(async move {
+                    workers.push(executor::spawn(async move {
                         // Discard the result, because this was not a wallet transaction.
                         receiver.await.unwrap().map(|_r| ())
                     }));
@@ -327,7 +325,7 @@ impl TrialDecryptions {
                     let timestamp = compact_block.time as u64;
                     let config = config.clone();
 
-                    workers.push(tokio::spawn(async move {
+                    workers.push(executor::spawn(async move {
                         let Ok(fvk) = D::wc_to_fvk(&wc) else {
                             // skip any scanning if the wallet doesn't have viewing capability
                             return Ok::<_, String>(());
@@ -335,7 +333,7 @@ impl TrialDecryptions {
 
                         //TODO: Wrong. We don't have fvk import, all our keys are spending
                         let have_spending_key = true;
-                        let uri = bsync_data.read().await.uri().clone();
+                        let block_source = bsync_data.read().await.block_source();
 
                         // Get the witness for the note
                         let witness = bsync_data
@@ -343,7 +341,7 @@ impl TrialDecryptions {
                             .await
                             .block_data
                             .get_note_witness::<D>(
-                                uri,
+                                &block_source,
                                 height,
                                 transaction_num,
                                 i,
@@ -452,3 +450,136 @@ fn update_witnesses<D>(
         }
     }
 }
+
+/// Whether a compact transaction that hasn't been mined yet pays to one of our shielded
+/// addresses or spends one of our notes, i.e. whether it's worth downloading in full.
+pub(crate) fn mempool_transaction_involves_wallet(
+    compact_transaction: &CompactTx,
+    height: BlockHeight,
+    chain: ChainType,
+    wc: &WalletCapability,
+    transaction_metadata_set: &TransactionMetadataSet,
+) -> bool {
+    let spends_our_sapling_note = transaction_metadata_set
+        .get_nullifiers_of_unspent_sapling_notes()
+        .iter()
+        .any(|(nullifier, _, _)| {
+            compact_transaction
+                .spends
+                .iter()
+                .any(|spend| spend.nf[..] == nullifier.0[..])
+        });
+    let spends_our_orchard_note = transaction_metadata_set
+        .get_nullifiers_of_unspent_orchard_notes()
+        .iter()
+        .any(|(nullifier, _, _)| {
+            compact_transaction
+                .actions
+                .iter()
+                .any(|action| action.nullifier[..] == nullifier.to_bytes()[..])
+        });
+    if spends_our_sapling_note || spends_our_orchard_note {
+        return true;
+    }
+
+    SaplingIvk::try_from(wc).ok().map_or(false, |sapling_ivk| {
+        decrypts_any_output::<SaplingDomain<ChainType>>(
+            compact_transaction,
+            zcash_primitives::sapling::note_encryption::PreparedIncomingViewingKey::new(
+                &sapling_ivk,
+            ),
+            chain,
+            height,
+        )
+    }) || OrchardIvk::try_from(wc).ok().map_or(false, |orchard_ivk| {
+        decrypts_any_output::<OrchardDomain>(
+            compact_transaction,
+            orchard::keys::PreparedIncomingViewingKey::new(&orchard_ivk),
+            chain,
+            height,
+        )
+    })
+}
+
+fn decrypts_any_output<D>(
+    compact_transaction: &CompactTx,
+    ivk: D::IncomingViewingKey,
+    chain: ChainType,
+    height: BlockHeight,
+) -> bool
+where
+    D: DomainWalletExt,
+    <D as Domain>::Recipient: Recipient,
+    <D as Domain>::Note: PartialEq + Clone,
+{
+    let outputs = D::CompactOutput::from_compact_transaction(compact_transaction)
+        .iter()
+        .map(|output| (output.domain(chain, height), output.clone()))
+        .collect::<Vec<_>>();
+    zcash_note_encryption::batch::try_compact_note_decryption(&[ivk], &outputs)
+        .into_iter()
+        .any(|decrypted| decrypted.is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        compact_formats::CompactSaplingSpend, wallet::data::ReceivedSaplingNoteAndMetadata,
+    };
+    use zcash_primitives::{
+        sapling::{self, value::NoteValue, Rseed},
+        zip32::ExtendedSpendingKey,
+    };
+
+    #[test]
+    fn mempool_transactions_spending_our_notes_involve_the_wallet() {
+        let (_, address) = ExtendedSpendingKey::master(&[1; 32])
+            .to_diversifiable_full_viewing_key()
+            .default_address();
+        let note = sapling::Note::from_parts(
+            address,
+            NoteValue::from_raw(5_000),
+            Rseed::AfterZip212([0; 32]),
+        );
+        let txid = TxId::from_bytes([1; 32]);
+        let mut transaction = TransactionMetadata::new(BlockHeight::from_u32(10), 0, &txid, false);
+        transaction
+            .sapling_notes
+            .push(ReceivedSaplingNoteAndMetadata::from_parts(
+                *note.recipient().diversifier(),
+                note,
+                Position::from(0),
+                Some(sapling::Nullifier([11; 32])),
+                None,
+                None,
+                None,
+                false,
+                true,
+                0,
+            ));
+        let mut transactions = TransactionMetadataSet::new_treeless();
+        transactions.current.insert(txid, transaction);
+
+        // Without keys to decrypt with, only spends of our notes can give a transaction away
+        let wc = WalletCapability::default();
+        let involves_wallet = |compact_transaction: &CompactTx| {
+            mempool_transaction_involves_wallet(
+                compact_transaction,
+                BlockHeight::from_u32(11),
+                ChainType::Regtest,
+                &wc,
+                &transactions,
+            )
+        };
+        let spending = |nullifier: u8| CompactTx {
+            spends: vec![CompactSaplingSpend {
+                nf: vec![nullifier; 32],
+            }],
+            ..Default::default()
+        };
+        assert!(involves_wallet(&spending(11)));
+        assert!(!involves_wallet(&spending(12)));
+        assert!(!involves_wallet(&CompactTx::default()));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::executor::{self, JoinHandle};
 use crate::wallet::MemoDownloadOption;
 use crate::wallet::{
     data::{PoolNullifier, TransactionMetadata},
@@ -8,9 +9,9 @@ use std::sync::Arc;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use tokio::join;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot;
 use tokio::sync::{mpsc::unbounded_channel, RwLock};
-use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
 
 use zcash_primitives::consensus::BlockHeight;
 use zcash_primitives::transaction::TxId;
@@ -59,7 +60,7 @@ impl UpdateNotes {
 
         let (blocks_done_transmitter, blocks_done_receiver) = oneshot::channel::<u64>();
 
-        let h0: JoinHandle<Result<(), String>> = tokio::spawn(async move {
+        let h0: JoinHandle<Result<(), String>> = executor::spawn(async move {
             // First, wait for notification that the blocks are done loading, and get the earliest block from there.
             let earliest_block = blocks_done_receiver
                 .await
@@ -86,7 +87,7 @@ impl UpdateNotes {
         });
 
         let wallet_transactions = self.transaction_metadata_set.clone();
-        let h1 = tokio::spawn(async move {
+        let h1 = executor::spawn(async move {
             let mut workers = FuturesUnordered::new();
 
             // Receive Txns that are sent to the wallet. We need to update the notes for this.
@@ -95,7 +96,7 @@ impl UpdateNotes {
                 let wallet_transactions = wallet_transactions.clone();
                 let fetch_full_sender = fetch_full_sender.clone();
 
-                workers.push(tokio::spawn(async move {
+                workers.push(executor::spawn(async move {
                     // If this nullifier was spent at a future height, fetch the TxId at the height and process it
                     if let Some(spent_height) = bsync_data
                         .read()
@@ -165,7 +166,7 @@ impl UpdateNotes {
             //info!("Finished Note Update processing");
         });
 
-        let h = tokio::spawn(async move {
+        let h = executor::spawn(async move {
             let (r0, r1) = join!(h0, h1);
             r0.map_err(|e| format!("{}", e))??;
             r1.map_err(|e| format!("{}", e))
@@ -1,9 +1,22 @@
+pub mod args;
+pub mod results;
+
 use crate::wallet::keys::is_shielded_address;
-use crate::wallet::{MemoDownloadOption, Pool};
-use crate::{lightclient::LightClient, wallet::utils};
+use crate::wallet::{ExpiryDelta, MemoDownloadOption, Pool, TransactionFetchPrivacy};
+use crate::{
+    lightclient::{records, LightClient, SendError},
+    wallet::utils,
+};
+use args::{ArgKind, ArgSpec, Args, Subcommand};
 use indoc::indoc;
-use json::object;
+use json::{object, JsonValue};
 use lazy_static::lazy_static;
+use results::{
+    Checked, DefaultFee, ExportedUfvk, Height, PaidRow, ParsedAddress, ParsedViewKey,
+    PayoutSummary, ScheduleListing, ScheduleRun, ScheduledId, Sent, Succeeded, SyncStatus, Synced,
+    WalletFile, WalletKind,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::str::FromStr;
@@ -16,12 +29,307 @@ lazy_static! {
     static ref RT: Runtime = tokio::runtime::Runtime::new().unwrap();
 }
 
+/// Why a command failed. The codes are stable, so scripts can tell failures apart without
+/// reading the messages. Serialized as `{"code": ..., "message": ...}`, with the `command`
+/// too for an unknown command.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "ErrorFields", try_from = "ErrorFields")]
+pub enum CommandError {
+    /// There is no command by this name
+    UnknownCommand(String),
+    /// The arguments were missing or malformed. The message ends with the command's usage.
+    InvalidArguments(String),
+    /// The wallet couldn't do what was asked, e.g. spend more than it has
+    Wallet(String),
+    /// The lightwalletd server (or the price server) couldn't be reached, or turned us down
+    Server(String),
+}
+
+impl CommandError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CommandError::UnknownCommand(_) => "unknown_command",
+            CommandError::InvalidArguments(_) => "invalid_arguments",
+            CommandError::Wallet(_) => "wallet_error",
+            CommandError::Server(_) => "server_error",
+        }
+    }
+}
+
+/// A send the server couldn't be reached for, or turned down, may go through if tried again
+impl From<SendError> for CommandError {
+    fn from(error: SendError) -> Self {
+        match error {
+            SendError::Wallet(message) => CommandError::Wallet(message),
+            SendError::Server(message) => CommandError::Server(message),
+        }
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::UnknownCommand(command) => write!(
+                f,
+                "Unknown command : {}. Type 'help' for a list of commands",
+                command
+            ),
+            CommandError::InvalidArguments(message)
+            | CommandError::Wallet(message)
+            | CommandError::Server(message) => f.write_str(message),
+        }
+    }
+}
+
+/// A `CommandError` as it is serialized
+#[derive(Serialize, Deserialize)]
+struct ErrorFields {
+    code: String,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+}
+
+impl From<CommandError> for ErrorFields {
+    fn from(error: CommandError) -> Self {
+        ErrorFields {
+            code: error.code().to_string(),
+            message: error.to_string(),
+            command: match error {
+                CommandError::UnknownCommand(command) => Some(command),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl TryFrom<ErrorFields> for CommandError {
+    type Error = String;
+
+    fn try_from(fields: ErrorFields) -> Result<Self, Self::Error> {
+        match (fields.code.as_str(), fields.command) {
+            ("unknown_command", Some(command)) => Ok(CommandError::UnknownCommand(command)),
+            ("invalid_arguments", _) => Ok(CommandError::InvalidArguments(fields.message)),
+            ("wallet_error", _) => Ok(CommandError::Wallet(fields.message)),
+            ("server_error", _) => Ok(CommandError::Server(fields.message)),
+            (code, _) => Err(format!("Unknown command error code '{}'", code)),
+        }
+    }
+}
+
+/// What a command answered: its result, and the text people are shown
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommandOutput {
+    /// The result, serialized from its type in [`results`] or
+    /// [`records`](crate::lightclient::records). Results that have no type are the JSON the
+    /// command always printed.
+    pub result: serde_json::Value,
+    /// A message, or the result printed as the command always printed it
+    pub text: String,
+}
+
+impl CommandOutput {
+    /// A message, which is both the result and its text
+    pub fn message(message: impl Into<String>) -> Self {
+        let message = message.into();
+        CommandOutput {
+            result: message.clone().into(),
+            text: message,
+        }
+    }
+
+    /// A typed result, shown as `text`
+    pub fn shown_as<T: Serialize>(result: &T, text: String) -> Self {
+        CommandOutput {
+            result: serde_json::to_value(result).expect("Command results serialize to JSON"),
+            text,
+        }
+    }
+
+    /// A typed result, shown pretty printed with `indent`. Fields are printed in the order
+    /// they are declared in.
+    pub fn pretty<T: Serialize>(result: &T, indent: u16) -> Self {
+        let text = records::to_json(result).pretty(indent);
+        Self::shown_as(result, text)
+    }
+
+    /// A result that only exists as JSON, shown pretty printed
+    pub fn json(json: JsonValue) -> Self {
+        CommandOutput {
+            result: serde_json::from_str(&json.dump()).expect("The json crate writes valid JSON"),
+            text: json.pretty(2),
+        }
+    }
+
+    /// Reads the result back as its type
+    pub fn result_as<T: DeserializeOwned>(&self) -> Result<T, CommandError> {
+        serde_json::from_value(self.result.clone())
+            .map_err(|e| CommandError::Wallet(format!("Unexpected command result: {}", e)))
+    }
+
+    /// The result as a `JsonValue`, for callers that answer in JSON of their own
+    pub fn to_json(&self) -> JsonValue {
+        json::parse(&self.result.to_string()).expect("serde_json writes valid JSON")
+    }
+}
+
+/// Why a command failed, and the text people are shown
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandFailure {
+    pub error: CommandError,
+    pub text: String,
+}
+
+impl CommandFailure {
+    pub fn new(error: CommandError, text: impl Into<String>) -> Self {
+        CommandFailure {
+            error,
+            text: text.into(),
+        }
+    }
+
+    /// Shown as `{"error": ...}`, which is what the apps look for when a send or a new
+    /// address fails
+    pub fn json_error(error: CommandError) -> Self {
+        let text = object! { "error" => error.to_string() }.pretty(2);
+        CommandFailure { error, text }
+    }
+}
+
+/// Shown as the error's message
+impl From<CommandError> for CommandFailure {
+    fn from(error: CommandError) -> Self {
+        let text = error.to_string();
+        CommandFailure { error, text }
+    }
+}
+
+impl std::fmt::Display for CommandFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+pub type CommandResult = Result<CommandOutput, CommandFailure>;
+
+/// How command results are written out
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// For people, as each command has always printed its results and failures
+    #[default]
+    Text,
+    /// For scripts, one line each: `{"status": "ok", "result": ...}` or
+    /// `{"status": "error", "error": {"code": ..., "message": ...}}`
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Unknown output format '{}', use text or json", s)),
+        }
+    }
+}
+
+/// A result as written with `OutputFormat::Json`
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+enum JsonOutput {
+    Ok { result: serde_json::Value },
+    Error { error: CommandError },
+}
+
+pub fn render(result: &CommandResult, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => match result {
+            Ok(output) => output.text.clone(),
+            Err(failure) => failure.text.clone(),
+        },
+        OutputFormat::Json => serde_json::to_string(&match result {
+            Ok(output) => JsonOutput::Ok {
+                result: output.result.clone(),
+            },
+            Err(failure) => JsonOutput::Error {
+                error: failure.error.clone(),
+            },
+        })
+        .expect("Command results serialize to JSON"),
+    }
+}
+
+/// Reads back a result rendered with `OutputFormat::Json`
+pub fn parse_json_output(output: &str) -> Result<serde_json::Value, CommandError> {
+    match serde_json::from_str(output) {
+        Ok(JsonOutput::Ok { result }) => Ok(result),
+        Ok(JsonOutput::Error { error }) => Err(error),
+        Err(e) => Err(CommandError::Wallet(format!(
+            "Couldn't read command output: {}",
+            e
+        ))),
+    }
+}
+
 pub trait Command {
     fn help(&self) -> &'static str;
 
     fn short_help(&self) -> &'static str;
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String;
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult;
+
+    /// The arguments of a command that declares them, from which its arguments are parsed,
+    /// its help is completed and the daemon's named params are mapped
+    fn args(&self) -> &'static [ArgSpec] {
+        &[]
+    }
+
+    /// The subcommands of a command that has them, such as `schedule add`, each with its own
+    /// arguments. They take the place of `args`.
+    fn subcommands(&self) -> &'static [Subcommand] {
+        &[]
+    }
+
+    /// The help, followed by the declared arguments or subcommands
+    fn full_help(&self) -> String {
+        if !self.subcommands().is_empty() {
+            format!(
+                "{}\n\n{}\n",
+                self.help().trim_end(),
+                args::describe_subcommands(self.subcommands())
+            )
+        } else if self.args().is_empty() {
+            self.help().to_string()
+        } else {
+            format!(
+                "{}\n\n{}\n",
+                self.help().trim_end(),
+                args::describe(self.args())
+            )
+        }
+    }
+
+    /// Parses `args` by the declared arguments
+    fn parse_args(&self, args: &[&str]) -> Result<Args, CommandError> {
+        args::parse(self.args(), args).map_err(|e| self.usage_error(&e))
+    }
+
+    /// Parses `args` as one of the declared subcommands, given first, and its arguments
+    fn parse_subcommand(&self, args: &[&str]) -> Result<(&'static str, Args), CommandError> {
+        args::parse_subcommand(self.subcommands(), args).map_err(|e| self.usage_error(&e))
+    }
+
+    /// Says what was wrong with the arguments, followed by how to use the command. An empty
+    /// `reason` gives just the usage.
+    fn usage_error(&self, reason: &str) -> CommandError {
+        CommandError::InvalidArguments(if reason.is_empty() {
+            self.full_help()
+        } else {
+            format!("{}\n\n{}", reason, self.full_help())
+        })
+    }
 }
 
 pub trait ShortCircuitedCommand {
@@ -39,8 +347,8 @@ impl Command for GetVersionCommand {
         "Get verion of build code"
     }
 
-    fn exec(&self, _args: &[&str], _lightclient: &LightClient) -> String {
-        crate::git_description().to_string()
+    fn exec(&self, _args: &[&str], _lightclient: &LightClient) -> CommandResult {
+        Ok(CommandOutput::message(crate::git_description()))
     }
 }
 struct ChangeServerCommand {}
@@ -48,9 +356,6 @@ impl Command for ChangeServerCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Change the lightwalletd server to receive blockchain data from
-            Usage:
-            changeserver [server_uri]
-
             Example:
             changeserver https://mainnet.lightwalletd.com:9067
         "#}
@@ -60,17 +365,25 @@ impl Command for ChangeServerCommand {
         "Change lightwalletd server"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        match args.len() {
-            1 => match http::Uri::from_str(args[0]) {
-                Ok(uri) => {
-                    lightclient.set_server(uri);
-                    "server set"
-                }
-                Err(_) => "invalid server uri",
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::required(
+            "server_uri",
+            ArgKind::Text,
+            "The server, with its scheme and port",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
+        match http::Uri::from_str(args.get("server_uri").unwrap()) {
+            Ok(uri) => {
+                lightclient.set_server(uri);
+                Ok(CommandOutput::message("server set"))
             }
-            .to_string(),
-            _ => self.help().to_string(),
+            Err(_) => Err(CommandFailure::new(
+                self.usage_error("invalid server uri"),
+                "invalid server uri",
+            )),
         }
     }
 }
@@ -92,8 +405,9 @@ impl Command for GetBirthdayCommand {
         "Get wallet birthday."
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        RT.block_on(async move { lightclient.wallet.get_birthday().await.to_string() })
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let birthday = RT.block_on(lightclient.wallet.get_birthday());
+        Ok(CommandOutput::shown_as(&birthday, birthday.to_string()))
     }
 }
 
@@ -111,20 +425,25 @@ impl Command for WalletKindCommand {
         "Displays the kind of wallet currently loaded"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         RT.block_on(async move {
-            if lightclient.do_seed_phrase().await.is_ok() {
-                object! {"kind" => "Seeded"}.pretty(4)
+            let kind = if lightclient.do_seed_phrase().await.is_ok() {
+                WalletKind {
+                    kind: "Seeded".to_string(),
+                    transparent: None,
+                    sapling: None,
+                    orchard: None,
+                }
             } else {
                 let capability = lightclient.wallet.wallet_capability();
-                object! {
-                    "kind" => "Loaded from key",
-                    "transparent" => capability.transparent.kind_str(),
-                    "sapling" => capability.sapling.kind_str(),
-                    "orchard" => capability.orchard.kind_str(),
+                WalletKind {
+                    kind: "Loaded from key".to_string(),
+                    transparent: Some(capability.transparent.kind_str().to_string()),
+                    sapling: Some(capability.sapling.kind_str().to_string()),
+                    orchard: Some(capability.orchard.kind_str().to_string()),
                 }
-                .pretty(4)
-            }
+            };
+            Ok(CommandOutput::pretty(&kind, 4))
         })
     }
 }
@@ -137,23 +456,19 @@ impl Command for InterruptCommand {
     fn short_help(&self) -> &'static str {
         "Toggle the sync interrupt after batch flag."
     }
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        match args.len() {
-            1 => RT.block_on(async move {
-                match args[0] {
-                    "true" => {
-                        lightclient.interrupt_sync_after_batch(true).await;
-                        "true".to_string()
-                    }
-                    "false" => {
-                        lightclient.interrupt_sync_after_batch(false).await;
-                        "false".to_string()
-                    }
-                    _ => self.help().to_string(),
-                }
-            }),
-            _ => self.help().to_string(),
-        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::required(
+            "interrupt",
+            ArgKind::Bool,
+            "Whether a running sync stops after its current batch",
+        )]
+    }
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let interrupt = self.parse_args(args)?.bool("interrupt");
+        RT.block_on(async move {
+            lightclient.interrupt_sync_after_batch(interrupt).await;
+            Ok(CommandOutput::shown_as(&interrupt, interrupt.to_string()))
+        })
     }
 }
 
@@ -162,9 +477,6 @@ impl Command for ParseAddressCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Parse an address
-            Usage:
-            parse_address [address]
-
             Example
             parse_address tmSwk8bjXdCgBvpS8Kybk5nUyE21QFcDqre
         "#}
@@ -174,59 +486,57 @@ impl Command for ParseAddressCommand {
         "Parse an address"
     }
 
-    fn exec(&self, args: &[&str], _lightclient: &LightClient) -> String {
-        match args.len() {
-            1 => json::stringify_pretty(
-                [
-                    zingoconfig::ChainType::Mainnet,
-                    zingoconfig::ChainType::Testnet,
-                    zingoconfig::ChainType::Regtest,
-                ]
-                .iter()
-                .find_map(|chain| RecipientAddress::decode(chain, args[0]).zip(Some(chain)))
-                .map(|(recipient_address, chain_name)| {
-                    let chain_name_string = match chain_name {
-                        zingoconfig::ChainType::Mainnet => "main",
-                        zingoconfig::ChainType::Testnet => "test",
-                        zingoconfig::ChainType::Regtest => "regtest",
-                        zingoconfig::ChainType::FakeMainnet => unreachable!(),
-                    };
-
-                    match recipient_address {
-                        RecipientAddress::Shielded(_) => object! {
-                            "status" => "success",
-                            "chain_name" => chain_name_string,
-                            "address_kind" => "sapling",
-                        },
-                        RecipientAddress::Transparent(_) => object! {
-                            "status" => "success",
-                            "chain_name" => chain_name_string,
-                            "address_kind" => "transparent",
-                        },
-                        RecipientAddress::Unified(ua) => {
-                            let mut receivers_available = vec![];
-                            if ua.orchard().is_some() {
-                                receivers_available.push("orchard")
-                            }
-                            if ua.sapling().is_some() {
-                                receivers_available.push("sapling")
-                            }
-                            if ua.transparent().is_some() {
-                                receivers_available.push("transparent")
-                            }
-                            object! {
-                                "status" => "success",
-                                "chain_name" => chain_name_string,
-                                "address_kind" => "unified",
-                                "receivers_available" => receivers_available,
-                            }
-                        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::required(
+            "address",
+            ArgKind::Text,
+            "An address of any chain",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], _lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
+        let address = args.get("address").unwrap();
+        let parsed = [
+            zingoconfig::ChainType::Mainnet,
+            zingoconfig::ChainType::Testnet,
+            zingoconfig::ChainType::Regtest,
+        ]
+        .iter()
+        .find_map(|chain| RecipientAddress::decode(chain, address).zip(Some(chain)))
+        .map(|(recipient_address, chain_name)| {
+            let chain_name_string = match chain_name {
+                zingoconfig::ChainType::Mainnet => "main",
+                zingoconfig::ChainType::Testnet => "test",
+                zingoconfig::ChainType::Regtest => "regtest",
+                zingoconfig::ChainType::FakeMainnet => unreachable!(),
+            };
+
+            let (address_kind, receivers_available) = match recipient_address {
+                RecipientAddress::Shielded(_) => ("sapling", None),
+                RecipientAddress::Transparent(_) => ("transparent", None),
+                RecipientAddress::Unified(ua) => {
+                    let mut receivers_available = vec![];
+                    if ua.orchard().is_some() {
+                        receivers_available.push("orchard".to_string())
                     }
-                }),
-                4,
-            ),
-            _ => self.help().to_string(),
-        }
+                    if ua.sapling().is_some() {
+                        receivers_available.push("sapling".to_string())
+                    }
+                    if ua.transparent().is_some() {
+                        receivers_available.push("transparent".to_string())
+                    }
+                    ("unified", Some(receivers_available))
+                }
+            };
+            ParsedAddress {
+                status: "success".to_string(),
+                chain_name: chain_name_string.to_string(),
+                address_kind: address_kind.to_string(),
+                receivers_available,
+            }
+        });
+        Ok(CommandOutput::pretty(&parsed, 4))
     }
 }
 
@@ -235,9 +545,6 @@ impl Command for ParseViewKeyCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Parse a View Key
-            Usage:
-            parse_viewkey viewing_key
-
             Example
             parse_viewkey uviewregtest1l6s73mncrefycjhksvcp3zd6x2rpwddewv852ms8w0j828wu77h8v07fs6ph68kyp0ujwk4qmr3w4v9js4mr3ufqyasr0sddgumzyjamcgreda44kxtv4ar084szez337ld58avd9at4r5lptltgkn6uayzd055upf8cnlkarnxp69kz0vzelfww08xxhm0q0azdsplxff0mn2yyve88jyl8ujfau66pnc37skvl9528zazztf6xgk8aeewswjg4eeahpml77cxh57spgywdsc99h99twmp8sqhmp7g78l3g90equ2l4vh9vy0va6r8p568qr7nm5l5y96qgwmw9j2j788lalpeywy0af86krh4td69xqrrye6dvfx0uff84s3pm50kqx3tg3ktx88j2ujswe25s7pqvv3w4x382x07w0dp5gguqu757wlyf80f5nu9uw7wqttxmvrjhkl22x43de960c7kt97ge0dkt52j7uckht54eq768
         "#}
@@ -247,52 +554,52 @@ impl Command for ParseViewKeyCommand {
         "Parse a view_key."
     }
 
-    fn exec(&self, args: &[&str], _lightclient: &LightClient) -> String {
-        match args.len() {
-            1 => {
-                json::stringify_pretty(
-                    match Ufvk::decode(args[0]) {
-                        Ok((network, ufvk)) => {
-                            let mut pools_available = vec![];
-                            for fvk in ufvk.items_as_parsed() {
-                                match fvk {
-                            zcash_address::unified::Fvk::Orchard(_) => {
-                                pools_available.push("orchard")
-                            }
-                            zcash_address::unified::Fvk::Sapling(_) => {
-                                pools_available.push("sapling")
-                            }
-                            zcash_address::unified::Fvk::P2pkh(_) => {
-                                pools_available.push("transparent")
-                            }
-                            zcash_address::unified::Fvk::Unknown { .. } => pools_available
-                                .push("Unknown future protocol. Perhaps you're using old software"),
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::required(
+            "viewing_key",
+            ArgKind::Text,
+            "A unified full viewing key",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], _lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
+        let parsed = match Ufvk::decode(args.get("viewing_key").unwrap()) {
+            Ok((network, ufvk)) => {
+                let mut pools_available = vec![];
+                for fvk in ufvk.items_as_parsed() {
+                    match fvk {
+                        zcash_address::unified::Fvk::Orchard(_) => pools_available.push("orchard"),
+                        zcash_address::unified::Fvk::Sapling(_) => pools_available.push("sapling"),
+                        zcash_address::unified::Fvk::P2pkh(_) => {
+                            pools_available.push("transparent")
                         }
-                            }
-                            object! {
-                                "status" => "success",
-                                "chain_name" => match network {
-                                    zcash_address::Network::Main => "main",
-                                    zcash_address::Network::Test => "test",
-                                    zcash_address::Network::Regtest => "regtest",
-                                },
-                                "address_kind" => "ufvk",
-                                "pools_available" => pools_available,
-                            }
-                        }
-                        Err(_) => {
-                            object! {
-                                "status" => "Invalid viewkey",
-                                "chain_name" => json::JsonValue::Null,
-                                "address_kind" => json::JsonValue::Null
-                            }
+                        zcash_address::unified::Fvk::Unknown { .. } => pools_available
+                            .push("Unknown future protocol. Perhaps you're using old software"),
+                    }
+                }
+                ParsedViewKey {
+                    status: "success".to_string(),
+                    chain_name: Some(
+                        match network {
+                            zcash_address::Network::Main => "main",
+                            zcash_address::Network::Test => "test",
+                            zcash_address::Network::Regtest => "regtest",
                         }
-                    },
-                    4,
-                )
+                        .to_string(),
+                    ),
+                    address_kind: Some("ufvk".to_string()),
+                    pools_available: Some(pools_available.into_iter().map(String::from).collect()),
+                }
             }
-            _ => self.help().to_string(),
-        }
+            Err(_) => ParsedViewKey {
+                status: "Invalid viewkey".to_string(),
+                chain_name: None,
+                address_kind: None,
+                pools_available: None,
+            },
+        };
+        Ok(CommandOutput::pretty(&parsed, 4))
     }
 }
 
@@ -304,6 +611,10 @@ impl Command for SyncCommand {
             Usage:
             sync
 
+            Once caught up, the wallet's transparent outputs are compared with the server's
+            UTXO set and balance. Missing outputs are imported, and any spent by a transaction
+            the wallet never saw are listed under "transparent_check".
+
         "#}
     }
 
@@ -311,12 +622,13 @@ impl Command for SyncCommand {
         "Download CompactBlocks and sync to the server"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         RT.block_on(async move {
-            match lightclient.do_sync(true).await {
-                Ok(j) => j.to_json().pretty(2),
-                Err(e) => e,
-            }
+            lightclient
+                .do_sync(true)
+                .await
+                .map(|synced| CommandOutput::pretty(&Synced::from(&synced), 2))
+                .map_err(|e| CommandError::Server(e).into())
         })
     }
 }
@@ -329,6 +641,9 @@ impl Command for SyncStatusCommand {
             Usage:
             syncstatus
 
+            If this sync picked up from an earlier, interrupted one, the block ranges it had
+            already covered are listed under "resumed_ranges".
+
         "#}
     }
 
@@ -336,35 +651,13 @@ impl Command for SyncStatusCommand {
         "Get the sync status of the wallet"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         RT.block_on(async move {
-            let status = lightclient.do_sync_status().await;
-
-            let o = if status.in_progress {
-                object! {
-                    "sync_id" => status.sync_id,
-                    "in_progress" => status.in_progress,
-                    "last_error" => status.last_error,
-                    "start_block" => status.start_block,
-                    "end_block" => status.end_block,
-                    "synced_blocks" => status.blocks_done,
-                    "trial_decryptions_blocks" => status.trial_dec_done,
-                    "txn_scan_blocks" => status.txn_scan_done,
-                    "witnesses_updated" => *status.witnesses_updated.values().min().unwrap_or(&0),
-                    "total_blocks" => status.blocks_total,
-                    "batch_num" => status.batch_num,
-                    "batch_total" => status.batch_total,
-                    "sync_interrupt" => lightclient.get_sync_interrupt().await
-                }
-            } else {
-                object! {
-                    "sync_id" => status.sync_id,
-                    "in_progress" => status.in_progress,
-                    "last_error" => status.last_error,
-
-                }
-            };
-            o.pretty(2)
+            let status = SyncStatus::new(
+                &lightclient.do_sync_status().await,
+                lightclient.get_sync_interrupt().await,
+            );
+            Ok(CommandOutput::pretty(&status, 2))
         })
     }
 }
@@ -383,12 +676,13 @@ impl Command for SendProgressCommand {
         "Get the progress of any send transactions that are currently computing"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         RT.block_on(async move {
-            match lightclient.do_send_progress().await {
-                Ok(p) => p.to_json().pretty(2),
-                Err(e) => e,
-            }
+            lightclient
+                .do_send_progress()
+                .await
+                .map(|p| CommandOutput::json(p.to_json()))
+                .map_err(|e| CommandError::Wallet(e).into())
         })
     }
 }
@@ -410,12 +704,51 @@ impl Command for RescanCommand {
         "Rescan the wallet, downloading and scanning all blocks and transactions"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         RT.block_on(async move {
-            match lightclient.do_rescan().await {
-                Ok(j) => j.to_json().pretty(2),
-                Err(e) => e,
-            }
+            lightclient
+                .do_rescan()
+                .await
+                .map(|synced| CommandOutput::pretty(&Synced::from(&synced), 2))
+                .map_err(|e| CommandError::Server(e).into())
+        })
+    }
+}
+
+struct CheckCommand {}
+impl Command for CheckCommand {
+    fn help(&self) -> &'static str {
+        indoc! {r#"
+            Check the wallet for internal inconsistencies, and optionally repair them
+            Verifies that every spent note is spent by a known transaction listing its nullifier, that
+            change flags are consistent, that the witness trees match the chain's tree state at the anchor
+            height, and that unspent transparent outputs are still unspent according to the server.
+            With repair, inconsistencies that can be fixed from the wallet's own data
+            are fixed and the wallet is saved. The rest are reported, and usually need a rescan.
+            Checks that need the server and couldn't get an answer from it are listed under "unchecked".
+        "#}
+    }
+
+    fn short_help(&self) -> &'static str {
+        "Check the wallet for inconsistencies, and optionally repair them"
+    }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::flag(
+            "repair",
+            "Fix what can be fixed from the wallet's own data",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let repair = self.parse_args(args)?.bool("repair");
+
+        RT.block_on(async move {
+            lightclient
+                .do_check(repair)
+                .await
+                .map(|report| CommandOutput::pretty(&Checked::from(&report), 2))
+                .map_err(|e| CommandError::Wallet(e).into())
         })
     }
 }
@@ -436,12 +769,11 @@ impl Command for ClearCommand {
         "Clear the wallet state, rolling back the wallet to an empty state."
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         RT.block_on(async move {
             lightclient.clear_state().await;
 
-            let result = object! { "result" => "success" };
-            result.pretty(2)
+            Ok(CommandOutput::pretty(&Succeeded::new(), 2))
         })
     }
 }
@@ -451,10 +783,7 @@ impl Command for HelpCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             List all available commands
-            Usage:
-            help [command_name]
-
-            If no "command_name" is specified, a list of all available commands is returned
+            Without a command_name, a list of all available commands is returned
             Example:
             help send
 
@@ -465,25 +794,32 @@ impl Command for HelpCommand {
         "Lists all available commands"
     }
 
-    fn exec(&self, args: &[&str], _: &LightClient) -> String {
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::optional(
+            "command_name",
+            ArgKind::Text,
+            "The command to show the help of",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], _: &LightClient) -> CommandResult {
         let mut responses = vec![];
 
         // Print a list of all commands
-        match args.len() {
-            0 => {
+        match self.parse_args(args)?.get("command_name") {
+            None => {
                 responses.push("Available commands:".to_string());
                 get_commands().iter().for_each(|(cmd, obj)| {
                     responses.push(format!("{} - {}", cmd, obj.short_help()));
                 });
 
                 responses.sort();
-                responses.join("\n")
+                Ok(CommandOutput::message(responses.join("\n")))
             }
-            1 => match get_commands().get(args[0]) {
-                Some(cmd) => cmd.help().to_string(),
-                None => format!("Command {} not found", args[0]),
+            Some(command_name) => match get_commands().get(command_name) {
+                Some(cmd) => Ok(CommandOutput::message(cmd.full_help())),
+                None => Err(CommandError::UnknownCommand(command_name.to_string()).into()),
             },
-            _ => self.help().to_string(),
         }
     }
 }
@@ -503,7 +839,7 @@ impl ShortCircuitedCommand for HelpCommand {
                 responses.join("\n")
             }
             1 => match get_commands().get(args[0].as_str()) {
-                Some(cmd) => cmd.help().to_string(),
+                Some(cmd) => cmd.full_help(),
                 None => format!("Command {} not found", args[0]),
             },
             _ => panic!("Unexpected number of parameters."),
@@ -525,8 +861,41 @@ impl Command for InfoCommand {
         "Get the lightwalletd server's info"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        RT.block_on(async move { lightclient.do_info().await })
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
+        match RT.block_on(lightclient.server_info()) {
+            Ok(info) => Ok(CommandOutput::shown_as(
+                &info,
+                JsonValue::from(info.clone()).pretty(2),
+            )),
+            Err(e) => Err(CommandError::Server(e).into()),
+        }
+    }
+}
+
+struct ServersCommand {}
+impl Command for ServersCommand {
+    fn help(&self) -> &'static str {
+        indoc! {r#"
+            Health check every configured lightwalletd server and compare their chain tips.
+            A server whose tip is too far behind (or ahead of) the others is reported as
+            "lagging" (or "ahead") and won't be synced from while a healthy server is available.
+            Usage:
+            servers
+
+        "#}
+    }
+
+    fn short_help(&self) -> &'static str {
+        "Show the status of all configured lightwalletd servers"
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
+        RT.block_on(async move {
+            Ok(CommandOutput::pretty(
+                &lightclient.do_server_statuses().await,
+                2,
+            ))
+        })
     }
 }
 
@@ -546,8 +915,13 @@ impl Command for UpdateCurrentPriceCommand {
         "Get the latest ZEC price in the wallet's currency (USD)"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        RT.block_on(async move { lightclient.update_current_price().await })
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
+        // The price, or the reason it couldn't be fetched
+        let price = RT.block_on(async move { lightclient.update_current_price().await });
+        match price.parse::<f64>() {
+            Ok(p) => Ok(CommandOutput::shown_as(&p, price)),
+            Err(_) => Err(CommandError::Server(price).into()),
+        }
     }
 }
 
@@ -560,6 +934,8 @@ impl Command for BalanceCommand {
             balance
 
             Transparent and Shielded balances, along with the addresses they belong to are displayed
+            Each pool also shows the value it has pending in the mempool: incoming (including the change
+            of pending sends) and outgoing (notes being spent by pending sends)
         "#}
     }
 
@@ -567,8 +943,12 @@ impl Command for BalanceCommand {
         "Show the current ZEC balance in the wallet"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        RT.block_on(async move { lightclient.do_balance().await.to_json().pretty(2) })
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let balance = RT.block_on(lightclient.do_balance());
+        Ok(CommandOutput::shown_as(
+            &balance,
+            balance.to_json().pretty(2),
+        ))
     }
 }
 
@@ -587,8 +967,12 @@ impl Command for AddressCommand {
         "List all addresses in the wallet"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        RT.block_on(async move { lightclient.do_addresses().await.pretty(2) })
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let addresses = RT.block_on(lightclient.list_addresses());
+        Ok(CommandOutput::shown_as(
+            &addresses,
+            records_text(&addresses),
+        ))
     }
 }
 
@@ -610,18 +994,21 @@ impl Command for ExportUfvkCommand {
         "Export full viewing key for wallet addresses"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         let ufvk_res = lightclient.wallet.transaction_context.key.ufvk();
         match ufvk_res {
             Ok(ufvk) => {
                 use zcash_address::unified::Encoding as _;
-                object! {
-                    "ufvk" => ufvk.encode(&lightclient.config().chain.to_zcash_address_network()),
-                    "birthday" => RT.block_on(lightclient.wallet.get_birthday())
-                }
-                .pretty(2)
+                let exported = ExportedUfvk {
+                    ufvk: ufvk.encode(&lightclient.config().chain.to_zcash_address_network()),
+                    birthday: RT.block_on(lightclient.wallet.get_birthday()),
+                };
+                Ok(CommandOutput::pretty(&exported, 2))
             }
-            Err(e) => format!("Error: {e}"),
+            Err(e) => Err(CommandFailure::new(
+                CommandError::Wallet(e.to_string()),
+                format!("Error: {e}"),
+            )),
         }
     }
 }
@@ -631,9 +1018,6 @@ impl Command for ShieldCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Shield all your transparent and/or orchard funds
-            Usage:
-            shield ['transparent' or 'sapling' or 'all'] [optional address]
-
             NOTE: The fee required to send this transaction (currently ZEC 0.0001) is additionally deducted from your balance.
             Example:
             shield all
@@ -645,32 +1029,37 @@ impl Command for ShieldCommand {
         "Shield your transparent and/or sapling ZEC into the orchard pool"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.is_empty() || args.len() > 2 {
-            return self.help().to_string();
-        }
-        let pools_to_shield: &[Pool] = match args[0] {
-            "transparent" => &[Pool::Transparent],
-            "sapling" => &[Pool::Sapling],
-            "all" => &[Pool::Sapling, Pool::Transparent],
-            _ => return self.help().to_string(),
-        };
-        // Parse the address or amount
-        let address = if args.len() == 2 {
-            Some(args[1].to_string())
-        } else {
-            None
+    fn args(&self) -> &'static [ArgSpec] {
+        &[
+            ArgSpec::required(
+                "pools",
+                ArgKind::Choice(&["transparent", "sapling", "all"]),
+                "The pools to shield from",
+            ),
+            ArgSpec::optional(
+                "address",
+                ArgKind::Text,
+                "Where to shield to, instead of the wallet's own orchard address",
+            ),
+        ]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
+        let pools_to_shield: &[Pool] = match args.get("pools") {
+            Some("transparent") => &[Pool::Transparent],
+            Some("sapling") => &[Pool::Sapling],
+            _ => &[Pool::Sapling, Pool::Transparent],
         };
+        let address = args.get("address").map(String::from);
         RT.block_on(async move {
-            match lightclient.do_shield(pools_to_shield, address).await {
-                Ok(transaction_id) => {
-                    object! { "txid" => transaction_id }
-                }
-                Err(e) => {
-                    object! { "error" => e }
-                }
+            match lightclient
+                .do_shield_with_expiry(pools_to_shield, address, None)
+                .await
+            {
+                Ok(txid) => Ok(CommandOutput::pretty(&Sent { txid }, 2)),
+                Err(e) => Err(CommandFailure::json_error(e.into())),
             }
-            .pretty(2)
         })
     }
 }
@@ -680,9 +1069,7 @@ impl Command for EncryptMessageCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Encrypt a memo to be sent to a z-address offline
-            Usage:
-            encryptmessage <address> "memo"
-            OR
+            The address and memo can also be given as a single JSON argument:
             encryptmessage "{'address': <address>, 'memo': <memo>}"
 
             NOTE: This command only returns the encrypted payload. It does not broadcast it. You are expected to send the encrypted payload to the recipient offline
@@ -696,54 +1083,48 @@ impl Command for EncryptMessageCommand {
         "Encrypt a memo to be sent to a z-address offline"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.is_empty() || args.len() > 3 {
-            return self.help().to_string();
-        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[
+            ArgSpec::required(
+                "address",
+                ArgKind::Text,
+                "The shielded address to encrypt to",
+            ),
+            ArgSpec::required("memo", ArgKind::Text, "The memo. Hex if it starts with 0x"),
+        ]
+    }
 
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
         // Check for a single argument that can be parsed as JSON
-        let (to, memo) = if args.len() == 1 {
-            let arg_list = args[0];
-            let j = match json::parse(arg_list) {
+        let (to, memo) = if let Some(arg) = args::single(args) {
+            let j = match json::parse(arg) {
                 Ok(j) => j,
                 Err(e) => {
-                    let es = format!("Couldn't understand JSON: {}", e);
-                    return format!("{}\n{}", es, self.help());
+                    return Err(self
+                        .usage_error(&format!("Couldn't understand JSON: {}", e))
+                        .into());
                 }
             };
-
-            if !j.has_key("address") || !j.has_key("memo") {
-                let es = "Need 'address' and 'memo'\n".to_string();
-                return format!("{}\n{}", es, self.help());
-            }
-
-            let memo = utils::interpret_memo_string(j["memo"].as_str().unwrap().to_string());
-            if memo.is_err() {
-                return format!("{}\n{}", memo.err().unwrap(), self.help());
+            match (j["address"].as_str(), j["memo"].as_str()) {
+                (Some(to), Some(memo)) => (to.to_string(), memo.to_string()),
+                _ => return Err(self.usage_error("Need 'address' and 'memo'").into()),
             }
-            let to = j["address"].as_str().unwrap().to_string();
-
-            (to, memo.unwrap())
-        } else if args.len() == 2 {
-            let to = args[0].to_string();
-
-            let memo = utils::interpret_memo_string(args[1].to_string());
-            if memo.is_err() {
-                return format!("{}\n{}", memo.err().unwrap(), self.help());
-            }
-
-            (to, memo.unwrap())
         } else {
-            return format!(
-                "Wrong number of arguments. Was expecting 1 or 2\n{}",
-                self.help()
-            );
+            let args = self.parse_args(args)?;
+            (
+                args.get("address").unwrap().to_string(),
+                args.get("memo").unwrap().to_string(),
+            )
         };
 
+        let memo = utils::interpret_memo_string(memo).map_err(|e| self.usage_error(&e))?;
         if let Ok(m) = memo.try_into() {
-            lightclient.do_encrypt_message(to, m).pretty(2)
+            Ok(CommandOutput::json(lightclient.do_encrypt_message(to, m)))
         } else {
-            "Couldn't encode memo".to_string()
+            Err(CommandFailure::new(
+                self.usage_error("Couldn't encode memo"),
+                "Couldn't encode memo",
+            ))
         }
     }
 }
@@ -753,9 +1134,6 @@ impl Command for DecryptMessageCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Attempt to decrypt a message with all the view keys in the wallet.
-            Usage:
-            decryptmessage "encrypted_message_base64"
-
             Example:
             decryptmessage RW5jb2RlIGFyYml0cmFyeSBvY3RldHMgYXMgYmFzZTY0LiBSZXR1cm5zIGEgU3RyaW5nLg==
 
@@ -766,16 +1144,25 @@ impl Command for DecryptMessageCommand {
         "Attempt to decrypt a message with all the view keys in the wallet."
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() != 1 {
-            return self.help().to_string();
-        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::required(
+            "encrypted_message_base64",
+            ArgKind::Text,
+            "The payload of encryptmessage",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let message = self
+            .parse_args(args)?
+            .get("encrypted_message_base64")
+            .unwrap()
+            .to_string();
 
         RT.block_on(async move {
-            lightclient
-                .do_decrypt_message(args[0].to_string())
-                .await
-                .pretty(2)
+            Ok(CommandOutput::json(
+                lightclient.do_decrypt_message(message).await,
+            ))
         })
     }
 }
@@ -785,14 +1172,13 @@ impl Command for SendCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Send ZEC to a given address(es)
-            Usage:
-            send <address> <amount in zatoshis> "optional_memo"
-            OR
+            Several addresses are paid at once with a single JSON argument instead:
             send '[{'address': <address>, 'amount': <amount in zatoshis>, 'memo': <optional memo>}, ...]'
 
             NOTE: The fee required to send this transaction (currently ZEC 0.0001) is additionally deducted from your balance.
             Example:
             send ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d 200000 "Hello from the command line"
+            send ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d 200000 --expiry none
 
         "#}
     }
@@ -801,84 +1187,88 @@ impl Command for SendCommand {
         "Send ZEC to the given address"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+    fn args(&self) -> &'static [ArgSpec] {
+        &[
+            ArgSpec::required("address", ArgKind::Text, "The address to send to"),
+            ArgSpec::required("amount", ArgKind::Zatoshis, "The amount to send"),
+            ArgSpec::optional(
+                "memo",
+                ArgKind::Text,
+                "A memo, for shielded addresses. Hex if it starts with 0x",
+            ),
+            ArgSpec::optional(
+                "expiry",
+                ArgKind::Text,
+                "Blocks until it expires, default or none, instead of send_expiry",
+            ),
+        ]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
         // Parse the args. There are two argument types.
-        // 1 - A set of 2(+1 optional) arguments for a single address send representing address, value, memo?
+        // 1 - The declared arguments, for a single address send representing address, value, memo?
         // 2 - A single argument in the form of a JSON string that is "[{address: address, value: value, memo: memo},...]"
-        if args.is_empty() || args.len() > 3 {
-            return self.help().to_string();
-        }
-
         RT.block_on(async move {
             // Check for a single argument that can be parsed as JSON
-            let send_args = if args.len() == 1 {
-                let arg_list = args[0];
-
+            let (send_args, expiry) = if let Some(arg_list) = args::single(args) {
                 let json_args = match json::parse(arg_list) {
                     Ok(j) => j,
                     Err(e) => {
-                        let es = format!("Couldn't understand JSON: {}", e);
-                        return format!("{}\n{}", es, self.help());
+                        return Err(self
+                            .usage_error(&format!("Couldn't understand JSON: {}", e))
+                            .into());
                     }
                 };
 
                 if !json_args.is_array() {
-                    return format!("Couldn't parse argument as array\n{}", self.help());
+                    return Err(self.usage_error("Couldn't parse argument as array").into());
                 }
 
-                let fee = u64::from(MINIMUM_FEE);
                 let maybe_send_args = json_args
                     .members()
                     .map(|j| {
                         if !j.has_key("address") || !j.has_key("amount") {
-                            Err("Need 'address' and 'amount'\n".to_string())
+                            Err("Need 'address' and 'amount'".to_string())
                         } else {
-                            let amount = Some(j["amount"].as_u64().unwrap());
-
-                            match amount {
-                                Some(amt) => Ok((
-                                    j["address"].as_str().unwrap().to_string(),
+                            match (j["address"].as_str(), j["amount"].as_u64()) {
+                                (Some(address), Some(amt)) => Ok((
+                                    address.to_string(),
                                     amt,
                                     j["memo"].as_str().map(|s| s.to_string()),
                                 )),
-                                None => Err(format!(
-                                    "Not enough in wallet to pay transaction fee of {}",
-                                    fee
-                                )),
+                                _ => Err(
+                                    "'address' must be a string and 'amount' a whole number of zatoshis"
+                                        .to_string(),
+                                ),
                             }
                         }
                     })
                     .collect::<Result<Vec<(String, u64, Option<String>)>, String>>();
 
-                match maybe_send_args {
-                    Ok(a) => a.clone(),
+                let send_args = match maybe_send_args {
+                    Ok(a) => a,
                     Err(s) => {
-                        return format!("Error: {}\n{}", s, self.help());
+                        return Err(self.usage_error(&s).into());
                     }
-                }
-            } else if args.len() == 2 || args.len() == 3 {
-                let address = args[0].to_string();
-
-                // Make sure we can parse the amount
-                let value = match args[1].parse::<u64>() {
-                    Ok(amt) => amt,
-                    Err(e) => return format!("Couldn't parse amount: {}", e),
-                };
-
-                let memo = if args.len() == 3 {
-                    Some(args[2].to_string())
-                } else {
-                    None
                 };
+                (send_args, None)
+            } else {
+                let args = self.parse_args(args)?;
+                let address = args.get("address").unwrap().to_string();
+                let value = args.u64("amount").unwrap();
+                let memo = args.get("memo").map(String::from);
+                let expiry = args
+                    .get("expiry")
+                    .map(|value| value.parse::<ExpiryDelta>().map_err(|e| self.usage_error(&e)))
+                    .transpose()?;
 
                 // Memo has to be None if not sending to a shielded address
                 if memo.is_some() && !is_shielded_address(&address, &lightclient.config) {
-                    return format!("Can't send a memo to the non-shielded address {}", address);
+                    let e = format!("Can't send a memo to the non-shielded address {}", address);
+                    return Err(CommandFailure::new(self.usage_error(&e), e));
                 }
 
-                vec![(args[0].to_string(), value, memo)]
-            } else {
-                return self.help().to_string();
+                (vec![(address, value, memo)], expiry)
             };
 
             // Convert to the right format.
@@ -905,57 +1295,249 @@ impl Command for SendCommand {
                 })
                 .collect::<Vec<_>>();
             if let Some(e) = error {
-                return e;
+                return Err(CommandFailure::new(self.usage_error(&e), e));
             }
 
-            match lightclient.do_send(tos).await {
-                Ok(transaction_id) => {
-                    object! { "txid" => transaction_id }
-                }
-                Err(e) => {
-                    object! { "error" => e }
-                }
+            match lightclient.do_send_with_expiry(tos, expiry).await {
+                Ok(txid) => Ok(CommandOutput::pretty(&Sent { txid }, 2)),
+                Err(e) => Err(CommandFailure::json_error(e.into())),
             }
-            .pretty(2)
         })
     }
 }
 
-fn wallet_saver(lightclient: &LightClient) -> String {
-    RT.block_on(async move {
-        match lightclient.do_save().await {
-            Ok(_) => {
-                let r = object! { "result" => "success",
-                "wallet_path" => lightclient.config.get_wallet_path().to_str().unwrap() };
-                r.pretty(2)
+struct SendManyCommand {}
+impl Command for SendManyCommand {
+    fn help(&self) -> &'static str {
+        indoc! {r#"
+            Pay many recipients from a CSV file of address,amount,memo rows
+            Amounts are in zatoshis, and memos are optional. A first row starting with 'address' is a header.
+            Every row is checked before anything is sent. The payouts are split into transactions that each stay
+            within zcashd's limit of 50 unpaid ZIP-317 actions, counting the notes they spend and the change, and
+            each paying the default fee. Each transaction is paid from notes the earlier ones leave.
+            With --dry-run, shows the transactions that would be sent without sending them.
+            Otherwise, reports the txid each row was paid in, or why it wasn't paid. A failed transaction
+            stops the payout, and the rows after it are reported as not sent.
+            Example:
+            sendmany --file payroll.csv --dry-run
+
+        "#}
+    }
+
+    fn short_help(&self) -> &'static str {
+        "Pay many recipients from a CSV file"
+    }
+
+    fn args(&self) -> &'static [ArgSpec] {
+        &[
+            ArgSpec::required("file", ArgKind::Text, "The CSV file to pay"),
+            ArgSpec::flag("dry_run", "Only show the transactions that would be sent"),
+        ]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
+        let path = args.get("file").unwrap();
+        let dry_run = args.bool("dry_run");
+
+        let csv = std::fs::read(path).map_err(|e| {
+            CommandError::InvalidArguments(format!("Couldn't read {}: {}", path, e))
+        })?;
+        let payouts = crate::lightclient::payouts::read_payouts(&csv, &lightclient.config.chain)
+            .map_err(CommandError::InvalidArguments)?;
+
+        RT.block_on(async move {
+            let plan = lightclient.plan_payouts(payouts).await;
+            let mut summary = PayoutSummary::new(&plan, dry_run);
+            if dry_run {
+                return Ok(CommandOutput::pretty(&summary, 2));
             }
-            Err(e) => {
-                let r = object! {
-                    "result" => "error",
-                    "error" => e
-                };
-                r.pretty(2)
+            if let Some(i) = plan.transactions.iter().position(|t| !t.is_funded()) {
+                return Err(CommandFailure::json_error(CommandError::Wallet(format!(
+                    "Insufficient funds: transaction {} of {} can't be paid from the notes the earlier ones leave. The payout needs {} zats including fees, and {} are spendable",
+                    i + 1,
+                    plan.transactions.len(),
+                    plan.total_amount() + plan.total_fees(),
+                    plan.spendable
+                ))));
             }
-        }
-    })
-}
-fn wallet_deleter(lightclient: &LightClient) -> String {
-    RT.block_on(async move {
-        match lightclient.do_delete().await {
-            Ok(_) => {
-                let r = object! { "result" => "success",
-                "wallet_path" => lightclient.config.get_wallet_path().to_str().unwrap() };
-                r.pretty(2)
+            if let Some(i) = plan
+                .transactions
+                .iter()
+                .position(|t| !t.is_within_action_limit())
+            {
+                return Err(CommandFailure::json_error(CommandError::Wallet(format!(
+                    "Transaction {} of the payout would spend too many notes to be accepted; shield or consolidate the wallet's notes first",
+                    i + 1
+                ))));
             }
-            Err(e) => {
-                let r = object! {
-                    "result" => "error",
-                    "error" => e
+            let results = lightclient.do_send_payouts(&plan).await;
+            summary.results = Some(results.iter().map(PaidRow::from).collect());
+            Ok(CommandOutput::pretty(&summary, 2))
+        })
+    }
+}
+
+struct ScheduleCommand {}
+impl Command for ScheduleCommand {
+    fn help(&self) -> &'static str {
+        indoc! {r#"
+            Schedule payments, once or repeatedly, at a block height or a time
+            A payment is added with --height or --time. With --every, it repeats until cancelled, or --times times.
+            Heights are those the wallet has synced to.
+            'schedule run' pays what is due, at most one run of each payment. The daemon (--daemon, --grpc-bind) does
+            so after every sync; otherwise, run it from cron or by hand.
+            Every run is journaled before it is built, and again with its txid before it is broadcast. A run whose
+            transaction may have gone out is "submitted" until the transaction is found on chain, or expires unmined.
+            A failed run is tried again after a backoff that doubles each time, and given up after 5 attempts.
+            Example:
+            schedule add ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d 200000 "Rent" --height 2500000 --every 34560
+
+        "#}
+    }
+
+    fn short_help(&self) -> &'static str {
+        "Schedule payments at block heights or times"
+    }
+
+    fn subcommands(&self) -> &'static [Subcommand] {
+        &[
+            Subcommand {
+                name: "add",
+                args: &[
+                    ArgSpec::required("address", ArgKind::Text, "The address to pay"),
+                    ArgSpec::required("amount", ArgKind::Zatoshis, "The amount of each run"),
+                    ArgSpec::optional("memo", ArgKind::Text, "A memo, for shielded addresses"),
+                    ArgSpec::optional("height", ArgKind::Number, "The height of the first run"),
+                    ArgSpec::optional("time", ArgKind::Number, "The unix time of the first run"),
+                    ArgSpec::optional("every", ArgKind::Number, "Blocks or seconds between runs"),
+                    ArgSpec::optional("times", ArgKind::Number, "How many runs, with every"),
+                ],
+                help: "Schedule a payment",
+            },
+            Subcommand {
+                name: "list",
+                args: &[],
+                help: "List the payments and the journal of their runs",
+            },
+            Subcommand {
+                name: "cancel",
+                args: &[ArgSpec::required(
+                    "id",
+                    ArgKind::Number,
+                    "The payment to cancel",
+                )],
+                help: "Cancel a payment",
+            },
+            Subcommand {
+                name: "run",
+                args: &[],
+                help: "Pay what is due",
+            },
+        ]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let (subcommand, args) = self.parse_subcommand(args)?;
+        match subcommand {
+            "add" => {
+                let payment = self.parse_payment(&args)?;
+                lightclient
+                    .schedule_payment(payment)
+                    .map(|id| CommandOutput::pretty(&ScheduledId { id }, 2))
+                    .map_err(|e| self.usage_error(&e).into())
+            }
+            "list" => {
+                let schedule = lightclient
+                    .payment_schedule()
+                    .map_err(|e| CommandFailure::json_error(CommandError::Wallet(e)))?;
+                let listing = ScheduleListing {
+                    payments: schedule.payments.iter().map(Into::into).collect(),
+                    journal: schedule.journal.iter().map(Into::into).collect(),
                 };
-                r.pretty(2)
+                Ok(CommandOutput::pretty(&listing, 2))
+            }
+            "cancel" => lightclient
+                .cancel_scheduled_payment(args.u64("id").unwrap())
+                .map(|_| CommandOutput::pretty(&Succeeded::new(), 2))
+                .map_err(|e| CommandFailure::json_error(CommandError::Wallet(e))),
+            _ => RT.block_on(async move {
+                match lightclient.run_scheduled_payments().await {
+                    Ok(runs) => Ok(CommandOutput::pretty(
+                        &runs.iter().map(ScheduleRun::from).collect::<Vec<_>>(),
+                        2,
+                    )),
+                    Err(e) => Err(CommandFailure::json_error(CommandError::Wallet(e))),
+                }
+            }),
+        }
+    }
+}
+
+impl ScheduleCommand {
+    fn parse_payment(
+        &self,
+        args: &Args,
+    ) -> Result<crate::lightclient::schedule::ScheduledPayment, CommandError> {
+        use crate::lightclient::schedule::{ScheduleClock, ScheduledPayment};
+
+        let (clock, start) = match (args.u64("height"), args.u64("time")) {
+            (Some(height), None) => (ScheduleClock::Height, height),
+            (None, Some(time)) => (ScheduleClock::Time, time),
+            _ => return Err(self.usage_error("Need one of --height and --time")),
+        };
+        let every = args.u64("every");
+        let runs = args.u64("times");
+        if runs.is_some() && every.is_none() {
+            return Err(self.usage_error("--times needs --every"));
+        }
+
+        Ok(ScheduledPayment {
+            id: 0,
+            address: args.get("address").unwrap().to_string(),
+            amount: args.u64("amount").unwrap(),
+            memo: args.get("memo").map(String::from),
+            clock,
+            start,
+            every,
+            runs,
+            next_run: 0,
+            cancelled: false,
+            failures: 0,
+            retry_at: 0,
+        })
+    }
+}
+
+/// Records shown as the JSON array the listing commands have always printed
+fn records_text<T: Clone + Into<JsonValue>>(records: &[T]) -> String {
+    JsonValue::Array(records.iter().cloned().map(Into::into).collect()).pretty(2)
+}
+
+fn wallet_file_result(lightclient: &LightClient, done: Result<(), String>) -> CommandResult {
+    match done {
+        Ok(_) => Ok(CommandOutput::pretty(
+            &WalletFile {
+                result: "success".to_string(),
+                wallet_path: lightclient.config.wallet_location(),
+            },
+            2,
+        )),
+        Err(e) => {
+            let text = object! {
+                "result" => "error",
+                "error" => e.clone()
             }
+            .pretty(2);
+            Err(CommandFailure::new(CommandError::Wallet(e), text))
         }
-    })
+    }
+}
+fn wallet_saver(lightclient: &LightClient) -> CommandResult {
+    wallet_file_result(lightclient, RT.block_on(lightclient.do_save()))
+}
+fn wallet_deleter(lightclient: &LightClient) -> CommandResult {
+    wallet_file_result(lightclient, RT.block_on(lightclient.do_delete()))
 }
 struct SaveCommand {}
 impl Command for SaveCommand {
@@ -975,7 +1557,7 @@ impl Command for SaveCommand {
         "Save wallet file to disk"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         wallet_saver(lightclient)
     }
 }
@@ -996,7 +1578,7 @@ impl Command for DeleteCommand {
         "Delete wallet file from disk"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         wallet_deleter(lightclient)
     }
 }
@@ -1017,14 +1599,16 @@ impl Command for SeedCommand {
         "Display the seed phrase"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        RT.block_on(async move {
-            match lightclient.do_seed_phrase().await {
-                Ok(j) => j,
-                Err(e) => object! { "error" => e },
-            }
-            .pretty(2)
-        })
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
+        match RT.block_on(lightclient.seed_phrase()) {
+            Ok(seed) => Ok(CommandOutput::shown_as(
+                &seed,
+                JsonValue::from(seed.clone()).pretty(2),
+            )),
+            Err(e) => Err(CommandFailure::json_error(CommandError::Wallet(
+                e.to_string(),
+            ))),
+        }
     }
 }
 
@@ -1034,9 +1618,7 @@ impl Command for TransactionsCommand {
         indoc! {r#"
             List all incoming and outgoing transactions from this wallet
             Usage:
-            list [allmemos]
-
-            If you include the 'allmemos' argument, all memos are returned in their raw hex format
+            list
 
         "#}
     }
@@ -1045,12 +1627,41 @@ impl Command for TransactionsCommand {
         "List all transactions in the wallet"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() > 1 {
-            return format!("Didn't understand arguments\n{}", self.help());
-        }
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        self.parse_args(args)?;
+
+        let transactions = RT.block_on(lightclient.list_transactions());
+        Ok(CommandOutput::shown_as(
+            &transactions,
+            records_text(&transactions),
+        ))
+    }
+}
+
+struct PendingCommand {}
+impl Command for PendingCommand {
+    fn help(&self) -> &'static str {
+        indoc! {r#"
+            List transactions that have been broadcast but not yet mined
+            Usage:
+            pending
+
+            Each entry shows the height it was submitted at and the height it expires at. Once the chain
+            passes the expiry height the transaction is removed and the funds it spent become spendable
+            again. Pending sends of this wallet are rebroadcast when syncing against a server for the
+            first time, as long as they haven't expired.
+
+        "#}
+    }
 
-        RT.block_on(async move { lightclient.do_list_transactions().await.pretty(2) })
+    fn short_help(&self) -> &'static str {
+        "List pending transactions and when they expire"
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        self.parse_args(args)?;
+
+        RT.block_on(async move { Ok(CommandOutput::json(lightclient.do_pending().await)) })
     }
 }
 
@@ -1068,13 +1679,13 @@ impl Command for ValueTxSummariesCommand {
         "List all value transfer summaries for this seed."
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() > 1 {
-            return format!("Didn't understand arguments\n{}", self.help());
-        }
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        self.parse_args(args)?;
 
         RT.block_on(async move {
-            json::JsonValue::from(lightclient.do_list_txsummaries().await).pretty(2)
+            Ok(CommandOutput::json(JsonValue::from(
+                lightclient.do_list_txsummaries().await,
+            )))
         })
     }
 }
@@ -1083,7 +1694,7 @@ impl Command for MemoBytesToAddressCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Get an object where keys are addresses and values are total bytes of memo sent to that address.
-            usage:
+            Usage:
             memobytes_to_address
         "#}
     }
@@ -1092,13 +1703,13 @@ impl Command for MemoBytesToAddressCommand {
         "Show by address memo_bytes transfers for this seed."
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() > 1 {
-            return format!("didn't understand arguments\n{}", self.help());
-        }
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        self.parse_args(args)?;
 
         RT.block_on(async move {
-            json::JsonValue::from(lightclient.do_total_memobytes_to_address().await).pretty(2)
+            Ok(CommandOutput::json(JsonValue::from(
+                lightclient.do_total_memobytes_to_address().await,
+            )))
         })
     }
 }
@@ -1107,7 +1718,7 @@ impl Command for ValueToAddressCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Get an object where keys are addresses and values are total value sent to that address.
-            usage:
+            Usage:
             value_to_address
         "#}
     }
@@ -1116,13 +1727,13 @@ impl Command for ValueToAddressCommand {
         "Show by address value transfers for this seed."
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() > 1 {
-            return format!("didn't understand arguments\n{}", self.help());
-        }
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        self.parse_args(args)?;
 
         RT.block_on(async move {
-            json::JsonValue::from(lightclient.do_total_value_to_address().await).pretty(2)
+            Ok(CommandOutput::json(JsonValue::from(
+                lightclient.do_total_value_to_address().await,
+            )))
         })
     }
 }
@@ -1131,7 +1742,7 @@ impl Command for SendsToAddressCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Get an object where keys are addresses and values are total value sent to that address.
-            usage:
+            Usage:
             sends_to_address
         "#}
     }
@@ -1140,13 +1751,13 @@ impl Command for SendsToAddressCommand {
         "Show by address number of sends for this seed."
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() > 1 {
-            return format!("didn't understand arguments\n{}", self.help());
-        }
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        self.parse_args(args)?;
 
         RT.block_on(async move {
-            json::JsonValue::from(lightclient.do_total_spends_to_address().await).pretty(2)
+            Ok(CommandOutput::json(JsonValue::from(
+                lightclient.do_total_spends_to_address().await,
+            )))
         })
     }
 }
@@ -1154,11 +1765,12 @@ struct SetOptionCommand {}
 impl Command for SetOptionCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
-            Set a wallet option
-            Usage:
-            setoption <optionname>=<optionvalue>
-            List of available options:
-            download_memos : none | wallet | all
+            Set wallet options, each given by name
+            send_expiry is the number of blocks a sent transaction stays valid for, 'default' for the
+            builder's default of 40, or 'none' for no expiry. Until the builder can set the expiry, sends
+            with anything but the default fail.
+            Example:
+            setoption download_memos=all transaction_fetch_privacy=decoys
 
         "#}
     }
@@ -1167,68 +1779,109 @@ impl Command for SetOptionCommand {
         "Set a wallet option"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() != 1 {
-            return format!("Error: Need exactly 1 argument\n\n{}", self.help());
-        }
-
-        let option = args[0];
-        let values: Vec<&str> = option.split('=').collect();
+    fn args(&self) -> &'static [ArgSpec] {
+        &[
+            ArgSpec::optional(
+                "download_memos",
+                ArgKind::Choice(&["none", "wallet", "all"]),
+                "Which memos to download",
+            ),
+            ArgSpec::optional(
+                "transaction_filter_threshold",
+                ArgKind::Number,
+                "Skip transactions with more outputs than this",
+            ),
+            ArgSpec::optional(
+                "transaction_fetch_privacy",
+                ArgKind::Choice(&["off", "decoys", "wholeblock"]),
+                "How the wallet's transactions are hidden when fetched",
+            ),
+            ArgSpec::optional(
+                "decoys_per_transaction",
+                ArgKind::Number,
+                "Decoys fetched with each of the wallet's transactions",
+            ),
+            ArgSpec::optional(
+                "privacy_bandwidth_budget",
+                ArgKind::Text,
+                "Bytes of decoys per sync batch, or none",
+            ),
+            ArgSpec::optional(
+                "send_expiry",
+                ArgKind::Text,
+                "Blocks until a sent transaction expires, default or none",
+            ),
+        ]
+    }
 
-        if values.len() != 2 {
-            return "Error: Please set option value like: <optionname>=<optionvalue>".to_string();
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
+        if self.args().iter().all(|spec| args.get(spec.name).is_none()) {
+            return Err(self.usage_error("Need an option to set").into());
         }
-
-        let option_name = values[0];
-        let option_value = values[1];
+        // Checked in full before any option is set
+        let transaction_filter_threshold = args
+            .get("transaction_filter_threshold")
+            .map(|value| {
+                value.parse::<u32>().map_err(|e| {
+                    self.usage_error(&format!("Error {e}, couldn't parse {value} as number"))
+                })
+            })
+            .transpose()?;
+        let decoys_per_transaction = args
+            .get("decoys_per_transaction")
+            .map(|value| {
+                value.parse::<u8>().map_err(|e| {
+                    self.usage_error(&format!("Error {e}, couldn't parse {value} as number"))
+                })
+            })
+            .transpose()?;
+        let privacy_bandwidth_budget = match args.get("privacy_bandwidth_budget") {
+            None => None,
+            Some("none") => Some(None),
+            Some(value) => Some(Some(value.parse::<u64>().map_err(|e| {
+                self.usage_error(&format!("Error {e}, couldn't parse {value} as number"))
+            })?)),
+        };
+        let send_expiry = args
+            .get("send_expiry")
+            .map(|value| {
+                value
+                    .parse::<ExpiryDelta>()
+                    .map_err(|e| self.usage_error(&e))
+            })
+            .transpose()?;
 
         RT.block_on(async move {
-            match option_name {
-                "download_memos" => match option_value {
-                    "none" => {
-                        lightclient
-                            .wallet
-                            .set_download_memo(MemoDownloadOption::NoMemos)
-                            .await
-                    }
-                    "wallet" => {
-                        lightclient
-                            .wallet
-                            .set_download_memo(MemoDownloadOption::WalletMemos)
-                            .await
-                    }
-                    "all" => {
-                        lightclient
-                            .wallet
-                            .set_download_memo(MemoDownloadOption::AllMemos)
-                            .await
-                    }
-                    _ => {
-                        return format!(
-                            "Error: Couldn't understand {} value {}",
-                            option_name, option_value
-                        )
-                    }
-                },
-                "transaction_filter_threshold" => match option_value.parse() {
-                    Ok(number) => {
-                        lightclient
-                            .wallet
-                            .wallet_options
-                            .write()
-                            .await
-                            .transaction_size_filter = Some(number)
-                    }
-                    Err(e) => return format!("Error {e}, couldn't parse {option_value} as number"),
-                },
-                _ => return format!("Error: Couldn't understand {}", option_name),
+            let mut options = lightclient.wallet.wallet_options.write().await;
+            match args.get("download_memos") {
+                Some("none") => options.download_memos = MemoDownloadOption::NoMemos,
+                Some("wallet") => options.download_memos = MemoDownloadOption::WalletMemos,
+                Some(_) => options.download_memos = MemoDownloadOption::AllMemos,
+                None => {}
+            }
+            if let Some(threshold) = transaction_filter_threshold {
+                options.transaction_size_filter = Some(threshold);
+            }
+            match args.get("transaction_fetch_privacy") {
+                Some("off") => options.fetch_privacy = TransactionFetchPrivacy::Off,
+                Some("decoys") => options.fetch_privacy = TransactionFetchPrivacy::Decoys,
+                Some(_) => options.fetch_privacy = TransactionFetchPrivacy::WholeBlock,
+                None => {}
+            }
+            if let Some(decoys) = decoys_per_transaction {
+                options.decoys_per_transaction = decoys;
+            }
+            if let Some(budget) = privacy_bandwidth_budget {
+                options.privacy_bandwidth_budget = budget;
+            }
+            if let Some(expiry) = send_expiry {
+                options.send_expiry = expiry;
             }
 
-            let r = object! {
+            Ok(CommandOutput::json(object! {
                 "success" => true
-            };
-
-            r.pretty(2)
+            }))
         })
     }
 }
@@ -1238,11 +1891,6 @@ impl Command for GetOptionCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Get a wallet option
-            Argument is either "download_memos" and "transaction_filter_threshold"
-
-            Usage:
-            getoption <optionname>
-
         "#}
     }
 
@@ -1250,12 +1898,24 @@ impl Command for GetOptionCommand {
         "Get a wallet option"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() != 1 {
-            return format!("Error: Need exactly 1 argument\n\n{}", self.help());
-        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::required(
+            "option_name",
+            ArgKind::Choice(&[
+                "download_memos",
+                "transaction_filter_threshold",
+                "transaction_fetch_privacy",
+                "decoys_per_transaction",
+                "privacy_bandwidth_budget",
+                "send_expiry",
+            ]),
+            "The option to get",
+        )]
+    }
 
-        let option_name = args[0];
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
+        let option_name = args.get("option_name").unwrap();
 
         RT.block_on(async move {
             let value = match option_name {
@@ -1278,14 +1938,45 @@ impl Command for GetOptionCommand {
                     .transaction_size_filter
                     .map(|filter| filter.to_string())
                     .unwrap_or("No filter".to_string()),
-                _ => return format!("Error: Couldn't understand {}", option_name),
+                "transaction_fetch_privacy" => {
+                    match lightclient.wallet.wallet_options.read().await.fetch_privacy {
+                        TransactionFetchPrivacy::Off => "off".to_string(),
+                        TransactionFetchPrivacy::Decoys => "decoys".to_string(),
+                        TransactionFetchPrivacy::WholeBlock => "wholeblock".to_string(),
+                    }
+                }
+                "decoys_per_transaction" => lightclient
+                    .wallet
+                    .wallet_options
+                    .read()
+                    .await
+                    .decoys_per_transaction
+                    .to_string(),
+                "privacy_bandwidth_budget" => lightclient
+                    .wallet
+                    .wallet_options
+                    .read()
+                    .await
+                    .privacy_bandwidth_budget
+                    .map(|budget| budget.to_string())
+                    .unwrap_or("none".to_string()),
+                "send_expiry" => lightclient
+                    .wallet
+                    .wallet_options
+                    .read()
+                    .await
+                    .send_expiry
+                    .to_string(),
+                _ => {
+                    return Err(self
+                        .usage_error(&format!("Couldn't understand {}", option_name))
+                        .into())
+                }
             };
 
-            let r = object! {
+            Ok(CommandOutput::json(object! {
                 option_name => value
-            };
-
-            r.pretty(2)
+            }))
         })
     }
 }
@@ -1295,10 +1986,7 @@ impl Command for HeightCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Get the latest block height that the wallet is at.
-            Usage:
-            height
-
-            Pass 'true' (default) to sync to the server to get the latest block height. Pass 'false' to get the latest height in the wallet without checking with the server.
+            With sync true, the wallet syncs with the server first, so the height is the chain's.
 
         "#}
     }
@@ -1307,9 +1995,29 @@ impl Command for HeightCommand {
         "Get the latest block height that the wallet is at"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::with_default(
+            "sync",
+            ArgKind::Bool,
+            "false",
+            "Whether to sync with the server first",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let sync = self.parse_args(args)?.bool("sync");
+
         RT.block_on(async move {
-            object! { "height" => lightclient.do_wallet_last_scanned_height().await}.pretty(2)
+            if sync {
+                lightclient
+                    .do_sync(true)
+                    .await
+                    .map_err(CommandError::Server)?;
+            }
+            let height = Height {
+                height: lightclient.wallet.last_synced_height().await,
+            };
+            Ok(CommandOutput::pretty(&height, 2))
         })
     }
 }
@@ -1319,9 +2027,6 @@ impl Command for DefaultFeeCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Returns the default fee in zats for outgoing transactions
-            Usage:
-            defaultfee <optional_block_height>
-
             Example:
             defaultfee
         "#}
@@ -1331,15 +2036,21 @@ impl Command for DefaultFeeCommand {
         "Returns the default fee in zats for outgoing transactions"
     }
 
-    fn exec(&self, args: &[&str], _lightclient: &LightClient) -> String {
-        if args.len() > 1 {
-            return format!("Was expecting at most 1 argument\n{}", self.help());
-        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::optional(
+            "block_height",
+            ArgKind::Number,
+            "The height to send at. The fee is currently the same at every height",
+        )]
+    }
 
-        RT.block_on(async move {
-            let j = object! { "defaultfee" => u64::from(MINIMUM_FEE)};
-            j.pretty(2)
-        })
+    fn exec(&self, args: &[&str], _lightclient: &LightClient) -> CommandResult {
+        self.parse_args(args)?;
+
+        let fee = DefaultFee {
+            defaultfee: u64::from(MINIMUM_FEE),
+        };
+        Ok(CommandOutput::pretty(&fee, 2))
     }
 }
 
@@ -1348,9 +2059,6 @@ impl Command for NewAddressCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Create a new address in this wallet
-            Usage:
-            new [z | t | o]
-
             Example:
             To create a new z address:
             new z
@@ -1361,17 +2069,23 @@ impl Command for NewAddressCommand {
         "Create a new address in this wallet"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() != 1 {
-            return format!("No address type specified\n{}", self.help());
-        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::required(
+            "receivers",
+            ArgKind::Text,
+            "The receivers of the address, any of z, t and o",
+        )]
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let args = self.parse_args(args)?;
 
         RT.block_on(async move {
-            match lightclient.do_new_address(args[0]).await {
-                Ok(j) => j,
-                Err(e) => object! { "error" => e },
-            }
-            .pretty(2)
+            lightclient
+                .do_new_address(args.get("receivers").unwrap())
+                .await
+                .map(CommandOutput::json)
+                .map_err(|e| CommandFailure::json_error(CommandError::Wallet(e)))
         })
     }
 }
@@ -1381,10 +2095,7 @@ impl Command for NotesCommand {
     fn help(&self) -> &'static str {
         indoc! {r#"
             Show all sapling notes and utxos in this wallet
-            Usage:
-            notes [all]
-
-            If you supply the "all" parameter, all previously spent sapling notes and spent utxos are also included
+            With all, previously spent sapling notes and spent utxos are also included
 
         "#}
     }
@@ -1393,28 +2104,18 @@ impl Command for NotesCommand {
         "List all sapling notes and utxos in the wallet"
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        // Parse the args.
-        if args.len() > 1 {
-            return self.short_help().to_string();
-        }
+    fn args(&self) -> &'static [ArgSpec] {
+        &[ArgSpec::flag("all", "Include spent notes and utxos")]
+    }
 
-        // Make sure we can parse the amount
-        let all_notes = if args.len() == 1 {
-            match args[0] {
-                "all" => true,
-                a => {
-                    return format!(
-                        "Invalid argument \"{}\". Specify 'all' to include unspent notes",
-                        a
-                    )
-                }
-            }
-        } else {
-            false
-        };
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> CommandResult {
+        let all_notes = self.parse_args(args)?.bool("all");
 
-        RT.block_on(async move { lightclient.do_list_notes(all_notes).await.pretty(2) })
+        let notes = RT.block_on(lightclient.list_notes(all_notes));
+        Ok(CommandOutput::shown_as(
+            &notes,
+            JsonValue::from(notes.clone()).pretty(2),
+        ))
     }
 }
 
@@ -1433,7 +2134,7 @@ impl Command for QuitCommand {
         "Quit the lightwallet, saving state to disk"
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> CommandResult {
         // before shutting down, shut down all child processes..
         // ...but only if the network being used is regtest.
         let o = RT.block_on(async move { lightclient.do_info().await });
@@ -1476,7 +2177,7 @@ impl Command for QuitCommand {
 }
 
 pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
-    let entries: [(&'static str, Box<dyn Command>); 37] = [
+    let entries: [(&'static str, Box<dyn Command>); 42] = [
         (("version"), Box::new(GetVersionCommand {})),
         ("sync", Box::new(SyncCommand {})),
         ("syncstatus", Box::new(SyncStatusCommand {})),
@@ -1488,6 +2189,7 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         ("changeserver", Box::new(ChangeServerCommand {})),
         ("rescan", Box::new(RescanCommand {})),
         ("clear", Box::new(ClearCommand {})),
+        ("check", Box::new(CheckCommand {})),
         ("help", Box::new(HelpCommand {})),
         ("balance", Box::new(BalanceCommand {})),
         ("addresses", Box::new(AddressCommand {})),
@@ -1504,12 +2206,16 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
         ("getoption", Box::new(GetOptionCommand {})),
         ("exportufvk", Box::new(ExportUfvkCommand {})),
         ("info", Box::new(InfoCommand {})),
+        ("servers", Box::new(ServersCommand {})),
         ("updatecurrentprice", Box::new(UpdateCurrentPriceCommand {})),
         ("send", Box::new(SendCommand {})),
+        ("sendmany", Box::new(SendManyCommand {})),
+        ("schedule", Box::new(ScheduleCommand {})),
         ("shield", Box::new(ShieldCommand {})),
         ("save", Box::new(SaveCommand {})),
         ("quit", Box::new(QuitCommand {})),
         ("list", Box::new(TransactionsCommand {})),
+        ("pending", Box::new(PendingCommand {})),
         ("notes", Box::new(NotesCommand {})),
         ("new", Box::new(NewAddressCommand {})),
         ("defaultfee", Box::new(DefaultFeeCommand {})),
@@ -1522,12 +2228,195 @@ pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
     HashMap::from(entries)
 }
 
-pub fn do_user_command(cmd: &str, args: &[&str], lightclient: &LightClient) -> String {
+pub fn run_user_command(cmd: &str, args: &[&str], lightclient: &LightClient) -> CommandResult {
     match get_commands().get(cmd.to_ascii_lowercase().as_str()) {
         Some(cmd) => cmd.exec(args, lightclient),
-        None => format!(
-            "Unknown command : {}. Type 'help' for a list of commands",
-            cmd
-        ),
+        None => Err(CommandError::UnknownCommand(cmd.to_string()).into()),
+    }
+}
+
+/// Runs a command and renders its result as text
+pub fn do_user_command(cmd: &str, args: &[&str], lightclient: &LightClient) -> String {
+    render(
+        &run_user_command(cmd, args, lightclient),
+        OutputFormat::Text,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_output_reads_back() {
+        let results: [CommandResult; 6] = [
+            Ok(CommandOutput::pretty(
+                &Sent {
+                    txid: "abc".to_string(),
+                },
+                2,
+            )),
+            Ok(CommandOutput::message("server set")),
+            Err(CommandError::UnknownCommand("sned".to_string()).into()),
+            Err(CommandError::InvalidArguments("Need 'address' and 'amount'".to_string()).into()),
+            Err(CommandFailure::json_error(CommandError::Wallet(
+                "Insufficient funds".to_string(),
+            ))),
+            Err(CommandError::Server("Connection refused".to_string()).into()),
+        ];
+        for result in results {
+            let output = render(&result, OutputFormat::Json);
+            assert!(!output.contains('\n'));
+            assert_eq!(
+                parse_json_output(&output),
+                result
+                    .as_ref()
+                    .map(|output| output.result.clone())
+                    .map_err(|failure| failure.error.clone())
+            );
+        }
+        assert_eq!(
+            json::parse(&render(
+                &Err(CommandError::Wallet("Insufficient funds".to_string()).into()),
+                OutputFormat::Json
+            ))
+            .unwrap()["error"]["code"],
+            "wallet_error"
+        );
+    }
+
+    #[test]
+    fn errors_serialize_with_their_code() {
+        for error in [
+            CommandError::UnknownCommand("sned".to_string()),
+            CommandError::InvalidArguments("Need 'address' and 'amount'".to_string()),
+            CommandError::Wallet("Insufficient funds".to_string()),
+            CommandError::Server("Connection refused".to_string()),
+        ] {
+            let serialized = serde_json::to_value(&error).unwrap();
+            assert_eq!(serialized["code"], error.code());
+            assert_eq!(serialized["message"], error.to_string());
+            assert_eq!(
+                serde_json::from_value::<CommandError>(serialized).unwrap(),
+                error
+            );
+        }
+        assert!(serde_json::from_value::<CommandError>(serde_json::json!({
+            "code": "no_such_code",
+            "message": "?"
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn failed_broadcasts_are_server_errors() {
+        let failure = CommandFailure::json_error(
+            SendError::Server("Send Error: unavailable".to_string()).into(),
+        );
+        assert_eq!(failure.error.code(), "server_error");
+        assert_eq!(
+            failure.text,
+            "{\n  \"error\": \"Send Error: unavailable\"\n}"
+        );
+        assert_eq!(
+            CommandError::from(SendError::Wallet("Insufficient funds".to_string())).code(),
+            "wallet_error"
+        );
+    }
+
+    #[test]
+    fn sync_status_has_progress_only_while_syncing() {
+        let mut status = crate::blaze::sync_status::BatchSyncStatus {
+            sync_id: 3,
+            resumed_ranges: vec![(100, 199)],
+            ..Default::default()
+        };
+        let idle = CommandOutput::pretty(&SyncStatus::new(&status, false), 2);
+        assert!(idle.to_json()["start_block"].is_null());
+        assert_eq!(idle.to_json()["resumed_ranges"][0], "100-199");
+        assert_eq!(idle.result_as::<SyncStatus>().unwrap().progress, None);
+
+        status.in_progress = true;
+        status.end_block = 150;
+        let syncing = CommandOutput::pretty(&SyncStatus::new(&status, true), 2);
+        assert_eq!(syncing.to_json()["end_block"], 150);
+        assert_eq!(syncing.to_json()["sync_interrupt"], true);
+        assert_eq!(
+            syncing.result_as::<SyncStatus>().unwrap(),
+            SyncStatus::new(&status, true)
+        );
+    }
+
+    #[test]
+    fn declared_arguments_are_in_the_help() {
+        for (name, command) in get_commands() {
+            let help = command.full_help();
+            for (i, spec) in command.args().iter().enumerate() {
+                assert!(
+                    help.contains(&format!("  {} ", spec.name)),
+                    "{} doesn't describe {}",
+                    name,
+                    spec.name
+                );
+                assert!(
+                    command.args()[..i].iter().all(|s| s.name != spec.name),
+                    "{} declares {} twice",
+                    name,
+                    spec.name
+                );
+            }
+        }
+        assert!(get_commands()["send"]
+            .parse_args(&["u1abc", "ten"])
+            .unwrap_err()
+            .to_string()
+            .starts_with("amount must be a whole number, not 'ten'\n\nSend ZEC"));
+    }
+
+    #[test]
+    fn text_output_keeps_its_shape() {
+        assert_eq!(
+            render(
+                &Ok(CommandOutput::message("server set")),
+                OutputFormat::Text
+            ),
+            "server set"
+        );
+        assert_eq!(
+            render(
+                &Err(CommandFailure::json_error(CommandError::Wallet(
+                    "Insufficient funds".to_string()
+                ))),
+                OutputFormat::Text
+            ),
+            "{\n  \"error\": \"Insufficient funds\"\n}"
+        );
+        assert_eq!(
+            render(
+                &Err(CommandError::Server("Connection refused".to_string()).into()),
+                OutputFormat::Text
+            ),
+            "Connection refused"
+        );
+        assert_eq!(
+            render(
+                &Err(CommandError::UnknownCommand("sned".to_string()).into()),
+                OutputFormat::Text
+            ),
+            "Unknown command : sned. Type 'help' for a list of commands"
+        );
+        let kind = CommandOutput::pretty(
+            &WalletKind {
+                kind: "Seeded".to_string(),
+                transparent: None,
+                sapling: None,
+                orchard: None,
+            },
+            4,
+        );
+        assert_eq!(kind.text, "{\n    \"kind\": \"Seeded\"\n}");
+        assert_eq!(kind.result_as::<WalletKind>().unwrap().kind, "Seeded");
+        let fee = CommandOutput::pretty(&DefaultFee { defaultfee: 1000 }, 2);
+        assert_eq!(fee.text, object! { "defaultfee" => 1000 }.pretty(2));
     }
 }
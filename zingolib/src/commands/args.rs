@@ -0,0 +1,465 @@
+//! Command arguments, declared once. A command lists its arguments as [`ArgSpec`]s, and from
+//! that list come the parsing and checking of what it is given, the argument section of its
+//! help, and the mapping of JSON-RPC params onto it.
+//!
+//! Arguments are given in order, by name, or both: `send <address> amount=1000`. A name is
+//! given as `name=value`, or as an option, `--name value` or `--name=value`, in which dashes
+//! stand for underscores. Named arguments are taken first and the others fill the remaining
+//! places in order. A token is only taken as named if the part before `=` is one of the
+//! command's argument names, so values such as memos may contain `=`. A flag is given by its
+//! name alone, as in `check repair` or `sendmany --dry-run`, or as `repair=true`.
+//!
+//! Everything after `--` is taken in order, so a value that looks like a name can still be
+//! given: `send <address> 1000 -- memo=thanks`.
+use json::JsonValue;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    Text,
+    /// A whole number of zatoshis
+    Zatoshis,
+    /// A whole number, such as a height
+    Number,
+    /// `true` or `false`
+    Bool,
+    /// Off unless given by name
+    Flag,
+    /// One of the listed words
+    Choice(&'static [&'static str]),
+}
+
+impl ArgKind {
+    /// As shown in help
+    pub fn name(&self) -> String {
+        match self {
+            ArgKind::Text => "text".to_string(),
+            ArgKind::Zatoshis => "zatoshis".to_string(),
+            ArgKind::Number => "number".to_string(),
+            ArgKind::Bool => "true|false".to_string(),
+            ArgKind::Flag => "flag".to_string(),
+            ArgKind::Choice(choices) => choices.join("|"),
+        }
+    }
+
+    fn check(&self, value: &str) -> Result<(), String> {
+        match self {
+            ArgKind::Text => Ok(()),
+            ArgKind::Zatoshis | ArgKind::Number => value
+                .parse::<u64>()
+                .map(|_| ())
+                .map_err(|_| "a whole number".to_string()),
+            ArgKind::Bool | ArgKind::Flag => match value {
+                "true" | "false" => Ok(()),
+                _ => Err("true or false".to_string()),
+            },
+            ArgKind::Choice(choices) => {
+                if choices.contains(&value) {
+                    Ok(())
+                } else {
+                    Err(format!("one of {}", choices.join(", ")))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArgSpec {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+    /// Taken when the argument isn't given
+    pub default: Option<&'static str>,
+    pub help: &'static str,
+}
+
+impl ArgSpec {
+    pub const fn required(name: &'static str, kind: ArgKind, help: &'static str) -> Self {
+        ArgSpec {
+            name,
+            kind,
+            optional: false,
+            default: None,
+            help,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind, help: &'static str) -> Self {
+        ArgSpec {
+            name,
+            kind,
+            optional: true,
+            default: None,
+            help,
+        }
+    }
+
+    pub const fn with_default(
+        name: &'static str,
+        kind: ArgKind,
+        default: &'static str,
+        help: &'static str,
+    ) -> Self {
+        ArgSpec {
+            name,
+            kind,
+            optional: true,
+            default: Some(default),
+            help,
+        }
+    }
+
+    pub const fn flag(name: &'static str, help: &'static str) -> Self {
+        Self::with_default(name, ArgKind::Flag, "false", help)
+    }
+}
+
+/// A subcommand, such as `schedule add`, and its own arguments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Subcommand {
+    pub name: &'static str,
+    pub args: &'static [ArgSpec],
+    pub help: &'static str,
+}
+
+/// Ends the named arguments: everything after it is taken in order
+pub const ESCAPE: &str = "--";
+
+/// `args` without a leading [`ESCAPE`], if it holds a single argument. For commands that
+/// also take their arguments as one JSON value.
+pub fn single(args: &[&str]) -> Option<&str> {
+    match args {
+        [arg] | [ESCAPE, arg] => Some(arg),
+        _ => None,
+    }
+}
+
+/// The arguments a command was given, checked against its specs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    values: Vec<(&'static str, String)>,
+}
+
+impl Args {
+    /// The argument's value, or its default. `None` for an optional argument without one.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// A number or amount, which was checked when parsing
+    pub fn u64(&self, name: &str) -> Option<u64> {
+        self.get(name).and_then(|value| value.parse().ok())
+    }
+
+    /// A bool or flag, false when not given
+    pub fn bool(&self, name: &str) -> bool {
+        self.get(name) == Some("true")
+    }
+}
+
+/// Matches `args` to `specs` and checks every value
+pub fn parse(specs: &'static [ArgSpec], args: &[&str]) -> Result<Args, String> {
+    let mut given: Vec<Option<String>> = vec![None; specs.len()];
+    let mut positional = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if *arg == ESCAPE {
+            positional.extend(args.by_ref().map(|arg| arg.to_string()));
+            break;
+        }
+        if let Some(option) = arg.strip_prefix("--") {
+            let (name, value) = match option.split_once('=') {
+                Some((name, value)) => (name.replace('-', "_"), Some(value.to_string())),
+                None => (option.replace('-', "_"), None),
+            };
+            let i = specs
+                .iter()
+                .position(|spec| spec.name == name)
+                .ok_or_else(|| format!("Unknown option '{}'", arg))?;
+            let value = match (value, specs[i].kind) {
+                (Some(value), _) => value,
+                (None, ArgKind::Flag) => "true".to_string(),
+                (None, _) => match args.next() {
+                    Some(value) => value.to_string(),
+                    None => return Err(format!("{} needs a value", arg)),
+                },
+            };
+            if given[i].is_some() {
+                return Err(format!("{} is given more than once", specs[i].name));
+            }
+            given[i] = Some(value);
+            continue;
+        }
+        let named = match arg.split_once('=') {
+            Some((name, value)) => specs
+                .iter()
+                .position(|spec| spec.name == name)
+                .map(|i| (i, value.to_string())),
+            None => specs
+                .iter()
+                .position(|spec| spec.kind == ArgKind::Flag && spec.name == *arg)
+                .map(|i| (i, "true".to_string())),
+        };
+        match named {
+            Some((i, value)) => {
+                if given[i].is_some() {
+                    return Err(format!("{} is given more than once", specs[i].name));
+                }
+                given[i] = Some(value);
+            }
+            None => positional.push(arg.to_string()),
+        }
+    }
+
+    // Flags only ever come by name
+    let mut open = (0..specs.len())
+        .filter(|&i| given[i].is_none() && specs[i].kind != ArgKind::Flag)
+        .collect::<Vec<_>>()
+        .into_iter();
+    for value in positional {
+        match open.next() {
+            Some(i) => given[i] = Some(value),
+            None => return Err(format!("Didn't expect the argument '{}'", value)),
+        }
+    }
+
+    let mut values = vec![];
+    for (spec, value) in specs.iter().zip(given) {
+        match value.or_else(|| spec.default.map(String::from)) {
+            Some(value) => {
+                spec.kind.check(&value).map_err(|expected| {
+                    format!("{} must be {}, not '{}'", spec.name, expected, value)
+                })?;
+                values.push((spec.name, value));
+            }
+            None if spec.optional => {}
+            None => return Err(format!("Missing {}", spec.name)),
+        }
+    }
+    Ok(Args { values })
+}
+
+/// Takes the subcommand off the front of `args` and parses the rest by its arguments
+pub fn parse_subcommand(
+    subcommands: &'static [Subcommand],
+    args: &[&str],
+) -> Result<(&'static str, Args), String> {
+    let names = || {
+        subcommands
+            .iter()
+            .map(|subcommand| subcommand.name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    // An escape before the subcommand holds for its arguments too
+    let (name, rest) = match args {
+        [ESCAPE, name, rest @ ..] => (
+            *name,
+            std::iter::once(ESCAPE)
+                .chain(rest.iter().copied())
+                .collect::<Vec<_>>(),
+        ),
+        [name, rest @ ..] => (*name, rest.to_vec()),
+        [] => return Err(format!("Missing one of {}", names())),
+    };
+    let subcommand = subcommands
+        .iter()
+        .find(|subcommand| subcommand.name == name)
+        .ok_or_else(|| format!("'{}' is not one of {}", name, names()))?;
+    Ok((subcommand.name, parse(subcommand.args, &rest)?))
+}
+
+/// The arguments section of a command's help
+pub fn describe(specs: &[ArgSpec]) -> String {
+    let width = specs.iter().map(|spec| spec.name.len()).max().unwrap_or(0);
+    let mut lines =
+        vec!["Arguments, given in order, as name=value or as --name value:".to_string()];
+    for spec in specs {
+        let kind = match (spec.default, spec.kind) {
+            (_, ArgKind::Flag) => "flag".to_string(),
+            (Some(default), kind) => format!("{}, default {}", kind.name(), default),
+            (None, kind) if spec.optional => format!("{}, optional", kind.name()),
+            (None, kind) => kind.name(),
+        };
+        lines.push(format!(
+            "  {:width$}  ({}) {}",
+            spec.name,
+            kind,
+            spec.help,
+            width = width
+        ));
+    }
+    lines.join("\n")
+}
+
+/// The subcommands section of a command's help, with the arguments of each
+pub fn describe_subcommands(subcommands: &[Subcommand]) -> String {
+    let width = subcommands.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    let mut lines = vec!["Subcommands, given first:".to_string()];
+    for subcommand in subcommands {
+        lines.push(format!(
+            "  {:width$}  {}",
+            subcommand.name,
+            subcommand.help,
+            width = width
+        ));
+    }
+    for subcommand in subcommands.iter().filter(|s| !s.args.is_empty()) {
+        lines.push(String::new());
+        lines.push(format!(
+            "{}: {}",
+            subcommand.name,
+            describe(subcommand.args)
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Turns JSON-RPC params into command arguments: an array gives them in order, after an
+/// [`ESCAPE`] so that none is taken as a name, and an object by name. Values that aren't
+/// strings are written as JSON.
+pub fn from_json_params(specs: &[ArgSpec], params: &JsonValue) -> Result<Vec<String>, String> {
+    let value = |value: &JsonValue| match value.as_str() {
+        Some(s) => s.to_string(),
+        None => value.dump(),
+    };
+    match params {
+        JsonValue::Null => Ok(vec![]),
+        JsonValue::Array(params) if params.is_empty() => Ok(vec![]),
+        JsonValue::Array(params) => Ok(std::iter::once(ESCAPE.to_string())
+            .chain(params.iter().map(value))
+            .collect()),
+        JsonValue::Object(params) => params
+            .iter()
+            .map(|(name, param)| {
+                if specs.iter().any(|spec| spec.name == name) {
+                    Ok(format!("{}={}", name, value(param)))
+                } else {
+                    Err(format!("Unknown parameter '{}'", name))
+                }
+            })
+            .collect(),
+        _ => Err("Params must be an array or an object".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use json::{array, object};
+
+    const SEND: &[ArgSpec] = &[
+        ArgSpec::required("address", ArgKind::Text, "Where to send"),
+        ArgSpec::required("amount", ArgKind::Zatoshis, "How much"),
+        ArgSpec::optional("memo", ArgKind::Text, "A note"),
+        ArgSpec::flag("dry_run", "Don't send"),
+    ];
+
+    #[test]
+    fn positional_and_named_arguments_mix() {
+        let args = parse(SEND, &["u1abc", "memo=a=b", "1000"]).unwrap();
+        assert_eq!(args.get("address"), Some("u1abc"));
+        assert_eq!(args.u64("amount"), Some(1000));
+        assert_eq!(args.get("memo"), Some("a=b"));
+        assert!(!args.bool("dry_run"));
+
+        let args = parse(SEND, &["amount=5", "dry_run", "u1abc"]).unwrap();
+        assert_eq!(args.get("address"), Some("u1abc"));
+        assert_eq!(args.get("memo"), None);
+        assert!(args.bool("dry_run"));
+
+        assert_eq!(parse(SEND, &["u1abc"]).unwrap_err(), "Missing amount");
+        assert_eq!(
+            parse(SEND, &["u1abc", "ten"]).unwrap_err(),
+            "amount must be a whole number, not 'ten'"
+        );
+        assert!(parse(SEND, &["u1abc", "1", "memo", "extra"]).is_err());
+        assert!(parse(SEND, &["u1abc", "1", "amount=2"]).is_err());
+    }
+
+    #[test]
+    fn everything_after_an_escape_is_in_order() {
+        let args = parse(SEND, &["u1abc", "--", "1", "memo=--dry-run"]).unwrap();
+        assert_eq!(args.get("memo"), Some("memo=--dry-run"));
+        assert!(!args.bool("dry_run"));
+        let args = parse(SEND, &["--dry-run", "--", "u1abc", "1", "dry_run"]).unwrap();
+        assert_eq!(args.get("memo"), Some("dry_run"));
+        assert!(args.bool("dry_run"));
+
+        assert_eq!(single(&["--", "[]"]), Some("[]"));
+        assert_eq!(single(&["[]"]), Some("[]"));
+        assert_eq!(single(&["--", "a", "b"]), None);
+    }
+
+    #[test]
+    fn subcommands_take_their_own_arguments() {
+        const SUBCOMMANDS: &[Subcommand] = &[
+            Subcommand {
+                name: "send",
+                args: SEND,
+                help: "Send",
+            },
+            Subcommand {
+                name: "list",
+                args: &[],
+                help: "List",
+            },
+        ];
+        let (name, args) = parse_subcommand(SUBCOMMANDS, &["send", "u1abc", "--amount=5"]).unwrap();
+        assert_eq!(name, "send");
+        assert_eq!(args.u64("amount"), Some(5));
+        assert_eq!(parse_subcommand(SUBCOMMANDS, &["list"]).unwrap().0, "list");
+        let (_, args) =
+            parse_subcommand(SUBCOMMANDS, &["--", "send", "u1abc", "5", "amount=6"]).unwrap();
+        assert_eq!(args.get("memo"), Some("amount=6"));
+        assert!(parse_subcommand(SUBCOMMANDS, &["list", "extra"]).is_err());
+        assert_eq!(
+            parse_subcommand(SUBCOMMANDS, &[]).unwrap_err(),
+            "Missing one of send, list"
+        );
+        assert!(describe_subcommands(SUBCOMMANDS).contains("send: Arguments"));
+    }
+
+    #[test]
+    fn options_name_arguments() {
+        let args = parse(SEND, &["--amount", "5", "u1abc", "--dry-run", "--memo=a b"]).unwrap();
+        assert_eq!(args.get("address"), Some("u1abc"));
+        assert_eq!(args.u64("amount"), Some(5));
+        assert_eq!(args.get("memo"), Some("a b"));
+        assert!(args.bool("dry_run"));
+
+        assert_eq!(
+            parse(SEND, &["u1abc", "1", "--memo"]).unwrap_err(),
+            "--memo needs a value"
+        );
+        assert_eq!(
+            parse(SEND, &["u1abc", "1", "--fee", "2"]).unwrap_err(),
+            "Unknown option '--fee'"
+        );
+    }
+
+    #[test]
+    fn json_params_map_to_arguments() {
+        let ordered = from_json_params(SEND, &array!["u1abc", 1000, "dry_run"]).unwrap();
+        assert_eq!(ordered, vec!["--", "u1abc", "1000", "dry_run"]);
+        let ordered = ordered.iter().map(String::as_str).collect::<Vec<_>>();
+        let args = parse(SEND, &ordered).unwrap();
+        assert_eq!(args.get("memo"), Some("dry_run"));
+        assert!(!args.bool("dry_run"));
+        assert!(from_json_params(SEND, &array![]).unwrap().is_empty());
+        let named = from_json_params(
+            SEND,
+            &object! { "address" => "u1abc", "amount" => 1000, "dry_run" => true },
+        )
+        .unwrap();
+        assert_eq!(named, vec!["address=u1abc", "amount=1000", "dry_run=true"]);
+        let named = named.iter().map(String::as_str).collect::<Vec<_>>();
+        assert!(parse(SEND, &named).unwrap().bool("dry_run"));
+        assert!(from_json_params(SEND, &object! { "to" => "u1abc" }).is_err());
+
+        assert!(describe(SEND).contains("  amount   (zatoshis) How much"));
+    }
+}
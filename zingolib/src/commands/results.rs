@@ -0,0 +1,416 @@
+//! The typed results of commands whose results have a fixed shape. A command serializes its
+//! result into [`CommandOutput::result`](super::CommandOutput::result), from which it is read
+//! back with [`CommandOutput::result_as`](super::CommandOutput::result_as).
+//!
+//! Commands that report on the wallet answer with the [`records`](crate::lightclient::records)
+//! of the light client instead, and the balance with
+//! [`PoolBalances`](crate::lightclient::PoolBalances).
+use serde::{Deserialize, Serialize};
+
+use crate::blaze::sync_status::BatchSyncStatus;
+use crate::grpc_connector::BytesDownloaded;
+use crate::lightclient::payouts::{PayoutPlan, PayoutResult};
+use crate::lightclient::schedule::{JournalEntry, RunOutcome, ScheduledPayment};
+use crate::lightclient::SyncResult;
+use crate::wallet::consistency::ConsistencyReport;
+
+/// `wallet_kind`: whether the wallet was made from a seed, or else what it can do in each pool
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletKind {
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transparent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sapling: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orchard: Option<String>,
+}
+
+/// `parse_address`, for an address of a known chain
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParsedAddress {
+    pub status: String,
+    pub chain_name: String,
+    pub address_kind: String,
+    /// The receivers of a unified address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receivers_available: Option<Vec<String>>,
+}
+
+/// `parse_viewkey`. Only the status is set for a key that couldn't be parsed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParsedViewKey {
+    pub status: String,
+    pub chain_name: Option<String>,
+    pub address_kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pools_available: Option<Vec<String>>,
+}
+
+/// `exportufvk`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedUfvk {
+    pub ufvk: String,
+    pub birthday: u64,
+}
+
+/// `send` and `shield`: the transaction that was broadcast
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sent {
+    pub txid: String,
+}
+
+/// `save`, `delete` and `quit`: where the wallet was saved, or deleted from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletFile {
+    pub result: String,
+    pub wallet_path: String,
+}
+
+/// `height`: the height the wallet is synced to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Height {
+    pub height: u64,
+}
+
+/// `defaultfee`, in zatoshis
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefaultFee {
+    pub defaultfee: u64,
+}
+
+/// `clear` and `schedule cancel`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Succeeded {
+    pub result: String,
+}
+
+impl Succeeded {
+    pub fn new() -> Self {
+        Succeeded {
+            result: "success".to_string(),
+        }
+    }
+}
+
+impl Default for Succeeded {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The bytes received from lightwalletd, by kind of data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Downloaded {
+    pub compact_blocks: u64,
+    pub full_transactions: u64,
+    pub tree_states: u64,
+    pub mempool: u64,
+    pub total: u64,
+}
+
+impl From<BytesDownloaded> for Downloaded {
+    fn from(downloaded: BytesDownloaded) -> Self {
+        Downloaded {
+            compact_blocks: downloaded.compact_blocks,
+            full_transactions: downloaded.full_transactions,
+            tree_states: downloaded.tree_states,
+            mempool: downloaded.mempool,
+            total: downloaded.total(),
+        }
+    }
+}
+
+/// `check`, and the `transparent_check` of `sync`: the inconsistencies found, by whether they
+/// were repaired
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checked {
+    pub consistent: bool,
+    pub repaired: Vec<String>,
+    pub unrepaired: Vec<String>,
+    /// Checks that couldn't be run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unchecked: Vec<String>,
+}
+
+impl From<&ConsistencyReport> for Checked {
+    fn from(report: &ConsistencyReport) -> Self {
+        let (repaired, unrepaired): (Vec<_>, Vec<_>) =
+            report.inconsistencies.iter().partition(|i| i.repaired);
+        Checked {
+            consistent: report.is_consistent(),
+            repaired: repaired
+                .into_iter()
+                .map(|i| i.description.clone())
+                .collect(),
+            unrepaired: unrepaired
+                .into_iter()
+                .map(|i| i.description.clone())
+                .collect(),
+            unchecked: report.unchecked.clone(),
+        }
+    }
+}
+
+/// `sync` and `rescan`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Synced {
+    /// "success" or "failure"
+    pub result: String,
+    pub latest_block: u64,
+    pub total_blocks_synced: u64,
+    pub bytes_downloaded: Downloaded,
+    pub transparent_check: Checked,
+}
+
+impl From<&SyncResult> for Synced {
+    fn from(synced: &SyncResult) -> Self {
+        Synced {
+            result: if synced.success { "success" } else { "failure" }.to_string(),
+            latest_block: synced.latest_block,
+            total_blocks_synced: synced.total_blocks_synced,
+            bytes_downloaded: synced.bytes_downloaded.into(),
+            transparent_check: Checked::from(&synced.transparent_check),
+        }
+    }
+}
+
+/// `syncstatus`. How far the sync has got is only there while one is in progress.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncStatus {
+    pub sync_id: u64,
+    pub in_progress: bool,
+    pub last_error: Option<String>,
+    #[serde(default, flatten)]
+    pub progress: Option<SyncProgress>,
+    /// The block ranges an earlier, interrupted sync had covered, as `lowest-highest`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resumed_ranges: Vec<String>,
+}
+
+/// How far a sync in progress has got
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncProgress {
+    pub start_block: u64,
+    pub end_block: u64,
+    pub synced_blocks: u64,
+    pub trial_decryptions_blocks: u64,
+    pub txn_scan_blocks: u64,
+    pub witnesses_updated: u64,
+    pub total_blocks: u64,
+    pub batch_num: usize,
+    pub batch_total: usize,
+    pub bytes_downloaded: Downloaded,
+    pub sync_interrupt: bool,
+}
+
+impl SyncStatus {
+    pub fn new(status: &BatchSyncStatus, sync_interrupt: bool) -> Self {
+        SyncStatus {
+            sync_id: status.sync_id,
+            in_progress: status.in_progress,
+            last_error: status.last_error.clone(),
+            progress: status.in_progress.then(|| SyncProgress {
+                start_block: status.start_block,
+                end_block: status.end_block,
+                synced_blocks: status.blocks_done,
+                trial_decryptions_blocks: status.trial_dec_done,
+                txn_scan_blocks: status.txn_scan_done,
+                witnesses_updated: *status.witnesses_updated.values().min().unwrap_or(&0),
+                total_blocks: status.blocks_total,
+                batch_num: status.batch_num,
+                batch_total: status.batch_total,
+                bytes_downloaded: status.bytes_downloaded.into(),
+                sync_interrupt,
+            }),
+            resumed_ranges: status
+                .resumed_ranges
+                .iter()
+                .map(|(lowest, highest)| format!("{}-{}", lowest, highest))
+                .collect(),
+        }
+    }
+}
+
+/// `sendmany`: the transactions the payout is made in, and unless it is a dry run, what became
+/// of each row
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayoutSummary {
+    pub recipients: usize,
+    pub transactions: Vec<PlannedTransaction>,
+    pub total_amount: u64,
+    pub total_fees: u64,
+    pub spendable_balance: u64,
+    pub funded: bool,
+    pub dry_run: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub results: Option<Vec<PaidRow>>,
+}
+
+/// A transaction of a payout
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedTransaction {
+    /// The lines of the rows it pays
+    pub lines: Vec<u64>,
+    pub amount: u64,
+    pub fee: u64,
+    /// `None` if what is left can't pay for it
+    pub notes_spent: Option<usize>,
+    pub logical_actions: usize,
+    pub within_action_limit: bool,
+    pub funded: bool,
+}
+
+/// What became of a row of a payout: the transaction it was paid in, or why it wasn't paid
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaidRow {
+    pub line: u64,
+    pub address: String,
+    pub amount: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl PayoutSummary {
+    pub fn new(plan: &PayoutPlan, dry_run: bool) -> Self {
+        PayoutSummary {
+            recipients: plan.transactions.iter().map(|t| t.payouts.len()).sum(),
+            transactions: plan
+                .transactions
+                .iter()
+                .map(|transaction| PlannedTransaction {
+                    lines: transaction.payouts.iter().map(|p| p.line).collect(),
+                    amount: transaction.amount(),
+                    fee: PayoutPlan::fee_per_transaction(),
+                    notes_spent: transaction
+                        .notes
+                        .as_ref()
+                        .map(|notes| notes.orchard.len() + notes.sapling.len()),
+                    logical_actions: transaction.logical_actions,
+                    within_action_limit: transaction.is_within_action_limit(),
+                    funded: transaction.is_funded(),
+                })
+                .collect(),
+            total_amount: plan.total_amount(),
+            total_fees: plan.total_fees(),
+            spendable_balance: plan.spendable,
+            funded: plan.is_funded(),
+            dry_run,
+            results: None,
+        }
+    }
+}
+
+impl From<&PayoutResult> for PaidRow {
+    fn from(result: &PayoutResult) -> Self {
+        PaidRow {
+            line: result.payout.line,
+            address: result.payout.address.clone(),
+            amount: result.payout.amount,
+            txid: result.txid.clone().ok(),
+            error: result.txid.clone().err(),
+        }
+    }
+}
+
+/// `schedule add`: the new payment's id
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledId {
+    pub id: u64,
+}
+
+/// `schedule list`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleListing {
+    pub payments: Vec<SchedulePayment>,
+    pub journal: Vec<ScheduleRun>,
+}
+
+/// A scheduled payment
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchedulePayment {
+    pub id: u64,
+    pub address: String,
+    pub amount: u64,
+    pub memo: Option<String>,
+    /// "height" or "time"
+    pub clock: String,
+    pub start: u64,
+    pub every: Option<u64>,
+    pub runs: Option<u64>,
+    pub next_run: u64,
+    pub next_due_at: Option<u64>,
+    pub cancelled: bool,
+    pub finished: bool,
+    pub failures: u32,
+    /// When the next run may be tried again, if it has failed
+    pub retry_at: Option<u64>,
+}
+
+impl From<&ScheduledPayment> for SchedulePayment {
+    fn from(payment: &ScheduledPayment) -> Self {
+        SchedulePayment {
+            id: payment.id,
+            address: payment.address.clone(),
+            amount: payment.amount,
+            memo: payment.memo.clone(),
+            clock: payment.clock.name().to_string(),
+            start: payment.start,
+            every: payment.every,
+            runs: payment.runs,
+            next_run: payment.next_run,
+            next_due_at: if payment.cancelled {
+                None
+            } else {
+                payment.due_at(payment.next_run)
+            },
+            cancelled: payment.cancelled,
+            finished: payment.is_finished(),
+            failures: payment.failures,
+            retry_at: (payment.failures > 0).then_some(payment.retry_at),
+        }
+    }
+}
+
+/// A run of a scheduled payment, in the journal of `schedule list`, and as `schedule run`
+/// made it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleRun {
+    pub payment_id: u64,
+    pub run: u64,
+    pub executed_at: u64,
+    /// "pending", "paid", "failed", "submitted" or "gave_up"
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiry_height: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<&JournalEntry> for ScheduleRun {
+    fn from(entry: &JournalEntry) -> Self {
+        let (outcome, txid, expiry_height, error) = match &entry.outcome {
+            RunOutcome::Pending => ("pending", None, None, None),
+            RunOutcome::Paid { txid } => ("paid", Some(txid.clone()), None, None),
+            RunOutcome::Failed { error } => ("failed", None, None, Some(error.clone())),
+            RunOutcome::Submitted {
+                txid,
+                expiry_height,
+            } => ("submitted", Some(txid.clone()), Some(*expiry_height), None),
+            RunOutcome::GaveUp { error } => ("gave_up", None, None, Some(error.clone())),
+        };
+        ScheduleRun {
+            payment_id: entry.payment_id,
+            run: entry.run,
+            executed_at: entry.executed_at,
+            outcome: outcome.to_string(),
+            txid,
+            expiry_height,
+            error,
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Where the sync engine runs its concurrent tasks.
+//!
+//! Natively, tasks are spawned onto the tokio runtime the caller is running on. In a browser
+//! (`wasm32`) there are no threads and no tokio runtime, so tasks go to the JS event loop
+//! through `wasm-bindgen-futures`. Either way, a task runs to completion even if its
+//! [`JoinHandle`] is dropped.
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Awaits the output of a spawned task
+pub struct JoinHandle<T> {
+    #[cfg(not(target_arch = "wasm32"))]
+    inner: tokio::task::JoinHandle<T>,
+    #[cfg(target_arch = "wasm32")]
+    inner: futures::channel::oneshot::Receiver<T>,
+}
+
+/// The task panicked, or was cancelled, before producing its output
+#[derive(Debug)]
+pub struct JoinError(String);
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.inner)
+            .poll(cx)
+            .map_err(|e| JoinError(e.to_string()))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    JoinHandle {
+        inner: tokio::spawn(future),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let (transmitter, receiver) = futures::channel::oneshot::channel();
+    wasm_bindgen_futures::spawn_local(async move {
+        // The handle may have been dropped, and nobody wants the output
+        let _ = transmitter.send(future.await);
+    });
+    JoinHandle { inner: receiver }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn spawned_tasks_hand_back_their_output() {
+        assert_eq!(spawn(async { 1 + 1 }).await.unwrap(), 2);
+        let panicked = spawn(async { panic!("boom") }).await;
+        assert!(panicked.is_err());
+    }
+}
@@ -1,41 +1,624 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use crate::compact_formats::compact_tx_streamer_client::CompactTxStreamerClient;
 use crate::compact_formats::{
-    BlockId, BlockRange, ChainSpec, CompactBlock, Empty, LightdInfo, RawTransaction,
+    AddressList, BlockId, BlockRange, ChainSpec, CompactBlock, CompactTx, Empty, Exclude,
+    GetAddressUtxosArg, GetAddressUtxosReply, LightdInfo, RawTransaction,
     TransparentAddressBlockFilter, TreeState, TxFilter,
 };
+use crate::executor::{self, JoinHandle};
 use futures::future::join_all;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 
+use http::Uri;
+#[cfg(not(target_arch = "wasm32"))]
 use http_body::combinators::UnsyncBoxBody;
-use hyper::{client::HttpConnector, Uri};
+#[cfg(not(target_arch = "wasm32"))]
+use http_body::Body as _;
+#[cfg(not(target_arch = "wasm32"))]
+use hyper::client::HttpConnector;
+use log::warn;
+use prost::Message;
+use sha2::{Digest, Sha256};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::net::TcpStream;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::sync::oneshot;
-use tokio::task::JoinHandle;
-use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier, ServerName};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_rustls::rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_socks::tcp::Socks5Stream;
 use tonic::Request;
 use tonic::Status;
-use tower::{util::BoxCloneService, ServiceExt};
+#[cfg(not(target_arch = "wasm32"))]
+use tower::{util::BoxCloneService, Service, ServiceExt};
 use zcash_primitives::consensus::{BlockHeight, BranchId, Parameters};
 use zcash_primitives::transaction::{Transaction, TxId};
+use zingoconfig::{ChainType, GrpcTransport, ProxyConfig, RateLimits, TlsConfig, ZingoConfig};
 
+mod grpc_web;
+use grpc_web::GrpcWebService;
+
+/// What our gRPC clients are built on: natively, either transport behind one type
+#[cfg(not(target_arch = "wasm32"))]
 type UnderlyingService = BoxCloneService<
     http::Request<UnsyncBoxBody<prost::bytes::Bytes, Status>>,
-    http::Response<hyper::Body>,
-    hyper::Error,
+    http::Response<UnsyncBoxBody<prost::bytes::Bytes, BoxError>>,
+    BoxError,
 >;
+/// What our gRPC clients are built on: in a browser, gRPC-web is all there is
+#[cfg(target_arch = "wasm32")]
+type UnderlyingService = GrpcWebService;
+
+/// Bytes received from lightwalletd, by kind of data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BytesDownloaded {
+    pub compact_blocks: u64,
+    pub full_transactions: u64,
+    pub tree_states: u64,
+    /// Mempool transactions, as the mempool monitor polls their compact form or streams them
+    pub mempool: u64,
+}
+
+impl BytesDownloaded {
+    pub fn total(&self) -> u64 {
+        self.compact_blocks + self.full_transactions + self.tree_states + self.mempool
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            "compact_blocks" => self.compact_blocks,
+            "full_transactions" => self.full_transactions,
+            "tree_states" => self.tree_states,
+            "mempool" => self.mempool,
+            "total" => self.total(),
+        }
+    }
+}
+
+/// Counts the bytes a connector and its clones receive, as they arrive.
+#[derive(Debug, Default)]
+struct ByteCounters {
+    compact_blocks: AtomicU64,
+    full_transactions: AtomicU64,
+    tree_states: AtomicU64,
+    mempool: AtomicU64,
+}
+
+impl ByteCounters {
+    fn read(&self) -> BytesDownloaded {
+        BytesDownloaded {
+            compact_blocks: self.compact_blocks.load(Ordering::SeqCst),
+            full_transactions: self.full_transactions.load(Ordering::SeqCst),
+            tree_states: self.tree_states.load(Ordering::SeqCst),
+            mempool: self.mempool.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_pem_certs(path: &std::path::Path) -> Result<Vec<Vec<u8>>, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+    rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+        .map_err(|e| format!("Couldn't read certificates from {}: {}", path.display(), e))
+}
 
+#[cfg(not(target_arch = "wasm32"))]
+fn read_pem_key(path: &std::path::Path) -> Result<PrivateKey, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Couldn't open {}: {}", path.display(), e))?;
+    rustls_pemfile::read_all(&mut std::io::BufReader::new(file))
+        .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or(format!("No private key in {}", path.display()))
+}
+
+/// Trusts the server's certificate because it matches one of the pinned hashes, rather than
+/// because a trusted CA issued it, so that a self-signed certificate can be pinned. The name
+/// and validity period of the certificate are still checked.
+#[cfg(not(target_arch = "wasm32"))]
+struct PinnedCertVerifier {
+    pinned_sha256: Vec<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        now: std::time::SystemTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        use tokio_rustls::rustls::Error::{General, InvalidCertificateData};
+
+        let (_, cert) = x509_parser::parse_x509_certificate(&end_entity.0)
+            .map_err(|e| InvalidCertificateData(format!("{}", e)))?;
+        let cert_hash = hex::encode(Sha256::digest(&end_entity.0));
+        let spki_hash = hex::encode(Sha256::digest(cert.public_key().raw));
+        if !self
+            .pinned_sha256
+            .iter()
+            .any(|pin| pin == &cert_hash || pin == &spki_hash)
+        {
+            return Err(General(format!(
+                "Server certificate (sha256 {}) doesn't match any pinned hash",
+                cert_hash
+            )));
+        }
+
+        if !certificate_names(&cert, server_name) {
+            return Err(InvalidCertificateData(format!(
+                "Pinned certificate isn't valid for {:?}",
+                server_name
+            )));
+        }
+
+        let now = now
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs() as i64)
+            .unwrap_or(0);
+        let validity = cert.validity();
+        if now < validity.not_before.timestamp() || now > validity.not_after.timestamp() {
+            return Err(InvalidCertificateData(format!(
+                "Pinned certificate is only valid from {} to {}",
+                validity.not_before, validity.not_after
+            )));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Whether the certificate was issued for `server_name`, by its subject alternative names or,
+/// failing any, its common name.
+#[cfg(not(target_arch = "wasm32"))]
+fn certificate_names(
+    cert: &x509_parser::certificate::X509Certificate,
+    server_name: &ServerName,
+) -> bool {
+    use x509_parser::extensions::GeneralName;
+
+    let alternative_names = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|extension| extension.value.general_names.clone())
+        .unwrap_or_default();
+    match server_name {
+        ServerName::DnsName(dns_name) => {
+            let dns_names = alternative_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let dns_names = if dns_names.is_empty() {
+                cert.subject()
+                    .iter_common_name()
+                    .filter_map(|common_name| common_name.as_str().ok())
+                    .map(str::to_string)
+                    .collect()
+            } else {
+                dns_names
+            };
+            dns_names
+                .iter()
+                .any(|pattern| dns_name_matches(pattern, dns_name.as_ref()))
+        }
+        ServerName::IpAddress(ip_address) => {
+            let octets = match ip_address {
+                std::net::IpAddr::V4(ip) => ip.octets().to_vec(),
+                std::net::IpAddr::V6(ip) => ip.octets().to_vec(),
+            };
+            alternative_names
+                .iter()
+                .any(|name| matches!(name, GeneralName::IPAddress(ip) if *ip == octets.as_slice()))
+        }
+        _ => false,
+    }
+}
+
+/// Matches a host name against a certificate's name, which may have a wildcard first label
+#[cfg(not(target_arch = "wasm32"))]
+fn dns_name_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => pattern == name,
+    }
+}
+
+/// The TLS settings of our connections to lightwalletd. With pins, the pin is what the server
+/// is trusted on; without, its certificate must chain to a webpki or extra root.
+#[cfg(not(target_arch = "wasm32"))]
+fn tls_client_config(tls_config: &TlsConfig) -> Result<ClientConfig, Box<dyn std::error::Error>> {
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let builder = if tls_config.pinned_sha256.is_empty() {
+        let mut roots = RootCertStore::empty();
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor_ref| {
+            tokio_rustls::rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor_ref.subject,
+                anchor_ref.spki,
+                anchor_ref.name_constraints,
+            )
+        }));
+
+        #[cfg(test)]
+        add_test_cert_to_roots(&mut roots);
+
+        for pem in &tls_config.extra_root_pems {
+            let (_added, ignored) = roots.add_parsable_certificates(&read_pem_certs(pem)?);
+            if ignored > 0 {
+                warn!(
+                    "Ignored {} unparseable certificates in {}",
+                    ignored,
+                    pem.display()
+                );
+            }
+        }
+        builder.with_root_certificates(roots)
+    } else {
+        builder.with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+            pinned_sha256: tls_config
+                .pinned_sha256
+                .iter()
+                .map(|pin| pin.to_lowercase())
+                .collect(),
+        }))
+    };
+    Ok(match &tls_config.client_cert {
+        Some((cert_chain, key)) => builder.with_single_cert(
+            read_pem_certs(cert_chain)?
+                .into_iter()
+                .map(Certificate)
+                .collect(),
+            read_pem_key(key)?,
+        )?,
+        None => builder.with_no_client_auth(),
+    })
+}
+
+/// An HTTP client for non-gRPC requests, going through the proxy if one is given.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn http_client(proxy: Option<&ProxyConfig>) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder();
+    let builder = match proxy {
+        Some(proxy) => builder.proxy(
+            // socks5h, so that the proxy resolves names and no DNS queries leak
+            reqwest::Proxy::all(format!("socks5h://{}", proxy.socks5_address))
+                .map_err(|e| format!("Invalid proxy {}: {}", proxy.socks5_address, e))?,
+        ),
+        None => builder,
+    };
+    builder.build().map_err(|e| format!("{}", e))
+}
+
+/// An HTTP client for non-gRPC requests. Browsers don't let pages pick a proxy, so a strict
+/// one can't be honoured at all.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn http_client(proxy: Option<&ProxyConfig>) -> Result<reqwest::Client, String> {
+    match proxy {
+        Some(proxy) if proxy.strict => Err(format!(
+            "Can't connect through proxy {} from a browser, and strict mode refuses direct connections",
+            proxy.socks5_address
+        )),
+        _ => Ok(reqwest::Client::new()),
+    }
+}
+
+/// Makes the TCP connections under our gRPC clients, either directly or through a SOCKS5 proxy.
+#[cfg(not(target_arch = "wasm32"))]
 #[derive(Clone)]
+enum ClientConnector {
+    Direct(HttpConnector),
+    Socks5 {
+        proxy: ProxyConfig,
+        direct: HttpConnector,
+    },
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ClientConnector {
+    fn new(proxy: Option<ProxyConfig>) -> Self {
+        let mut http_connector = HttpConnector::new();
+        http_connector.enforce_http(false);
+        match proxy {
+            Some(proxy) => Self::Socks5 {
+                proxy,
+                direct: http_connector,
+            },
+            None => Self::Direct(http_connector),
+        }
+    }
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Service<Uri> for ClientConnector {
+    type Response = TcpStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        match self {
+            Self::Direct(connector)
+            | Self::Socks5 {
+                direct: connector, ..
+            } => connector.poll_ready(cx).map_err(Into::into),
+        }
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self {
+            Self::Direct(connector) => {
+                let connecting = connector.call(uri);
+                Box::pin(async move { connecting.await.map_err(Into::into) })
+            }
+            Self::Socks5 { proxy, direct } => {
+                Box::pin(connect_through_proxy(proxy.clone(), direct.clone(), uri))
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn connect_through_proxy(
+    proxy: ProxyConfig,
+    mut direct: HttpConnector,
+    uri: Uri,
+) -> Result<TcpStream, BoxError> {
+    let host = uri.host().ok_or("Server URI has no host")?.to_string();
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    });
+    // Hand the proxy the host name rather than an address, so that it does the DNS lookup
+    match Socks5Stream::connect(proxy.socks5_address.as_str(), (host.as_str(), port)).await {
+        Ok(stream) => Ok(stream.into_inner()),
+        Err(e) if proxy.strict => Err(format!(
+            "Couldn't reach {} through proxy {}, and strict mode refuses direct connections: {}",
+            uri, proxy.socks5_address, e
+        )
+        .into()),
+        Err(e) => {
+            warn!(
+                "Couldn't reach {} through proxy {}, connecting directly: {}",
+                uri, proxy.socks5_address, e
+            );
+            direct.call(uri).await.map_err(Into::into)
+        }
+    }
+}
+
+/// How many blocks a server's tip may differ from the median tip of all reachable servers
+/// before we consider it to be lagging (or lying about being ahead).
+pub const MAX_SERVER_TIP_DIVERGENCE: u64 = 2;
+
+/// The result of a single health check against a lightwalletd server.
+#[derive(Clone, Debug)]
+pub struct ServerStatus {
+    pub uri: http::Uri,
+    pub latest_block_height: Option<u64>,
+    pub latency_ms: Option<u128>,
+    pub error: Option<String>,
+}
+
+impl ServerStatus {
+    pub fn is_reachable(&self) -> bool {
+        self.error.is_none() && self.latest_block_height.is_some()
+    }
+
+    /// A server is trusted if it is reachable and its tip agrees with the other servers.
+    pub fn is_trusted(&self, median_tip: Option<u64>) -> bool {
+        match (self.latest_block_height, median_tip) {
+            (Some(height), Some(median)) if self.error.is_none() => {
+                height.abs_diff(median) <= MAX_SERVER_TIP_DIVERGENCE
+            }
+            _ => false,
+        }
+    }
+
+    pub fn describe(&self, median_tip: Option<u64>) -> &'static str {
+        if !self.is_reachable() {
+            "unreachable"
+        } else if self.is_trusted(median_tip) {
+            "ok"
+        } else if self.latest_block_height < median_tip {
+            "lagging"
+        } else {
+            "ahead"
+        }
+    }
+}
+
+/// The median tip height reported by the reachable servers, if any are reachable. With an even
+/// number of them it is the lower of the middle two, so that a server lying about being ahead
+/// can't pull the median up.
+pub fn median_server_tip(statuses: &[ServerStatus]) -> Option<u64> {
+    let mut heights = statuses
+        .iter()
+        .filter(|status| status.is_reachable())
+        .filter_map(|status| status.latest_block_height)
+        .collect::<Vec<_>>();
+    heights.sort_unstable();
+
+    heights.get(heights.len().saturating_sub(1) / 2).copied()
+}
+
+/// gRPC over HTTP/2, with these TLS settings and through the proxy if one is given
+#[cfg(not(target_arch = "wasm32"))]
+fn native_service(
+    uri: &Uri,
+    proxy: Option<ProxyConfig>,
+    tls_config: &TlsConfig,
+) -> Result<UnderlyingService, Box<dyn std::error::Error>> {
+    let client_connector = ClientConnector::new(proxy);
+    if uri.scheme_str() == Some("https") {
+        let tls = tls_client_config(tls_config)?;
+        let connector = tower::ServiceBuilder::new()
+            .layer_fn(move |s| {
+                let tls = tls.clone();
+
+                hyper_rustls::HttpsConnectorBuilder::new()
+                    .with_tls_config(tls)
+                    .https_or_http()
+                    .enable_http2()
+                    .wrap_connector(s)
+            })
+            .service(client_connector);
+        let client = Box::new(hyper::Client::builder().build(connector));
+        let uri = uri.clone();
+        let svc = tower::ServiceBuilder::new()
+            //Here, we take all the pieces of our uri, and add in the path from the Requests's uri
+            .map_request(move |mut req: http::Request<tonic::body::BoxBody>| {
+                let uri = Uri::builder()
+                    .scheme(uri.scheme().unwrap().clone())
+                    .authority(uri.authority().unwrap().clone())
+                    //here. The Request's uri contains the path to the GRPC sever and
+                    //the method being called
+                    .path_and_query(req.uri().path_and_query().unwrap().clone())
+                    .build()
+                    .unwrap();
+
+                *req.uri_mut() = uri;
+                req
+            })
+            .service(client);
+
+        Ok(svc
+            .map_response(|response: http::Response<hyper::Body>| {
+                response.map(|body| UnsyncBoxBody::new(body.map_err(BoxError::from)))
+            })
+            .map_err(BoxError::from)
+            .boxed_clone())
+    } else {
+        let connector = tower::ServiceBuilder::new().service(client_connector);
+        let client = Box::new(hyper::Client::builder().http2_only(true).build(connector));
+        let uri = uri.clone();
+        let svc = tower::ServiceBuilder::new()
+            //Here, we take all the pieces of our uri, and add in the path from the Requests's uri
+            .map_request(move |mut req: http::Request<tonic::body::BoxBody>| {
+                let uri = Uri::builder()
+                    .scheme(uri.scheme().unwrap().clone())
+                    .authority(uri.authority().unwrap().clone())
+                    //here. The Request's uri contains the path to the GRPC sever and
+                    //the method being called
+                    .path_and_query(req.uri().path_and_query().unwrap().clone())
+                    .build()
+                    .unwrap();
+
+                *req.uri_mut() = uri;
+                req
+            })
+            .service(client);
+
+        Ok(svc
+            .map_response(|response: http::Response<hyper::Body>| {
+                response.map(|body| UnsyncBoxBody::new(body.map_err(BoxError::from)))
+            })
+            .map_err(BoxError::from)
+            .boxed_clone())
+    }
+}
+
+/// Talks to one lightwalletd server, connecting the way the `ZingoConfig` it was made from says.
+#[derive(Clone, Debug)]
 pub struct GrpcConnector {
     uri: http::Uri,
+    proxy: Option<ProxyConfig>,
+    /// Ignored in a browser, which does TLS itself
+    tls: TlsConfig,
+    /// Ignored in a browser, where only gRPC-web works
+    transport: GrpcTransport,
+    rate_limits: RateLimits,
+    /// When the last block-range request was made, by this connector or one of its clones
+    last_block_range_request: Arc<tokio::sync::Mutex<Option<Instant>>>,
+    bytes: Arc<ByteCounters>,
 }
 
 impl GrpcConnector {
+    /// A direct connection to `uri`
     pub fn new(uri: http::Uri) -> Self {
-        Self { uri }
+        Self {
+            uri,
+            proxy: None,
+            tls: TlsConfig::default(),
+            transport: GrpcTransport::default(),
+            rate_limits: RateLimits::default(),
+            last_block_range_request: Arc::new(tokio::sync::Mutex::new(None)),
+            bytes: Arc::new(ByteCounters::default()),
+        }
+    }
+
+    /// A connection to the config's current server, over its transport, with its TLS settings
+    /// and within its rate limits, through its proxy if it has one. The new connector and its
+    /// clones count the bytes they download apart from any other connector.
+    pub fn from_config(config: &ZingoConfig) -> Self {
+        Self {
+            uri: config.get_lightwalletd_uri(),
+            proxy: config.proxy.clone(),
+            tls: config.tls.clone(),
+            transport: config.grpc_transport,
+            rate_limits: config.rate_limits,
+            last_block_range_request: Arc::new(tokio::sync::Mutex::new(None)),
+            bytes: Arc::new(ByteCounters::default()),
+        }
+    }
+
+    /// The same connection settings, for another server. Its requests count against the same
+    /// rate limit and its downloads into the same byte counts.
+    pub fn with_uri(&self, uri: http::Uri) -> Self {
+        Self {
+            uri,
+            ..self.clone()
+        }
+    }
+
+    pub fn uri(&self) -> &http::Uri {
+        &self.uri
+    }
+
+    /// What this connector and its clones have downloaded so far
+    pub fn bytes_downloaded(&self) -> BytesDownloaded {
+        self.bytes.read()
+    }
+
+    /// Waits until another block-range request is allowed under the configured rate.
+    async fn wait_for_block_range_slot(&self) {
+        let interval = match self.rate_limits.block_range_requests_per_minute {
+            Some(per_minute) if per_minute > 0 => Duration::from_secs(60) / per_minute,
+            _ => return,
+        };
+
+        let mut last_request = self.last_block_range_request.lock().await;
+        if let Some(last) = *last_request {
+            tokio::time::sleep_until((last + interval).into()).await;
+        }
+        *last_request = Some(Instant::now());
     }
 
     pub(crate) fn get_client(
@@ -43,83 +626,32 @@ impl GrpcConnector {
     ) -> impl std::future::Future<
         Output = Result<CompactTxStreamerClient<UnderlyingService>, Box<dyn std::error::Error>>,
     > {
-        let uri = Arc::new(self.uri.clone());
+        let uri = self.uri.clone();
+        let proxy = self.proxy.clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        let (transport, tls) = (self.transport, self.tls.clone());
         async move {
-            let mut http_connector = HttpConnector::new();
-            http_connector.enforce_http(false);
-            if uri.scheme_str() == Some("https") {
-                let mut roots = RootCertStore::empty();
-                roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(
-                    |anchor_ref| {
-                        tokio_rustls::rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                            anchor_ref.subject,
-                            anchor_ref.spki,
-                            anchor_ref.name_constraints,
-                        )
-                    },
-                ));
-
-                #[cfg(test)]
-                add_test_cert_to_roots(&mut roots);
-
-                let tls = ClientConfig::builder()
-                    .with_safe_defaults()
-                    .with_root_certificates(roots)
-                    .with_no_client_auth();
-                let connector = tower::ServiceBuilder::new()
-                    .layer_fn(move |s| {
-                        let tls = tls.clone();
-
-                        hyper_rustls::HttpsConnectorBuilder::new()
-                            .with_tls_config(tls)
-                            .https_or_http()
-                            .enable_http2()
-                            .wrap_connector(s)
-                    })
-                    .service(http_connector);
-                let client = Box::new(hyper::Client::builder().build(connector));
-                let uri = uri.clone();
-                let svc = tower::ServiceBuilder::new()
-                    //Here, we take all the pieces of our uri, and add in the path from the Requests's uri
-                    .map_request(move |mut req: http::Request<tonic::body::BoxBody>| {
-                        let uri = Uri::builder()
-                            .scheme(uri.scheme().unwrap().clone())
-                            .authority(uri.authority().unwrap().clone())
-                            //here. The Request's uri contains the path to the GRPC sever and
-                            //the method being called
-                            .path_and_query(req.uri().path_and_query().unwrap().clone())
-                            .build()
-                            .unwrap();
-
-                        *req.uri_mut() = uri;
-                        req
-                    })
-                    .service(client);
-
-                Ok(CompactTxStreamerClient::new(svc.boxed_clone()))
-            } else {
-                let connector = tower::ServiceBuilder::new().service(http_connector);
-                let client = Box::new(hyper::Client::builder().http2_only(true).build(connector));
-                let uri = uri.clone();
-                let svc = tower::ServiceBuilder::new()
-                    //Here, we take all the pieces of our uri, and add in the path from the Requests's uri
-                    .map_request(move |mut req: http::Request<tonic::body::BoxBody>| {
-                        let uri = Uri::builder()
-                            .scheme(uri.scheme().unwrap().clone())
-                            .authority(uri.authority().unwrap().clone())
-                            //here. The Request's uri contains the path to the GRPC sever and
-                            //the method being called
-                            .path_and_query(req.uri().path_and_query().unwrap().clone())
-                            .build()
-                            .unwrap();
-
-                        *req.uri_mut() = uri;
-                        req
-                    })
-                    .service(client);
-
-                Ok(CompactTxStreamerClient::new(svc.boxed_clone()))
+            #[cfg(not(target_arch = "wasm32"))]
+            if transport == GrpcTransport::Native {
+                return Ok(CompactTxStreamerClient::new(native_service(
+                    &uri, proxy, &tls,
+                )?));
+            }
+            // gRPC-web goes through a plain HTTP client, which doesn't take these settings
+            #[cfg(not(target_arch = "wasm32"))]
+            if tls != TlsConfig::default() {
+                return Err(
+                    "Extra TLS roots, pins and client certificates can't be used with gRPC-web"
+                        .into(),
+                );
             }
+
+            let svc = GrpcWebService::new(http_client(proxy.as_ref())?, uri);
+            #[cfg(not(target_arch = "wasm32"))]
+            let svc = svc
+                .map_response(|response| response.map(UnsyncBoxBody::new))
+                .boxed_clone();
+            Ok(CompactTxStreamerClient::new(svc))
         }
     }
 
@@ -131,13 +663,12 @@ impl GrpcConnector {
     ) {
         let (transmitter, mut receiver) =
             unbounded_channel::<(u64, oneshot::Sender<Result<TreeState, String>>)>();
-        let uri = self.uri.clone();
+        let connector = self.clone();
 
-        let h = tokio::spawn(async move {
-            let uri = uri.clone();
+        let h = executor::spawn(async move {
             while let Some((height, result_transmitter)) = receiver.recv().await {
                 result_transmitter
-                    .send(Self::get_trees(uri.clone(), height).await)
+                    .send(connector.get_trees(height).await)
                     .unwrap()
             }
         });
@@ -158,10 +689,9 @@ impl GrpcConnector {
             (Vec<String>, u64, u64),
             oneshot::Sender<Vec<UnboundedReceiver<Result<RawTransaction, String>>>>,
         )>();
-        let uri = self.uri.clone();
+        let connector = self.clone();
 
-        let h = tokio::spawn(async move {
-            let uri = uri.clone();
+        let h = executor::spawn(async move {
             if let Ok(((taddrs, start_height, end_height), result_transmitter)) = receiver.await {
                 let mut transaction_receivers = vec![];
                 let mut transaction_receivers_workers = vec![];
@@ -170,13 +700,14 @@ impl GrpcConnector {
                 for taddr in taddrs {
                     let (transaction_s, transaction_receiver) = unbounded_channel();
                     transaction_receivers.push(transaction_receiver);
-                    transaction_receivers_workers.push(tokio::spawn(Self::get_taddr_transactions(
-                        uri.clone(),
-                        taddr,
-                        start_height,
-                        end_height,
-                        transaction_s,
-                    )));
+                    transaction_receivers_workers.push(executor::spawn(
+                        connector.clone().get_taddr_transactions(
+                            taddr,
+                            start_height,
+                            end_height,
+                            transaction_s,
+                        ),
+                    ));
                 }
 
                 // Dispatch a set of receivers
@@ -199,26 +730,27 @@ impl GrpcConnector {
     ) {
         let (transmitter, mut receiver) =
             unbounded_channel::<(TxId, oneshot::Sender<Result<Transaction, String>>)>();
-        let uri = self.uri.clone();
+        let connector = self.clone();
+        // Only allow so many API calls in parallel, both to stay under the OS's limit on
+        // simultaneous connections and to spare metered connections
+        let max_concurrent = self.rate_limits.max_concurrent_transaction_fetches.max(1);
 
-        let h = tokio::spawn(async move {
+        let h = executor::spawn(async move {
             let mut workers = FuturesUnordered::new();
             while let Some((transaction_id, result_transmitter)) = receiver.recv().await {
-                let uri = uri.clone();
-                workers.push(tokio::spawn(async move {
+                let connector = connector.clone();
+                workers.push(executor::spawn(async move {
                     result_transmitter
                         .send(
-                            Self::get_full_transaction(uri.clone(), &transaction_id, network).await,
+                            connector
+                                .get_full_transaction(&transaction_id, network)
+                                .await,
                         )
                         .unwrap()
                 }));
 
-                // Do only 16 API calls in parallel, otherwise it might overflow OS's limit of
-                // number of simultaneous connections
-                if workers.len() > 16 {
-                    while let Some(_r) = workers.next().await {
-                        // Do nothing
-                    }
+                while workers.len() >= max_concurrent {
+                    workers.next().await;
                 }
             }
         });
@@ -232,6 +764,7 @@ impl GrpcConnector {
         end_height: u64,
         senders: &[UnboundedSender<CompactBlock>; 2],
     ) -> Result<(), String> {
+        self.wait_for_block_range_slot().await;
         let mut client = self.get_client().await.map_err(|e| format!("{}", e))?;
 
         let bs = BlockId {
@@ -255,6 +788,9 @@ impl GrpcConnector {
             .into_inner();
 
         while let Some(block) = response.message().await.map_err(|e| format!("{}", e))? {
+            self.bytes
+                .compact_blocks
+                .fetch_add(block.encoded_len() as u64, Ordering::SeqCst);
             senders[0]
                 .send(block.clone())
                 .map_err(|e| format!("{}", e))?;
@@ -264,12 +800,24 @@ impl GrpcConnector {
         Ok(())
     }
 
-    async fn get_full_transaction(
-        uri: http::Uri,
+    pub(crate) async fn get_full_transaction(
+        &self,
         transaction_id: &TxId,
         network: impl Parameters,
     ) -> Result<Transaction, String> {
-        let client = Arc::new(GrpcConnector::new(uri));
+        let response = self.get_raw_transaction(transaction_id).await?;
+
+        Transaction::read(
+            &response.data[..],
+            BranchId::for_height(&network, BlockHeight::from_u32(response.height as u32)),
+        )
+        .map_err(|e| format!("Error parsing Transaction: {}", e))
+    }
+
+    pub(crate) async fn get_raw_transaction(
+        &self,
+        transaction_id: &TxId,
+    ) -> Result<RawTransaction, String> {
         let request = Request::new(TxFilter {
             block: None,
             index: 0,
@@ -278,7 +826,7 @@ impl GrpcConnector {
 
         // log::info!("Full fetching {}", transaction_id);
 
-        let mut client = client
+        let mut client = self
             .get_client()
             .await
             .map_err(|e| format!("Error getting client: {:?}", e))?;
@@ -288,23 +836,20 @@ impl GrpcConnector {
             .await
             .map_err(|e| format!("{}", e))?
             .into_inner();
+        self.bytes
+            .full_transactions
+            .fetch_add(response.encoded_len() as u64, Ordering::SeqCst);
 
-        Transaction::read(
-            &response.data[..],
-            BranchId::for_height(&network, BlockHeight::from_u32(response.height as u32)),
-        )
-        .map_err(|e| format!("Error parsing Transaction: {}", e))
+        Ok(response)
     }
 
     async fn get_taddr_transactions(
-        uri: http::Uri,
+        self,
         taddr: String,
         start_height: u64,
         end_height: u64,
         transactions_sender: UnboundedSender<Result<RawTransaction, String>>,
     ) -> Result<(), String> {
-        let client = Arc::new(GrpcConnector::new(uri));
-
         // Make sure start_height is smaller than end_height, because the API expects it like that
         let (start_height, end_height) = if start_height < end_height {
             (start_height, end_height)
@@ -327,7 +872,7 @@ impl GrpcConnector {
         };
         let request = Request::new(args.clone());
 
-        let mut client = client
+        let mut client = self
             .get_client()
             .await
             .map_err(|e| format!("Error getting client: {:?}", e))?;
@@ -357,10 +902,9 @@ impl GrpcConnector {
         Ok(())
     }
 
-    pub async fn get_info(uri: http::Uri) -> Result<LightdInfo, String> {
-        let client = Arc::new(GrpcConnector::new(uri.clone()));
-
-        let mut client = client
+    pub async fn get_info(&self) -> Result<LightdInfo, String> {
+        let uri = &self.uri;
+        let mut client = self
             .get_client()
             .await
             .map_err(|e| format!("Error getting client: {:?}", e))?;
@@ -374,13 +918,49 @@ impl GrpcConnector {
         Ok(response.into_inner())
     }
 
+    /// Health check the server with `GetLightdInfo`, making sure it serves the chain we expect.
+    pub async fn check_server(&self, chain: ChainType) -> ServerStatus {
+        let uri = self.uri.clone();
+        let started = std::time::Instant::now();
+        match self.get_info().await {
+            Ok(info) if info.chain_name != chain.to_string() => ServerStatus {
+                uri,
+                latest_block_height: Some(info.block_height),
+                latency_ms: Some(started.elapsed().as_millis()),
+                error: Some(format!(
+                    "Server is on chain {}, expected {}",
+                    info.chain_name, chain
+                )),
+            },
+            Ok(info) => ServerStatus {
+                uri,
+                latest_block_height: Some(info.block_height),
+                latency_ms: Some(started.elapsed().as_millis()),
+                error: None,
+            },
+            Err(e) => ServerStatus {
+                uri,
+                latest_block_height: None,
+                latency_ms: None,
+                error: Some(e),
+            },
+        }
+    }
+
+    /// Health check each of `uris`, connecting to them as this connector does.
+    pub async fn check_servers(&self, uris: &[http::Uri], chain: ChainType) -> Vec<ServerStatus> {
+        join_all(
+            uris.iter()
+                .map(|uri| async move { self.with_uri(uri.clone()).check_server(chain).await }),
+        )
+        .await
+    }
+
     pub async fn monitor_mempool(
-        uri: http::Uri,
+        &self,
         mempool_transmitter: UnboundedSender<RawTransaction>,
     ) -> Result<(), String> {
-        let client = Arc::new(GrpcConnector::new(uri));
-
-        let mut client = client
+        let mut client = self
             .get_client()
             .await
             .map_err(|e| format!("Error getting client: {:?}", e))?;
@@ -393,6 +973,9 @@ impl GrpcConnector {
             .map_err(|e| format!("{}", e))?
             .into_inner();
         while let Some(r_transmitter) = response.message().await.map_err(|e| format!("{}", e))? {
+            self.bytes
+                .mempool
+                .fetch_add(r_transmitter.encoded_len() as u64, Ordering::SeqCst);
             mempool_transmitter
                 .send(r_transmitter)
                 .map_err(|e| format!("{}", e))?;
@@ -401,9 +984,81 @@ impl GrpcConnector {
         Ok(())
     }
 
-    pub async fn get_trees(uri: http::Uri, height: u64) -> Result<TreeState, String> {
-        let client = Arc::new(GrpcConnector::new(uri.clone()));
-        let mut client = client
+    /// The compact form of every transaction in the server's mempool, except those whose txids
+    /// start with one of the `exclude` entries. Unlike `monitor_mempool`, this returns as soon
+    /// as the current mempool has been sent.
+    pub async fn get_mempool_compact_transactions(
+        &self,
+        exclude: Vec<Vec<u8>>,
+    ) -> Result<Vec<CompactTx>, String> {
+        let uri = &self.uri;
+        let mut client = self
+            .get_client()
+            .await
+            .map_err(|e| format!("Error getting client: {:?}", e))?;
+
+        let mut response = client
+            .get_mempool_tx(Request::new(Exclude { txid: exclude }))
+            .await
+            .map_err(|e| format!("Error with get_mempool_tx response at {uri}: {:?}", e))?
+            .into_inner();
+
+        let mut compact_transactions = vec![];
+        while let Some(compact_transaction) =
+            response.message().await.map_err(|e| format!("{}", e))?
+        {
+            self.bytes
+                .mempool
+                .fetch_add(compact_transaction.encoded_len() as u64, Ordering::SeqCst);
+            compact_transactions.push(compact_transaction);
+        }
+
+        Ok(compact_transactions)
+    }
+
+    /// Every output the server considers unspent for these transparent addresses
+    pub async fn get_address_utxos(
+        &self,
+        addresses: Vec<String>,
+    ) -> Result<Vec<GetAddressUtxosReply>, String> {
+        let uri = &self.uri;
+        let mut client = self
+            .get_client()
+            .await
+            .map_err(|e| format!("Error getting client: {:?}", e))?;
+
+        let request = Request::new(GetAddressUtxosArg {
+            addresses,
+            start_height: 0,
+            max_entries: 0,
+        });
+        let response = client
+            .get_address_utxos(request)
+            .await
+            .map_err(|e| format!("Error with get_address_utxos response at {uri}: {:?}", e))?;
+
+        Ok(response.into_inner().address_utxos)
+    }
+
+    /// The server's view of the total unspent balance of these transparent addresses
+    pub async fn get_taddress_balance(&self, addresses: Vec<String>) -> Result<u64, String> {
+        let uri = &self.uri;
+        let mut client = self
+            .get_client()
+            .await
+            .map_err(|e| format!("Error getting client: {:?}", e))?;
+
+        let response = client
+            .get_taddress_balance(Request::new(AddressList { addresses }))
+            .await
+            .map_err(|e| format!("Error with get_taddress_balance response at {uri}: {:?}", e))?;
+
+        Ok(response.into_inner().value_zat as u64)
+    }
+
+    pub async fn get_trees(&self, height: u64) -> Result<TreeState, String> {
+        let uri = &self.uri;
+        let mut client = self
             .get_client()
             .await
             .map_err(|e| format!("Error getting client: {:?}", e))?;
@@ -415,15 +1070,41 @@ impl GrpcConnector {
         let response = client
             .get_tree_state(Request::new(b))
             .await
-            .map_err(|e| format!("Error with get_tree_state response at {uri}: {:?}", e))?;
+            .map_err(|e| format!("Error with get_tree_state response at {uri}: {:?}", e))?
+            .into_inner();
+        self.bytes
+            .tree_states
+            .fetch_add(response.encoded_len() as u64, Ordering::SeqCst);
 
-        Ok(response.into_inner())
+        Ok(response)
+    }
+
+    /// When the block at `height` was mined, as a Unix timestamp
+    pub async fn get_block_time(&self, height: u64) -> Result<u32, String> {
+        let uri = &self.uri;
+        let mut client = self
+            .get_client()
+            .await
+            .map_err(|e| format!("Error getting client: {:?}", e))?;
+
+        let block = client
+            .get_block(Request::new(BlockId {
+                height,
+                hash: vec![],
+            }))
+            .await
+            .map_err(|e| format!("Error with get_block response at {uri}: {:?}", e))?
+            .into_inner();
+        self.bytes
+            .compact_blocks
+            .fetch_add(block.encoded_len() as u64, Ordering::SeqCst);
+
+        Ok(block.time)
     }
 
-    // get_latest_block GRPC call
-    pub async fn get_latest_block(uri: http::Uri) -> Result<BlockId, String> {
-        let client = Arc::new(GrpcConnector::new(uri.clone()));
-        let mut client = client
+    pub async fn get_latest_block(&self) -> Result<BlockId, String> {
+        let uri = &self.uri;
+        let mut client = self
             .get_client()
             .await
             .map_err(|e| format!("Error getting client: {:?}", e))?;
@@ -438,12 +1119,8 @@ impl GrpcConnector {
         Ok(response.into_inner())
     }
 
-    pub async fn send_transaction(
-        uri: http::Uri,
-        transaction_bytes: Box<[u8]>,
-    ) -> Result<String, String> {
-        let client = Arc::new(GrpcConnector::new(uri));
-        let mut client = client
+    pub async fn send_transaction(&self, transaction_bytes: Box<[u8]>) -> Result<String, String> {
+        let mut client = self
             .get_client()
             .await
             .map_err(|e| format!("Error getting client: {:?}", e))?;
@@ -473,6 +1150,369 @@ impl GrpcConnector {
 }
 
 #[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(height: Option<u64>) -> ServerStatus {
+        ServerStatus {
+            uri: http::Uri::default(),
+            latest_block_height: height,
+            latency_ms: None,
+            error: height.is_none().then(|| "unreachable".to_string()),
+        }
+    }
+
+    #[test]
+    fn median_tip_flags_lagging_and_lying_servers() {
+        let statuses = vec![
+            status(Some(2_000_000)),
+            status(Some(2_000_001)),
+            status(Some(1_999_000)),
+            status(Some(9_999_999)),
+            status(None),
+        ];
+        let median = median_server_tip(&statuses);
+        assert_eq!(median, Some(2_000_000));
+
+        let descriptions = statuses
+            .iter()
+            .map(|status| status.describe(median))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            descriptions,
+            vec!["ok", "ok", "lagging", "ahead", "unreachable"]
+        );
+    }
+
+    /// Answers every gRPC call with `messages`, streamed as a lightwalletd streams its replies,
+    /// and returns where it listens
+    fn fake_lightwalletd(messages: Vec<Vec<u8>>) -> Uri {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = hyper::Server::from_tcp(listener)
+            .unwrap()
+            .http2_only(true)
+            .serve(hyper::service::make_service_fn(move |_| {
+                let messages = messages.clone();
+                async move {
+                    Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |_| {
+                        let messages = messages.clone();
+                        let (mut sender, body) = hyper::Body::channel();
+                        tokio::spawn(async move {
+                            for message in messages {
+                                // Uncompressed, then the length of the message
+                                let mut frame = vec![0];
+                                frame.extend((message.len() as u32).to_be_bytes());
+                                frame.extend(message);
+                                sender.send_data(frame.into()).await.unwrap();
+                            }
+                            let mut trailers = http::HeaderMap::new();
+                            trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+                            sender.send_trailers(trailers).await.unwrap();
+                        });
+                        async move {
+                            http::Response::builder()
+                                .header("content-type", "application/grpc")
+                                .body(body)
+                        }
+                    }))
+                }
+            }));
+        tokio::spawn(server);
+        uri
+    }
+
+    #[tokio::test]
+    async fn mempool_bytes_are_counted_apart() {
+        let transactions = vec![
+            CompactTx {
+                index: 0,
+                hash: vec![7; 32],
+                ..Default::default()
+            },
+            CompactTx {
+                index: 0,
+                hash: vec![8; 32],
+                fee: 10_000,
+                ..Default::default()
+            },
+        ];
+        let connector = GrpcConnector::new(fake_lightwalletd(
+            transactions.iter().map(Message::encode_to_vec).collect(),
+        ));
+
+        let received = connector
+            .get_mempool_compact_transactions(vec![])
+            .await
+            .unwrap();
+        assert_eq!(received, transactions);
+        let downloaded = connector.bytes_downloaded();
+        assert_eq!(
+            downloaded.mempool,
+            transactions
+                .iter()
+                .map(|transaction| transaction.encoded_len() as u64)
+                .sum::<u64>()
+        );
+        assert_eq!(downloaded.compact_blocks, 0);
+        assert_eq!(downloaded.total(), downloaded.mempool);
+    }
+
+    /// Takes one SOCKS5 CONNECT, answers that it succeeded and returns the host and port it was
+    /// asked to connect to
+    async fn fake_socks5_proxy(listener: tokio::net::TcpListener) -> (String, u16) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut greeting = [0u8; 2];
+        stream.read_exact(&mut greeting).await.unwrap();
+        let mut methods = vec![0u8; greeting[1] as usize];
+        stream.read_exact(&mut methods).await.unwrap();
+        stream.write_all(&[5, 0]).await.unwrap();
+
+        let mut request = [0u8; 4];
+        stream.read_exact(&mut request).await.unwrap();
+        // A host name rather than an address, so no DNS lookup was made outside the proxy
+        assert_eq!(request[3], 3);
+        let mut host_len = [0u8; 1];
+        stream.read_exact(&mut host_len).await.unwrap();
+        let mut host = vec![0u8; host_len[0] as usize];
+        stream.read_exact(&mut host).await.unwrap();
+        let mut port = [0u8; 2];
+        stream.read_exact(&mut port).await.unwrap();
+        stream
+            .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+            .await
+            .unwrap();
+
+        (String::from_utf8(host).unwrap(), u16::from_be_bytes(port))
+    }
+
+    #[tokio::test]
+    async fn connector_uses_the_proxy_of_its_config() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = ProxyConfig {
+            socks5_address: listener.local_addr().unwrap().to_string(),
+            strict: true,
+        };
+        let proxy_task = tokio::spawn(fake_socks5_proxy(listener));
+
+        let mut config = ZingoConfig::create_unconnected(ChainType::Regtest, None);
+        *config.lightwalletd_uri.write().unwrap() =
+            "http://lightwalletd.example:9067".parse().unwrap();
+        config.proxy = Some(proxy);
+        // A config made later without a proxy leaves this one's alone
+        let _unproxied = ZingoConfig::create_unconnected(ChainType::Regtest, None);
+
+        // The fake proxy hangs up after the handshake, so the call itself fails
+        let _ = GrpcConnector::from_config(&config).get_info().await;
+        assert_eq!(
+            proxy_task.await.unwrap(),
+            ("lightwalletd.example".to_string(), 9067)
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_proxy_never_connects_directly() {
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_uri: Uri = format!("http://{}", server.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        // Nothing listens here, so the proxy can't be reached
+        let strict = ProxyConfig {
+            socks5_address: format!("127.0.0.1:{}", portpicker::pick_unused_port().unwrap()),
+            strict: true,
+        };
+
+        let refused = ClientConnector::new(Some(strict.clone()))
+            .oneshot(server_uri.clone())
+            .await;
+        assert!(refused.unwrap_err().to_string().contains("strict mode"));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), server.accept())
+                .await
+                .is_err()
+        );
+
+        let lax = ProxyConfig {
+            strict: false,
+            ..strict
+        };
+        assert!(ClientConnector::new(Some(lax))
+            .oneshot(server_uri)
+            .await
+            .is_ok());
+        assert!(server.accept().await.is_ok());
+    }
+
+    fn self_signed(names: &[&str], validity: Option<(i32, i32)>) -> (Certificate, PrivateKey) {
+        let mut params = rcgen::CertificateParams::default();
+        params.subject_alt_names = names
+            .iter()
+            .map(|name| match name.parse() {
+                Ok(ip_address) => rcgen::SanType::IpAddress(ip_address),
+                Err(_) => rcgen::SanType::DnsName(name.to_string()),
+            })
+            .collect();
+        if let Some((from_year, to_year)) = validity {
+            params.not_before = rcgen::date_time_ymd(from_year, 1, 1);
+            params.not_after = rcgen::date_time_ymd(to_year, 1, 1);
+        }
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        (
+            Certificate(cert.serialize_der().unwrap()),
+            PrivateKey(cert.serialize_private_key_der()),
+        )
+    }
+
+    fn verify_pinned(
+        cert: &Certificate,
+        pins: Vec<String>,
+        server_name: &str,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        PinnedCertVerifier {
+            pinned_sha256: pins,
+        }
+        .verify_server_cert(
+            cert,
+            &[],
+            &ServerName::try_from(server_name).unwrap(),
+            &mut std::iter::empty(),
+            &[],
+            std::time::SystemTime::now(),
+        )
+    }
+
+    #[test]
+    fn pinned_self_signed_certificate_is_trusted() {
+        let (cert, _) = self_signed(&["lightwalletd.local", "127.0.0.1"], None);
+        let cert_pin = hex::encode(Sha256::digest(&cert.0));
+        let spki_pin = x509_parser::parse_x509_certificate(&cert.0)
+            .map(|(_, parsed)| hex::encode(Sha256::digest(parsed.public_key().raw)))
+            .unwrap();
+
+        assert!(verify_pinned(&cert, vec![cert_pin.clone()], "lightwalletd.local").is_ok());
+        assert!(verify_pinned(&cert, vec![spki_pin], "lightwalletd.local").is_ok());
+        assert!(verify_pinned(&cert, vec![cert_pin.clone()], "127.0.0.1").is_ok());
+
+        // The pin doesn't stand in for the name
+        assert!(verify_pinned(&cert, vec![cert_pin], "other.example").is_err());
+        assert!(verify_pinned(&cert, vec!["00".repeat(32)], "lightwalletd.local").is_err());
+    }
+
+    #[tokio::test]
+    async fn grpc_web_refuses_tls_settings() {
+        let mut config = ZingoConfig::create_unconnected(ChainType::Regtest, None);
+        config.grpc_transport = GrpcTransport::Web;
+        assert!(GrpcConnector::from_config(&config)
+            .get_client()
+            .await
+            .is_ok());
+
+        config.tls.pinned_sha256 = vec!["00".repeat(32)];
+        assert!(GrpcConnector::from_config(&config)
+            .get_client()
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn pinned_certificate_must_be_in_its_validity_period() {
+        let (expired, _) = self_signed(&["lightwalletd.local"], Some((2000, 2001)));
+        let pin = hex::encode(Sha256::digest(&expired.0));
+        assert!(verify_pinned(&expired, vec![pin], "lightwalletd.local").is_err());
+    }
+
+    #[test]
+    fn wildcard_names_cover_one_label() {
+        assert!(dns_name_matches("*.example.com", "lwd.example.com"));
+        assert!(dns_name_matches("LWD.example.com", "lwd.example.com."));
+        assert!(!dns_name_matches("*.example.com", "example.com"));
+        assert!(!dns_name_matches("*.example.com", "a.lwd.example.com"));
+    }
+
+    #[tokio::test]
+    async fn tls_handshake_with_pinned_self_signed_server() {
+        let (cert, key) = self_signed(&["localhost"], None);
+        let server_config = tokio_rustls::rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let _ = acceptor.accept(stream).await;
+            }
+        });
+
+        let handshake = |pin: String| async move {
+            let client_config = tls_client_config(&TlsConfig {
+                pinned_sha256: vec![pin],
+                ..TlsConfig::default()
+            })
+            .unwrap();
+            tokio_rustls::TlsConnector::from(Arc::new(client_config))
+                .connect(
+                    ServerName::try_from("localhost").unwrap(),
+                    TcpStream::connect(address).await.unwrap(),
+                )
+                .await
+        };
+        assert!(
+            handshake(hex::encode(Sha256::digest(&cert.0)).to_uppercase())
+                .await
+                .is_ok()
+        );
+        assert!(handshake("00".repeat(32)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn block_range_requests_are_spaced_out() {
+        let mut config = ZingoConfig::create_unconnected(ChainType::Regtest, None);
+        config.rate_limits.block_range_requests_per_minute = Some(600);
+        let connector = GrpcConnector::from_config(&config);
+
+        let start = Instant::now();
+        for _ in 0..2 {
+            connector.wait_for_block_range_slot().await;
+        }
+        // A clone, say for another server, shares the limit
+        connector
+            .with_uri(http::Uri::default())
+            .wait_for_block_range_slot()
+            .await;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // Another connector doesn't
+        let start = Instant::now();
+        GrpcConnector::from_config(&config)
+            .wait_for_block_range_slot()
+            .await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn connectors_count_their_own_bytes() {
+        let config = ZingoConfig::create_unconnected(ChainType::Regtest, None);
+        let sync_connector = GrpcConnector::from_config(&config);
+        let other_connector = GrpcConnector::from_config(&config);
+
+        sync_connector
+            .with_uri(http::Uri::default())
+            .bytes
+            .tree_states
+            .fetch_add(10, Ordering::SeqCst);
+        assert_eq!(sync_connector.bytes_downloaded().tree_states, 10);
+        assert_eq!(other_connector.bytes_downloaded().total(), 0);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
 fn add_test_cert_to_roots(roots: &mut RootCertStore) {
     const TEST_PEMFILE_PATH: &str = "test-data/localhost.pem";
     let fd = std::fs::File::open(TEST_PEMFILE_PATH).unwrap();
@@ -0,0 +1,277 @@
+//! gRPC-web: gRPC calls carried over plain HTTP/1.1 requests, as browsers can make them.
+//! Lightwalletd doesn't speak it itself, so the server has to sit behind a gRPC-web proxy such
+//! as Envoy.
+//!
+//! Requests and responses keep gRPC's length-prefixed message framing. The difference is in the
+//! trailers, `grpc-status` among them, which gRPC-web sends as a last frame of the body, flagged
+//! with 0x80, because HTTP/1.1 clients can't read real trailers. This service turns that frame
+//! back into trailers, so that tonic sees an ordinary gRPC response.
+//!
+//! Responses are decoded frame by frame as they arrive, so the messages of a streaming call
+//! reach the caller while the server is still sending.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{ready, Stream, StreamExt};
+use http::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use http_body::Body;
+use prost::bytes::{Bytes, BytesMut};
+use tower::Service;
+
+use super::BoxError;
+
+const GRPC_WEB_PROTO: &str = "application/grpc-web+proto";
+const TRAILER_FLAG: u8 = 0x80;
+const FRAME_HEADER_LEN: usize = 5;
+
+#[cfg(not(target_arch = "wasm32"))]
+type ResponseFuture =
+    Pin<Box<dyn Future<Output = Result<http::Response<GrpcWebBody>, BoxError>> + Send>>;
+#[cfg(target_arch = "wasm32")]
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<http::Response<GrpcWebBody>, BoxError>>>>;
+
+#[cfg(not(target_arch = "wasm32"))]
+type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, BoxError>> + Send>>;
+#[cfg(target_arch = "wasm32")]
+type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, BoxError>>>>;
+
+/// Makes the gRPC requests of a tonic client as gRPC-web requests to `server`
+#[derive(Clone)]
+pub(crate) struct GrpcWebService {
+    client: reqwest::Client,
+    server: http::Uri,
+}
+
+impl GrpcWebService {
+    pub(crate) fn new(client: reqwest::Client, server: http::Uri) -> Self {
+        Self { client, server }
+    }
+}
+
+impl Service<http::Request<tonic::body::BoxBody>> for GrpcWebService {
+    type Response = http::Response<GrpcWebBody>;
+    type Error = BoxError;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<tonic::body::BoxBody>) -> Self::Future {
+        let client = self.client.clone();
+        // The request's uri has the method being called as its path
+        let url = format!(
+            "{}://{}{}",
+            self.server.scheme_str().unwrap_or("http"),
+            self.server.authority().map(|a| a.as_str()).unwrap_or(""),
+            request.uri().path()
+        );
+        Box::pin(async move {
+            let (parts, mut body) = request.into_parts();
+            let mut request_bytes = BytesMut::new();
+            while let Some(chunk) = body.data().await {
+                request_bytes.extend_from_slice(&chunk?);
+            }
+
+            let mut headers = parts.headers;
+            // HTTP/2 only, and meaningless to a gRPC-web proxy
+            headers.remove(http::header::TE);
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_WEB_PROTO));
+            headers.insert(ACCEPT, HeaderValue::from_static(GRPC_WEB_PROTO));
+            headers.insert("x-grpc-web", HeaderValue::from_static("1"));
+
+            let response = client
+                .post(url)
+                .headers(headers)
+                .body(request_bytes.freeze())
+                .send()
+                .await?;
+            let mut builder = http::Response::builder().status(response.status());
+            for (name, value) in response.headers() {
+                builder = builder.header(name, value);
+            }
+            Ok(builder.body(GrpcWebBody::new(
+                response
+                    .bytes_stream()
+                    .map(|chunk| chunk.map_err(BoxError::from)),
+            ))?)
+        })
+    }
+}
+
+/// Takes the first frame off the front of `buffer`, header and all, once the whole of it has
+/// arrived.
+fn split_frame(buffer: &mut BytesMut) -> Option<Bytes> {
+    if buffer.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+    let len = u32::from_be_bytes([buffer[1], buffer[2], buffer[3], buffer[4]]) as usize;
+    if buffer.len() < FRAME_HEADER_LEN + len {
+        return None;
+    }
+    Some(buffer.split_to(FRAME_HEADER_LEN + len).freeze())
+}
+
+/// Trailers come as an HTTP/1.1 header block: `name: value` lines separated by CRLF
+fn parse_trailers(block: &[u8]) -> Result<HeaderMap, BoxError> {
+    let mut trailers = HeaderMap::new();
+    for line in std::str::from_utf8(block)?.split("\r\n") {
+        if line.is_empty() {
+            continue;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Malformed gRPC-web trailer '{}'", line))?;
+        trailers.append(
+            HeaderName::from_bytes(name.trim().to_lowercase().as_bytes())?,
+            HeaderValue::from_str(value.trim())?,
+        );
+    }
+    Ok(trailers)
+}
+
+/// A response body that hands on each gRPC message frame as soon as all of it has arrived,
+/// and the trailers from the trailer frame once the body has ended.
+pub(crate) struct GrpcWebBody {
+    /// `None` once the response has ended
+    chunks: Option<ByteStream>,
+    /// What has arrived of the frames not yet handed on
+    buffer: BytesMut,
+    trailers: Option<HeaderMap>,
+}
+
+impl GrpcWebBody {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new(chunks: impl Stream<Item = Result<Bytes, BoxError>> + Send + 'static) -> Self {
+        Self::from_chunks(Box::pin(chunks))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn new(chunks: impl Stream<Item = Result<Bytes, BoxError>> + 'static) -> Self {
+        Self::from_chunks(Box::pin(chunks))
+    }
+
+    fn from_chunks(chunks: ByteStream) -> Self {
+        Self {
+            chunks: Some(chunks),
+            buffer: BytesMut::new(),
+            trailers: None,
+        }
+    }
+}
+
+impl Body for GrpcWebBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, BoxError>>> {
+        let body = self.get_mut();
+        loop {
+            if let Some(frame) = split_frame(&mut body.buffer) {
+                if frame[0] & TRAILER_FLAG != TRAILER_FLAG {
+                    return Poll::Ready(Some(Ok(frame)));
+                }
+                match parse_trailers(&frame[FRAME_HEADER_LEN..]) {
+                    Ok(trailers) => body.trailers = Some(trailers),
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                }
+                continue;
+            }
+
+            let chunks = match body.chunks.as_mut() {
+                Some(chunks) => chunks,
+                None => return Poll::Ready(None),
+            };
+            match ready!(chunks.as_mut().poll_next(cx)) {
+                Some(Ok(chunk)) => body.buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    body.chunks = None;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None => {
+                    body.chunks = None;
+                    if !body.buffer.is_empty() {
+                        body.buffer.clear();
+                        return Poll::Ready(Some(Err("Truncated gRPC-web frame".into())));
+                    }
+                    return Poll::Ready(None);
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, BoxError>> {
+        Poll::Ready(Ok(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.chunks.is_none() && self.buffer.is_empty() && self.trailers.is_none()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::mpsc;
+
+    fn frame(flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![flags];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn frames_are_handed_on_as_they_arrive() {
+        let (chunk_sender, chunks) = mpsc::unbounded::<Result<Bytes, BoxError>>();
+        let mut body = GrpcWebBody::new(chunks);
+
+        // A frame split across chunks comes out whole, without waiting for the rest of the body
+        let first = frame(0, b"block 1");
+        chunk_sender
+            .unbounded_send(Ok(Bytes::copy_from_slice(&first[..3])))
+            .unwrap();
+        let mut second = first[3..].to_vec();
+        second.extend(frame(0, b"blo"));
+        chunk_sender
+            .unbounded_send(Ok(Bytes::from(second)))
+            .unwrap();
+        assert_eq!(&body.data().await.unwrap().unwrap()[..], &first[..]);
+
+        let mut rest = b"ck 2".to_vec();
+        rest.extend(frame(0, b""));
+        rest.extend(frame(
+            TRAILER_FLAG,
+            b"grpc-status: 0\r\nGrpc-Message: all good\r\n",
+        ));
+        chunk_sender.unbounded_send(Ok(Bytes::from(rest))).unwrap();
+        drop(chunk_sender);
+        assert_eq!(
+            &body.data().await.unwrap().unwrap()[..],
+            &frame(0, b"block 2")[..]
+        );
+        assert_eq!(&body.data().await.unwrap().unwrap()[..], &frame(0, b"")[..]);
+        assert!(body.data().await.is_none());
+
+        let trailers = body.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(trailers["grpc-message"], "all good");
+        assert!(body.is_end_stream());
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_is_an_error() {
+        let mut body = GrpcWebBody::new(futures::stream::iter(vec![Ok(Bytes::from(vec![
+            0, 0, 0, 0, 9, 1,
+        ]))]));
+        assert!(body.data().await.unwrap().is_err());
+        assert!(body.data().await.is_none());
+    }
+}
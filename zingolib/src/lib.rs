@@ -6,6 +6,7 @@ mod test_framework;
 pub mod blaze;
 pub mod commands;
 pub mod compact_formats;
+pub mod executor;
 pub mod grpc_connector;
 pub mod lightclient;
 pub mod wallet;
@@ -24,7 +25,7 @@ use std::{
     path::PathBuf,
     sync::{Arc, RwLock},
 };
-use zingoconfig::{ChainType, ZingoConfig, DEFAULT_LOGFILE_NAME, DEFAULT_WALLET_NAME};
+use zingoconfig::{ChainType, ProxyConfig, ZingoConfig, DEFAULT_LOGFILE_NAME, DEFAULT_WALLET_NAME};
 
 pub fn load_clientconfig(
     lightwallet_uri: http::Uri,
@@ -32,39 +33,87 @@ pub fn load_clientconfig(
     chain: ChainType,
     monitor_mempool: bool,
 ) -> Result<ZingoConfig> {
-    use std::net::ToSocketAddrs;
-    format!(
-        "{}:{}",
-        lightwallet_uri.host().unwrap(),
-        lightwallet_uri.port().unwrap()
-    )
-    .to_socket_addrs()?
-    .next()
-    .ok_or(std::io::Error::new(
-        ErrorKind::ConnectionRefused,
-        "Couldn't resolve server!",
-    ))?;
+    load_clientconfig_with_proxy(lightwallet_uri, data_dir, chain, monitor_mempool, None)
+}
+
+/// Like `load_clientconfig`, but routes every outgoing connection made with the returned
+/// config through the given SOCKS5 proxy.
+pub fn load_clientconfig_with_proxy(
+    lightwallet_uri: http::Uri,
+    data_dir: Option<PathBuf>,
+    chain: ChainType,
+    monitor_mempool: bool,
+    proxy: Option<ProxyConfig>,
+) -> Result<ZingoConfig> {
+    // Behind a proxy, the proxy resolves the server's name, so we mustn't look it up ourselves
+    if proxy.is_none() {
+        use std::net::ToSocketAddrs;
+        format!(
+            "{}:{}",
+            lightwallet_uri.host().unwrap(),
+            lightwallet_uri.port().unwrap()
+        )
+        .to_socket_addrs()?
+        .next()
+        .ok_or(std::io::Error::new(
+            ErrorKind::ConnectionRefused,
+            "Couldn't resolve server!",
+        ))?;
+    }
 
     // Create a Light Client Config
     let config = ZingoConfig {
-        lightwalletd_uri: Arc::new(RwLock::new(lightwallet_uri)),
+        lightwalletd_uri: Arc::new(RwLock::new(lightwallet_uri.clone())),
+        server_uris: vec![lightwallet_uri],
         chain,
         monitor_mempool,
+        mempool_monitor_mode: zingoconfig::MempoolMonitorMode::default(),
         reorg_buffer_offset: zingoconfig::REORG_BUFFER_OFFSET,
         wallet_dir: data_dir,
         wallet_name: DEFAULT_WALLET_NAME.into(),
         logfile_name: DEFAULT_LOGFILE_NAME.into(),
+        block_source_file: None,
+        proxy,
+        tls: zingoconfig::TlsConfig::default(),
+        rate_limits: zingoconfig::RateLimits::default(),
+        grpc_transport: zingoconfig::GrpcTransport::default(),
+        storage: None,
     };
 
     Ok(config)
 }
-pub fn get_latest_block_height(lightwalletd_uri: http::Uri) -> std::io::Result<u64> {
+pub fn get_latest_block_height(config: &ZingoConfig) -> std::io::Result<u64> {
     tokio::runtime::Runtime::new()
         .unwrap()
         .block_on(async move {
-            crate::grpc_connector::GrpcConnector::get_info(lightwalletd_uri)
+            crate::grpc_connector::GrpcConnector::from_config(config)
+                .get_info()
                 .await
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::ConnectionRefused, e))
         })
         .map(|ld_info| ld_info.block_height)
 }
+
+/// Height of the last block mined before `timestamp`, less a small reorg margin
+pub fn get_birthday_height_at(config: &ZingoConfig, timestamp: i64) -> std::io::Result<u64> {
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(crate::wallet::birthday::find_height_at_time(
+            &crate::grpc_connector::GrpcConnector::from_config(config),
+            config.chain,
+            timestamp,
+        ))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::ConnectionRefused, e))
+}
+
+pub fn get_latest_block_height_from_file(block_file: &std::path::Path) -> std::io::Result<u64> {
+    tokio::runtime::Runtime::new()
+        .unwrap()
+        .block_on(async move {
+            crate::blaze::block_source::BlockSource::local_file(block_file)
+                .get_latest_block()
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e))
+        })
+        .map(|block_id| block_id.height)
+}
@@ -1,44 +1,59 @@
 use crate::{
     blaze::{
-        block_witness_data::BlockAndWitnessData, fetch_compact_blocks::FetchCompactBlocks,
+        block_source::BlockSource,
+        block_witness_data::BlockAndWitnessData,
+        fetch_compact_blocks::FetchCompactBlocks,
         fetch_full_transaction::TransactionContext,
-        fetch_taddr_transactions::FetchTaddrTransactions, sync_status::BatchSyncStatus,
-        syncdata::BlazeSyncData, trial_decryptions::TrialDecryptions, update_notes::UpdateNotes,
+        fetch_taddr_transactions::FetchTaddrTransactions,
+        sync_checkpoint::SyncCheckpoint,
+        sync_status::BatchSyncStatus,
+        syncdata::BlazeSyncData,
+        trial_decryptions::{mempool_transaction_involves_wallet, TrialDecryptions},
+        update_notes::UpdateNotes,
     },
     compact_formats::RawTransaction,
-    grpc_connector::GrpcConnector,
+    executor,
+    grpc_connector::{median_server_tip, BytesDownloaded, GrpcConnector},
     wallet::{
+        consistency::ConsistencyReport,
         data::{
             finsight, summaries::ValueTransfer, summaries::ValueTransferKind, OutgoingTxData,
             TransactionMetadata,
         },
         keys::{
-            address_from_pubkeyhash,
+            address_from_pubkeyhash, is_shielded_address,
             unified::{ReceiverSelection, WalletCapability},
         },
         message::Message,
         now,
         traits::{DomainWalletExt, ReceivedNoteAndMetadata, Recipient},
-        LightWallet, Pool, SendProgress, WalletBase,
+        utils::interpret_memo_string,
+        ExpiryDelta, LightWallet, MemoDownloadOption, Pool, SendProgress, WalletBase,
+        REORG_ANCHOR_SPACING,
     },
 };
 use futures::future::join_all;
 use json::{array, object, JsonValue};
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 use orchard::note_encryption::OrchardDomain;
+use serde::{Deserialize, Serialize};
 use std::{
     cmp::{self, Ordering},
-    collections::HashMap,
-    fs::{remove_file, File},
-    io::{self, BufReader, Error, ErrorKind, Read, Write},
+    collections::{HashMap, HashSet},
+    fs::File,
+    future::Future,
+    io::{self, Error, ErrorKind, Read, Write},
     path::Path,
-    sync::Arc,
+    sync::{atomic::AtomicBool, Arc},
     time::Duration,
 };
 use tokio::{
     join,
     runtime::Runtime,
-    sync::{mpsc::unbounded_channel, oneshot, Mutex, RwLock},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot, Mutex, RwLock,
+    },
     task::yield_now,
     time::sleep,
 };
@@ -56,15 +71,44 @@ use zcash_primitives::{
     transaction::{fees::zip317::MINIMUM_FEE, Transaction, TxId},
 };
 use zcash_proofs::prover::LocalTxProver;
-use zingoconfig::{ChainType, ZingoConfig, MAX_REORG};
+use zingoconfig::{ChainType, MempoolMonitorMode, ProxyConfig, ZingoConfig, MAX_REORG};
+
+pub mod payouts;
+pub mod records;
+pub mod schedule;
+use payouts::{Payout, PayoutPlan, PayoutResult, SpendableNotes};
+use records::{
+    AddressRecord, NoteList, OutgoingRecord, ReceiverRecord, SeedPhrase, ServerInfo,
+    ServerStatusRecord, ShieldedNoteRecord, TransactionRecord, UtxoRecord,
+};
+use schedule::{JournalEntry, PaymentSchedule, RunOutcome, ScheduledPayment};
 
 static LOG_INIT: std::sync::Once = std::sync::Once::new();
 
+/// How often the compact mempool monitor asks the server for new transactions
+const MEMPOOL_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How many bytes of each seen txid go in the compact mempool monitor's exclude list. Entries
+/// this short rarely match more than one transaction, and when they do, all are sent again.
+const MEMPOOL_EXCLUDE_BYTES: usize = 8;
+
+/// The exclude list for a compact mempool poll that leaves out the transactions already seen.
+/// lightwalletd reverses each entry and matches it against the start of the txid as displayed,
+/// so a shortened entry is the end of the txid as CompactTx carries it.
+fn mempool_exclude(seen_txids: &HashSet<Vec<u8>>) -> Vec<Vec<u8>> {
+    seen_txids
+        .iter()
+        .map(|txid| txid[txid.len().saturating_sub(MEMPOOL_EXCLUDE_BYTES)..].to_vec())
+        .collect()
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct SyncResult {
     pub success: bool,
     pub latest_block: u64,
     pub total_blocks_synced: u64,
+    pub bytes_downloaded: BytesDownloaded,
+    /// What the end-of-sync comparison with the server's transparent UTXO set found
+    pub transparent_check: ConsistencyReport,
 }
 
 impl SyncResult {
@@ -74,6 +118,8 @@ impl SyncResult {
             "result" => if self.success { "success" } else { "failure" },
             "latest_block" => self.latest_block,
             "total_blocks_synced" => self.total_blocks_synced,
+            "bytes_downloaded" => self.bytes_downloaded.to_json(),
+            "transparent_check" => self.transparent_check.to_json(),
         }
     }
 }
@@ -82,14 +128,48 @@ impl std::fmt::Display for SyncResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(
             format!(
-                "{{ success: {}, latest_block: {}, total_blocks_synced: {}}}",
-                self.success, self.latest_block, self.total_blocks_synced
+                "{{ success: {}, latest_block: {}, total_blocks_synced: {}, bytes_downloaded: {}}}",
+                self.success,
+                self.latest_block,
+                self.total_blocks_synced,
+                self.bytes_downloaded.total()
             )
             .as_str(),
         )
     }
 }
 
+/// Why a send failed: the wallet couldn't build it, or the server couldn't be reached or turned
+/// it down, in which case trying again may get it through
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SendError {
+    Wallet(String),
+    Server(String),
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Wallet(message) | SendError::Server(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<SendError> for String {
+    fn from(error: SendError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A failed send's error, at the server if its broadcast was what failed
+fn send_error(error: String, broadcast_failed: &AtomicBool) -> SendError {
+    if broadcast_failed.load(std::sync::atomic::Ordering::SeqCst) {
+        SendError::Server(error)
+    } else {
+        SendError::Wallet(error)
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct WalletStatus {
     pub is_syncing: bool,
@@ -117,6 +197,9 @@ pub struct LightClient {
 
     bsync_data: Arc<RwLock<BlazeSyncData>>,
     interrupt_sync: Arc<RwLock<bool>>,
+
+    // Server our pending transactions were last rebroadcast to
+    rebroadcast_to: Mutex<Option<http::Uri>>,
 }
 impl LightClient {
     pub fn create_from_extant_wallet(wallet: LightWallet, config: ZingoConfig) -> Self {
@@ -127,6 +210,7 @@ impl LightClient {
             sync_lock: Mutex::new(()),
             bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(&config))),
             interrupt_sync: Arc::new(RwLock::new(false)),
+            rebroadcast_to: Mutex::new(None),
         }
     }
     /// The wallet this fn associates with the lightclient is specifically derived from
@@ -156,8 +240,8 @@ impl LightClient {
                 return Err(Error::new(
                     ErrorKind::AlreadyExists,
                     format!(
-                        "Cannot create a new wallet from seed, because a wallet already exists at:\n{:?}",
-                        config.get_wallet_path().as_os_str()
+                        "Cannot create a new wallet from seed, because a wallet already exists at:\n{}",
+                        config.wallet_location()
                     ),
                 ));
             }
@@ -169,6 +253,7 @@ impl LightClient {
             sync_lock: Mutex::new(()),
             bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(config))),
             interrupt_sync: Arc::new(RwLock::new(false)),
+            rebroadcast_to: Mutex::new(None),
         };
 
         lightclient.set_wallet_initial_state(birthday).await;
@@ -193,6 +278,7 @@ impl LightClient {
             bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(config))),
             sync_lock: Mutex::new(()),
             interrupt_sync: Arc::new(RwLock::new(false)),
+            rebroadcast_to: Mutex::new(None),
         })
     }
 
@@ -250,6 +336,7 @@ impl LightClient {
             sync_lock: Mutex::new(()),
             bsync_data: Arc::new(RwLock::new(BlazeSyncData::new(config))),
             interrupt_sync: Arc::new(RwLock::new(false)),
+            rebroadcast_to: Mutex::new(None),
         };
 
         debug!(
@@ -261,19 +348,18 @@ impl LightClient {
         Ok(lc)
     }
 
+    /// Reads the wallet from the config's storage, by default the wallet file
     pub fn read_wallet_from_disk(config: &ZingoConfig) -> io::Result<Self> {
-        let wallet_path = if config.wallet_exists() {
-            config.get_wallet_path()
-        } else {
-            return Err(Error::new(
+        match config.read_wallet()? {
+            Some(wallet_bytes) => LightClient::read_wallet_from_buffer(config, &wallet_bytes[..]),
+            None => Err(Error::new(
                 ErrorKind::NotFound,
                 format!(
                     "Cannot read wallet. No file at {}",
-                    config.get_wallet_path().display()
+                    config.wallet_location()
                 ),
-            ));
-        };
-        LightClient::read_wallet_from_buffer(config, BufReader::new(File::open(wallet_path)?))
+            )),
+        }
     }
 
     async fn ensure_witness_tree_not_above_wallet_blocks(&self) {
@@ -318,19 +404,25 @@ impl LightWalletSendProgress {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct PoolBalances {
     pub sapling_balance: Option<u64>,
     pub verified_sapling_balance: Option<u64>,
     pub spendable_sapling_balance: Option<u64>,
     pub unverified_sapling_balance: Option<u64>,
+    pub pending_incoming_sapling_balance: Option<u64>,
+    pub pending_outgoing_sapling_balance: Option<u64>,
 
     pub orchard_balance: Option<u64>,
     pub verified_orchard_balance: Option<u64>,
     pub unverified_orchard_balance: Option<u64>,
     pub spendable_orchard_balance: Option<u64>,
+    pub pending_incoming_orchard_balance: Option<u64>,
+    pub pending_outgoing_orchard_balance: Option<u64>,
 
     pub transparent_balance: Option<u64>,
+    pub pending_incoming_transparent_balance: Option<u64>,
+    pub pending_outgoing_transparent_balance: Option<u64>,
 }
 
 impl PoolBalances {
@@ -340,11 +432,17 @@ impl PoolBalances {
             "verified_sapling_balance"        => self.verified_sapling_balance,
             "spendable_sapling_balance"       => self.spendable_sapling_balance,
             "unverified_sapling_balance"      => self.unverified_sapling_balance,
+            "pending_incoming_sapling_balance" => self.pending_incoming_sapling_balance,
+            "pending_outgoing_sapling_balance" => self.pending_outgoing_sapling_balance,
             "orchard_balance"                 => self.orchard_balance,
             "verified_orchard_balance"        => self.verified_orchard_balance,
             "spendable_orchard_balance"       => self.spendable_orchard_balance,
             "unverified_orchard_balance"      => self.unverified_orchard_balance,
+            "pending_incoming_orchard_balance" => self.pending_incoming_orchard_balance,
+            "pending_outgoing_orchard_balance" => self.pending_outgoing_orchard_balance,
             "transparent_balance"             => self.transparent_balance,
+            "pending_incoming_transparent_balance" => self.pending_incoming_transparent_balance,
+            "pending_outgoing_transparent_balance" => self.pending_outgoing_transparent_balance,
         }
     }
 }
@@ -354,7 +452,7 @@ impl LightClient {
         &'a self,
         transaction_metadata: &'b TransactionMetadata,
         unified_spend_auth: &'c WalletCapability,
-    ) -> impl Iterator<Item = JsonValue> + 'b
+    ) -> impl Iterator<Item = TransactionRecord> + 'b
     where
         'a: 'b,
         'c: 'b,
@@ -375,7 +473,7 @@ impl LightClient {
         &'a self,
         transaction_metadata: &'b TransactionMetadata,
         unified_spend_auth: &'c WalletCapability,
-    ) -> impl Iterator<Item = JsonValue> + 'b
+    ) -> impl Iterator<Item = TransactionRecord> + 'b
     where
         'a: 'b,
         'c: 'b,
@@ -384,28 +482,35 @@ impl LightClient {
         <D as Domain>::Recipient: Recipient,
         <D as Domain>::Note: PartialEq + Clone,
     {
-        D::WalletNote::transaction_metadata_notes(transaction_metadata).iter().filter(|nd| !nd.is_change()).enumerate().map(|(i, nd)| {
-                    let block_height: u32 = transaction_metadata.block_height.into();
-                    object! {
-                        "block_height" => block_height,
-                        "unconfirmed" => transaction_metadata.unconfirmed,
-                        "datetime"     => transaction_metadata.datetime,
-                        "position"     => i,
-                        "txid"         => format!("{}", transaction_metadata.txid),
-                        "amount"       => nd.value() as i64,
-                        "zec_price"    => transaction_metadata.price.map(|p| (p * 100.0).round() / 100.0),
-                        "address"      => LightWallet::note_address::<D>(&self.config.chain, nd, unified_spend_auth),
-                        "memo"         => LightWallet::memo_str(nd.memo().clone())
-                    }
-
-                })
+        D::WalletNote::transaction_metadata_notes(transaction_metadata)
+            .iter()
+            .filter(|nd| !nd.is_change())
+            .enumerate()
+            .map(|(i, nd)| TransactionRecord {
+                block_height: transaction_metadata.block_height.into(),
+                unconfirmed: transaction_metadata.unconfirmed,
+                datetime: transaction_metadata.datetime,
+                txid: format!("{}", transaction_metadata.txid),
+                amount: nd.value() as i64,
+                zec_price: transaction_metadata
+                    .price
+                    .map(|p| (p * 100.0).round() / 100.0),
+                position: Some(i),
+                address: Some(LightWallet::note_address::<D>(
+                    &self.config.chain,
+                    nd,
+                    unified_spend_auth,
+                )),
+                memo: LightWallet::memo_str(nd.memo().clone()),
+                outgoing_metadata: None,
+            })
     }
 
     /// This fn is _only_ called insde a block conditioned on "is_outgoing_transaction"
     fn append_change_notes(
         wallet_transaction: &TransactionMetadata,
         received_utxo_value: u64,
-    ) -> JsonValue {
+    ) -> TransactionRecord {
         // TODO:  Understand why sapling and orchard have an "is_change" filter, but transparent does not
         // It seems like this already depends on an invariant where all outgoing utxos are change.
         // This should never be true _AFTER SOME VERSION_ since we only send change to orchard.
@@ -426,29 +531,31 @@ impl LightClient {
             + received_utxo_value;
 
         // Collect outgoing metadata
-        let outgoing_json = wallet_transaction
+        let outgoing_metadata = wallet_transaction
             .outgoing_tx_data
             .iter()
-            .map(|om| {
-                object! {
-                    // Is this address ever different than the address in the containing struct
-                    // this is the full UA.
-                    "address" => om.recipient_ua.clone().unwrap_or(om.to_address.clone()),
-                    "value"   => om.value,
-                    "memo"    => LightWallet::memo_str(Some(om.memo.clone()))
-                }
+            .map(|om| OutgoingRecord {
+                // Is this address ever different than the address in the containing struct
+                // this is the full UA.
+                address: om.recipient_ua.clone().unwrap_or(om.to_address.clone()),
+                value: om.value,
+                memo: LightWallet::memo_str(Some(om.memo.clone())),
             })
-            .collect::<Vec<JsonValue>>();
-
-        let block_height: u32 = wallet_transaction.block_height.into();
-        object! {
-            "block_height" => block_height,
-            "unconfirmed" => wallet_transaction.unconfirmed,
-            "datetime"     => wallet_transaction.datetime,
-            "txid"         => format!("{}", wallet_transaction.txid),
-            "zec_price"    => wallet_transaction.price.map(|p| (p * 100.0).round() / 100.0),
-            "amount"       => total_change as i64 - wallet_transaction.total_value_spent() as i64,
-            "outgoing_metadata" => outgoing_json,
+            .collect::<Vec<_>>();
+
+        TransactionRecord {
+            block_height: wallet_transaction.block_height.into(),
+            unconfirmed: wallet_transaction.unconfirmed,
+            datetime: wallet_transaction.datetime,
+            txid: format!("{}", wallet_transaction.txid),
+            amount: total_change as i64 - wallet_transaction.total_value_spent() as i64,
+            zec_price: wallet_transaction
+                .price
+                .map(|p| (p * 100.0).round() / 100.0),
+            position: None,
+            address: None,
+            memo: None,
+            outgoing_metadata: Some(outgoing_metadata),
         }
     }
 
@@ -465,23 +572,37 @@ impl LightClient {
         &self.config
     }
 
-    pub async fn do_addresses(&self) -> JsonValue {
-        let mut objectified_addresses = Vec::new();
-        for address in self.wallet.wallet_capability().addresses().iter() {
-            let encoded_ua = address.encode(&self.config.chain);
-            let transparent = address
-                .transparent()
-                .map(|taddr| address_from_pubkeyhash(&self.config, *taddr));
-            objectified_addresses.push(object! {
-            "address" => encoded_ua,
-            "receivers" => object!(
-                "transparent" => transparent,
-                "sapling" => address.sapling().map(|z_addr| encode_payment_address(self.config.chain.hrp_sapling_payment_address(), z_addr)),
-                "orchard_exists" => address.orchard().is_some(),
-                )
+    pub async fn list_addresses(&self) -> Vec<AddressRecord> {
+        self.wallet
+            .wallet_capability()
+            .addresses()
+            .iter()
+            .map(|address| AddressRecord {
+                address: address.encode(&self.config.chain),
+                receivers: ReceiverRecord {
+                    transparent: address
+                        .transparent()
+                        .map(|taddr| address_from_pubkeyhash(&self.config, *taddr)),
+                    sapling: address.sapling().map(|z_addr| {
+                        encode_payment_address(
+                            self.config.chain.hrp_sapling_payment_address(),
+                            z_addr,
+                        )
+                    }),
+                    orchard_exists: address.orchard().is_some(),
+                },
             })
-        }
-        JsonValue::Array(objectified_addresses)
+            .collect()
+    }
+
+    pub async fn do_addresses(&self) -> JsonValue {
+        JsonValue::Array(
+            self.list_addresses()
+                .await
+                .into_iter()
+                .map(JsonValue::from)
+                .collect(),
+        )
     }
 
     pub async fn do_balance(&self) -> PoolBalances {
@@ -490,11 +611,23 @@ impl LightClient {
             verified_sapling_balance: self.wallet.verified_sapling_balance(None).await,
             spendable_sapling_balance: self.wallet.spendable_sapling_balance(None).await,
             unverified_sapling_balance: self.wallet.unverified_sapling_balance(None).await,
+            pending_incoming_sapling_balance: self.wallet.pending_incoming_sapling_balance().await,
+            pending_outgoing_sapling_balance: self.wallet.pending_outgoing_sapling_balance().await,
             orchard_balance: self.wallet.maybe_verified_orchard_balance(None).await,
             verified_orchard_balance: self.wallet.verified_orchard_balance(None).await,
             spendable_orchard_balance: self.wallet.spendable_orchard_balance(None).await,
             unverified_orchard_balance: self.wallet.unverified_orchard_balance(None).await,
+            pending_incoming_orchard_balance: self.wallet.pending_incoming_orchard_balance().await,
+            pending_outgoing_orchard_balance: self.wallet.pending_outgoing_orchard_balance().await,
             transparent_balance: self.wallet.tbalance(None).await,
+            pending_incoming_transparent_balance: self
+                .wallet
+                .pending_incoming_transparent_balance()
+                .await,
+            pending_outgoing_transparent_balance: self
+                .wallet
+                .pending_outgoing_transparent_balance()
+                .await,
         }
     }
 
@@ -537,119 +670,138 @@ impl LightClient {
         }
     }
 
+    pub async fn server_info(&self) -> Result<ServerInfo, String> {
+        let i = self
+            .with_server_failover(|connector| async move { connector.get_info().await })
+            .await?;
+        Ok(ServerInfo {
+            version: i.version,
+            git_commit: i.git_commit,
+            server_uri: self.get_server_uri().to_string(),
+            vendor: i.vendor,
+            taddr_support: i.taddr_support,
+            chain_name: i.chain_name,
+            sapling_activation_height: i.sapling_activation_height,
+            consensus_branch_id: i.consensus_branch_id,
+            latest_block_height: i.block_height,
+        })
+    }
+
     pub async fn do_info(&self) -> String {
-        match GrpcConnector::get_info(self.get_server_uri()).await {
-            Ok(i) => {
-                let o = object! {
-                    "version" => i.version,
-                    "git_commit" => i.git_commit,
-                    "server_uri" => self.get_server_uri().to_string(),
-                    "vendor" => i.vendor,
-                    "taddr_support" => i.taddr_support,
-                    "chain_name" => i.chain_name,
-                    "sapling_activation_height" => i.sapling_activation_height,
-                    "consensus_branch_id" => i.consensus_branch_id,
-                    "latest_block_height" => i.block_height
-                };
-                o.pretty(2)
-            }
+        match self.server_info().await {
+            Ok(info) => JsonValue::from(info).pretty(2),
             Err(e) => e,
         }
     }
 
     /// Return a list of all notes, spent and unspent
-    pub async fn do_list_notes(&self, all_notes: bool) -> JsonValue {
-        let mut unspent_sapling_notes: Vec<JsonValue> = vec![];
-        let mut spent_sapling_notes: Vec<JsonValue> = vec![];
-        let mut pending_sapling_notes: Vec<JsonValue> = vec![];
+    pub async fn list_notes(&self, all_notes: bool) -> NoteList {
+        let mut notes = NoteList::new(all_notes);
 
         let anchor_height = BlockHeight::from_u32(self.wallet.get_anchor_height().await);
 
         {
             // Collect Sapling notes
-            self.wallet.transaction_context.transaction_metadata_set.read().await.current.iter()
-                .flat_map( |(transaction_id, transaction_metadata)| {
-                    transaction_metadata.sapling_notes.iter().filter_map(move |note_metadata|
-                        if !all_notes && note_metadata.spent.is_some() {
-                            None
-                        } else {
-                            let address = LightWallet::note_address::<SaplingDomain<ChainType>>(&self.config.chain, note_metadata, &self.wallet.wallet_capability());
-                            let spendable = transaction_metadata.block_height <= anchor_height && note_metadata.spent.is_none() && note_metadata.unconfirmed_spent.is_none();
-
-                            let created_block:u32 = transaction_metadata.block_height.into();
-                            Some(object!{
-                                "created_in_block"   => created_block,
-                                "datetime"           => transaction_metadata.datetime,
-                                "created_in_txid"    => format!("{}", transaction_id.clone()),
-                                "value"              => note_metadata.note.value().inner(),
-                                "unconfirmed"        => transaction_metadata.unconfirmed,
-                                "is_change"          => note_metadata.is_change,
-                                "address"            => address,
-                                "spendable"          => spendable,
-                                "spent"              => note_metadata.spent.map(|(spent_transaction_id, _)| format!("{}", spent_transaction_id)),
-                                "spent_at_height"    => note_metadata.spent.map(|(_, h)| h),
-                                "unconfirmed_spent"  => note_metadata.unconfirmed_spent.map(|(spent_transaction_id, _)| format!("{}", spent_transaction_id)),
-                            })
-                        }
-                    )
+            self.wallet
+                .transaction_context
+                .transaction_metadata_set
+                .read()
+                .await
+                .current
+                .iter()
+                .flat_map(|(transaction_id, transaction_metadata)| {
+                    transaction_metadata
+                        .sapling_notes
+                        .iter()
+                        .filter_map(move |note_metadata| {
+                            if !all_notes && note_metadata.spent.is_some() {
+                                None
+                            } else {
+                                let address = LightWallet::note_address::<SaplingDomain<ChainType>>(
+                                    &self.config.chain,
+                                    note_metadata,
+                                    &self.wallet.wallet_capability(),
+                                );
+                                let spendable = transaction_metadata.block_height <= anchor_height
+                                    && note_metadata.spent.is_none()
+                                    && note_metadata.unconfirmed_spent.is_none();
+
+                                Some(ShieldedNoteRecord {
+                                    created_in_block: transaction_metadata.block_height.into(),
+                                    datetime: transaction_metadata.datetime,
+                                    created_in_txid: format!("{}", transaction_id.clone()),
+                                    value: note_metadata.note.value().inner(),
+                                    unconfirmed: transaction_metadata.unconfirmed,
+                                    is_change: note_metadata.is_change,
+                                    address,
+                                    spendable,
+                                    spent: note_metadata.spent.map(|(spent_transaction_id, _)| {
+                                        format!("{}", spent_transaction_id)
+                                    }),
+                                    spent_at_height: note_metadata.spent.map(|(_, h)| h),
+                                    unconfirmed_spent: note_metadata.unconfirmed_spent.map(
+                                        |(spent_transaction_id, _)| {
+                                            format!("{}", spent_transaction_id)
+                                        },
+                                    ),
+                                })
+                            }
+                        })
                 })
-                .for_each( |note| {
-                    if note["spent"].is_null() && note["unconfirmed_spent"].is_null() {
-                        unspent_sapling_notes.push(note);
-                    } else if !note["spent"].is_null() {
-                        spent_sapling_notes.push(note);
-                    } else {
-                        pending_sapling_notes.push(note);
-                    }
-                });
+                .for_each(|note| notes.add_sapling_note(note));
         }
 
-        let mut unspent_orchard_notes: Vec<JsonValue> = vec![];
-        let mut spent_orchard_notes: Vec<JsonValue> = vec![];
-        let mut pending_orchard_notes: Vec<JsonValue> = vec![];
-
         {
-            self.wallet.transaction_context.transaction_metadata_set.read().await.current.iter()
-                .flat_map( |(transaction_id, transaction_metadata)| {
-                    transaction_metadata.orchard_notes.iter().filter_map(move |orch_note_metadata|
-                        if !all_notes && orch_note_metadata.is_spent() {
-                            None
-                        } else {
-                            let address = LightWallet::note_address::<OrchardDomain>(&self.config.chain, orch_note_metadata, &self.wallet.wallet_capability());
-                            let spendable = transaction_metadata.block_height <= anchor_height && orch_note_metadata.spent.is_none() && orch_note_metadata.unconfirmed_spent.is_none();
-
-                            let created_block:u32 = transaction_metadata.block_height.into();
-                            Some(object!{
-                                "created_in_block"   => created_block,
-                                "datetime"           => transaction_metadata.datetime,
-                                "created_in_txid"    => format!("{}", transaction_id),
-                                "value"              => orch_note_metadata.note.value().inner(),
-                                "unconfirmed"        => transaction_metadata.unconfirmed,
-                                "is_change"          => orch_note_metadata.is_change,
-                                "address"            => address,
-                                "spendable"          => spendable,
-                                "spent"              => orch_note_metadata.spent.map(|(spent_transaction_id, _)| format!("{}", spent_transaction_id)),
-                                "spent_at_height"    => orch_note_metadata.spent.map(|(_, h)| h),
-                                "unconfirmed_spent"  => orch_note_metadata.unconfirmed_spent.map(|(spent_transaction_id, _)| format!("{}", spent_transaction_id)),
-                            })
-                        }
+            self.wallet
+                .transaction_context
+                .transaction_metadata_set
+                .read()
+                .await
+                .current
+                .iter()
+                .flat_map(|(transaction_id, transaction_metadata)| {
+                    transaction_metadata.orchard_notes.iter().filter_map(
+                        move |orch_note_metadata| {
+                            if !all_notes && orch_note_metadata.is_spent() {
+                                None
+                            } else {
+                                let address = LightWallet::note_address::<OrchardDomain>(
+                                    &self.config.chain,
+                                    orch_note_metadata,
+                                    &self.wallet.wallet_capability(),
+                                );
+                                let spendable = transaction_metadata.block_height <= anchor_height
+                                    && orch_note_metadata.spent.is_none()
+                                    && orch_note_metadata.unconfirmed_spent.is_none();
+
+                                Some(ShieldedNoteRecord {
+                                    created_in_block: transaction_metadata.block_height.into(),
+                                    datetime: transaction_metadata.datetime,
+                                    created_in_txid: format!("{}", transaction_id),
+                                    value: orch_note_metadata.note.value().inner(),
+                                    unconfirmed: transaction_metadata.unconfirmed,
+                                    is_change: orch_note_metadata.is_change,
+                                    address,
+                                    spendable,
+                                    spent: orch_note_metadata.spent.map(
+                                        |(spent_transaction_id, _)| {
+                                            format!("{}", spent_transaction_id)
+                                        },
+                                    ),
+                                    spent_at_height: orch_note_metadata.spent.map(|(_, h)| h),
+                                    unconfirmed_spent: orch_note_metadata.unconfirmed_spent.map(
+                                        |(spent_transaction_id, _)| {
+                                            format!("{}", spent_transaction_id)
+                                        },
+                                    ),
+                                })
+                            }
+                        },
                     )
                 })
-                .for_each( |note| {
-                    if note["spent"].is_null() && note["unconfirmed_spent"].is_null() {
-                        unspent_orchard_notes.push(note);
-                    } else if !note["spent"].is_null() {
-                        spent_orchard_notes.push(note);
-                    } else {
-                        pending_orchard_notes.push(note);
-                    }
-                });
+                .for_each(|note| notes.add_orchard_note(note));
         }
 
-        let mut unspent_utxos: Vec<JsonValue> = vec![];
-        let mut spent_utxos: Vec<JsonValue> = vec![];
-        let mut pending_utxos: Vec<JsonValue> = vec![];
-
         {
             self.wallet.transaction_context.transaction_metadata_set.read().await.current.iter()
                 .flat_map( |(transaction_id, wtx)| {
@@ -657,174 +809,146 @@ impl LightClient {
                         if !all_notes && utxo.spent.is_some() {
                             None
                         } else {
-                            let created_block:u32 = wtx.block_height.into();
                             let recipient = RecipientAddress::decode(&self.config.chain, &utxo.address);
                             let taddr = match recipient {
                             Some(RecipientAddress::Transparent(taddr)) => taddr,
                                 _otherwise => panic!("Read invalid taddr from wallet-local Utxo, this should be impossible"),
                             };
 
-                            Some(object!{
-                                "created_in_block"   => created_block,
-                                "datetime"           => wtx.datetime,
-                                "created_in_txid"    => format!("{}", transaction_id),
-                                "value"              => utxo.value,
-                                "scriptkey"          => hex::encode(utxo.script.clone()),
-                                "is_change"          => false, // TODO: Identify notes as change if we send change to our own taddrs
-                                "address"            => self.wallet.wallet_capability().get_ua_from_contained_transparent_receiver(&taddr).map(|ua| ua.encode(&self.config.chain)),
-                                "spent_at_height"    => utxo.spent_at_height,
-                                "spent"              => utxo.spent.map(|spent_transaction_id| format!("{}", spent_transaction_id)),
-                                "unconfirmed_spent"  => utxo.unconfirmed_spent.map(|(spent_transaction_id, _)| format!("{}", spent_transaction_id)),
+                            Some(UtxoRecord {
+                                created_in_block: wtx.block_height.into(),
+                                datetime: wtx.datetime,
+                                created_in_txid: format!("{}", transaction_id),
+                                value: utxo.value,
+                                scriptkey: hex::encode(utxo.script.clone()),
+                                is_change: false, // TODO: Identify notes as change if we send change to our own taddrs
+                                address: self.wallet.wallet_capability().get_ua_from_contained_transparent_receiver(&taddr).map(|ua| ua.encode(&self.config.chain)),
+                                spent_at_height: utxo.spent_at_height,
+                                spent: utxo.spent.map(|spent_transaction_id| format!("{}", spent_transaction_id)),
+                                unconfirmed_spent: utxo.unconfirmed_spent.map(|(spent_transaction_id, _)| format!("{}", spent_transaction_id)),
                             })
                         }
                     )
                 })
-                .for_each( |utxo| {
-                    if utxo["spent"].is_null() && utxo["unconfirmed_spent"].is_null() {
-                        unspent_utxos.push(utxo);
-                    } else if !utxo["spent"].is_null() {
-                        spent_utxos.push(utxo);
-                    } else {
-                        pending_utxos.push(utxo);
-                    }
-                });
+                .for_each(|utxo| notes.add_utxo(utxo));
         }
 
-        unspent_sapling_notes.sort_by_key(|note| note["created_in_block"].as_u64());
-        spent_sapling_notes.sort_by_key(|note| note["created_in_block"].as_u64());
-        pending_sapling_notes.sort_by_key(|note| note["created_in_block"].as_u64());
-        unspent_orchard_notes.sort_by_key(|note| note["created_in_block"].as_u64());
-        spent_orchard_notes.sort_by_key(|note| note["created_in_block"].as_u64());
-        pending_orchard_notes.sort_by_key(|note| note["created_in_block"].as_u64());
-        unspent_utxos.sort_by_key(|note| note["created_in_block"].as_u64());
-        pending_utxos.sort_by_key(|note| note["created_in_block"].as_u64());
-        spent_utxos.sort_by_key(|note| note["created_in_block"].as_u64());
-
-        let mut res = object! {
-            "unspent_sapling_notes" => unspent_sapling_notes,
-            "pending_sapling_notes" => pending_sapling_notes,
-            "unspent_orchard_notes" => unspent_orchard_notes,
-            "pending_orchard_notes" => pending_orchard_notes,
-            "utxos"         => unspent_utxos,
-            "pending_utxos" => pending_utxos,
-        };
-
-        if all_notes {
-            res["spent_sapling_notes"] = JsonValue::Array(spent_sapling_notes);
-            res["spent_orchard_notes"] = JsonValue::Array(spent_orchard_notes);
-            res["spent_utxos"] = JsonValue::Array(spent_utxos);
-        }
+        notes.sort();
+        notes
+    }
 
-        res
+    pub async fn do_list_notes(&self, all_notes: bool) -> JsonValue {
+        self.list_notes(all_notes).await.into()
     }
 
-    pub async fn do_list_transactions(&self) -> JsonValue {
+    pub async fn list_transactions(&self) -> Vec<TransactionRecord> {
         // Create a list of TransactionItems from wallet transactions
         let mut consumer_ui_notes = self
             .wallet
-            .transaction_context.transaction_metadata_set
+            .transaction_context
+            .transaction_metadata_set
             .read()
             .await
             .current
             .iter()
             .flat_map(|(txid, wallet_transaction)| {
-                let mut consumer_notes_by_tx: Vec<JsonValue> = vec![];
+                let mut consumer_notes_by_tx: Vec<TransactionRecord> = vec![];
 
-                let total_transparent_received = wallet_transaction.received_utxos.iter().map(|u| u.value).sum::<u64>();
+                let total_transparent_received = wallet_transaction
+                    .received_utxos
+                    .iter()
+                    .map(|u| u.value)
+                    .sum::<u64>();
                 if wallet_transaction.is_outgoing_transaction() {
                     // If money was spent, create a consumer_ui_note. For this, we'll subtract
                     // all the change notes + Utxos
-                    consumer_notes_by_tx.push(Self::append_change_notes(wallet_transaction, total_transparent_received));
+                    consumer_notes_by_tx.push(Self::append_change_notes(
+                        wallet_transaction,
+                        total_transparent_received,
+                    ));
                 }
 
                 // For each note that is not a change, add a consumer_ui_note.
-                consumer_notes_by_tx.extend(self.add_nonchange_notes(wallet_transaction, &self.wallet.wallet_capability()));
+                consumer_notes_by_tx.extend(
+                    self.add_nonchange_notes(wallet_transaction, &self.wallet.wallet_capability()),
+                );
 
                 // TODO:  determine if all notes are either Change-or-NotChange, if that's the case
                 // add a sanity check that asserts all notes are processed by this point
 
                 // Get the total transparent value received in this transaction
                 // Again we see the assumption that utxos are incoming.
-                let net_transparent_value = total_transparent_received as i64 - wallet_transaction.total_transparent_value_spent as i64;
-                let address = wallet_transaction.received_utxos.iter().map(|utxo| utxo.address.clone()).collect::<Vec<String>>().join(",");
+                let net_transparent_value = total_transparent_received as i64
+                    - wallet_transaction.total_transparent_value_spent as i64;
+                let address = wallet_transaction
+                    .received_utxos
+                    .iter()
+                    .map(|utxo| utxo.address.clone())
+                    .collect::<Vec<String>>()
+                    .join(",");
                 if net_transparent_value > 0 {
-                    if let Some(transaction) = consumer_notes_by_tx.iter_mut().find(|transaction| transaction["txid"] == txid.to_string()) {
+                    if let Some(transaction) = consumer_notes_by_tx
+                        .iter_mut()
+                        .find(|transaction| transaction.txid == txid.to_string())
+                    {
                         // If this transaction is outgoing:
                         // Then we've already accounted for the entire balance.
 
                         if !wallet_transaction.is_outgoing_transaction() {
                             // If not, we've added sapling/orchard, and need to add transparent
-                            let old_amount = transaction.remove("amount").as_i64().unwrap();
-                            transaction.insert("amount", old_amount + net_transparent_value).unwrap();
+                            transaction.amount += net_transparent_value;
                         }
                     } else {
                         // Create an input transaction for the transparent value as well.
-                        let block_height: u32 = wallet_transaction.block_height.into();
-                        consumer_notes_by_tx.push(object! {
-                            "block_height" => block_height,
-                            "unconfirmed"  => wallet_transaction.unconfirmed,
-                            "datetime"     => wallet_transaction.datetime,
-                            "txid"         => format!("{}", txid),
-                            "amount"       => net_transparent_value,
-                            "zec_price"    => wallet_transaction.price.map(|p| (p * 100.0).round() / 100.0),
-                            "address"      => address,
-                            "memo"         => None::<String>
+                        consumer_notes_by_tx.push(TransactionRecord {
+                            block_height: wallet_transaction.block_height.into(),
+                            unconfirmed: wallet_transaction.unconfirmed,
+                            datetime: wallet_transaction.datetime,
+                            txid: format!("{}", txid),
+                            amount: net_transparent_value,
+                            zec_price: wallet_transaction
+                                .price
+                                .map(|p| (p * 100.0).round() / 100.0),
+                            position: None,
+                            address: Some(address),
+                            memo: None,
+                            outgoing_metadata: None,
                         })
                     }
                 }
 
                 consumer_notes_by_tx
             })
-            .collect::<Vec<JsonValue>>();
-        /*
-        if let Some(tx) = transaction_list
-            .iter_mut()
-            .find(|a| a["memo"] == "Enviado desde YWallet, Enviado desde YWallet")
-        {
-            dbg!("{}", json::stringify_pretty(tx.clone(), 2));
-        }
-        */
+            .collect::<Vec<TransactionRecord>>();
 
-        let match_by_txid =
-            |a: &JsonValue, b: &JsonValue| a["txid"].to_string().cmp(&b["txid"].to_string());
-        consumer_ui_notes.sort_by(match_by_txid);
+        consumer_ui_notes.sort_by(|a, b| a.txid.cmp(&b.txid));
         consumer_ui_notes.dedup_by(|a, b| {
-            if match_by_txid(a, b) == Ordering::Equal {
-                let val_b = b.remove("amount").as_i64().unwrap();
-                b.insert(
-                    "amount",
-                    JsonValue::from(val_b + a.remove("amount").as_i64().unwrap()),
-                )
-                .unwrap();
-                let memo_b = b.remove("memo").to_string();
-                b.insert("memo", [a.remove("memo").to_string(), memo_b].join(", "))
-                    .unwrap();
-                for (key, a_val) in a.entries_mut() {
-                    let b_val = b.remove(key);
-                    if b_val == JsonValue::Null {
-                        b.insert(key, a_val.clone()).unwrap();
-                    } else {
-                        if a_val != &b_val {
-                            log::error!("{key}: {a_val} does not match {key}: {b_val}");
-                        }
-                        b.insert(key, b_val).unwrap()
-                    }
-                }
-
+            if a.txid == b.txid {
+                b.merge(a.clone());
                 true
             } else {
                 false
             }
         });
         consumer_ui_notes.sort_by(|a, b| {
-            if a["block_height"] == b["block_height"] {
-                a["txid"].as_str().cmp(&b["txid"].as_str())
+            if a.block_height == b.block_height {
+                a.txid.cmp(&b.txid)
             } else {
-                a["block_height"].as_i32().cmp(&b["block_height"].as_i32())
+                a.block_height.cmp(&b.block_height)
             }
         });
 
-        JsonValue::Array(consumer_ui_notes)
+        consumer_ui_notes
+    }
+
+    pub async fn do_list_transactions(&self) -> JsonValue {
+        JsonValue::Array(
+            self.list_transactions()
+                .await
+                .into_iter()
+                .map(JsonValue::from)
+                .collect(),
+        )
     }
 
     pub async fn do_list_txsummaries(&self) -> Vec<ValueTransfer> {
@@ -895,6 +1019,285 @@ impl LightClient {
 
         response
     }
+    /// Checks the wallet's invariants: spends and change flags against its own transactions,
+    /// witness trees against the chain's tree state at the anchor height, and unspent
+    /// transparent outputs against the server's UTXO set. Checks the server can't be asked for
+    /// are reported as unchecked. With `repair`, fixes what it can once every check has run,
+    /// and saves the wallet.
+    pub async fn do_check(&self, repair: bool) -> Result<ConsistencyReport, String> {
+        let mut report = ConsistencyReport::default();
+        self.wallet
+            .transactions()
+            .read()
+            .await
+            .check_consistency(&mut report);
+
+        let checkpoint_depth = self.config.reorg_buffer_offset as usize;
+        let anchor_height = self
+            .wallet
+            .last_synced_height()
+            .await
+            .saturating_sub(checkpoint_depth as u64);
+        match BlockSource::from_config(&self.config)
+            .get_trees(anchor_height)
+            .await
+        {
+            Ok(tree_state) => {
+                let transactions = self.wallet.transactions();
+                let transactions = transactions.read().await;
+                transactions.check_witness_tree::<SaplingDomain<ChainType>>(
+                    &tree_state,
+                    checkpoint_depth,
+                    &mut report,
+                );
+                transactions.check_witness_tree::<OrchardDomain>(
+                    &tree_state,
+                    checkpoint_depth,
+                    &mut report,
+                );
+            }
+            Err(e) => report.unchecked(format!(
+                "Witness trees, for lack of the chain's tree state at {}: {}",
+                anchor_height, e
+            )),
+        }
+
+        // A local block file has no UTXO set to compare with
+        if self.config.block_source_file.is_none() {
+            let taddrs = self
+                .wallet
+                .wallet_capability()
+                .get_all_taddrs(&self.config)
+                .into_iter()
+                .collect::<Vec<_>>();
+            if !taddrs.is_empty() {
+                match self.connector().get_address_utxos(taddrs).await {
+                    Ok(server_utxos) => self
+                        .wallet
+                        .transactions()
+                        .read()
+                        .await
+                        .check_utxos(&server_utxos, &mut report),
+                    Err(e) => report.unchecked(format!(
+                        "Transparent outputs, for lack of the server's UTXO set: {}",
+                        e
+                    )),
+                }
+            }
+        }
+
+        if repair {
+            self.wallet
+                .transactions()
+                .write()
+                .await
+                .apply_repairs(&mut report);
+            if report.any_repaired() {
+                self.do_save().await?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Cross-checks the wallet's transparent outputs with the server's UTXO set and balance
+    /// for our addresses. Outputs the wallet is missing are imported by fetching and scanning
+    /// their transactions, and reported as imported once the wallet has them. Outputs the
+    /// server no longer has were spent by a transaction the wallet never saw, so those are only
+    /// flagged, as is any balance difference left over. Every call fails over to another of
+    /// the configured servers.
+    async fn reconcile_transparent(&self) -> Result<ConsistencyReport, String> {
+        let mut report = ConsistencyReport::default();
+        let taddrs = self
+            .wallet
+            .wallet_capability()
+            .get_all_taddrs(&self.config)
+            .into_iter()
+            .collect::<Vec<_>>();
+        if taddrs.is_empty() {
+            return Ok(report);
+        }
+
+        let server_utxos = self
+            .with_server_failover(|connector| {
+                let taddrs = taddrs.clone();
+                async move { connector.get_address_utxos(taddrs).await }
+            })
+            .await?;
+        let missing_utxos = self
+            .wallet
+            .transactions()
+            .read()
+            .await
+            .utxos_missing_from_wallet(&server_utxos);
+
+        let mut scan_errors = HashMap::new();
+        for (txid, utxo) in &missing_utxos {
+            // Scanning the transaction picks up all of our outputs in it at once
+            if scan_errors.contains_key(txid) {
+                continue;
+            }
+            let chain = self.config.chain;
+            let height = utxo.height;
+            let fetched = self
+                .with_server_failover(|connector| async move {
+                    let transaction = connector.get_full_transaction(txid, chain).await?;
+                    let block_time = connector.get_trees(height).await?.time;
+                    Ok((transaction, block_time))
+                })
+                .await;
+            let scanned = match fetched {
+                Ok((transaction, block_time)) => {
+                    self.wallet
+                        .transaction_context
+                        .scan_full_tx(
+                            transaction,
+                            BlockHeight::from_u32(height as u32),
+                            false,
+                            block_time,
+                            None,
+                        )
+                        .await;
+                    None
+                }
+                Err(e) => Some(e),
+            };
+            scan_errors.insert(*txid, scanned);
+        }
+
+        // Only what the scans actually added counts as imported
+        let still_missing = self
+            .wallet
+            .transactions()
+            .read()
+            .await
+            .utxos_missing_from_wallet(&server_utxos)
+            .into_iter()
+            .map(|(txid, utxo)| (txid, utxo.index))
+            .collect::<HashSet<_>>();
+        for (txid, utxo) in missing_utxos {
+            if !still_missing.contains(&(txid, utxo.index)) {
+                report.found(
+                    format!(
+                        "Imported transparent output {}:{} to {} ({} zats) that was missing from the wallet",
+                        txid, utxo.index, utxo.address, utxo.value_zat
+                    ),
+                    true,
+                );
+                continue;
+            }
+            let reason = match scan_errors.get(&txid) {
+                Some(Some(e)) => format!("its transaction couldn't be fetched: {}", e),
+                _ => "scanning its transaction didn't add it".to_string(),
+            };
+            report.found(
+                format!(
+                    "Transparent output {}:{} to {} ({} zats) is on chain but missing from the wallet, and {}",
+                    txid, utxo.index, utxo.address, utxo.value_zat, reason
+                ),
+                false,
+            );
+        }
+
+        {
+            let transactions = self.wallet.transactions();
+            let transactions = transactions.read().await;
+            for utxo in transactions.utxos_spent_elsewhere(&server_utxos) {
+                report.found(
+                    format!(
+                        "Transparent output {}:{} to {} ({} zats) was spent by a transaction the wallet hasn't seen",
+                        utxo.txid, utxo.output_index, utxo.address, utxo.value
+                    ),
+                    false,
+                );
+            }
+        }
+
+        let server_balance = self
+            .with_server_failover(|connector| {
+                let taddrs = taddrs.clone();
+                async move { connector.get_taddress_balance(taddrs).await }
+            })
+            .await?;
+        self.wallet
+            .transactions()
+            .read()
+            .await
+            .check_transparent_balance(server_balance, &mut report);
+
+        #[cfg(not(any(target_os = "ios", target_os = "android")))]
+        if report.any_repaired() {
+            self.do_save().await?;
+        }
+
+        Ok(report)
+    }
+
+    /// Sends our pending transactions that can still be mined to the server again, the first
+    /// time we sync against it. A server we've just connected or failed over to may never have
+    /// seen them.
+    async fn rebroadcast_pending(&self) {
+        let connector = self.connector();
+        let uri = connector.uri().clone();
+        let mut rebroadcast_to = self.rebroadcast_to.lock().await;
+        if rebroadcast_to.as_ref() == Some(&uri) {
+            return;
+        }
+
+        let last_synced_height = self.wallet.last_synced_height().await;
+        let pending = self
+            .wallet
+            .transactions()
+            .read()
+            .await
+            .rebroadcastable(last_synced_height);
+        for (txid, raw_transaction) in pending {
+            match connector
+                .send_transaction(raw_transaction.into_boxed_slice())
+                .await
+            {
+                Ok(_) => info!("Rebroadcast pending transaction {}", txid),
+                // Usually because the server already has it in its mempool
+                Err(e) => warn!("Couldn't rebroadcast pending transaction {}: {}", txid, e),
+            }
+        }
+        *rebroadcast_to = Some(uri);
+    }
+
+    /// Transactions that have been broadcast but not yet mined, oldest first
+    pub async fn do_pending(&self) -> JsonValue {
+        let last_synced_height = self.wallet.last_synced_height().await;
+        let transactions = self.wallet.transactions();
+        let transactions = transactions.read().await;
+        let mut pending = transactions
+            .current
+            .values()
+            .filter(|transaction_metadata| transaction_metadata.unconfirmed)
+            .collect::<Vec<_>>();
+        pending.sort_by_key(|transaction_metadata| transaction_metadata.datetime);
+
+        JsonValue::Array(
+            pending
+                .into_iter()
+                .map(|transaction_metadata| {
+                    let submission_height: u32 = transaction_metadata.block_height.into();
+                    let expiry_height = transaction_metadata.expiry_height.map(u32::from);
+                    object! {
+                        "txid" => transaction_metadata.txid.to_string(),
+                        "datetime" => transaction_metadata.datetime,
+                        "submission_height" => submission_height,
+                        "expiry_height" => expiry_height,
+                        "blocks_until_expiry" => expiry_height
+                            .map(|expiry| (expiry as u64).saturating_sub(last_synced_height)),
+                        "outgoing" => transaction_metadata.is_outgoing_transaction(),
+                        "value_outgoing" => transaction_metadata.value_outgoing(),
+                        "rebroadcastable" => transaction_metadata.raw_transaction.is_some(),
+                    }
+                })
+                .collect(),
+        )
+    }
+
     pub async fn do_delete(&self) -> Result<(), String> {
         #[cfg(any(target_os = "ios", target_os = "android"))]
         // on mobile platforms, disable the delete, as it will be handled by the native layer
@@ -911,7 +1314,7 @@ impl LightClient {
 
             // Check if the file exists before attempting to delete
             if self.config.wallet_exists() {
-                match remove_file(self.config.get_wallet_path()) {
+                match self.config.remove_wallet() {
                     Ok(_) => {
                         log::debug!("File deleted successfully!");
                         Ok(())
@@ -951,8 +1354,8 @@ impl LightClient {
             let mut wallet_bytes = vec![];
             match self.wallet.write(&mut wallet_bytes).await {
                 Ok(_) => {
-                    let mut file = File::create(self.config.get_wallet_path()).unwrap();
-                    file.write_all(&wallet_bytes)
+                    self.config
+                        .write_wallet(&wallet_bytes)
                         .map_err(|e| format!("{}", e))?;
                     log::debug!("In the guts of a successful save!");
                     Ok(())
@@ -984,16 +1387,20 @@ impl LightClient {
             .block_on(async move { self.do_save_to_buffer().await })
     }
 
-    pub async fn do_seed_phrase(&self) -> Result<JsonValue, &str> {
+    pub async fn seed_phrase(&self) -> Result<SeedPhrase, &str> {
         match self.wallet.mnemonic() {
-            Some(m) => Ok(object! {
-                "seed"     => m.to_string(),
-                "birthday" => self.wallet.get_birthday().await
+            Some(m) => Ok(SeedPhrase {
+                seed: m.to_string(),
+                birthday: self.wallet.get_birthday().await,
             }),
             None => Err("This wallet is watch-only."),
         }
     }
 
+    pub async fn do_seed_phrase(&self) -> Result<JsonValue, &str> {
+        self.seed_phrase().await.map(JsonValue::from)
+    }
+
     pub fn do_seed_phrase_sync(&self) -> Result<JsonValue, &str> {
         Runtime::new()
             .unwrap()
@@ -1004,7 +1411,42 @@ impl LightClient {
         &self,
         address_amount_memo_tuples: Vec<(&str, u64, Option<MemoBytes>)>,
     ) -> Result<String, String> {
-        let transaction_submission_height = self.get_submission_height().await?;
+        self.do_send_noting(address_amount_memo_tuples, None, |_| Ok(()))
+            .await
+            .map_err(String::from)
+    }
+
+    /// Sends as [`Self::do_send`] does, with `expiry` instead of the wallet's send_expiry option
+    /// if it is given, telling failures at the server apart from the wallet's
+    pub async fn do_send_with_expiry(
+        &self,
+        address_amount_memo_tuples: Vec<(&str, u64, Option<MemoBytes>)>,
+        expiry: Option<ExpiryDelta>,
+    ) -> Result<String, SendError> {
+        self.do_send_noting(address_amount_memo_tuples, expiry, |_| Ok(()))
+            .await
+    }
+
+    /// Sends as [`Self::do_send`] does, calling `note_built` with the transaction once it is
+    /// built and before it is broadcast. If that fails, the transaction isn't broadcast.
+    async fn do_send_noting<N>(
+        &self,
+        address_amount_memo_tuples: Vec<(&str, u64, Option<MemoBytes>)>,
+        expiry: Option<ExpiryDelta>,
+        note_built: N,
+    ) -> Result<String, SendError>
+    where
+        N: Fn(&Transaction) -> Result<(), String>,
+    {
+        let expiry = match expiry {
+            Some(expiry) => expiry,
+            None => self.wallet.wallet_options.read().await.send_expiry,
+        };
+        let transaction_submission_height = self
+            .get_submission_height()
+            .await
+            .map_err(SendError::Server)?;
+        let broadcast_failed = AtomicBool::new(false);
         // First, get the concensus branch ID
         debug!("Creating transaction");
 
@@ -1013,7 +1455,8 @@ impl LightClient {
             // I am not clear on how long this operation may take, but it's
             // clearly unnecessary in a send that doesn't include sapling
             // TODO: Remove from sends that don't include Sapling
-            let (sapling_output, sapling_spend) = self.read_sapling_params()?;
+            let (sapling_output, sapling_spend) =
+                self.read_sapling_params().map_err(SendError::Wallet)?;
 
             let sapling_prover = LocalTxProver::from_bytes(&sapling_spend, &sapling_output);
 
@@ -1024,14 +1467,269 @@ impl LightClient {
                     // spend from transparent.
                     address_amount_memo_tuples,
                     transaction_submission_height,
+                    expiry,
                     |transaction_bytes| {
-                        GrpcConnector::send_transaction(self.get_server_uri(), transaction_bytes)
+                        let noted = Transaction::read(
+                            &transaction_bytes[..],
+                            BranchId::for_height(&self.config.chain, transaction_submission_height),
+                        )
+                        .map_err(|e| format!("Couldn't read the built transaction: {}", e))
+                        .and_then(|transaction| note_built(&transaction));
+                        let broadcast = self.broadcast(transaction_bytes, &broadcast_failed);
+                        async move {
+                            noted?;
+                            broadcast.await
+                        }
                     },
                 )
                 .await
         };
 
-        result.map(|(transaction_id, _)| transaction_id)
+        result
+            .map(|(transaction_id, _)| transaction_id)
+            .map_err(|e| send_error(e, &broadcast_failed))
+    }
+
+    /// Broadcasts a built transaction, setting `failed` if the server couldn't be reached or
+    /// turned it down
+    async fn broadcast(
+        &self,
+        transaction_bytes: Box<[u8]>,
+        failed: &AtomicBool,
+    ) -> Result<String, String> {
+        let broadcast = self
+            .with_server_failover(move |connector| {
+                let transaction_bytes = transaction_bytes.clone();
+                async move { connector.send_transaction(transaction_bytes).await }
+            })
+            .await;
+        if broadcast.is_err() {
+            failed.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+        broadcast
+    }
+
+    /// Plans paying `payouts` from the notes the wallet can spend now
+    pub async fn plan_payouts(&self, payouts: Vec<Payout>) -> PayoutPlan {
+        // The pools do_send spends from
+        let notes = SpendableNotes {
+            orchard: self.wallet.spendable_note_values::<OrchardDomain>().await,
+            sapling: self
+                .wallet
+                .spendable_note_values::<SaplingDomain<ChainType>>()
+                .await,
+        };
+        PayoutPlan::new(payouts, notes, &self.config.chain)
+    }
+
+    /// Sends the transactions of a payout plan one after the other. A transaction that fails
+    /// stops the payout, and its rows and those after it are reported as not paid.
+    ///
+    /// The change of each transaction can't be spent until it is mined, so later
+    /// transactions are paid from notes the earlier ones left.
+    pub async fn do_send_payouts(&self, plan: &PayoutPlan) -> Vec<PayoutResult> {
+        let mut results = vec![];
+        let mut failure: Option<String> = None;
+        for transaction in &plan.transactions {
+            let payouts = &transaction.payouts;
+            let txid = match &failure {
+                Some(e) => Err(format!("Not sent, as an earlier transaction failed: {}", e)),
+                None => {
+                    let tos = payouts
+                        .iter()
+                        .map(|p| (p.address.as_str(), p.amount, p.memo_bytes()))
+                        .collect();
+                    self.do_send(tos).await
+                }
+            };
+            if failure.is_none() {
+                failure = txid.clone().err();
+            }
+            results.extend(payouts.iter().map(|payout| PayoutResult {
+                payout: payout.clone(),
+                txid: txid.clone(),
+            }));
+        }
+        results
+    }
+
+    fn load_payment_schedule(&self) -> Result<PaymentSchedule, String> {
+        let name = self.config.payment_schedule_storage_name();
+        let storage = self.config.get_storage();
+        PaymentSchedule::load(storage.as_ref(), &name).map_err(|e| {
+            format!(
+                "Couldn't read the payment schedule {}: {}",
+                storage.describe(&name),
+                e
+            )
+        })
+    }
+
+    fn save_payment_schedule(&self, schedule: &PaymentSchedule) -> Result<(), String> {
+        let name = self.config.payment_schedule_storage_name();
+        let storage = self.config.get_storage();
+        schedule.save(storage.as_ref(), &name).map_err(|e| {
+            format!(
+                "Couldn't write the payment schedule {}: {}",
+                storage.describe(&name),
+                e
+            )
+        })
+    }
+
+    /// The wallet's scheduled payments, and the journal of their runs. Runs an earlier process
+    /// left pending are reported as failed.
+    pub fn payment_schedule(&self) -> Result<PaymentSchedule, String> {
+        let mut schedule = self.load_payment_schedule()?;
+        schedule.recover_interrupted(now());
+        Ok(schedule)
+    }
+
+    /// Schedules a payment, returning its id. The schedule is read and written whole, so
+    /// changes to it should be made one at a time, as the command loop does.
+    pub fn schedule_payment(&self, payment: ScheduledPayment) -> Result<u64, String> {
+        if RecipientAddress::decode(&self.config.chain, &payment.address).is_none() {
+            return Err(format!(
+                "'{}' is not a {} address",
+                payment.address, self.config.chain
+            ));
+        }
+        if let Some(memo) = &payment.memo {
+            if !is_shielded_address(&payment.address, &self.config) {
+                return Err(format!(
+                    "Can't send a memo to the non-shielded address {}",
+                    payment.address
+                ));
+            }
+            interpret_memo_string(memo.clone())?;
+        }
+        if payment.every == Some(0) {
+            return Err("A repeating payment can't repeat every 0 blocks or seconds".to_string());
+        }
+        let mut schedule = self.load_payment_schedule()?;
+        let id = schedule.add(payment);
+        self.save_payment_schedule(&schedule)?;
+        Ok(id)
+    }
+
+    pub fn cancel_scheduled_payment(&self, id: u64) -> Result<(), String> {
+        let mut schedule = self.load_payment_schedule()?;
+        schedule.cancel(id)?;
+        self.save_payment_schedule(&schedule)
+    }
+
+    /// Pays the scheduled payments that are due, at most one run of each, against the height the
+    /// wallet has synced to. Runs submitted earlier are first settled against the chain. Returns
+    /// the journal entries of the runs made or settled.
+    pub async fn run_scheduled_payments(&self) -> Result<Vec<JournalEntry>, String> {
+        let mut schedule = self.load_payment_schedule()?;
+        if schedule.recover_interrupted(now()) > 0 {
+            self.save_payment_schedule(&schedule)?;
+        }
+
+        let height = self.wallet.last_synced_height().await;
+        let mut entries = vec![];
+        for i in schedule.submitted() {
+            let (txid, expiry_height) = match &schedule.journal[i].outcome {
+                RunOutcome::Submitted {
+                    txid,
+                    expiry_height,
+                } => (txid.clone(), *expiry_height),
+                _ => continue,
+            };
+            if self.is_transaction_known(&txid).await {
+                schedule.settle(i, Ok(txid), now());
+            } else if height > expiry_height {
+                schedule.settle(
+                    i,
+                    Err(format!("{} expired without being mined", txid)),
+                    now(),
+                );
+            } else {
+                continue;
+            }
+            entries.push(schedule.journal[i].clone());
+            self.save_payment_schedule(&schedule)?;
+        }
+
+        for id in schedule.due(height, now()) {
+            let payment = match schedule.payments.iter().find(|payment| payment.id == id) {
+                Some(payment) => payment.clone(),
+                None => continue,
+            };
+            // Recorded before sending, so that a crash mid-send can't lead to paying twice
+            schedule.journal.push(JournalEntry {
+                payment_id: id,
+                run: payment.next_run,
+                executed_at: now(),
+                outcome: RunOutcome::Pending,
+            });
+            self.save_payment_schedule(&schedule)?;
+            let i = schedule.journal.len() - 1;
+
+            // The schedule as saved once the transaction is built, before it is broadcast
+            let submitted_schedule = std::sync::Mutex::new(None);
+            let sent = match payment.memo.clone().map(interpret_memo_string).transpose() {
+                Ok(memo) => self
+                    .do_send_noting(
+                        vec![(payment.address.as_str(), payment.amount, memo)],
+                        None,
+                        |transaction| {
+                            let mut submitted = schedule.clone();
+                            submitted.journal[i].outcome = RunOutcome::Submitted {
+                                txid: transaction.txid().to_string(),
+                                expiry_height: u32::from(transaction.expiry_height()) as u64,
+                            };
+                            self.save_payment_schedule(&submitted)?;
+                            *submitted_schedule.lock().unwrap() = Some(submitted);
+                            Ok(())
+                        },
+                    )
+                    .await
+                    .map_err(String::from),
+                Err(e) => Err(e),
+            };
+            match (sent, submitted_schedule.into_inner().unwrap()) {
+                (Ok(txid), _) => schedule.settle(i, Ok(txid), now()),
+                // It may have reached the network all the same, so it waits to be settled
+                (Err(_), Some(submitted)) => schedule = submitted,
+                (Err(error), None) => schedule.settle(i, Err(error), now()),
+            }
+            entries.push(schedule.journal[i].clone());
+            self.save_payment_schedule(&schedule)?;
+        }
+        Ok(entries)
+    }
+
+    /// Whether the wallet or a server knows of the transaction `txid`, mined or in the mempool
+    async fn is_transaction_known(&self, txid: &str) -> bool {
+        let known_to_wallet = self
+            .wallet
+            .transactions()
+            .read()
+            .await
+            .current
+            .keys()
+            .any(|known| known.to_string() == txid);
+        if known_to_wallet {
+            return true;
+        }
+        let txid = match hex::decode(txid)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        {
+            // Shown byte-reversed
+            Some(mut bytes) => {
+                bytes.reverse();
+                TxId::from_bytes(bytes)
+            }
+            None => return false,
+        };
+        self.with_server_failover(
+            |connector| async move { connector.get_raw_transaction(&txid).await },
+        )
+        .await
+        .is_ok()
     }
 
     pub async fn do_send_progress(&self) -> Result<LightWalletSendProgress, String> {
@@ -1047,7 +1745,23 @@ impl LightClient {
         pools_to_shield: &[Pool],
         address: Option<String>,
     ) -> Result<String, String> {
-        let transaction_submission_height = self.get_submission_height().await?;
+        self.do_shield_with_expiry(pools_to_shield, address, None)
+            .await
+            .map_err(String::from)
+    }
+
+    /// Shields as [`Self::do_shield`] does, with `expiry` instead of the wallet's send_expiry
+    /// option if it is given, telling failures at the server apart from the wallet's
+    pub async fn do_shield_with_expiry(
+        &self,
+        pools_to_shield: &[Pool],
+        address: Option<String>,
+        expiry: Option<ExpiryDelta>,
+    ) -> Result<String, SendError> {
+        let transaction_submission_height = self
+            .get_submission_height()
+            .await
+            .map_err(SendError::Server)?;
         let fee = u64::from(MINIMUM_FEE); // TODO: This can no longer be hard coded, and must be calced
                                           // as a fn of the transactions structure.
         let tbal = self
@@ -1072,18 +1786,24 @@ impl LightClient {
             0
         };
         if balance_to_shield <= fee {
-            return Err(format!(
+            return Err(SendError::Wallet(format!(
                 "Not enough transparent/sapling balance to shield. Have {} zats, need more than {} zats to cover tx fee",
                 balance_to_shield, fee
-            ));
+            )));
         }
 
         let addr = address
             .unwrap_or(self.wallet.wallet_capability().addresses()[0].encode(&self.config.chain));
+        let expiry = match expiry {
+            Some(expiry) => expiry,
+            None => self.wallet.wallet_options.read().await.send_expiry,
+        };
+        let broadcast_failed = AtomicBool::new(false);
 
         let result = {
             let _lock = self.sync_lock.lock().await;
-            let (sapling_output, sapling_spend) = self.read_sapling_params()?;
+            let (sapling_output, sapling_spend) =
+                self.read_sapling_params().map_err(SendError::Wallet)?;
 
             let sapling_prover = LocalTxProver::from_bytes(&sapling_spend, &sapling_output);
 
@@ -1093,14 +1813,15 @@ impl LightClient {
                     pools_to_shield.to_vec(),
                     vec![(&addr, balance_to_shield - fee, None)],
                     transaction_submission_height,
-                    |transaction_bytes| {
-                        GrpcConnector::send_transaction(self.get_server_uri(), transaction_bytes)
-                    },
+                    expiry,
+                    |transaction_bytes| self.broadcast(transaction_bytes, &broadcast_failed),
                 )
                 .await
         };
 
-        result.map(|(transaction_id, _)| transaction_id)
+        result
+            .map(|(transaction_id, _)| transaction_id)
+            .map_err(|e| send_error(e, &broadcast_failed))
     }
 
     pub async fn do_sync(&self, print_updates: bool) -> Result<SyncResult, String> {
@@ -1215,7 +1936,10 @@ impl LightClient {
             "Getting sapling tree from LightwalletD at height {}",
             height
         );
-        match GrpcConnector::get_trees(self.config.get_lightwalletd_uri(), height).await {
+        match BlockSource::from_config(&self.config)
+            .get_trees(height)
+            .await
+        {
             Ok(tree_state) => {
                 let hash = tree_state.hash.clone();
                 let tree = tree_state.sapling_tree.clone();
@@ -1236,9 +1960,14 @@ impl LightClient {
         self.config.get_lightwalletd_uri()
     }
 
+    /// A connection to the current server, made as the config says
+    fn connector(&self) -> GrpcConnector {
+        GrpcConnector::from_config(&self.config)
+    }
+
     async fn get_submission_height(&self) -> Result<BlockHeight, String> {
         Ok(BlockHeight::from_u32(
-            GrpcConnector::get_latest_block(self.config.get_lightwalletd_uri())
+            self.with_server_failover(|connector| async move { connector.get_latest_block().await })
                 .await?
                 .height as u32,
         ) + 1)
@@ -1375,6 +2104,87 @@ impl LightClient {
         *self.config.lightwalletd_uri.write().unwrap() = server
     }
 
+    fn servers_to_check(&self) -> Vec<http::Uri> {
+        let current = self.get_server_uri();
+        let mut uris = self.config.get_server_uris();
+        if !uris.contains(&current) {
+            uris.insert(0, current);
+        }
+        uris
+    }
+
+    /// Health check every known server, cross-checking their chain tips against each other.
+    pub async fn do_server_statuses(&self) -> Vec<ServerStatusRecord> {
+        let current = self.get_server_uri();
+        let statuses = self
+            .connector()
+            .check_servers(&self.servers_to_check(), self.config.chain)
+            .await;
+        let median_tip = median_server_tip(&statuses);
+
+        statuses
+            .iter()
+            .map(|status| ServerStatusRecord {
+                uri: status.uri.to_string(),
+                current: status.uri == current,
+                status: status.describe(median_tip).to_string(),
+                latest_block_height: status.latest_block_height,
+                latency_ms: status.latency_ms.map(|ms| ms as u64),
+                error: status.error.clone(),
+            })
+            .collect()
+    }
+
+    /// Keep the current server if it is healthy and agrees with the others about the chain tip,
+    /// otherwise fail over to the first trusted server, in order of preference.
+    pub async fn ensure_trusted_server(&self) -> Result<http::Uri, String> {
+        let current = self.get_server_uri();
+        let statuses = self
+            .connector()
+            .check_servers(&self.servers_to_check(), self.config.chain)
+            .await;
+        let median_tip = median_server_tip(&statuses);
+
+        if statuses
+            .iter()
+            .any(|status| status.uri == current && status.is_trusted(median_tip))
+        {
+            return Ok(current);
+        }
+        match statuses
+            .into_iter()
+            .find(|status| status.is_trusted(median_tip))
+        {
+            Some(status) => {
+                warn!("Failing over from server {} to {}", current, status.uri);
+                self.set_server(status.uri.clone());
+                Ok(status.uri)
+            }
+            None => Err("No healthy lightwalletd server is available".to_string()),
+        }
+    }
+
+    /// Make a server call, and if it fails and other servers are configured, retry it once
+    /// against whichever server we fail over to.
+    async fn with_server_failover<T, F, Fut>(&self, call: F) -> Result<T, String>
+    where
+        F: Fn(GrpcConnector) -> Fut,
+        Fut: Future<Output = Result<T, String>>,
+    {
+        let connector = self.connector();
+        let uri = connector.uri().clone();
+        match call(connector.clone()).await {
+            Err(e) if self.config.get_server_uris().len() > 1 => {
+                warn!("Call to server {} failed: {}", uri, e);
+                match self.ensure_trusted_server().await {
+                    Ok(new_uri) if new_uri != uri => call(connector.with_uri(new_uri)).await,
+                    _ => Err(e),
+                }
+            }
+            result => result,
+        }
+    }
+
     pub async fn set_wallet_initial_state(&self, height: u64) {
         let state = self.get_initial_state(height).await;
 
@@ -1386,6 +2196,8 @@ impl LightClient {
         }
     }
 
+    /// Monitors the mempool on a dedicated thread with its own runtime, for callers that don't
+    /// have a runtime of their own. See `run_mempool_monitor` otherwise.
     pub fn start_mempool_monitor(lc: Arc<LightClient>) {
         if !lc.config.monitor_mempool {
             return;
@@ -1395,80 +2207,155 @@ impl LightClient {
             return;
         }
 
-        let config = lc.config.clone();
-        let lci = lc.clone();
-
-        debug!("Mempool monitoring starting");
-
-        let uri = lc.config.get_lightwalletd_uri();
         // Start monitoring the mempool in a new thread
+        let lci = lc.clone();
         let h = std::thread::spawn(move || {
             // Start a new async runtime, which is fine because we are in a new thread.
-            Runtime::new().unwrap().block_on(async move {
-                let (mempool_transmitter, mut mempool_receiver) =
-                    unbounded_channel::<RawTransaction>();
-                let lc1 = lci.clone();
-
-                let h1 = tokio::spawn(async move {
-                    let key = lc1.wallet.wallet_capability();
-                    let transaction_metadata_set = lc1
-                        .wallet
-                        .transaction_context
-                        .transaction_metadata_set
-                        .clone();
-                    let price = lc1.wallet.price.clone();
-
-                    while let Some(rtransaction) = mempool_receiver.recv().await {
-                        if let Ok(transaction) = Transaction::read(
-                            &rtransaction.data[..],
-                            BranchId::for_height(
-                                &config.chain,
-                                BlockHeight::from_u32(rtransaction.height as u32),
-                            ),
-                        ) {
-                            let price = price.read().await.clone();
-                            //debug!("Mempool attempting to scan {}", tx.txid());
-
-                            TransactionContext::new(
-                                &config,
-                                key.clone(),
-                                transaction_metadata_set.clone(),
-                            )
-                            .scan_full_tx(
-                                transaction,
-                                BlockHeight::from_u32(rtransaction.height as u32),
-                                true,
-                                now() as u32,
-                                TransactionMetadata::get_price(now(), &price),
-                            )
-                            .await;
-                        }
-                    }
-                });
+            Runtime::new()
+                .unwrap()
+                .block_on(LightClient::run_mempool_monitor(lci));
+        });
+
+        *lc.mempool_monitor.write().unwrap() = Some(h);
+    }
+
+    /// Scans the server's mempool for transactions involving this wallet, which is what the
+    /// pending balances are made of, and syncs whenever a new block ends the mempool stream.
+    /// Runs until the task is dropped, on whatever runtime the caller spawns it on. Only one
+    /// monitor should run per client.
+    pub async fn run_mempool_monitor(lc: Arc<LightClient>) {
+        let config = lc.config.clone();
 
-                let h2 = tokio::spawn(async move {
-                    loop {
-                        //debug!("Monitoring mempool");
-                        let r = GrpcConnector::monitor_mempool(
-                            uri.clone(),
-                            mempool_transmitter.clone(),
+        debug!("Mempool monitoring starting");
+
+        let connector = lc.connector();
+        let (mempool_transmitter, mut mempool_receiver) = unbounded_channel::<RawTransaction>();
+        let lc1 = lc.clone();
+
+        let scan_mempool = async move {
+            let key = lc1.wallet.wallet_capability();
+            let transaction_metadata_set = lc1
+                .wallet
+                .transaction_context
+                .transaction_metadata_set
+                .clone();
+            let price = lc1.wallet.price.clone();
+
+            while let Some(rtransaction) = mempool_receiver.recv().await {
+                if let Ok(transaction) = Transaction::read(
+                    &rtransaction.data[..],
+                    BranchId::for_height(
+                        &config.chain,
+                        BlockHeight::from_u32(rtransaction.height as u32),
+                    ),
+                ) {
+                    let price = price.read().await.clone();
+                    //debug!("Mempool attempting to scan {}", tx.txid());
+
+                    TransactionContext::new(&config, key.clone(), transaction_metadata_set.clone())
+                        .scan_full_tx(
+                            transaction,
+                            BlockHeight::from_u32(rtransaction.height as u32),
+                            true,
+                            now() as u32,
+                            TransactionMetadata::get_price(now(), &price),
                         )
                         .await;
+                }
+            }
+        };
 
-                        if r.is_err() {
-                            warn!("Mempool monitor returned {:?}, will restart listening", r);
-                            sleep(Duration::from_secs(10)).await;
-                        } else {
-                            let _ = lci.do_sync(false).await;
-                        }
+        let listen_to_mempool = async move {
+            match lc.config.mempool_monitor_mode {
+                MempoolMonitorMode::FullStream => loop {
+                    //debug!("Monitoring mempool");
+                    let r = connector.monitor_mempool(mempool_transmitter.clone()).await;
+
+                    if r.is_err() {
+                        warn!("Mempool monitor returned {:?}, will restart listening", r);
+                        sleep(Duration::from_secs(10)).await;
+                    } else {
+                        let _ = lc.do_sync(false).await;
                     }
-                });
+                },
+                MempoolMonitorMode::Compact => {
+                    lc.poll_compact_mempool(connector, mempool_transmitter)
+                        .await
+                }
+            }
+        };
 
-                let (_, _) = join!(h1, h2);
-            });
-        });
+        join!(scan_mempool, listen_to_mempool);
+    }
 
-        *lc.mempool_monitor.write().unwrap() = Some(h);
+    /// Polls the server's mempool for compact transactions, leaving out the ones already seen,
+    /// and passes on the full transactions of those that pay to or spend from the wallet.
+    /// A new block starts the mempool over and triggers a sync.
+    async fn poll_compact_mempool(
+        &self,
+        connector: GrpcConnector,
+        mempool_transmitter: UnboundedSender<RawTransaction>,
+    ) {
+        let mut seen_txids: HashSet<Vec<u8>> = HashSet::new();
+        let mut tip_height = self.wallet.last_synced_height().await;
+        loop {
+            match connector.get_latest_block().await {
+                Ok(latest_block) if latest_block.height > tip_height => {
+                    tip_height = latest_block.height;
+                    seen_txids.clear();
+                    let _ = self.do_sync(false).await;
+                }
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "Couldn't get the latest block for the mempool monitor: {}",
+                    e
+                ),
+            }
+
+            match connector
+                .get_mempool_compact_transactions(mempool_exclude(&seen_txids))
+                .await
+            {
+                Ok(compact_transactions) => {
+                    let height = BlockHeight::from_u32(tip_height as u32 + 1);
+                    let wc = self.wallet.wallet_capability();
+                    for compact_transaction in compact_transactions {
+                        if !seen_txids.insert(compact_transaction.hash.clone()) {
+                            continue;
+                        }
+                        let involves_wallet = mempool_transaction_involves_wallet(
+                            &compact_transaction,
+                            height,
+                            self.config.chain,
+                            &wc,
+                            &*self.wallet.transactions().read().await,
+                        );
+                        if !involves_wallet {
+                            continue;
+                        }
+
+                        let txid = TransactionMetadata::new_txid(&compact_transaction.hash);
+                        match connector.get_raw_transaction(&txid).await {
+                            Ok(mut raw_transaction) => {
+                                // Like GetMempoolStream, report the height of the latest block
+                                raw_transaction.height = tip_height;
+                                if mempool_transmitter.send(raw_transaction).is_err() {
+                                    return;
+                                }
+                            }
+                            Err(e) => {
+                                warn!("Couldn't fetch mempool transaction {}: {}", txid, e);
+                                // Try again on the next poll
+                                seen_txids.remove(&compact_transaction.hash);
+                            }
+                        }
+                    }
+                }
+                Err(e) => warn!("Mempool poll returned {}, will retry", e),
+            }
+
+            sleep(MEMPOOL_POLL_INTERVAL).await;
+        }
     }
 
     async fn wallet_has_any_empty_commitment_trees(&self) -> bool {
@@ -1492,7 +2379,93 @@ impl LightClient {
                         .is_none()
             })
     }
-    /// Start syncing in batches with the max size, to manage memory consumption.
+    /// If the lowest block we keep for reorgs is no longer on the chain, the reorg goes deeper
+    /// than we can follow block by block. Find the fork point by comparing our older anchors
+    /// against the chain's tree states, and rewind the wallet to it.
+    async fn rewind_deep_reorg(&self, block_source: &BlockSource) -> Result<(), String> {
+        let blocks = self.wallet.get_blocks().await;
+        let (tip, lowest_block) = match (blocks.first(), blocks.last()) {
+            (Some(tip), Some(lowest_block)) => (tip.clone(), lowest_block.clone()),
+            _ => return Ok(()),
+        };
+        // Nothing can have been reorged if the chain still ends at the wallet's tip. The latest
+        // block hash comes in the reverse byte order of the block itself.
+        if let Ok(latest) = block_source.get_latest_block().await {
+            if latest.height == tip.height && latest.hash.iter().rev().eq(tip.cb().hash.iter()) {
+                return Ok(());
+            }
+        }
+        match block_source.get_trees(lowest_block.height).await {
+            Ok(tree_state) if tree_state.hash == lowest_block.hash() => return Ok(()),
+            Ok(_) => (),
+            Err(e) => {
+                // Without the chain's view of that block we can't tell either way
+                log::info!("Couldn't check for a deep reorg: {}", e);
+                return Ok(());
+            }
+        }
+
+        warn!(
+            "Block {} is no longer on the chain, the reorg goes past the last {} blocks",
+            lowest_block.height, MAX_REORG
+        );
+        let anchors = self
+            .wallet
+            .reorg_anchors
+            .read()
+            .await
+            .iter()
+            .filter(|anchor| anchor.height < lowest_block.height)
+            .cloned()
+            .collect::<Vec<_>>();
+        // The anchors are highest first, and every one below an anchor still on the chain is on
+        // it too, so the highest one still on it is found by bisection. Below the window every
+        // block is an anchor for a while, so a fork there is found exactly.
+        let (mut off_chain, mut on_chain) = (0, anchors.len());
+        let mut fork_point = None;
+        while off_chain < on_chain {
+            let middle = (off_chain + on_chain) / 2;
+            let tree_state = block_source.get_trees(anchors[middle].height).await?;
+            if tree_state.hash == anchors[middle].hash() {
+                fork_point = Some(tree_state);
+                on_chain = middle;
+            } else {
+                off_chain = middle + 1;
+            }
+        }
+        // Before the birthday the wallet has nothing, so that is always common ground
+        let fork_point = match fork_point {
+            Some(tree_state) => tree_state,
+            None => {
+                block_source
+                    .get_trees(self.wallet.get_birthday().await.saturating_sub(1))
+                    .await?
+            }
+        };
+
+        warn!("Rewinding wallet to block {}", fork_point.height);
+        self.wallet.rewind_to(fork_point).await
+    }
+
+    /// Another trusted server to carry on a sync from, after `block_source` failed it. None if
+    /// only one server is configured, the blocks come from a local file, or no other server
+    /// can be trusted.
+    async fn fail_over_block_source(&self, block_source: &BlockSource) -> Option<BlockSource> {
+        let connector = block_source.connector()?;
+        if self.config.get_server_uris().len() < 2 {
+            return None;
+        }
+        match self.ensure_trusted_server().await {
+            Ok(new_uri) if &new_uri != connector.uri() => {
+                Some(BlockSource::Lightwalletd(connector.with_uri(new_uri)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Start syncing in batches with the max size, to manage memory consumption. A batch that
+    /// fails is tried once more against another server, if one can be failed over to, and the
+    /// sync carries on from there.
     async fn start_sync(&self) -> Result<SyncResult, String> {
         // We can only do one sync at a time because we sync blocks in serial order
         // If we allow multiple syncs, they'll all get jumbled up.
@@ -1501,8 +2474,44 @@ impl LightClient {
         // we might parallelize sync.
         let lightclient_exclusion_lock = self.sync_lock.lock().await;
 
+        // With several servers configured, make sure the one we sync from isn't lagging or lying
+        if self.config.block_source_file.is_none() && self.config.get_server_uris().len() > 1 {
+            self.ensure_trusted_server().await?;
+        }
+        let mut block_source = BlockSource::from_config(&self.config);
+
+        // Reorgs within the last MAX_REORG blocks are handled while syncing, but a deeper one
+        // has to be rewound before we start
+        self.rewind_deep_reorg(&block_source).await?;
+
         // The top of the wallet
-        let last_synced_height = dbg!(self.wallet.last_synced_height().await);
+        let mut last_synced_height = dbg!(self.wallet.last_synced_height().await);
+
+        // Pick up the progress of an interrupted sync, as far as the saved wallet has it
+        let storage = self.config.get_storage();
+        let checkpoint_name = self.config.sync_checkpoint_storage_name();
+        let resumed_checkpoint = SyncCheckpoint::load(storage.as_ref(), &checkpoint_name)
+            .map(|mut checkpoint| {
+                checkpoint.truncate_to(last_synced_height);
+                checkpoint
+            })
+            .filter(|checkpoint| checkpoint.scanned_to() == Some(last_synced_height));
+
+        // The interrupted run only vouches for the witnesses up to where it checkpointed them,
+        // so witness updates start again from there
+        if let Some(checkpoint) = &resumed_checkpoint {
+            if checkpoint.witnesses_updated_to < last_synced_height {
+                warn!(
+                    "Witnesses were only updated to {}, rescanning from there",
+                    checkpoint.witnesses_updated_to
+                );
+                let tree_state = block_source
+                    .get_trees(checkpoint.witnesses_updated_to)
+                    .await?;
+                self.wallet.rewind_to(tree_state).await?;
+                last_synced_height = self.wallet.last_synced_height().await;
+            }
+        }
 
         // If our internal state gets damaged somehow (for example,
         // a resync that gets interrupted partway through) we need to make sure
@@ -1511,17 +2520,11 @@ impl LightClient {
 
         // This is a fresh wallet. We need to get the initial trees
         if self.wallet_has_any_empty_commitment_trees().await && last_synced_height != 0 {
-            let trees = crate::grpc_connector::GrpcConnector::get_trees(
-                self.get_server_uri(),
-                last_synced_height,
-            )
-            .await
-            .unwrap();
+            let trees = block_source.get_trees(last_synced_height).await.unwrap();
             self.wallet.initiate_witness_trees(trees).await;
         };
 
-        let latest_blockid =
-            GrpcConnector::get_latest_block(self.config.get_lightwalletd_uri()).await?;
+        let latest_blockid = block_source.get_latest_block().await?;
         // Block hashes are reversed when stored in BlockDatas, so we reverse here to match
         let latest_blockid =
             crate::wallet::data::BlockData::new_with(latest_blockid.height, &latest_blockid.hash);
@@ -1564,20 +2567,111 @@ impl LightClient {
             latest_block_batches.push(batch);
         }
 
+        let mut checkpoint = resumed_checkpoint
+            .map(|mut checkpoint| {
+                checkpoint.truncate_to(last_scanned_height);
+                checkpoint
+            })
+            .filter(|checkpoint| checkpoint.scanned_to() == Some(last_scanned_height))
+            .unwrap_or_else(|| SyncCheckpoint::new(latest_blockid.height));
+        checkpoint.sync_target = cmp::max(checkpoint.sync_target, latest_blockid.height);
+        if !checkpoint.scanned_ranges.is_empty() {
+            log::info!(
+                "Resuming sync to {} after {:?}",
+                checkpoint.sync_target,
+                checkpoint.scanned_ranges
+            );
+        }
+
         // Increment the sync ID so the caller can determine when it is over
-        self.bsync_data
-            .write()
-            .await
-            .sync_status
-            .write()
-            .await
-            .start_new(latest_block_batches.len());
+        {
+            let bsync_data = self.bsync_data.write().await;
+            let mut sync_status = bsync_data.sync_status.write().await;
+            sync_status.start_new(latest_block_batches.len());
+            sync_status.resumed_ranges = checkpoint.scanned_ranges.clone();
+            // Transactions the interrupted run already downloaded and scanned aren't fetched
+            // again, as long as the saved wallet still has them
+            let transactions = self.wallet.transactions();
+            let transactions = transactions.read().await;
+            sync_status.fetched_txids = checkpoint
+                .fetched_txids
+                .iter()
+                .filter(|txid| transactions.current.contains_key(txid))
+                .copied()
+                .collect();
+        }
 
+        let batch_count = latest_block_batches.len();
         let mut res = Err("No batches were run!".to_string());
         for (batch_num, batch_latest_block) in latest_block_batches.into_iter().enumerate() {
-            res = self.sync_nth_batch(batch_latest_block, batch_num).await;
-            res.as_ref()?;
-            if *self.interrupt_sync.read().await {
+            let mut sync_result = match self
+                .sync_nth_batch(&block_source, batch_latest_block, batch_num)
+                .await
+            {
+                Ok(sync_result) => sync_result,
+                Err(e) => match self.fail_over_block_source(&block_source).await {
+                    Some(new_source) => {
+                        warn!(
+                            "Batch {} failed ({}), resuming it from another server",
+                            batch_num, e
+                        );
+                        block_source = new_source;
+                        // The failed batch may have left witnesses above the wallet's blocks
+                        self.ensure_witness_tree_not_above_wallet_blocks().await;
+                        self.sync_nth_batch(&block_source, batch_latest_block, batch_num)
+                            .await?
+                    }
+                    None => return Err(e),
+                },
+            };
+            // The block source was made for this sync, and a failed over one shares its
+            // counters, so it has counted this sync's downloads
+            sync_result.bytes_downloaded = block_source.bytes_downloaded();
+            self.bsync_data
+                .read()
+                .await
+                .sync_status
+                .write()
+                .await
+                .bytes_downloaded = sync_result.bytes_downloaded;
+
+            // The wallet has been saved with this batch in it, so checkpoint it too
+            if sync_result.total_blocks_synced > 0 {
+                let fetched_txids = self
+                    .bsync_data
+                    .read()
+                    .await
+                    .sync_status
+                    .read()
+                    .await
+                    .fetched_txids
+                    .clone();
+                checkpoint.record_batch(
+                    sync_result.latest_block - sync_result.total_blocks_synced + 1,
+                    sync_result.latest_block,
+                    &fetched_txids,
+                );
+                if let Err(e) = checkpoint.save(storage.as_ref(), &checkpoint_name) {
+                    warn!("Couldn't save sync checkpoint: {}", e);
+                }
+            }
+
+            let last_batch = batch_num + 1 == batch_count;
+            if last_batch {
+                SyncCheckpoint::remove(storage.as_ref(), &checkpoint_name);
+                // The wallet is caught up, so its transparent outputs should match the
+                // server's UTXO set. A local block file has none to compare with.
+                if self.config.block_source_file.is_none() {
+                    match self.reconcile_transparent().await {
+                        Ok(report) => sync_result.transparent_check = report,
+                        Err(e) => warn!("Couldn't check transparent balance: {}", e),
+                    }
+                    self.rebroadcast_pending().await;
+                }
+            }
+            res = Ok(sync_result);
+
+            if !last_batch && *self.interrupt_sync.read().await {
                 log::debug!("LightClient interrupt_sync is true");
                 break;
             }
@@ -1592,6 +2686,7 @@ impl LightClient {
     /// get the status of the sync
     async fn sync_nth_batch(
         &self,
+        block_source: &BlockSource,
         start_block: u64,
         batch_num: usize,
     ) -> Result<SyncResult, String> {
@@ -1609,6 +2704,8 @@ impl LightClient {
                 success: true,
                 latest_block: last_synced_height,
                 total_blocks_synced: 0,
+                bytes_downloaded: BytesDownloaded::default(),
+                transparent_check: ConsistencyReport::default(),
             });
         }
 
@@ -1616,27 +2713,31 @@ impl LightClient {
 
         let end_block = last_synced_height + 1;
 
+        // A local block file has no full transactions to offer, so don't ask for any
+        let mut wallet_options = *self.wallet.wallet_options.read().await;
+        if self.config.block_source_file.is_some() {
+            wallet_options.download_memos = MemoDownloadOption::NoMemos;
+        }
+
         // Before we start, we need to do a few things
         // 1. Pre-populate the last 100 blocks, in case of reorgs
         bsync_data
             .write()
             .await
             .setup_nth_batch(
+                block_source.clone(),
                 start_block,
                 end_block,
                 batch_num,
                 self.wallet.get_blocks().await,
                 self.wallet.verified_tree.read().await.clone(),
-                *self.wallet.wallet_options.read().await,
+                wallet_options,
             )
             .await;
 
         // 2. Update the current price:: Who's concern is price?
         //self.update_current_price().await;
 
-        // Sapling Tree GRPC Fetcher
-        let grpc_connector = GrpcConnector::new(self.config.get_lightwalletd_uri());
-
         // A signal to detect reorgs, and if so, ask the block_fetcher to fetch new blocks.
         let (reorg_transmitter, reorg_receiver) = unbounded_channel();
 
@@ -1654,13 +2755,12 @@ impl LightClient {
             .await;
 
         // Full Tx GRPC fetcher
-        let (full_transaction_fetcher_handle, full_transaction_fetcher_transmitter) =
-            grpc_connector
-                .start_full_transaction_fetcher(self.config.chain)
-                .await;
+        let (full_transaction_fetcher_handle, full_transaction_fetcher_transmitter) = block_source
+            .start_full_transaction_fetcher(self.config.chain)
+            .await;
         // Transparent Transactions Fetcher
         let (taddr_fetcher_handle, taddr_fetcher_transmitter) =
-            grpc_connector.start_taddr_transaction_fetcher().await;
+            block_source.start_taddr_transaction_fetcher().await;
 
         // Local state necessary for a transaction fetch
         let transaction_context = TransactionContext::new(
@@ -1706,8 +2806,8 @@ impl LightClient {
             .await;
 
         // Fetch Compact blocks and send them to nullifier cache, node-and-witness cache and the trial-decryption processor
-        let fetch_compact_blocks = Arc::new(FetchCompactBlocks::new(&self.config));
-        let fetch_compact_blocks_handle = tokio::spawn(async move {
+        let fetch_compact_blocks = Arc::new(FetchCompactBlocks::new(block_source.clone()));
+        let fetch_compact_blocks_handle = executor::spawn(async move {
             fetch_compact_blocks
                 .start(
                     [
@@ -1748,7 +2848,7 @@ impl LightClient {
         // Wait for everything to finish
 
         // Await all the futures
-        let r1 = tokio::spawn(async move {
+        let r1 = executor::spawn(async move {
             join_all(vec![
                 trial_decrypts_handle,
                 full_transaction_fetcher_handle,
@@ -1771,7 +2871,7 @@ impl LightClient {
         .try_for_each(|r| r.map_err(|e| format!("{}", e))?)?;
 
         let verify_handle =
-            tokio::spawn(async move { block_data.read().await.block_data.verify_trees().await });
+            executor::spawn(async move { block_data.read().await.block_data.verify_trees().await });
         let (verified, highest_tree) = verify_handle.await.map_err(|e| e.to_string())?;
         debug!("tree verification {}", verified);
         debug!("highest tree exists: {}", highest_tree.is_some());
@@ -1783,12 +2883,17 @@ impl LightClient {
 
         let blaze_sync_data = bsync_data.read().await;
         // Post sync, we have to do a bunch of stuff
-        // 1. Get the last 100 blocks and store it into the wallet, needed for future re-orgs
-        let blocks = blaze_sync_data
+        // 1. Get the last 100 blocks and store it into the wallet, needed for future re-orgs,
+        // and keep those below them as anchors for deeper ones
+        let mut blocks = blaze_sync_data
             .block_data
-            .drain_existingblocks_into_blocks_with_truncation(MAX_REORG)
+            .drain_existingblocks_into_blocks_with_truncation(
+                MAX_REORG + REORG_ANCHOR_SPACING as usize,
+            )
             .await;
+        let below_window = blocks.split_off(blocks.len().min(MAX_REORG));
         self.wallet.set_blocks(blocks).await;
+        self.wallet.record_reorg_anchors(below_window).await;
 
         // 2. If sync was successfull, also try to get historical prices
         // self.update_historical_prices().await;
@@ -1818,6 +2923,8 @@ impl LightClient {
             success: true,
             latest_block: start_block,
             total_blocks_synced: start_block - end_block + 1,
+            bytes_downloaded: BytesDownloaded::default(),
+            transparent_check: ConsistencyReport::default(),
         })
     }
 
@@ -1955,7 +3062,7 @@ impl LightClient {
 
     pub(crate) async fn update_current_price(&self) -> String {
         // Get the zec price from the server
-        match get_recent_median_price_from_gemini().await {
+        match get_recent_median_price_from_gemini(self.config.proxy.as_ref()).await {
             Ok(price) => {
                 self.wallet.set_latest_zec_price(price).await;
                 price.to_string()
@@ -2070,14 +3177,21 @@ fn repr_price_as_f64(from_gemini: &Value) -> Result<f64, PriceReprError> {
     }
 }
 
-async fn get_recent_median_price_from_gemini() -> Result<f64, PriceFetchError> {
-    let httpget =
-        match reqwest::get("https://api.gemini.com/v1/trades/zecusd?limit_trades=11").await {
-            Ok(httpresponse) => httpresponse,
-            Err(e) => {
-                return Err(PriceFetchError::ReqwestError(e.to_string()));
-            }
-        };
+async fn get_recent_median_price_from_gemini(
+    proxy: Option<&ProxyConfig>,
+) -> Result<f64, PriceFetchError> {
+    let client =
+        crate::grpc_connector::http_client(proxy).map_err(PriceFetchError::ReqwestError)?;
+    let httpget = match client
+        .get("https://api.gemini.com/v1/trades/zecusd?limit_trades=11")
+        .send()
+        .await
+    {
+        Ok(httpresponse) => httpresponse,
+        Err(e) => {
+            return Err(PriceFetchError::ReqwestError(e.to_string()));
+        }
+    };
     let serialized = match httpget.json::<Value>().await {
         Ok(asjson) => asjson,
         Err(_) => {
@@ -2114,10 +3228,18 @@ async fn get_recent_median_price_from_gemini() -> Result<f64, PriceFetchError> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
     use tokio::runtime::Runtime;
     use zingoconfig::{ChainType, ZingoConfig};
 
-    use crate::{lightclient::LightClient, wallet::WalletBase};
+    use zcash_primitives::block::BlockHash;
+
+    use crate::{
+        blaze::{block_source::write_length_prefixed, test_utils::FakeCompactBlock},
+        compact_formats::{CompactBlock, TreeState},
+        lightclient::LightClient,
+        wallet::WalletBase,
+    };
 
     #[test]
     fn new_wallet_from_phrase() {
@@ -2171,5 +3293,55 @@ mod tests {
             );
         });
     }
+
+    #[tokio::test]
+    async fn sync_from_block_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let block_file = temp_dir.path().join("blocks");
+
+        // Two batches' worth of blocks, full of other people's transactions
+        let mut blocks: Vec<CompactBlock> = vec![];
+        for height in 1..=150 {
+            let prev_hash = blocks.last().map_or(BlockHash([0; 32]), |block| {
+                BlockHash::from_slice(&block.hash)
+            });
+            let mut fake_block = FakeCompactBlock::new(height, prev_hash);
+            fake_block.add_random_sapling_transaction(2);
+            blocks.push(fake_block.into_cb());
+        }
+        write_length_prefixed(&block_file, &blocks).unwrap();
+        write_length_prefixed::<TreeState>(&block_file.with_extension("treestates"), &[]).unwrap();
+
+        // The server is never asked for anything, so it doesn't matter that there is none
+        let mut config = ZingoConfig::create_unconnected(
+            ChainType::Regtest,
+            Some(temp_dir.path().to_path_buf()),
+        );
+        config.block_source_file = Some(block_file);
+        let lc = LightClient::create_from_wallet_base_async(
+            WalletBase::MnemonicPhrase(TEST_SEED.to_string()),
+            &config,
+            0,
+            false,
+        )
+        .await
+        .unwrap();
+
+        let sync_result = lc.do_sync(false).await.unwrap();
+        assert_eq!(sync_result.latest_block, 150);
+        assert_eq!(sync_result.bytes_downloaded.total(), 0);
+        assert_eq!(lc.wallet.last_synced_height().await, 150);
+    }
+
+    #[test]
+    fn mempool_exclude_entries_are_txid_ends() {
+        let txid = (0..32).collect::<Vec<u8>>();
+        let exclude = super::mempool_exclude(&HashSet::from([txid, vec![7; 4]]));
+        assert_eq!(exclude.len(), 2);
+        assert!(exclude.contains(&(24..32).collect::<Vec<u8>>()));
+        // Anything already shorter than an entry is sent whole
+        assert!(exclude.contains(&vec![7; 4]));
+    }
+
     pub const TEST_SEED: &str = "chimney better bulb horror rebuild whisper improve intact letter giraffe brave rib appear bulk aim burst snap salt hill sad merge tennis phrase raise";
 }
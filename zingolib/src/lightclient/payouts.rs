@@ -0,0 +1,450 @@
+//! Batch payouts: many recipients, read from a CSV file, paid in as few transactions as the
+//! fee rules allow.
+//!
+//! Each transaction is planned against the notes the wallet will actually spend on it, picked
+//! the way the wallet picks them, so that its ZIP-317 action count and its funding are known
+//! before anything is sent.
+//!
+//! Each row is `address,amount,memo`, with the amount in zatoshis and the memo optional. A
+//! first row starting with `address` is taken as a header. All rows are checked before
+//! anything is sent, and every problem found is reported at once, so a payroll file can be
+//! fixed in one go.
+use zcash_client_backend::address::RecipientAddress;
+use zcash_primitives::memo::MemoBytes;
+use zcash_primitives::transaction::fees::zip317::MINIMUM_FEE;
+use zingoconfig::ChainType;
+
+use crate::wallet::utils::interpret_memo_string;
+use crate::wallet::Pool;
+
+/// The logical actions ZIP-317's minimum fee pays for, which is all the wallet pays for
+pub const GRACE_ACTIONS: usize = 2;
+
+/// zcashd turns away transactions with more ZIP-317 logical actions than their fee pays for,
+/// beyond this many
+pub const UNPAID_ACTION_LIMIT: usize = 50;
+
+/// One row of a payout file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Payout {
+    /// The row's line in the file, for reports
+    pub line: u64,
+    pub address: String,
+    pub amount: u64,
+    pub memo: Option<String>,
+}
+
+impl Payout {
+    pub(crate) fn memo_bytes(&self) -> Option<MemoBytes> {
+        // Checked when the file was read
+        self.memo
+            .clone()
+            .map(|memo| interpret_memo_string(memo).expect("checked memo"))
+    }
+}
+
+/// Reads and checks the rows of a payout file, for addresses on `chain`
+pub fn read_payouts(csv: &[u8], chain: &ChainType) -> Result<Vec<Payout>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(csv);
+
+    let mut payouts = vec![];
+    let mut problems = vec![];
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| format!("Couldn't read the payout file: {}", e))?;
+        let line = record.position().map_or(i as u64 + 1, |p| p.line());
+        if i == 0
+            && record
+                .get(0)
+                .map_or(false, |f| f.eq_ignore_ascii_case("address"))
+        {
+            continue;
+        }
+        match read_payout(&record, line, chain) {
+            Ok(payout) => payouts.push(payout),
+            Err(e) => problems.push(format!("Line {}: {}", line, e)),
+        }
+    }
+
+    if !problems.is_empty() {
+        return Err(problems.join("\n"));
+    }
+    if payouts.is_empty() {
+        return Err("The payout file has no payouts".to_string());
+    }
+    Ok(payouts)
+}
+
+fn read_payout(record: &csv::StringRecord, line: u64, chain: &ChainType) -> Result<Payout, String> {
+    if record.len() < 2 || record.len() > 3 {
+        return Err(format!(
+            "Expected address,amount,memo but found {} fields",
+            record.len()
+        ));
+    }
+    let address = record[0].to_string();
+    let shielded = match RecipientAddress::decode(chain, &address) {
+        Some(RecipientAddress::Transparent(_)) => false,
+        Some(_) => true,
+        None => return Err(format!("'{}' is not a {} address", address, chain)),
+    };
+    let amount = match record[1].parse::<u64>() {
+        Ok(0) => return Err("The amount must be more than zero".to_string()),
+        Ok(amount) => amount,
+        Err(_) => {
+            return Err(format!(
+                "'{}' is not a whole number of zatoshis",
+                &record[1]
+            ))
+        }
+    };
+    let memo = record
+        .get(2)
+        .filter(|memo| !memo.is_empty())
+        .map(String::from);
+    if let Some(memo) = &memo {
+        if !shielded {
+            return Err(format!(
+                "Can't send a memo to the non-shielded address {}",
+                address
+            ));
+        }
+        interpret_memo_string(memo.clone())?;
+    }
+    Ok(Payout {
+        line,
+        address,
+        amount,
+        memo,
+    })
+}
+
+/// The values of the notes the wallet can spend, each pool's largest first
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpendableNotes {
+    pub orchard: Vec<u64>,
+    pub sapling: Vec<u64>,
+}
+
+impl SpendableNotes {
+    pub fn total(&self) -> u64 {
+        self.orchard.iter().chain(&self.sapling).sum()
+    }
+
+    /// The notes the wallet picks to pay `target`: Orchard ones, largest first, until they
+    /// cover it, then Sapling ones. `None` if all of them together don't.
+    fn select(&self, target: u64) -> Option<SpendableNotes> {
+        let orchard = take_until(&self.orchard, target);
+        let orchard_total = orchard.iter().sum::<u64>();
+        if orchard_total >= target {
+            return Some(SpendableNotes {
+                orchard,
+                sapling: vec![],
+            });
+        }
+        let sapling = take_until(&self.sapling, target - orchard_total);
+        let selected = SpendableNotes { orchard, sapling };
+        (selected.total() >= target).then_some(selected)
+    }
+
+    /// Forgets notes a planned transaction spends. Selections are always from the front.
+    fn remove(&mut self, spent: &SpendableNotes) {
+        self.orchard.drain(..spent.orchard.len());
+        self.sapling.drain(..spent.sapling.len());
+    }
+}
+
+fn take_until(values: &[u64], target: u64) -> Vec<u64> {
+    let mut total = 0;
+    values
+        .iter()
+        .take_while(|value| {
+            let needed = total < target;
+            total += **value;
+            needed
+        })
+        .copied()
+        .collect()
+}
+
+/// One transaction of a payout plan
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayoutTransaction {
+    pub payouts: Vec<Payout>,
+    /// The notes the wallet will spend on it, or `None` if what is left can't pay for it
+    pub notes: Option<SpendableNotes>,
+    /// ZIP-317 logical actions, with the change output and the notes spent
+    pub logical_actions: usize,
+}
+
+impl PayoutTransaction {
+    fn plan(payouts: Vec<Payout>, notes: &SpendableNotes, chain: &ChainType) -> Self {
+        let amount = payouts.iter().map(|p| p.amount).sum::<u64>();
+        let notes = notes.select(amount + PayoutPlan::fee_per_transaction());
+
+        let (mut transparent_outputs, mut sapling_outputs, mut orchard_outputs) = (0, 0, 0);
+        for payout in &payouts {
+            match output_pool(&payout.address, chain) {
+                Pool::Transparent => transparent_outputs += 1,
+                Pool::Sapling => sapling_outputs += 1,
+                Pool::Orchard => orchard_outputs += 1,
+            }
+        }
+        // The change always goes to the wallet's Orchard receiver
+        orchard_outputs += 1;
+        // A shielded bundle that is there at all is padded to two actions
+        let bundle_actions = |spends: usize, outputs: usize| match spends.max(outputs) {
+            0 => 0,
+            actions => actions.max(2),
+        };
+        let (sapling_spends, orchard_spends) = notes
+            .as_ref()
+            .map_or((0, 0), |notes| (notes.sapling.len(), notes.orchard.len()));
+        // A P2PKH output is 34 bytes, which ZIP-317 counts as one action
+        let logical_actions = transparent_outputs
+            + bundle_actions(sapling_spends, sapling_outputs)
+            + bundle_actions(orchard_spends, orchard_outputs);
+
+        PayoutTransaction {
+            payouts,
+            notes,
+            logical_actions,
+        }
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.payouts.iter().map(|p| p.amount).sum()
+    }
+
+    pub fn is_funded(&self) -> bool {
+        self.notes.is_some()
+    }
+
+    pub fn unpaid_actions(&self) -> usize {
+        self.logical_actions.saturating_sub(GRACE_ACTIONS)
+    }
+
+    pub fn is_within_action_limit(&self) -> bool {
+        self.unpaid_actions() <= UNPAID_ACTION_LIMIT
+    }
+}
+
+/// The pool the wallet pays `address` in
+fn output_pool(address: &str, chain: &ChainType) -> Pool {
+    match RecipientAddress::decode(chain, address) {
+        Some(RecipientAddress::Shielded(_)) => Pool::Sapling,
+        Some(RecipientAddress::Unified(ua)) if ua.orchard().is_some() => Pool::Orchard,
+        Some(RecipientAddress::Unified(ua)) if ua.sapling().is_some() => Pool::Sapling,
+        _ => Pool::Transparent,
+    }
+}
+
+/// The transactions a payout is made in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayoutPlan {
+    pub transactions: Vec<PayoutTransaction>,
+    /// What the wallet could spend when the plan was made, in zatoshis
+    pub spendable: u64,
+}
+
+impl PayoutPlan {
+    /// Splits `payouts` into transactions, in the order of the file. Each takes as many rows
+    /// as it can while the notes left after the earlier transactions still pay for it and
+    /// its unpaid actions stay within the limit. The change of a transaction can't be spent
+    /// until it is mined, so it doesn't fund later ones. Rows that can't be funded are still
+    /// grouped by the action limit, so the plan shows how far the money goes.
+    pub fn new(payouts: Vec<Payout>, notes: SpendableNotes, chain: &ChainType) -> Self {
+        let spendable = notes.total();
+        let mut notes = notes;
+        let mut transactions = vec![];
+        let mut current: Option<PayoutTransaction> = None;
+        for payout in payouts {
+            if let Some(transaction) = current.take() {
+                let mut grown_payouts = transaction.payouts.clone();
+                grown_payouts.push(payout.clone());
+                let grown = PayoutTransaction::plan(grown_payouts, &notes, chain);
+                if grown.is_within_action_limit() && (grown.is_funded() || !transaction.is_funded())
+                {
+                    current = Some(grown);
+                    continue;
+                }
+                if let Some(spent) = &transaction.notes {
+                    notes.remove(spent);
+                }
+                transactions.push(transaction);
+            }
+            current = Some(PayoutTransaction::plan(vec![payout], &notes, chain));
+        }
+        transactions.extend(current);
+
+        PayoutPlan {
+            transactions,
+            spendable,
+        }
+    }
+
+    pub fn fee_per_transaction() -> u64 {
+        u64::from(MINIMUM_FEE)
+    }
+
+    pub fn total_amount(&self) -> u64 {
+        self.transactions
+            .iter()
+            .map(PayoutTransaction::amount)
+            .sum()
+    }
+
+    pub fn total_fees(&self) -> u64 {
+        self.transactions.len() as u64 * Self::fee_per_transaction()
+    }
+
+    pub fn is_funded(&self) -> bool {
+        self.transactions.iter().all(PayoutTransaction::is_funded)
+    }
+
+    /// Whether every transaction is funded and will be accepted by the network
+    pub fn is_sendable(&self) -> bool {
+        self.transactions
+            .iter()
+            .all(|t| t.is_funded() && t.is_within_action_limit())
+    }
+}
+
+/// What became of one row of a payout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayoutResult {
+    pub payout: Payout,
+    /// The transaction the row was paid in, or why it wasn't paid
+    pub txid: Result<String, String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAPLING: &str =
+        "ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d";
+    const TRANSPARENT: &str = "tmBsTi2xWTjUdEXnuTceL7fecEQKeWaPDJd";
+
+    #[test]
+    fn rows_are_read_and_checked() {
+        let csv = format!(
+            "address,amount,memo\n{},1000,\"Thanks, see you\"\n# a comment\n{},2000\n",
+            SAPLING, TRANSPARENT
+        );
+        let payouts = read_payouts(csv.as_bytes(), &ChainType::Testnet).unwrap();
+        assert_eq!(payouts.len(), 2);
+        assert_eq!(payouts[0].memo.as_deref(), Some("Thanks, see you"));
+        assert_eq!(payouts[1].line, 4);
+        assert_eq!(payouts[1].memo, None);
+
+        let csv = format!(
+            "{},0\nnot_an_address,10\n{},10,memo\n{},ten\n",
+            SAPLING, TRANSPARENT, SAPLING
+        );
+        let problems = read_payouts(csv.as_bytes(), &ChainType::Testnet).unwrap_err();
+        assert_eq!(problems.lines().count(), 4);
+        assert!(problems.starts_with("Line 1: "));
+        assert!(read_payouts(csv.as_bytes(), &ChainType::Mainnet)
+            .unwrap_err()
+            .contains(&format!("'{}' is not a main address", SAPLING)));
+    }
+
+    fn payouts(count: u64, address: &str, amount: u64) -> Vec<Payout> {
+        (1..=count)
+            .map(|line| Payout {
+                line,
+                address: address.to_string(),
+                amount,
+                memo: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn notes_are_picked_as_the_wallet_picks_them() {
+        let notes = SpendableNotes {
+            orchard: vec![5_000, 3_000],
+            sapling: vec![10_000, 1_000],
+        };
+        assert_eq!(
+            notes.select(4_000),
+            Some(SpendableNotes {
+                orchard: vec![5_000],
+                sapling: vec![],
+            })
+        );
+        assert_eq!(
+            notes.select(12_000),
+            Some(SpendableNotes {
+                orchard: vec![5_000, 3_000],
+                sapling: vec![10_000],
+            })
+        );
+        assert_eq!(notes.select(20_000), None);
+    }
+
+    #[test]
+    fn transactions_stay_within_the_unpaid_action_limit() {
+        // Plenty of money in one note: each transaction spends one note, and has room for
+        // all but the change among its paid and unpaid actions
+        let notes = SpendableNotes {
+            orchard: vec![10_000_000; 3],
+            sapling: vec![],
+        };
+        let plan = PayoutPlan::new(payouts(100, TRANSPARENT, 1_000), notes, &ChainType::Testnet);
+        let per_transaction = UNPAID_ACTION_LIMIT + GRACE_ACTIONS - 2;
+        assert_eq!(plan.transactions[0].payouts.len(), per_transaction);
+        assert_eq!(
+            plan.transactions[0].logical_actions,
+            UNPAID_ACTION_LIMIT + GRACE_ACTIONS
+        );
+        assert!(plan
+            .transactions
+            .iter()
+            .all(PayoutTransaction::is_within_action_limit));
+        assert_eq!(
+            plan.transactions
+                .iter()
+                .map(|t| t.payouts.len())
+                .sum::<usize>(),
+            100
+        );
+        assert!(plan.is_sendable());
+
+        // Many small notes leave less room for recipients: n payouts of 1000 and the fee
+        // spend 2n + 20 notes of 500, so only 10 payouts fit in 50 unpaid actions
+        let notes = SpendableNotes {
+            orchard: vec![500; 200],
+            sapling: vec![],
+        };
+        let plan = PayoutPlan::new(payouts(20, TRANSPARENT, 1_000), notes, &ChainType::Testnet);
+        assert_eq!(plan.transactions.len(), 2);
+        for transaction in &plan.transactions {
+            assert_eq!(transaction.payouts.len(), 10);
+            assert_eq!(transaction.notes.as_ref().unwrap().orchard.len(), 40);
+            assert_eq!(transaction.unpaid_actions(), 48);
+        }
+    }
+
+    #[test]
+    fn funding_is_reported_per_transaction() {
+        let fee = PayoutPlan::fee_per_transaction();
+        // Enough for the first transaction, but its change can't pay for the second
+        let notes = SpendableNotes {
+            orchard: vec![60_000 + fee],
+            sapling: vec![],
+        };
+        let plan = PayoutPlan::new(payouts(60, TRANSPARENT, 1_000), notes, &ChainType::Testnet);
+        assert_eq!(plan.spendable, 60_000 + fee);
+        assert_eq!(plan.transactions.len(), 2);
+        assert!(plan.transactions[0].is_funded());
+        assert!(!plan.transactions[1].is_funded());
+        assert!(!plan.is_funded());
+        assert!(!plan.is_sendable());
+    }
+}
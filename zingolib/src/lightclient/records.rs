@@ -0,0 +1,394 @@
+//! Typed records of what a [`LightClient`](super::LightClient) reports about its wallet: its
+//! addresses, notes and transactions, the server it's connected to, and its seed.
+//!
+//! The `do_*` methods that predate these answer with the records' serde serialization as a
+//! `JsonValue`, through the `From` conversions here, in the shape Zingo Mobile and the CLI
+//! commands expect. Rust callers can use the records directly, or serialize them with serde.
+use json::JsonValue;
+use serde::{Deserialize, Serialize};
+
+/// `record` as the `JsonValue` of its serde serialization, with its fields in the order they
+/// are declared in
+pub(crate) fn to_json<T: Serialize>(record: &T) -> JsonValue {
+    let serialized = serde_json::to_string(record).expect("Records serialize to JSON");
+    json::parse(&serialized).expect("serde_json writes valid JSON")
+}
+
+/// A unified address of the wallet, and the receivers it contains
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressRecord {
+    pub address: String,
+    pub receivers: ReceiverRecord,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiverRecord {
+    pub transparent: Option<String>,
+    pub sapling: Option<String>,
+    pub orchard_exists: bool,
+}
+
+impl From<AddressRecord> for JsonValue {
+    fn from(address: AddressRecord) -> Self {
+        to_json(&address)
+    }
+}
+
+/// A sapling or orchard note received by the wallet
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShieldedNoteRecord {
+    pub created_in_block: u32,
+    pub datetime: u64,
+    pub created_in_txid: String,
+    pub value: u64,
+    pub unconfirmed: bool,
+    pub is_change: bool,
+    pub address: String,
+    pub spendable: bool,
+    pub spent: Option<String>,
+    pub spent_at_height: Option<u32>,
+    pub unconfirmed_spent: Option<String>,
+}
+
+impl ShieldedNoteRecord {
+    fn is_pending(&self) -> bool {
+        self.spent.is_none() && self.unconfirmed_spent.is_some()
+    }
+}
+
+impl From<ShieldedNoteRecord> for JsonValue {
+    fn from(note: ShieldedNoteRecord) -> Self {
+        to_json(&note)
+    }
+}
+
+/// A transparent output received by the wallet
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoRecord {
+    pub created_in_block: u32,
+    pub datetime: u64,
+    pub created_in_txid: String,
+    pub value: u64,
+    pub scriptkey: String,
+    pub is_change: bool,
+    /// The unified address whose transparent receiver this was sent to
+    pub address: Option<String>,
+    pub spent_at_height: Option<i32>,
+    pub spent: Option<String>,
+    pub unconfirmed_spent: Option<String>,
+}
+
+impl UtxoRecord {
+    fn is_pending(&self) -> bool {
+        self.spent.is_none() && self.unconfirmed_spent.is_some()
+    }
+}
+
+impl From<UtxoRecord> for JsonValue {
+    fn from(utxo: UtxoRecord) -> Self {
+        to_json(&utxo)
+    }
+}
+
+/// The wallet's notes and transparent outputs, grouped by whether they are unspent, spent by
+/// a pending transaction, or spent. Each group is sorted by the height it was created at.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteList {
+    pub unspent_sapling_notes: Vec<ShieldedNoteRecord>,
+    pub pending_sapling_notes: Vec<ShieldedNoteRecord>,
+    pub unspent_orchard_notes: Vec<ShieldedNoteRecord>,
+    pub pending_orchard_notes: Vec<ShieldedNoteRecord>,
+    pub utxos: Vec<UtxoRecord>,
+    pub pending_utxos: Vec<UtxoRecord>,
+    /// Only listed when spent notes were asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spent_sapling_notes: Option<Vec<ShieldedNoteRecord>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spent_orchard_notes: Option<Vec<ShieldedNoteRecord>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spent_utxos: Option<Vec<UtxoRecord>>,
+}
+
+impl NoteList {
+    /// An empty list, with room for spent notes if `all_notes` is set
+    pub(super) fn new(all_notes: bool) -> Self {
+        NoteList {
+            spent_sapling_notes: all_notes.then(Vec::new),
+            spent_orchard_notes: all_notes.then(Vec::new),
+            spent_utxos: all_notes.then(Vec::new),
+            ..Default::default()
+        }
+    }
+
+    pub(super) fn add_sapling_note(&mut self, note: ShieldedNoteRecord) {
+        if note.spent.is_some() {
+            if let Some(spent) = self.spent_sapling_notes.as_mut() {
+                spent.push(note);
+            }
+        } else if note.is_pending() {
+            self.pending_sapling_notes.push(note);
+        } else {
+            self.unspent_sapling_notes.push(note);
+        }
+    }
+
+    pub(super) fn add_orchard_note(&mut self, note: ShieldedNoteRecord) {
+        if note.spent.is_some() {
+            if let Some(spent) = self.spent_orchard_notes.as_mut() {
+                spent.push(note);
+            }
+        } else if note.is_pending() {
+            self.pending_orchard_notes.push(note);
+        } else {
+            self.unspent_orchard_notes.push(note);
+        }
+    }
+
+    pub(super) fn add_utxo(&mut self, utxo: UtxoRecord) {
+        if utxo.spent.is_some() {
+            if let Some(spent) = self.spent_utxos.as_mut() {
+                spent.push(utxo);
+            }
+        } else if utxo.is_pending() {
+            self.pending_utxos.push(utxo);
+        } else {
+            self.utxos.push(utxo);
+        }
+    }
+
+    pub(super) fn sort(&mut self) {
+        for notes in [
+            &mut self.unspent_sapling_notes,
+            &mut self.pending_sapling_notes,
+            &mut self.unspent_orchard_notes,
+            &mut self.pending_orchard_notes,
+        ]
+        .into_iter()
+        .chain(self.spent_sapling_notes.as_mut())
+        .chain(self.spent_orchard_notes.as_mut())
+        {
+            notes.sort_by_key(|note| note.created_in_block);
+        }
+        for utxos in [&mut self.utxos, &mut self.pending_utxos]
+            .into_iter()
+            .chain(self.spent_utxos.as_mut())
+        {
+            utxos.sort_by_key(|utxo| utxo.created_in_block);
+        }
+    }
+}
+
+impl From<NoteList> for JsonValue {
+    fn from(notes: NoteList) -> Self {
+        to_json(&notes)
+    }
+}
+
+/// A payment made by a transaction the wallet sent
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutgoingRecord {
+    /// The unified address paid, if the wallet knows it, otherwise the receiver paid
+    pub address: String,
+    pub value: u64,
+    pub memo: Option<String>,
+}
+
+impl From<OutgoingRecord> for JsonValue {
+    fn from(outgoing: OutgoingRecord) -> Self {
+        to_json(&outgoing)
+    }
+}
+
+/// An entry in the wallet's transaction list: value received by a note or by transparent
+/// outputs, or the net value a transaction sent. Entries for the same transaction are merged.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub block_height: u32,
+    pub unconfirmed: bool,
+    pub datetime: u64,
+    pub txid: String,
+    /// The change in the wallet's balance, in zatoshis
+    pub amount: i64,
+    pub zec_price: Option<f64>,
+    /// The note's index among the received notes of its kind, for received notes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    /// Where the value was received, for received value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    pub memo: Option<String>,
+    /// What was paid, for transactions the wallet sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outgoing_metadata: Option<Vec<OutgoingRecord>>,
+}
+
+impl TransactionRecord {
+    /// Folds `other`, an entry for the same transaction, into this one. Amounts add up and
+    /// memos are listed together; otherwise the fields of this entry win over those of `other`.
+    pub(super) fn merge(&mut self, other: TransactionRecord) {
+        let memo_str = |memo: Option<String>| memo.unwrap_or_else(|| "null".to_string());
+        if self.block_height != other.block_height
+            || self.datetime != other.datetime
+            || self.unconfirmed != other.unconfirmed
+        {
+            log::error!(
+                "Entries of transaction {} disagree: {:?} and {:?}",
+                self.txid,
+                self,
+                other
+            );
+        }
+        self.amount += other.amount;
+        self.memo = Some(format!(
+            "{}, {}",
+            memo_str(other.memo),
+            memo_str(self.memo.take())
+        ));
+        self.zec_price = self.zec_price.or(other.zec_price);
+        self.position = self.position.or(other.position);
+        self.address = self.address.take().or(other.address);
+        self.outgoing_metadata = self.outgoing_metadata.take().or(other.outgoing_metadata);
+    }
+}
+
+impl From<TransactionRecord> for JsonValue {
+    fn from(transaction: TransactionRecord) -> Self {
+        // Entries for received value carry a memo, even a missing one. The entry for what a
+        // transaction sent only has one once merged with others.
+        let has_memo = transaction.address.is_some() || transaction.memo.is_some();
+        let mut o = to_json(&transaction);
+        if !has_memo {
+            o.remove("memo");
+        }
+        o
+    }
+}
+
+/// What the lightwalletd server says about itself
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub version: String,
+    pub git_commit: String,
+    pub server_uri: String,
+    pub vendor: String,
+    pub taddr_support: bool,
+    pub chain_name: String,
+    pub sapling_activation_height: u64,
+    pub consensus_branch_id: String,
+    pub latest_block_height: u64,
+}
+
+impl From<ServerInfo> for JsonValue {
+    fn from(info: ServerInfo) -> Self {
+        to_json(&info)
+    }
+}
+
+/// How a lightwalletd server answered a health check, next to the others
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerStatusRecord {
+    pub uri: String,
+    /// Whether it is the server the wallet is using
+    pub current: bool,
+    /// "ok", "lagging", "ahead" or "unreachable"
+    pub status: String,
+    pub latest_block_height: Option<u64>,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// The seed phrase of a wallet, and the height to restore it from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeedPhrase {
+    pub seed: String,
+    pub birthday: u64,
+}
+
+impl From<SeedPhrase> for JsonValue {
+    fn from(seed: SeedPhrase) -> Self {
+        to_json(&seed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn received(txid: &str, amount: i64, memo: Option<&str>) -> TransactionRecord {
+        TransactionRecord {
+            block_height: 5,
+            unconfirmed: false,
+            datetime: 1_694_820_763,
+            txid: txid.to_string(),
+            amount,
+            zec_price: None,
+            position: Some(0),
+            address: Some("uregtest1".to_string()),
+            memo: memo.map(|memo| memo.to_string()),
+            outgoing_metadata: None,
+        }
+    }
+
+    #[test]
+    fn transaction_json_keeps_its_shape() {
+        let json = JsonValue::from(received("ab", 100, None));
+        assert!(json.has_key("memo"));
+        assert!(json["memo"].is_null());
+        assert_eq!(json["position"], 0);
+
+        let sent = TransactionRecord {
+            position: None,
+            address: None,
+            amount: -30_000,
+            outgoing_metadata: Some(vec![OutgoingRecord {
+                address: "tmBsTi2xWTjUdEXnuTceL7fecEQKeWaPDJd".to_string(),
+                value: 20_000,
+                memo: None,
+            }]),
+            ..received("cd", 0, None)
+        };
+        let json = JsonValue::from(sent);
+        assert!(!json.has_key("memo"));
+        assert!(!json.has_key("address"));
+        assert!(!json.has_key("position"));
+        assert_eq!(json["outgoing_metadata"][0]["value"], 20_000);
+    }
+
+    #[test]
+    fn json_is_the_serde_serialization() {
+        let utxo = UtxoRecord {
+            created_in_block: 5,
+            datetime: 1_694_820_763,
+            created_in_txid: "ab".to_string(),
+            value: 100_000,
+            scriptkey: "76a914".to_string(),
+            is_change: false,
+            address: None,
+            spent_at_height: None,
+            spent: None,
+            unconfirmed_spent: Some("cd".to_string()),
+        };
+        let mut notes = NoteList::new(false);
+        notes.add_utxo(utxo.clone());
+        assert_eq!(
+            JsonValue::from(utxo.clone()).dump(),
+            serde_json::to_string(&utxo).unwrap()
+        );
+        assert_eq!(
+            JsonValue::from(notes.clone()).dump(),
+            serde_json::to_string(&notes).unwrap()
+        );
+        assert!(!JsonValue::from(notes).has_key("spent_utxos"));
+    }
+
+    #[test]
+    fn merged_transactions_add_up() {
+        let mut first = received("ab", 100, Some("hello"));
+        let mut second = received("ab", 50, None);
+        second.position = Some(1);
+        first.merge(second);
+        assert_eq!(first.amount, 150);
+        assert_eq!(first.memo.as_deref(), Some("null, hello"));
+        assert_eq!(first.position, Some(0));
+    }
+}
@@ -0,0 +1,550 @@
+//! Payments made on a schedule: once or repeatedly, at a block height or a point in time.
+//!
+//! The schedule is kept next to the wallet, together with a journal of every run. A run is
+//! written to the journal as pending before its transaction is built, and as submitted, with
+//! the transaction's id, before it is broadcast. A run still pending when the schedule is next
+//! loaded was cut short before anything went out, so it is tried again. A submitted run may or
+//! may not have reached the network, even if broadcasting it failed, so it is only settled
+//! against the chain: paid once its transaction is found, failed once it has expired without
+//! being found. A missed payment can be made by hand, a doubled one can't be taken back.
+//!
+//! A failed run is tried again after a backoff that doubles with every failure, and given up
+//! after [`MAX_RUN_ATTEMPTS`].
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use zcash_encoding::{Optional, Vector};
+use zingoconfig::storage::WalletStorage;
+
+use crate::wallet::utils::{read_string, write_string};
+
+/// How often a run is tried before it is given up
+pub const MAX_RUN_ATTEMPTS: u32 = 5;
+/// How long to wait before trying a run again after its first failure, in seconds. Doubled for
+/// every failure after that.
+pub const RETRY_BACKOFF_SECONDS: u64 = 60;
+/// The longest wait before trying a run again, in seconds
+pub const MAX_RETRY_BACKOFF_SECONDS: u64 = 60 * 60;
+
+/// What a schedule's `start` and `every` count
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduleClock {
+    /// Block heights, as far as the wallet has synced
+    Height,
+    /// Seconds since the unix epoch
+    Time,
+}
+
+impl ScheduleClock {
+    fn tag(&self) -> u8 {
+        match self {
+            ScheduleClock::Height => 0,
+            ScheduleClock::Time => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> io::Result<Self> {
+        match tag {
+            0 => Ok(ScheduleClock::Height),
+            1 => Ok(ScheduleClock::Time),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown schedule clock {}", tag),
+            )),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ScheduleClock::Height => "height",
+            ScheduleClock::Time => "time",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduledPayment {
+    pub id: u64,
+    pub address: String,
+    pub amount: u64,
+    pub memo: Option<String>,
+    pub clock: ScheduleClock,
+    /// When the first run is due
+    pub start: u64,
+    /// How long after each run the next one is due. Without it, the payment is made once.
+    pub every: Option<u64>,
+    /// How many runs there are, if not every one until cancelled
+    pub runs: Option<u64>,
+    /// The run to be paid next, counting from 0
+    pub next_run: u64,
+    pub cancelled: bool,
+    /// How often the next run has failed
+    pub failures: u32,
+    /// When the next run may be tried again after failing, in seconds since the unix epoch
+    pub retry_at: u64,
+}
+
+impl ScheduledPayment {
+    /// When run `run` is due, or `None` if there is no such run
+    pub fn due_at(&self, run: u64) -> Option<u64> {
+        let runs = match self.every {
+            Some(_) => self.runs,
+            None => Some(1),
+        };
+        if runs.map_or(false, |runs| run >= runs) {
+            return None;
+        }
+        self.start
+            .checked_add(run.checked_mul(self.every.unwrap_or(0))?)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cancelled || self.due_at(self.next_run).is_none()
+    }
+
+    /// Whether the next run is due at `height` and `now`
+    pub fn is_due(&self, height: u64, now: u64) -> bool {
+        if self.cancelled || now < self.retry_at {
+            return false;
+        }
+        let clock = match self.clock {
+            ScheduleClock::Height => height,
+            ScheduleClock::Time => now,
+        };
+        self.due_at(self.next_run)
+            .map_or(false, |due_at| due_at <= clock)
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let id = reader.read_u64::<LittleEndian>()?;
+        let address = read_string(&mut reader)?;
+        let amount = reader.read_u64::<LittleEndian>()?;
+        let memo = Optional::read(&mut reader, |r| read_string(r))?;
+        let clock = ScheduleClock::from_tag(reader.read_u8()?)?;
+        let start = reader.read_u64::<LittleEndian>()?;
+        let every = Optional::read(&mut reader, |r| r.read_u64::<LittleEndian>())?;
+        let runs = Optional::read(&mut reader, |r| r.read_u64::<LittleEndian>())?;
+        let next_run = reader.read_u64::<LittleEndian>()?;
+        let cancelled = reader.read_u8()? > 0;
+        let failures = reader.read_u32::<LittleEndian>()?;
+        let retry_at = reader.read_u64::<LittleEndian>()?;
+        Ok(Self {
+            id,
+            address,
+            amount,
+            memo,
+            clock,
+            start,
+            every,
+            runs,
+            next_run,
+            cancelled,
+            failures,
+            retry_at,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(self.id)?;
+        write_string(&mut writer, &self.address)?;
+        writer.write_u64::<LittleEndian>(self.amount)?;
+        Optional::write(&mut writer, self.memo.as_ref(), |w, memo| {
+            write_string(w, memo)
+        })?;
+        writer.write_u8(self.clock.tag())?;
+        writer.write_u64::<LittleEndian>(self.start)?;
+        Optional::write(&mut writer, self.every, |w, every| {
+            w.write_u64::<LittleEndian>(every)
+        })?;
+        Optional::write(&mut writer, self.runs, |w, runs| {
+            w.write_u64::<LittleEndian>(runs)
+        })?;
+        writer.write_u64::<LittleEndian>(self.next_run)?;
+        writer.write_u8(self.cancelled as u8)?;
+        writer.write_u32::<LittleEndian>(self.failures)?;
+        writer.write_u64::<LittleEndian>(self.retry_at)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// Being built, or cut short before its transaction was broadcast
+    Pending,
+    Paid {
+        txid: String,
+    },
+    /// Not paid. The run is tried again after a backoff.
+    Failed {
+        error: String,
+    },
+    /// Built and broadcast, or being broadcast. Whether it reached the network is known once
+    /// its transaction is found on chain, or has expired without being found.
+    Submitted {
+        txid: String,
+        expiry_height: u64,
+    },
+    /// Failed [`MAX_RUN_ATTEMPTS`] times, so skipped. Not tried again.
+    GaveUp {
+        error: String,
+    },
+}
+
+/// A run of a scheduled payment, as recorded in the journal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub payment_id: u64,
+    pub run: u64,
+    /// When the run was made, in seconds since the unix epoch
+    pub executed_at: u64,
+    pub outcome: RunOutcome,
+}
+
+impl JournalEntry {
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let payment_id = reader.read_u64::<LittleEndian>()?;
+        let run = reader.read_u64::<LittleEndian>()?;
+        let executed_at = reader.read_u64::<LittleEndian>()?;
+        let outcome = match reader.read_u8()? {
+            0 => RunOutcome::Pending,
+            1 => RunOutcome::Paid {
+                txid: read_string(&mut reader)?,
+            },
+            2 => RunOutcome::Failed {
+                error: read_string(&mut reader)?,
+            },
+            3 => RunOutcome::Submitted {
+                txid: read_string(&mut reader)?,
+                expiry_height: reader.read_u64::<LittleEndian>()?,
+            },
+            4 => RunOutcome::GaveUp {
+                error: read_string(&mut reader)?,
+            },
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown run outcome {}", tag),
+                ))
+            }
+        };
+        Ok(Self {
+            payment_id,
+            run,
+            executed_at,
+            outcome,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(self.payment_id)?;
+        writer.write_u64::<LittleEndian>(self.run)?;
+        writer.write_u64::<LittleEndian>(self.executed_at)?;
+        match &self.outcome {
+            RunOutcome::Pending => writer.write_u8(0),
+            RunOutcome::Paid { txid } => {
+                writer.write_u8(1)?;
+                write_string(&mut writer, txid)
+            }
+            RunOutcome::Failed { error } => {
+                writer.write_u8(2)?;
+                write_string(&mut writer, error)
+            }
+            RunOutcome::Submitted {
+                txid,
+                expiry_height,
+            } => {
+                writer.write_u8(3)?;
+                write_string(&mut writer, txid)?;
+                writer.write_u64::<LittleEndian>(*expiry_height)
+            }
+            RunOutcome::GaveUp { error } => {
+                writer.write_u8(4)?;
+                write_string(&mut writer, error)
+            }
+        }
+    }
+}
+
+/// The scheduled payments of a wallet, and the journal of their runs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaymentSchedule {
+    next_id: u64,
+    pub payments: Vec<ScheduledPayment>,
+    pub journal: Vec<JournalEntry>,
+}
+
+impl PaymentSchedule {
+    pub const fn serialized_version() -> u64 {
+        1
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let version = reader.read_u64::<LittleEndian>()?;
+        if version > Self::serialized_version() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Don't know how to read payment schedule version {}",
+                    version
+                ),
+            ));
+        }
+
+        let next_id = reader.read_u64::<LittleEndian>()?;
+        let payments = Vector::read(&mut reader, |r| ScheduledPayment::read(r))?;
+        let journal = Vector::read(&mut reader, |r| JournalEntry::read(r))?;
+        Ok(Self {
+            next_id,
+            payments,
+            journal,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u64::<LittleEndian>(Self::serialized_version())?;
+
+        writer.write_u64::<LittleEndian>(self.next_id)?;
+        Vector::write(&mut writer, &self.payments, |w, payment| payment.write(w))?;
+        Vector::write(&mut writer, &self.journal, |w, entry| entry.write(w))
+    }
+
+    /// Reads the schedule stored as `name`, or an empty one if there is none. Unlike a sync
+    /// checkpoint, an unreadable schedule is an error: starting afresh would lose the journal.
+    pub fn load(storage: &dyn WalletStorage, name: &str) -> io::Result<Self> {
+        match storage.read(name)? {
+            Some(bytes) => Self::read(&bytes[..]),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, storage: &dyn WalletStorage, name: &str) -> io::Result<()> {
+        let mut bytes = vec![];
+        self.write(&mut bytes)?;
+        storage.write(name, &bytes)
+    }
+
+    /// Adds a payment, giving it the next id, which is returned
+    pub fn add(&mut self, mut payment: ScheduledPayment) -> u64 {
+        self.next_id += 1;
+        payment.id = self.next_id;
+        payment.next_run = 0;
+        payment.cancelled = false;
+        self.payments.push(payment);
+        self.next_id
+    }
+
+    pub fn cancel(&mut self, id: u64) -> Result<(), String> {
+        match self.payments.iter_mut().find(|payment| payment.id == id) {
+            Some(payment) if payment.is_finished() => {
+                Err(format!("Scheduled payment {} has no runs left", id))
+            }
+            Some(payment) => {
+                payment.cancelled = true;
+                Ok(())
+            }
+            None => Err(format!("There is no scheduled payment {}", id)),
+        }
+    }
+
+    /// Marks the runs an earlier process left pending as failed at `now`. Nothing of them was
+    /// broadcast, so they are tried again like any other failure. Returns how many there were.
+    pub fn recover_interrupted(&mut self, now: u64) -> usize {
+        let interrupted = self
+            .journal
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.outcome == RunOutcome::Pending)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        for &i in &interrupted {
+            self.settle(
+                i,
+                Err("Cut short before its transaction was broadcast".to_string()),
+                now,
+            );
+        }
+        interrupted.len()
+    }
+
+    /// The journal entries of runs whose transaction went out, or may have, and isn't settled
+    pub fn submitted(&self) -> Vec<usize> {
+        self.journal
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| matches!(entry.outcome, RunOutcome::Submitted { .. }))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Settles the run of journal entry `i` as paid in a transaction or failed at `now`. A
+    /// failed run is tried again after a backoff, or given up after [`MAX_RUN_ATTEMPTS`].
+    pub fn settle(&mut self, i: usize, paid: Result<String, String>, now: u64) {
+        let entry = &mut self.journal[i];
+        let payment = match self
+            .payments
+            .iter_mut()
+            .find(|payment| payment.id == entry.payment_id)
+        {
+            Some(payment) => payment,
+            None => return,
+        };
+        entry.outcome = match paid {
+            Ok(txid) => {
+                payment.next_run = payment.next_run.max(entry.run + 1);
+                payment.failures = 0;
+                payment.retry_at = 0;
+                RunOutcome::Paid { txid }
+            }
+            Err(error) if payment.failures + 1 >= MAX_RUN_ATTEMPTS => {
+                payment.next_run = payment.next_run.max(entry.run + 1);
+                payment.failures = 0;
+                payment.retry_at = 0;
+                RunOutcome::GaveUp { error }
+            }
+            Err(error) => {
+                payment.failures += 1;
+                let backoff = RETRY_BACKOFF_SECONDS
+                    .saturating_mul(1 << (payment.failures - 1).min(32))
+                    .min(MAX_RETRY_BACKOFF_SECONDS);
+                payment.retry_at = now.saturating_add(backoff);
+                RunOutcome::Failed { error }
+            }
+        };
+    }
+
+    /// The payments whose next run is due at `height` and `now`. A payment with a submitted run
+    /// waits until that run is settled.
+    pub fn due(&self, height: u64, now: u64) -> Vec<u64> {
+        let unsettled = self
+            .submitted()
+            .into_iter()
+            .map(|i| self.journal[i].payment_id)
+            .collect::<Vec<_>>();
+        self.payments
+            .iter()
+            .filter(|payment| payment.is_due(height, now) && !unsettled.contains(&payment.id))
+            .map(|payment| payment.id)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zingoconfig::storage::MemoryStorage;
+
+    fn monthly() -> ScheduledPayment {
+        ScheduledPayment {
+            id: 0,
+            address: "tmBsTi2xWTjUdEXnuTceL7fecEQKeWaPDJd".to_string(),
+            amount: 10_000,
+            memo: None,
+            clock: ScheduleClock::Height,
+            start: 100,
+            every: Some(30),
+            runs: Some(2),
+            next_run: 0,
+            cancelled: false,
+            failures: 0,
+            retry_at: 0,
+        }
+    }
+
+    #[test]
+    fn runs_fall_due_and_run_out() {
+        let mut schedule = PaymentSchedule::default();
+        let id = schedule.add(monthly());
+        assert_eq!(schedule.due(99, 0), Vec::<u64>::new());
+        assert_eq!(schedule.due(100, 0), vec![id]);
+
+        schedule.payments[0].next_run = 1;
+        assert_eq!(schedule.due(129, 0), Vec::<u64>::new());
+        assert_eq!(schedule.due(130, 0), vec![id]);
+
+        schedule.payments[0].next_run = 2;
+        assert!(schedule.payments[0].is_finished());
+        assert_eq!(schedule.due(1_000, 0), Vec::<u64>::new());
+        assert!(schedule.cancel(id).is_err());
+    }
+
+    fn journal_run(schedule: &mut PaymentSchedule, id: u64, outcome: RunOutcome) -> usize {
+        schedule.journal.push(JournalEntry {
+            payment_id: id,
+            run: schedule.payments[0].next_run,
+            executed_at: 1_700_000_000,
+            outcome,
+        });
+        schedule.journal.len() - 1
+    }
+
+    #[test]
+    fn pending_runs_are_tried_again() {
+        let mut schedule = PaymentSchedule::default();
+        let id = schedule.add(monthly());
+        journal_run(&mut schedule, id, RunOutcome::Pending);
+
+        let storage = MemoryStorage::default();
+        schedule.save(&storage, "wallet.schedule").unwrap();
+        let mut reloaded = PaymentSchedule::load(&storage, "wallet.schedule").unwrap();
+        assert_eq!(reloaded, schedule);
+
+        assert_eq!(reloaded.recover_interrupted(1_000), 1);
+        assert!(matches!(
+            reloaded.journal[0].outcome,
+            RunOutcome::Failed { .. }
+        ));
+        assert_eq!(reloaded.payments[0].next_run, 0);
+        assert_eq!(reloaded.due(100, 1_000), Vec::<u64>::new());
+        assert_eq!(reloaded.due(100, 1_000 + RETRY_BACKOFF_SECONDS), vec![id]);
+
+        assert_eq!(
+            PaymentSchedule::load(&storage, "missing").unwrap(),
+            PaymentSchedule::default()
+        );
+    }
+
+    #[test]
+    fn failed_runs_back_off_and_are_given_up() {
+        let mut schedule = PaymentSchedule::default();
+        let id = schedule.add(monthly());
+        let mut now = 1_000;
+        for failure in 1..MAX_RUN_ATTEMPTS {
+            let i = journal_run(&mut schedule, id, RunOutcome::Pending);
+            schedule.settle(i, Err("no funds".to_string()), now);
+            let backoff = (RETRY_BACKOFF_SECONDS << (failure - 1)).min(MAX_RETRY_BACKOFF_SECONDS);
+            assert_eq!(schedule.payments[0].retry_at, now + backoff);
+            assert_eq!(schedule.due(100, now + backoff - 1), Vec::<u64>::new());
+            now += backoff;
+            assert_eq!(schedule.due(100, now), vec![id]);
+        }
+
+        let i = journal_run(&mut schedule, id, RunOutcome::Pending);
+        schedule.settle(i, Err("no funds".to_string()), now);
+        assert!(matches!(
+            schedule.journal[i].outcome,
+            RunOutcome::GaveUp { .. }
+        ));
+        assert_eq!(schedule.payments[0].next_run, 1);
+        assert_eq!(schedule.payments[0].failures, 0);
+        assert_eq!(schedule.due(130, now), vec![id]);
+    }
+
+    #[test]
+    fn submitted_runs_wait_to_be_settled() {
+        let mut schedule = PaymentSchedule::default();
+        let id = schedule.add(monthly());
+        let submitted = RunOutcome::Submitted {
+            txid: "ab".repeat(32),
+            expiry_height: 140,
+        };
+        let i = journal_run(&mut schedule, id, submitted.clone());
+        assert_eq!(schedule.recover_interrupted(0), 0);
+        assert_eq!(schedule.journal[i].outcome, submitted);
+        assert_eq!(schedule.submitted(), vec![i]);
+        assert_eq!(schedule.due(100, 0), Vec::<u64>::new());
+
+        schedule.settle(i, Ok("ab".repeat(32)), 0);
+        assert_eq!(schedule.submitted(), Vec::<usize>::new());
+        assert_eq!(schedule.payments[0].next_run, 1);
+        assert_eq!(schedule.due(130, 0), vec![id]);
+    }
+}
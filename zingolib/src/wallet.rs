@@ -59,12 +59,14 @@ use self::{
 };
 use zingoconfig::ZingoConfig;
 
+pub mod birthday;
+pub mod consistency;
 pub mod data;
 pub mod keys;
 pub(crate) mod message;
 pub mod traits;
 pub(crate) mod transactions;
-pub(crate) mod utils;
+pub mod utils;
 
 pub fn now() -> u64 {
     SystemTime::now()
@@ -127,26 +129,94 @@ pub enum MemoDownloadOption {
     AllMemos,
 }
 
+/// How much we hide which full transactions are ours when fetching them from the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionFetchPrivacy {
+    /// Fetch exactly the transactions we're interested in.
+    Off = 0,
+    /// Mix in `decoys_per_transaction` random other transactions from the same block.
+    Decoys,
+    /// Fetch every transaction in each block that contains one we're interested in.
+    WholeBlock,
+}
+
+/// How long a sent transaction stays valid for if it isn't mined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryDelta {
+    /// The transaction builder's default of `DEFAULT_TX_EXPIRY_DELTA` blocks.
+    Default,
+    /// This many blocks after the height the transaction is built for.
+    Blocks(u32),
+    /// The transaction never expires.
+    Never,
+}
+
+impl std::str::FromStr for ExpiryDelta {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "default" => Ok(ExpiryDelta::Default),
+            "none" => Ok(ExpiryDelta::Never),
+            _ => match value.parse::<u32>() {
+                Ok(0) => Err("An expiry delta of 0 blocks; use none for no expiry".to_string()),
+                Ok(blocks) => Ok(ExpiryDelta::Blocks(blocks)),
+                Err(e) => Err(format!(
+                    "Error {e}, couldn't parse {value} as a number of blocks, default or none"
+                )),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ExpiryDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpiryDelta::Default => write!(f, "default"),
+            ExpiryDelta::Blocks(blocks) => write!(f, "{}", blocks),
+            ExpiryDelta::Never => write!(f, "none"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct WalletOptions {
     pub(crate) download_memos: MemoDownloadOption,
     pub(crate) transaction_size_filter: Option<u32>,
+    pub(crate) fetch_privacy: TransactionFetchPrivacy,
+    pub(crate) decoys_per_transaction: u8,
+    /// The most bytes of decoy transactions to download per sync batch, if limited.
+    pub(crate) privacy_bandwidth_budget: Option<u64>,
+    /// The expiry of sent transactions, unless a send overrides it.
+    pub(crate) send_expiry: ExpiryDelta,
 }
 
 pub const MAX_TRANSACTION_SIZE_DEFAULT: u32 = 500;
+/// How many blocks below the reorg window every block hash is kept for, and how far apart
+/// the ones kept below that are
+pub const REORG_ANCHOR_SPACING: u64 = 1_000;
+/// How many of the spaced out block hashes to keep, to find where a very deep reorg forked
+pub const MAX_REORG_ANCHORS: usize = 50;
+
+pub const DECOYS_PER_TRANSACTION_DEFAULT: u8 = 3;
+pub const PRIVACY_BANDWIDTH_BUDGET_DEFAULT: u64 = 5_000_000;
 
 impl Default for WalletOptions {
     fn default() -> Self {
         WalletOptions {
             download_memos: MemoDownloadOption::WalletMemos,
             transaction_size_filter: Some(MAX_TRANSACTION_SIZE_DEFAULT),
+            fetch_privacy: TransactionFetchPrivacy::Off,
+            decoys_per_transaction: DECOYS_PER_TRANSACTION_DEFAULT,
+            privacy_bandwidth_budget: Some(PRIVACY_BANDWIDTH_BUDGET_DEFAULT),
+            send_expiry: ExpiryDelta::Default,
         }
     }
 }
 
 impl WalletOptions {
     pub const fn serialized_version() -> u64 {
-        2
+        4
     }
 
     pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
@@ -165,14 +235,63 @@ impl WalletOptions {
         };
 
         let transaction_size_filter = if external_version > 1 {
-            Optional::read(reader, |mut r| r.read_u32::<LittleEndian>())?
+            Optional::read(&mut reader, |r| r.read_u32::<LittleEndian>())?
         } else {
             Some(500)
         };
 
+        let (fetch_privacy, decoys_per_transaction, privacy_bandwidth_budget) =
+            if external_version > 2 {
+                let fetch_privacy = match reader.read_u8()? {
+                    0 => TransactionFetchPrivacy::Off,
+                    1 => TransactionFetchPrivacy::Decoys,
+                    2 => TransactionFetchPrivacy::WholeBlock,
+                    v => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Bad fetch privacy option {}", v),
+                        ));
+                    }
+                };
+                let decoys_per_transaction = reader.read_u8()?;
+                let privacy_bandwidth_budget =
+                    Optional::read(&mut reader, |r| r.read_u64::<LittleEndian>())?;
+                (
+                    fetch_privacy,
+                    decoys_per_transaction,
+                    privacy_bandwidth_budget,
+                )
+            } else {
+                (
+                    TransactionFetchPrivacy::Off,
+                    DECOYS_PER_TRANSACTION_DEFAULT,
+                    Some(PRIVACY_BANDWIDTH_BUDGET_DEFAULT),
+                )
+            };
+
+        let send_expiry = if external_version > 3 {
+            match reader.read_u8()? {
+                0 => ExpiryDelta::Default,
+                1 => ExpiryDelta::Blocks(reader.read_u32::<LittleEndian>()?),
+                2 => ExpiryDelta::Never,
+                v => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Bad send expiry option {}", v),
+                    ));
+                }
+            }
+        } else {
+            ExpiryDelta::Default
+        };
+
         Ok(Self {
             download_memos,
             transaction_size_filter,
+            fetch_privacy,
+            decoys_per_transaction,
+            privacy_bandwidth_budget,
+            send_expiry,
         })
     }
 
@@ -181,12 +300,51 @@ impl WalletOptions {
         writer.write_u64::<LittleEndian>(Self::serialized_version())?;
 
         writer.write_u8(self.download_memos as u8)?;
-        Optional::write(writer, self.transaction_size_filter, |mut w, filter| {
+        Optional::write(&mut writer, self.transaction_size_filter, |w, filter| {
             w.write_u32::<LittleEndian>(filter)
-        })
+        })?;
+
+        writer.write_u8(self.fetch_privacy as u8)?;
+        writer.write_u8(self.decoys_per_transaction)?;
+        Optional::write(&mut writer, self.privacy_bandwidth_budget, |w, budget| {
+            w.write_u64::<LittleEndian>(budget)
+        })?;
+
+        match self.send_expiry {
+            ExpiryDelta::Default => writer.write_u8(0),
+            ExpiryDelta::Blocks(blocks) => {
+                writer.write_u8(1)?;
+                writer.write_u32::<LittleEndian>(blocks)
+            }
+            ExpiryDelta::Never => writer.write_u8(2),
+        }
     }
 }
 
+/// Thins `anchors`, highest first, to every block within REORG_ANCHOR_SPACING of the highest,
+/// and below that the highest MAX_REORG_ANCHORS of a run at least REORG_ANCHOR_SPACING apart.
+fn thin_reorg_anchors(anchors: Vec<BlockData>) -> Vec<BlockData> {
+    let top = match anchors.first() {
+        Some(block) => block.height,
+        None => return anchors,
+    };
+    let (mut dense, older): (Vec<_>, Vec<_>) = anchors
+        .into_iter()
+        .partition(|block| block.height + REORG_ANCHOR_SPACING > top);
+
+    // Spaced from the lowest up, so that the anchors kept stay where they are as more are added
+    let mut spaced: Vec<BlockData> = vec![];
+    for block in older.into_iter().rev() {
+        if spaced.last().map_or(true, |last| {
+            block.height >= last.height + REORG_ANCHOR_SPACING
+        }) {
+            spaced.push(block);
+        }
+    }
+    dense.extend(spaced.into_iter().rev().take(MAX_REORG_ANCHORS));
+    dense
+}
+
 /// Data used to initialize new instance of LightWallet
 pub enum WalletBase {
     FreshEntropy,
@@ -220,6 +378,10 @@ pub struct LightWallet {
     // The last 100 blocks, used if something gets re-orged
     pub blocks: Arc<RwLock<Vec<BlockData>>>,
 
+    // Older blocks, highest first, used to find the fork point of a reorg deeper than `blocks`:
+    // every block for REORG_ANCHOR_SPACING blocks below them, then one in REORG_ANCHOR_SPACING
+    pub(crate) reorg_anchors: Arc<RwLock<Vec<BlockData>>>,
+
     // Wallet options
     pub(crate) wallet_options: Arc<RwLock<WalletOptions>>,
 
@@ -318,6 +480,7 @@ impl LightWallet {
     /// and the wallet will need to be rescanned
     pub async fn clear_all(&self) {
         self.blocks.write().await.clear();
+        self.reorg_anchors.write().await.clear();
         self.transaction_context
             .transaction_metadata_set
             .write()
@@ -422,6 +585,21 @@ impl LightWallet {
         candidate_notes
     }
 
+    /// The values of the notes sends pick from, each pool's largest first, as they pick them
+    pub(crate) async fn spendable_note_values<D>(&self) -> Vec<u64>
+    where
+        D: DomainWalletExt,
+        <D as Domain>::Recipient: traits::Recipient,
+        <D as Domain>::Note: PartialEq + Clone,
+    {
+        self.get_all_domain_specific_notes::<D>()
+            .await
+            .iter()
+            .filter(|note| note.spend_key().is_some())
+            .map(|note| D::WalletNote::value_from_note(note.note()))
+            .collect()
+    }
+
     /// Get the height of the anchor block
     pub async fn get_anchor_height(&self) -> u32 {
         match self.get_target_height_and_anchor_offset().await {
@@ -644,6 +822,7 @@ impl LightWallet {
             TransactionContext::new(&config, Arc::new(wc), transaction_metadata_set);
         Ok(Self {
             blocks: Arc::new(RwLock::new(vec![])),
+            reorg_anchors: Arc::new(RwLock::new(vec![])),
             mnemonic,
             wallet_options: Arc::new(RwLock::new(WalletOptions::default())),
             birthday: AtomicU64::new(height),
@@ -813,8 +992,15 @@ impl LightWallet {
             None
         };
 
+        let reorg_anchors = if external_version <= 27 {
+            vec![]
+        } else {
+            Vector::read(&mut reader, |r| BlockData::read(r))?
+        };
+
         let lw = Self {
             blocks: Arc::new(RwLock::new(blocks)),
+            reorg_anchors: Arc::new(RwLock::new(reorg_anchors)),
             mnemonic,
             wallet_options: Arc::new(RwLock::new(wallet_options)),
             birthday: AtomicU64::new(birthday),
@@ -925,6 +1111,7 @@ impl LightWallet {
         policy: NoteSelectionPolicy,
         tos: Vec<(&str, u64, Option<MemoBytes>)>,
         submission_height: BlockHeight,
+        expiry: ExpiryDelta,
         broadcast_fn: F,
     ) -> Result<(String, Vec<u8>), String>
     where
@@ -936,7 +1123,14 @@ impl LightWallet {
 
         // Call the internal function
         match self
-            .send_to_addresses_inner(sapling_prover, policy, tos, submission_height, broadcast_fn)
+            .send_to_addresses_inner(
+                sapling_prover,
+                policy,
+                tos,
+                submission_height,
+                expiry,
+                broadcast_fn,
+            )
             .await
         {
             Ok((transaction_id, raw_transaction)) => {
@@ -1062,6 +1256,7 @@ impl LightWallet {
         policy: NoteSelectionPolicy,
         tos: Vec<(&str, u64, Option<MemoBytes>)>,
         submission_height: BlockHeight,
+        expiry: ExpiryDelta,
         broadcast_fn: F,
     ) -> Result<(String, Vec<u8>), String>
     where
@@ -1073,6 +1268,15 @@ impl LightWallet {
             return Err("Need at least one destination address".to_string());
         }
 
+        // The builder always sets the expiry height to the submission height plus
+        // DEFAULT_TX_EXPIRY_DELTA, and the height is signed, so it can't be changed afterwards
+        if expiry != ExpiryDelta::Default {
+            return Err(format!(
+                "Can't send with an expiry delta of {}: the transaction builder only supports its default expiry. Set send_expiry to default to send.",
+                expiry
+            ));
+        }
+
         if !self.wallet_capability().can_spend_from_all_pools() {
             // Creating transactions in context of all possible combinations
             // of wallet capabilities requires a rigorous case study
@@ -1351,6 +1555,7 @@ impl LightWallet {
         // Add this transaction to the mempool structure
         {
             let price = self.price.read().await.clone();
+            let txid = transaction.txid();
 
             self.transaction_context
                 .scan_full_tx(
@@ -1361,13 +1566,19 @@ impl LightWallet {
                     TransactionMetadata::get_price(now(), &price),
                 )
                 .await;
+            // Keep the bytes around in case the Tx has to be rebroadcast
+            self.transaction_context
+                .transaction_metadata_set
+                .write()
+                .await
+                .set_raw_transaction(&txid, raw_transaction.clone());
         }
 
         Ok((transaction_id, raw_transaction))
     }
 
     pub const fn serialized_version() -> u64 {
-        27
+        28
     }
 
     pub async fn set_blocks(&self, new_blocks: Vec<BlockData>) {
@@ -1376,6 +1587,48 @@ impl LightWallet {
         blocks.extend_from_slice(&new_blocks[..]);
     }
 
+    /// Keeps the blocks that fell out of the bottom of the reorg window, highest first, as
+    /// anchors. Every block is kept for REORG_ANCHOR_SPACING blocks below the window, and
+    /// below that only blocks at least REORG_ANCHOR_SPACING apart.
+    pub(crate) async fn record_reorg_anchors(&self, below_window: Vec<BlockData>) {
+        let mut anchors = self.reorg_anchors.write().await;
+        let lowest_new = below_window.last().map(|block| block.height);
+        let mut merged = below_window;
+        merged.extend(
+            anchors
+                .drain(..)
+                .filter(|anchor| lowest_new.map_or(true, |lowest| anchor.height < lowest)),
+        );
+        *anchors = thin_reorg_anchors(merged);
+    }
+
+    /// Rolls the wallet back to the block described by `tree_state`: transactions and spends
+    /// above it are forgotten, the witness trees are cut back to its sizes, and it becomes the
+    /// wallet's only known block.
+    pub(crate) async fn rewind_to(&self, tree_state: TreeState) -> Result<(), String> {
+        let height = tree_state.height;
+        let hash = hex::decode(&tree_state.hash)
+            .map_err(|e| format!("Bad block hash in tree state at {}: {}", height, e))?;
+        let (sapling_frontier, orchard_frontier) = Self::get_legacy_frontiers(tree_state.clone());
+        let sapling_tree_size = sapling_frontier.map_or(0, |f| u64::from(f.position()) + 1);
+        let orchard_tree_size = orchard_frontier.map_or(0, |f| u64::from(f.position()) + 1);
+
+        self.transactions().write().await.rewind_to_height(
+            height,
+            sapling_tree_size,
+            orchard_tree_size,
+        );
+        self.set_blocks(vec![BlockData::new_with(height, &hash)])
+            .await;
+        self.reorg_anchors
+            .write()
+            .await
+            .retain(|anchor| anchor.height <= height);
+        *self.verified_tree.write().await = Some(tree_state);
+
+        Ok(())
+    }
+
     pub async fn set_download_memo(&self, value: MemoDownloadOption) {
         self.wallet_options.write().await.download_memos = value;
     }
@@ -1507,6 +1760,92 @@ impl LightWallet {
         }
     }
 
+    /// Value arriving in this pool from transactions still in the mempool, including the
+    /// change of our own pending sends. Once everything pending is mined, the pool's balance
+    /// is its current balance plus this, minus the pending outgoing value.
+    async fn pending_incoming_balance<D: DomainWalletExt>(&self) -> Option<u64>
+    where
+        <D as Domain>::Recipient: Recipient,
+        <D as Domain>::Note: PartialEq + Clone,
+    {
+        #[allow(clippy::type_complexity)]
+        let filters: &[Box<dyn Fn(&&D::WalletNote, &TransactionMetadata) -> bool>] =
+            &[Box::new(|_, transaction| transaction.unconfirmed)];
+        self.shielded_balance::<D>(None, filters).await
+    }
+
+    /// Value of this pool's notes that are being spent by transactions still in the mempool
+    async fn pending_outgoing_balance<D: DomainWalletExt>(&self) -> Option<u64>
+    where
+        <D as Domain>::Recipient: Recipient,
+        <D as Domain>::Note: PartialEq + Clone,
+    {
+        D::wc_to_fvk(&self.wallet_capability()).ok()?;
+        Some(
+            self.transaction_context
+                .transaction_metadata_set
+                .read()
+                .await
+                .current
+                .values()
+                .flat_map(|transaction| D::WalletNote::transaction_metadata_notes(transaction))
+                .filter(|notedata| notedata.spent().is_none() && notedata.pending_spent().is_some())
+                .map(|notedata| <D::WalletNote as traits::ReceivedNoteAndMetadata>::value(notedata))
+                .sum::<u64>(),
+        )
+    }
+
+    pub async fn pending_incoming_sapling_balance(&self) -> Option<u64> {
+        self.pending_incoming_balance::<SaplingDomain<zingoconfig::ChainType>>()
+            .await
+    }
+
+    pub async fn pending_outgoing_sapling_balance(&self) -> Option<u64> {
+        self.pending_outgoing_balance::<SaplingDomain<zingoconfig::ChainType>>()
+            .await
+    }
+
+    pub async fn pending_incoming_orchard_balance(&self) -> Option<u64> {
+        self.pending_incoming_balance::<OrchardDomain>().await
+    }
+
+    pub async fn pending_outgoing_orchard_balance(&self) -> Option<u64> {
+        self.pending_outgoing_balance::<OrchardDomain>().await
+    }
+
+    pub async fn pending_incoming_transparent_balance(&self) -> Option<u64> {
+        if !self.wallet_capability().transparent.can_view() {
+            return None;
+        }
+        Some(
+            self.transaction_context
+                .transaction_metadata_set
+                .read()
+                .await
+                .current
+                .values()
+                .filter(|transaction| transaction.unconfirmed)
+                .flat_map(|transaction| transaction.received_utxos.iter())
+                .filter(|utxo| utxo.spent.is_none())
+                .map(|utxo| utxo.value)
+                .sum::<u64>(),
+        )
+    }
+
+    pub async fn pending_outgoing_transparent_balance(&self) -> Option<u64> {
+        if !self.wallet_capability().transparent.can_view() {
+            return None;
+        }
+        Some(
+            self.get_utxos()
+                .await
+                .iter()
+                .filter(|utxo| utxo.unconfirmed_spent.is_some())
+                .map(|utxo| utxo.value)
+                .sum::<u64>(),
+        )
+    }
+
     pub fn transactions(&self) -> Arc<RwLock<TransactionMetadataSet>> {
         self.transaction_context.transaction_metadata_set.clone()
     }
@@ -1617,6 +1956,10 @@ impl LightWallet {
         };
         Vector::write(&mut writer, &seed_bytes, |w, byte| w.write_u8(*byte))?;
 
+        Vector::write(&mut writer, &self.reorg_anchors.read().await, |w, b| {
+            b.write(w)
+        })?;
+
         Ok(())
     }
 }
@@ -1702,4 +2045,70 @@ mod test {
         let anchor = orchard::Anchor::from(MerkleHashOrchard::from_bytes(&anchor).unwrap());
         assert_eq!(orchard::Anchor::from(orchard_tree.root()), anchor);
     }
+
+    #[test]
+    fn wallet_options_round_trip() {
+        let options = super::WalletOptions {
+            fetch_privacy: super::TransactionFetchPrivacy::WholeBlock,
+            decoys_per_transaction: 7,
+            privacy_bandwidth_budget: None,
+            send_expiry: super::ExpiryDelta::Blocks(400),
+            ..Default::default()
+        };
+        let mut bytes = vec![];
+        options.write(&mut bytes).unwrap();
+        let read_back = super::WalletOptions::read(&bytes[..]).unwrap();
+
+        assert_eq!(read_back.fetch_privacy, options.fetch_privacy);
+        assert_eq!(read_back.decoys_per_transaction, 7);
+        assert_eq!(read_back.privacy_bandwidth_budget, None);
+        assert_eq!(read_back.send_expiry, super::ExpiryDelta::Blocks(400));
+        assert_eq!(
+            read_back.transaction_size_filter,
+            options.transaction_size_filter
+        );
+    }
+
+    #[test]
+    fn expiry_delta_reads_back_as_written() {
+        use super::ExpiryDelta;
+
+        for expiry in [
+            ExpiryDelta::Default,
+            ExpiryDelta::Blocks(400),
+            ExpiryDelta::Never,
+        ] {
+            assert_eq!(expiry.to_string().parse::<ExpiryDelta>(), Ok(expiry));
+        }
+        assert!("0".parse::<ExpiryDelta>().is_err());
+        assert!("soon".parse::<ExpiryDelta>().is_err());
+    }
+
+    #[test]
+    fn reorg_anchors_are_every_block_below_the_window_then_spaced_out() {
+        use super::{thin_reorg_anchors, BlockData, MAX_REORG_ANCHORS, REORG_ANCHOR_SPACING};
+
+        // Blocks fall out of the window 100 at a time, highest first
+        let mut anchors: Vec<BlockData> = vec![];
+        for chunk_top in (99..100_000).step_by(100) {
+            let mut below_window: Vec<BlockData> = (chunk_top - 99..=chunk_top)
+                .rev()
+                .map(|height| BlockData::new_with(height, &[0; 32]))
+                .collect();
+            below_window.extend(anchors);
+            anchors = thin_reorg_anchors(below_window);
+        }
+
+        let heights: Vec<u64> = anchors.iter().map(|block| block.height).collect();
+        let dense = REORG_ANCHOR_SPACING as usize;
+        assert_eq!(heights.len(), dense + MAX_REORG_ANCHORS);
+        assert!(heights[..dense]
+            .iter()
+            .zip((100_000 - REORG_ANCHOR_SPACING..100_000).rev())
+            .all(|(height, expected)| *height == expected));
+        assert!(heights[dense..]
+            .windows(2)
+            .all(|pair| pair[0] - pair[1] == REORG_ANCHOR_SPACING));
+        assert!(heights[dense] < 100_000 - REORG_ANCHOR_SPACING);
+    }
 }
@@ -0,0 +1,167 @@
+//! Turning a calendar date into a wallet birthday height.
+//!
+//! People restoring a seed rarely know the block height their wallet was created at, but they
+//! usually know roughly when. Offline, the height is estimated from a small table of mainnet
+//! upgrade activations and the target block spacing. Online, the server's block times are
+//! searched for the real height. Either way the result errs on the early side: starting the
+//! scan a little too soon costs some time, starting it too late loses funds.
+use zcash_primitives::consensus::{NetworkUpgrade, Parameters};
+use zingoconfig::ChainType;
+
+use crate::grpc_connector::GrpcConnector;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Target block spacing before and after Blossom, in seconds
+const PRE_BLOSSOM_SPACING: i64 = 150;
+const POST_BLOSSOM_SPACING: i64 = 75;
+
+/// Mainnet (height, start of the UTC day it activated) pairs, lowest first
+const MAINNET_CHECKPOINTS: [(u64, i64); 5] = [
+    (419_200, 1_540_684_800),   // Sapling, 2018-10-28
+    (653_600, 1_576_022_400),   // Blossom, 2019-12-11
+    (903_000, 1_594_857_600),   // Heartwood, 2020-07-16
+    (1_046_400, 1_605_657_600), // Canopy, 2020-11-18
+    (1_687_104, 1_653_955_200), // NU5, 2022-05-31
+];
+
+/// How many blocks before the located height a birthday found online is placed, to cover
+/// miners' clocks being off
+const ONLINE_MARGIN: u64 = 100;
+
+fn sapling_activation(chain: ChainType) -> u64 {
+    chain
+        .activation_height(NetworkUpgrade::Sapling)
+        .map(u64::from)
+        .unwrap_or(1)
+}
+
+/// Parses a `YYYY-MM-DD` date into the Unix timestamp of the start of that day (UTC).
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    let invalid = || format!("'{}' is not a date of the form YYYY-MM-DD", date);
+    let parts = date
+        .trim()
+        .split('-')
+        .map(|part| part.parse::<i64>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, String>>()?;
+    let (year, month, day) = match parts[..] {
+        [year, month, day] => (year, month, day),
+        _ => return Err(invalid()),
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+
+    // Days since 1970-01-01, counting in years that start on March 1st so that the leap
+    // day falls at the end (Howard Hinnant's days_from_civil)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Ok(days * SECONDS_PER_DAY)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Estimates, without a server, a height mined no later than `timestamp`. Only mainnet has a
+/// checkpoint table; other chains get their Sapling activation height.
+pub fn estimate_height_at(chain: ChainType, timestamp: i64) -> u64 {
+    let activation = sapling_activation(chain);
+    if !matches!(chain, ChainType::Mainnet) {
+        return activation;
+    }
+    let (checkpoint_height, checkpoint_time) = match MAINNET_CHECKPOINTS
+        .iter()
+        .rev()
+        .find(|(_, time)| *time <= timestamp)
+    {
+        Some(&checkpoint) => checkpoint,
+        None => return activation,
+    };
+
+    let blossom = MAINNET_CHECKPOINTS[1].0;
+    let spacing = if checkpoint_height < blossom {
+        PRE_BLOSSOM_SPACING
+    } else {
+        POST_BLOSSOM_SPACING
+    };
+    let elapsed_blocks = ((timestamp - checkpoint_time) / spacing) as u64;
+    // A day's worth of blocks, plus 1% of the distance for blocks coming in slower than target
+    let margin = (SECONDS_PER_DAY / spacing) as u64 + elapsed_blocks / 100;
+
+    (checkpoint_height + elapsed_blocks)
+        .saturating_sub(margin)
+        .max(activation)
+}
+
+/// Finds the height of the last block mined before `timestamp` by searching the server's block
+/// times, and puts the birthday a little before it.
+pub async fn find_height_at_time(
+    connector: &GrpcConnector,
+    chain: ChainType,
+    timestamp: i64,
+) -> Result<u64, String> {
+    let activation = sapling_activation(chain);
+    let tip = connector.get_latest_block().await?.height;
+    if tip <= activation {
+        return Ok(activation);
+    }
+
+    // Invariant: the block at `low` was mined before `timestamp`, or `low` is activation
+    let (mut low, mut high) = (activation, tip);
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        if connector.get_block_time(mid).await? as i64 <= timestamp {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+
+    Ok(low.saturating_sub(ONLINE_MARGIN).max(activation))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dates_parse_to_day_starts() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2018-10-28"), Ok(MAINNET_CHECKPOINTS[0].1));
+        assert_eq!(parse_date("2022-05-31"), Ok(MAINNET_CHECKPOINTS[4].1));
+        assert_eq!(parse_date("2024-02-29"), Ok(1_709_164_800));
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("2023-13-01").is_err());
+        assert!(parse_date("2023-01").is_err());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn estimates_err_early() {
+        let chain = ChainType::Mainnet;
+        assert_eq!(estimate_height_at(chain, 0), 419_200);
+        for (height, time) in MAINNET_CHECKPOINTS {
+            assert!(estimate_height_at(chain, time) <= height);
+            assert!(estimate_height_at(chain, time + SECONDS_PER_DAY) <= height + 1_152);
+        }
+        let later = parse_date("2023-06-01").unwrap();
+        assert!(estimate_height_at(chain, later) > 1_687_104);
+        assert!(estimate_height_at(chain, later) < estimate_height_at(chain, later + 1_000_000));
+        assert_eq!(
+            estimate_height_at(ChainType::Testnet, later),
+            sapling_activation(ChainType::Testnet)
+        );
+    }
+}
@@ -0,0 +1,696 @@
+//! Invariants a healthy wallet keeps. Checking reports every place they don't hold, along with
+//! a repair for the ones that can be fixed from what the wallet already knows. Repairs are only
+//! applied once every check has run. The rest need data the wallet never saw, and are reported
+//! so the user can rescan.
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+
+use incrementalmerkletree::frontier::CommitmentTree;
+use incrementalmerkletree::Hashable;
+use json::{object, JsonValue};
+use orchard::note_encryption::OrchardDomain;
+use zcash_note_encryption::Domain;
+use zcash_primitives::{
+    memo::Memo,
+    merkle_tree::{read_commitment_tree, HashSer},
+    sapling::note_encryption::SaplingDomain,
+    transaction::TxId,
+};
+use zingoconfig::ChainType;
+
+use super::{
+    data::{PoolNullifier, ReceivedTransparentOutput, COMMITMENT_TREE_LEVELS},
+    traits::{DomainWalletExt, Nullifier as _, ReceivedNoteAndMetadata, Recipient, ToBytes as _},
+    transactions::TransactionMetadataSet,
+    Pool,
+};
+use crate::compact_formats::{GetAddressUtxosReply, TreeState};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Inconsistency {
+    pub description: String,
+    pub repaired: bool,
+    /// How to fix it from the wallet's own data, if that can be done
+    pub(crate) repair: Option<Repair>,
+}
+
+/// A fix worked out while checking, to be applied once all the checks are done
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Repair {
+    /// List a nullifier the spending transaction left out
+    ListNullifier {
+        spend_txid: TxId,
+        nullifier: PoolNullifier,
+        value: u64,
+    },
+    /// Mark a note spent by the transaction that lists its nullifier
+    MarkSpent {
+        txid: TxId,
+        pool: Pool,
+        index: usize,
+        spend_txid: TxId,
+        spend_height: u32,
+    },
+    SetChange {
+        txid: TxId,
+        pool: Pool,
+        index: usize,
+        is_change: bool,
+    },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConsistencyReport {
+    pub inconsistencies: Vec<Inconsistency>,
+    /// Checks that couldn't be run, e.g. because the server couldn't be reached
+    pub unchecked: Vec<String>,
+}
+
+impl ConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+
+    pub fn any_repaired(&self) -> bool {
+        self.inconsistencies.iter().any(|i| i.repaired)
+    }
+
+    pub(crate) fn found(&mut self, description: String, repaired: bool) {
+        self.inconsistencies.push(Inconsistency {
+            description,
+            repaired,
+            repair: None,
+        });
+    }
+
+    fn found_repairable(&mut self, description: String, repair: Repair) {
+        self.inconsistencies.push(Inconsistency {
+            description,
+            repaired: false,
+            repair: Some(repair),
+        });
+    }
+
+    pub(crate) fn unchecked(&mut self, description: String) {
+        self.unchecked.push(description);
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let (repaired, unrepaired): (Vec<_>, Vec<_>) =
+            self.inconsistencies.iter().partition(|i| i.repaired);
+        let mut json = object! {
+            "consistent" => self.is_consistent(),
+            "repaired" => repaired
+                .into_iter()
+                .map(|i| i.description.clone())
+                .collect::<Vec<_>>(),
+            "unrepaired" => unrepaired
+                .into_iter()
+                .map(|i| i.description.clone())
+                .collect::<Vec<_>>(),
+        };
+        if !self.unchecked.is_empty() {
+            json["unchecked"] = self.unchecked.clone().into();
+        }
+        json
+    }
+}
+
+impl TransactionMetadataSet {
+    /// Checks the invariants that only involve the wallet's own transactions, working out a
+    /// repair for each inconsistency that can be fixed.
+    pub fn check_consistency(&self, report: &mut ConsistencyReport) {
+        self.check_spends::<SaplingDomain<ChainType>>(report);
+        self.check_spends::<OrchardDomain>(report);
+        self.check_change_flags(report);
+    }
+
+    /// Applies the repairs the checks found, marking what they fixed as repaired.
+    pub fn apply_repairs(&mut self, report: &mut ConsistencyReport) {
+        for inconsistency in &mut report.inconsistencies {
+            if inconsistency.repaired {
+                continue;
+            }
+            if let Some(repair) = &inconsistency.repair {
+                inconsistency.repaired = self.apply_repair(repair);
+            }
+        }
+    }
+
+    fn apply_repair(&mut self, repair: &Repair) -> bool {
+        let (txid, pool, index) = match *repair {
+            Repair::ListNullifier {
+                spend_txid,
+                nullifier,
+                value,
+            } => {
+                return match self.current.get_mut(&spend_txid) {
+                    Some(spending_transaction) => {
+                        spending_transaction.add_spent_nullifier(nullifier, value);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Repair::MarkSpent {
+                txid, pool, index, ..
+            }
+            | Repair::SetChange {
+                txid, pool, index, ..
+            } => (txid, pool, index),
+        };
+        let transaction_metadata = match self.current.get_mut(&txid) {
+            Some(transaction_metadata) => transaction_metadata,
+            None => return false,
+        };
+        match pool {
+            Pool::Sapling => transaction_metadata
+                .sapling_notes
+                .get_mut(index)
+                .map(|note| repair_note(note, repair))
+                .is_some(),
+            Pool::Orchard => transaction_metadata
+                .orchard_notes
+                .get_mut(index)
+                .map(|note| repair_note(note, repair))
+                .is_some(),
+            Pool::Transparent => false,
+        }
+    }
+
+    /// A note marked spent must be spent by a transaction we know, which lists its nullifier,
+    /// and a nullifier a transaction lists must belong to a note marked spent by it.
+    fn check_spends<D: DomainWalletExt>(&self, report: &mut ConsistencyReport)
+    where
+        <D as Domain>::Note: PartialEq + Clone,
+        <D as Domain>::Recipient: Recipient,
+    {
+        let pool = <D::WalletNote as ReceivedNoteAndMetadata>::pool();
+
+        let mut notes_by_nullifier = HashMap::new();
+        let mut marked_spends = vec![];
+        for (txid, transaction_metadata) in &self.current {
+            for (index, note) in D::to_notes_vec(transaction_metadata).iter().enumerate() {
+                if note.pending_receipt() {
+                    continue;
+                }
+                notes_by_nullifier.insert(note.nullifier().to_bytes(), (*txid, index));
+                if let Some((spend_txid, _)) = note.spent() {
+                    marked_spends.push((*txid, note.nullifier(), note.value(), *spend_txid));
+                }
+            }
+        }
+
+        for (txid, nullifier, value, spend_txid) in marked_spends {
+            match self.current.get(&spend_txid) {
+                None => report.found(
+                    format!(
+                        "{:?} note {} received in {} is marked spent by {}, which the wallet doesn't have. Rescan to recover the spend",
+                        pool,
+                        hex::encode(nullifier.to_bytes()),
+                        txid,
+                        spend_txid
+                    ),
+                    false,
+                ),
+                Some(spending_transaction) => {
+                    if !<D::WalletNote as ReceivedNoteAndMetadata>::Nullifier::get_nullifiers_spent_in_transaction(spending_transaction)
+                        .contains(&nullifier)
+                    {
+                        report.found_repairable(
+                            format!(
+                                "{} spends {:?} note {} but didn't list its nullifier",
+                                spend_txid,
+                                pool,
+                                hex::encode(nullifier.to_bytes())
+                            ),
+                            Repair::ListNullifier {
+                                spend_txid,
+                                nullifier: nullifier.into(),
+                                value,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut listed_spends = vec![];
+        for (spend_txid, transaction_metadata) in &self.current {
+            for nullifier in
+                <D::WalletNote as ReceivedNoteAndMetadata>::Nullifier::get_nullifiers_spent_in_transaction(
+                    transaction_metadata,
+                )
+            {
+                listed_spends.push((*spend_txid, transaction_metadata.block_height, *nullifier));
+            }
+        }
+
+        for (spend_txid, spend_height, nullifier) in listed_spends {
+            let (txid, index) = match notes_by_nullifier.get(&nullifier.to_bytes()) {
+                Some(location) => *location,
+                None => {
+                    report.found(
+                        format!(
+                            "{} spends {:?} nullifier {}, which matches none of the wallet's notes. Rescan to recover the note",
+                            spend_txid,
+                            pool,
+                            hex::encode(nullifier.to_bytes())
+                        ),
+                        false,
+                    );
+                    continue;
+                }
+            };
+            let note = &D::to_notes_vec(&self.current[&txid])[index];
+            match *note.spent() {
+                Some((marked_txid, _)) if marked_txid == spend_txid => (),
+                Some((marked_txid, _)) => report.found(
+                    format!(
+                        "{:?} note {} received in {} is spent by both {} and {}",
+                        pool,
+                        hex::encode(nullifier.to_bytes()),
+                        txid,
+                        marked_txid,
+                        spend_txid
+                    ),
+                    false,
+                ),
+                None => report.found_repairable(
+                    format!(
+                        "{:?} note {} received in {} is spent by {} but wasn't marked spent",
+                        pool,
+                        hex::encode(nullifier.to_bytes()),
+                        txid,
+                        spend_txid
+                    ),
+                    Repair::MarkSpent {
+                        txid,
+                        pool,
+                        index,
+                        spend_txid,
+                        spend_height: u32::from(spend_height),
+                    },
+                ),
+            }
+        }
+    }
+
+    /// Notes are change exactly when `check_notes_mark_change` would say so.
+    fn check_change_flags(&self, report: &mut ConsistencyReport) {
+        for (txid, transaction_metadata) in &self.current {
+            let outgoing = self.total_funds_spent_in(txid) > 0;
+            check_pool_change_flags(txid, outgoing, &transaction_metadata.sapling_notes, report);
+            check_pool_change_flags(txid, outgoing, &transaction_metadata.orchard_notes, report);
+        }
+    }
+
+    /// Every transparent output we think is unspent should be in the server's UTXO set, and
+    /// every output the server has for our addresses should be in the wallet.
+    pub fn check_utxos(
+        &self,
+        server_utxos: &[GetAddressUtxosReply],
+        report: &mut ConsistencyReport,
+    ) {
+        for utxo in self.utxos_spent_elsewhere(server_utxos) {
+            report.found(
+                format!(
+                    "Transparent output {}:{} to {} is unspent in the wallet but not on chain. Rescan to find the spend",
+                    utxo.txid, utxo.output_index, utxo.address
+                ),
+                false,
+            );
+        }
+
+        for (txid, utxo) in self.utxos_missing_from_wallet(server_utxos) {
+            report.found(
+                format!(
+                    "Transparent output {}:{} to {} ({} zats) is on chain but missing from the wallet",
+                    txid, utxo.index, utxo.address, utxo.value_zat
+                ),
+                false,
+            );
+        }
+    }
+
+    /// Confirmed outputs the wallet holds as unspent that the server's UTXO set doesn't have,
+    /// i.e. ones spent by a transaction the wallet never saw. Outputs with a pending spend of
+    /// our own are left out, since the server doesn't look at the mempool.
+    pub fn utxos_spent_elsewhere(
+        &self,
+        server_utxos: &[GetAddressUtxosReply],
+    ) -> Vec<&ReceivedTransparentOutput> {
+        let server_outpoints = server_utxos
+            .iter()
+            .filter_map(|utxo| Some((utxo_txid(utxo)?, utxo.index as u64)))
+            .collect::<HashSet<_>>();
+
+        self.current
+            .values()
+            .filter(|transaction_metadata| !transaction_metadata.unconfirmed)
+            .flat_map(|transaction_metadata| transaction_metadata.received_utxos.iter())
+            .filter(|utxo| {
+                utxo.spent.is_none()
+                    && utxo.unconfirmed_spent.is_none()
+                    && !server_outpoints.contains(&(utxo.txid, utxo.output_index))
+            })
+            .collect()
+    }
+
+    /// Outputs in the server's UTXO set that the wallet has no record of, with their txids
+    pub fn utxos_missing_from_wallet<'a>(
+        &self,
+        server_utxos: &'a [GetAddressUtxosReply],
+    ) -> Vec<(TxId, &'a GetAddressUtxosReply)> {
+        let wallet_outpoints = self
+            .current
+            .values()
+            .flat_map(|transaction_metadata| transaction_metadata.received_utxos.iter())
+            .map(|utxo| (utxo.txid, utxo.output_index))
+            .collect::<HashSet<_>>();
+
+        server_utxos
+            .iter()
+            .filter_map(|utxo| Some((utxo_txid(utxo)?, utxo)))
+            .filter(|(txid, utxo)| !wallet_outpoints.contains(&(*txid, utxo.index as u64)))
+            .collect()
+    }
+
+    /// The confirmed transparent balance must be what the server reports for our addresses.
+    /// Like the server, this counts outputs whose spend is still in the mempool.
+    pub fn check_transparent_balance(&self, server_balance: u64, report: &mut ConsistencyReport) {
+        let wallet_balance = self
+            .current
+            .values()
+            .filter(|transaction_metadata| !transaction_metadata.unconfirmed)
+            .flat_map(|transaction_metadata| transaction_metadata.received_utxos.iter())
+            .filter(|utxo| utxo.spent.is_none())
+            .map(|utxo| utxo.value)
+            .sum::<u64>();
+        if wallet_balance != server_balance {
+            report.found(
+                format!(
+                    "Transparent balance is {} zats in the wallet but {} zats on chain",
+                    wallet_balance, server_balance
+                ),
+                false,
+            );
+        }
+    }
+
+    /// The witness tree's root at `checkpoint_depth` must be the chain's root at the height of
+    /// `tree_state`.
+    pub fn check_witness_tree<D: DomainWalletExt>(
+        &self,
+        tree_state: &TreeState,
+        checkpoint_depth: usize,
+        report: &mut ConsistencyReport,
+    ) where
+        <D as Domain>::Note: PartialEq + Clone,
+        <D as Domain>::Recipient: Recipient,
+    {
+        let pool = <D::WalletNote as ReceivedNoteAndMetadata>::pool();
+        let tree = match D::get_shardtree(self) {
+            Some(tree) => tree,
+            None => return,
+        };
+
+        let ours = match tree.root_at_checkpoint(checkpoint_depth) {
+            Ok(root) => root,
+            Err(e) => {
+                report.found(
+                    format!(
+                        "Couldn't compute the {:?} witness tree root at {}: {}",
+                        pool, tree_state.height, e
+                    ),
+                    false,
+                );
+                return;
+            }
+        };
+        let theirs = match chain_tree_root::<<D::WalletNote as ReceivedNoteAndMetadata>::Node>(
+            D::get_tree(tree_state),
+        ) {
+            Ok(root) => root,
+            Err(e) => {
+                report.found(
+                    format!(
+                        "Couldn't read the chain's {:?} tree at {}: {}",
+                        pool, tree_state.height, e
+                    ),
+                    false,
+                );
+                return;
+            }
+        };
+
+        if ours != theirs {
+            report.found(
+                format!(
+                    "The {:?} witness tree doesn't match the chain at {}. Rescan to rebuild it",
+                    pool, tree_state.height
+                ),
+                false,
+            );
+        }
+    }
+}
+
+fn check_pool_change_flags<Note: ReceivedNoteAndMetadata>(
+    txid: &TxId,
+    outgoing: bool,
+    notes: &[Note],
+    report: &mut ConsistencyReport,
+) {
+    for (index, note) in notes.iter().enumerate() {
+        let expected = outgoing && !matches!(note.memo(), Some(Memo::Text(_)));
+        if note.is_change() != expected {
+            let description = format!(
+                "{:?} note {} of {} is {}marked as change",
+                Note::pool(),
+                note.output_index(),
+                txid,
+                if expected { "not " } else { "" }
+            );
+            report.found_repairable(
+                description,
+                Repair::SetChange {
+                    txid: *txid,
+                    pool: Note::pool(),
+                    index,
+                    is_change: expected,
+                },
+            );
+        }
+    }
+}
+
+fn repair_note<Note: ReceivedNoteAndMetadata>(note: &mut Note, repair: &Repair) {
+    match *repair {
+        Repair::MarkSpent {
+            spend_txid,
+            spend_height,
+            ..
+        } => {
+            *note.spent_mut() = Some((spend_txid, spend_height));
+            // The spend is mined, so it is no longer pending
+            *note.pending_spent_mut() = None;
+        }
+        Repair::SetChange { is_change, .. } => *note.is_change_mut() = is_change,
+        Repair::ListNullifier { .. } => (),
+    }
+}
+
+fn chain_tree_root<Node: Hashable + HashSer + Clone>(tree_hex: &str) -> Result<Node, String> {
+    if tree_hex.is_empty() {
+        return Ok(CommitmentTree::<Node, COMMITMENT_TREE_LEVELS>::empty().root());
+    }
+    let bytes = hex::decode(tree_hex).map_err(|e| e.to_string())?;
+    read_commitment_tree::<Node, _, COMMITMENT_TREE_LEVELS>(&bytes[..])
+        .map(|tree| tree.root())
+        .map_err(|e| e.to_string())
+}
+
+fn utxo_txid(utxo: &GetAddressUtxosReply) -> Option<TxId> {
+    let txid_bytes: [u8; 32] = utxo.txid.clone().try_into().ok()?;
+    Some(TxId::from_bytes(txid_bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wallet::data::{ReceivedSaplingNoteAndMetadata, TransactionMetadata};
+    use crate::wallet::traits::FromCommitment;
+    use incrementalmerkletree::Position;
+    use zcash_primitives::{
+        consensus::BlockHeight,
+        merkle_tree::write_commitment_tree,
+        sapling::{self, value::NoteValue, Rseed},
+        zip32::ExtendedSpendingKey,
+    };
+
+    fn sapling_note(value: u64) -> sapling::Note {
+        let (_, address) = ExtendedSpendingKey::master(&[1; 32])
+            .to_diversifiable_full_viewing_key()
+            .default_address();
+        sapling::Note::from_parts(
+            address,
+            NoteValue::from_raw(value),
+            Rseed::AfterZip212([0; 32]),
+        )
+    }
+
+    fn received_note(
+        nullifier: u8,
+        value: u64,
+        unconfirmed_spent: Option<(TxId, u32)>,
+    ) -> ReceivedSaplingNoteAndMetadata {
+        let note = sapling_note(value);
+        ReceivedSaplingNoteAndMetadata::from_parts(
+            *note.recipient().diversifier(),
+            note,
+            Position::from(0),
+            Some(sapling::Nullifier([nullifier; 32])),
+            None,
+            unconfirmed_spent,
+            None,
+            false,
+            true,
+            0,
+        )
+    }
+
+    #[test]
+    fn note_repairs_wait_for_the_whole_check() {
+        let receive_txid = TxId::from_bytes([1; 32]);
+        let spend_txid = TxId::from_bytes([2; 32]);
+
+        // A note whose spend was broadcast, then mined without the note being marked spent
+        let mut receive =
+            TransactionMetadata::new(BlockHeight::from_u32(10), 0, &receive_txid, false);
+        receive
+            .sapling_notes
+            .push(received_note(11, 5_000, Some((spend_txid, 12))));
+        // The spend's change note, which isn't flagged as change
+        let mut spend = TransactionMetadata::new(BlockHeight::from_u32(12), 0, &spend_txid, false);
+        spend.add_spent_nullifier(PoolNullifier::Sapling(sapling::Nullifier([11; 32])), 5_000);
+        spend.sapling_notes.push(received_note(22, 4_000, None));
+
+        let mut transactions = TransactionMetadataSet::new_treeless();
+        transactions.current.insert(receive_txid, receive);
+        transactions.current.insert(spend_txid, spend);
+
+        let mut report = ConsistencyReport::default();
+        transactions.check_consistency(&mut report);
+        assert_eq!(report.inconsistencies.len(), 2);
+        assert!(!report.any_repaired());
+        // Checking alone leaves the wallet as it was
+        assert_eq!(
+            transactions.current[&receive_txid].sapling_notes[0].spent,
+            None
+        );
+
+        transactions.apply_repairs(&mut report);
+        assert!(report.inconsistencies.iter().all(|i| i.repaired));
+        let repaired_note = &transactions.current[&receive_txid].sapling_notes[0];
+        assert_eq!(repaired_note.spent, Some((spend_txid, 12)));
+        assert_eq!(repaired_note.unconfirmed_spent, None);
+        assert!(transactions.current[&spend_txid].sapling_notes[0].is_change);
+
+        let mut report = ConsistencyReport::default();
+        transactions.check_consistency(&mut report);
+        assert!(report.is_consistent());
+    }
+
+    #[test]
+    fn witness_tree_compared_with_the_chain() {
+        let mut transactions = TransactionMetadataSet::new_with_witness_trees();
+        transactions
+            .witness_trees
+            .as_mut()
+            .unwrap()
+            .add_checkpoint(BlockHeight::from_u32(10));
+
+        let empty_chain = TreeState {
+            height: 10,
+            ..Default::default()
+        };
+        let mut report = ConsistencyReport::default();
+        transactions.check_witness_tree::<SaplingDomain<ChainType>>(&empty_chain, 0, &mut report);
+        transactions.check_witness_tree::<OrchardDomain>(&empty_chain, 0, &mut report);
+        assert!(report.is_consistent());
+
+        // The chain has a commitment the wallet's tree never got
+        let mut chain_tree = CommitmentTree::<sapling::Node, COMMITMENT_TREE_LEVELS>::empty();
+        chain_tree
+            .append(sapling::Node::from_commitment(&sapling_note(1).cmu().to_bytes()).unwrap())
+            .unwrap();
+        let mut chain_tree_bytes = vec![];
+        write_commitment_tree(&chain_tree, &mut chain_tree_bytes).unwrap();
+        let busier_chain = TreeState {
+            height: 10,
+            sapling_tree: hex::encode(chain_tree_bytes),
+            ..Default::default()
+        };
+        transactions.check_witness_tree::<SaplingDomain<ChainType>>(&busier_chain, 0, &mut report);
+        assert_eq!(report.inconsistencies.len(), 1);
+        assert_eq!(report.inconsistencies[0].repair, None);
+    }
+
+    #[test]
+    fn utxos_compared_both_ways() {
+        let txid = TxId::from_bytes([7; 32]);
+        let mut transaction_metadata =
+            TransactionMetadata::new(BlockHeight::from_u32(10), 0, &txid, false);
+        transaction_metadata
+            .received_utxos
+            .push(ReceivedTransparentOutput {
+                address: "t1ours".to_string(),
+                txid,
+                output_index: 0,
+                script: vec![],
+                value: 1_000,
+                height: 10,
+                spent_at_height: None,
+                spent: None,
+                unconfirmed_spent: None,
+            });
+        let mut transactions = TransactionMetadataSet::new_treeless();
+        transactions.current.insert(txid, transaction_metadata);
+
+        let mut report = ConsistencyReport::default();
+        let server_utxos = vec![GetAddressUtxosReply {
+            address: "t1ours".to_string(),
+            txid: txid.as_ref().to_vec(),
+            index: 0,
+            value_zat: 1_000,
+            height: 10,
+            ..Default::default()
+        }];
+        transactions.check_utxos(&server_utxos, &mut report);
+        assert!(report.is_consistent());
+
+        let server_utxos = vec![GetAddressUtxosReply {
+            address: "t1ours".to_string(),
+            txid: [8; 32].to_vec(),
+            index: 1,
+            value_zat: 2_000,
+            height: 11,
+            ..Default::default()
+        }];
+        transactions.check_utxos(&server_utxos, &mut report);
+        assert_eq!(report.inconsistencies.len(), 2);
+        assert!(!report.any_repaired());
+        assert_eq!(transactions.utxos_spent_elsewhere(&server_utxos).len(), 1);
+        assert_eq!(
+            transactions.utxos_missing_from_wallet(&server_utxos)[0].0,
+            TxId::from_bytes([8; 32])
+        );
+
+        let mut report = ConsistencyReport::default();
+        transactions.check_transparent_balance(1_000, &mut report);
+        assert!(report.is_consistent());
+        transactions.check_transparent_balance(3_000, &mut report);
+        assert_eq!(report.inconsistencies.len(), 1);
+    }
+}
@@ -156,6 +156,18 @@ impl WitnessTrees {
         self.witness_tree_sapling.checkpoint(height).unwrap();
         self.witness_tree_orchard.checkpoint(height).unwrap();
     }
+    /// Cuts both trees back to the given number of leaves and checkpoints them at `height`.
+    /// Unlike truncating to a checkpoint, this works however far back `height` is.
+    pub(crate) fn truncate_to_sizes(
+        &mut self,
+        height: BlockHeight,
+        sapling_size: u64,
+        orchard_size: u64,
+    ) {
+        truncate_shardtree_to_size(&mut self.witness_tree_sapling, height, sapling_size);
+        truncate_shardtree_to_size(&mut self.witness_tree_orchard, height, orchard_size);
+        self.add_checkpoint(height);
+    }
     const VERSION: u8 = 0;
     pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
         let _serialized_version = reader.read_u8()?;
@@ -191,6 +203,39 @@ impl WitnessTrees {
     }
 }
 
+/// Plants a checkpoint at `size` leaves and truncates to it, since a shardtree can only be
+/// truncated to one of its checkpoints.
+fn truncate_shardtree_to_size<H: Hashable + Clone + Eq>(
+    tree: &mut shardtree::ShardTree<
+        MemoryShardStore<H, BlockHeight>,
+        COMMITMENT_TREE_LEVELS,
+        MAX_SHARD_LEVEL,
+    >,
+    height: BlockHeight,
+    size: u64,
+) {
+    let mut store = std::mem::replace(
+        tree,
+        shardtree::ShardTree::new(MemoryShardStore::empty(), 0),
+    )
+    .into_store();
+    let tree_state = match size.checked_sub(1) {
+        Some(last_position) => {
+            shardtree::store::TreeState::AtPosition(Position::from(last_position))
+        }
+        None => shardtree::store::TreeState::Empty,
+    };
+    store
+        .add_checkpoint(
+            height,
+            Checkpoint::from_parts(tree_state, Default::default()),
+        )
+        .expect("Infallible");
+    *tree = shardtree::ShardTree::new(store, MAX_REORG);
+    tree.truncate_removing_checkpoint(&height)
+        .expect("Infallible");
+}
+
 fn read_shardtree<
     H: Hashable + Clone + HashSer + Eq,
     C: Ord + std::fmt::Debug + Copy + From<u32>,
@@ -899,6 +944,13 @@ pub struct TransactionMetadata {
 
     // Price of Zec when this Tx was created
     pub price: Option<f64>,
+
+    // Last height this Tx can be mined at, if it expires at all. Added in v24
+    pub expiry_height: Option<BlockHeight>,
+
+    // Raw bytes of a Tx this wallet created, kept while it is pending so it can be rebroadcast.
+    // Added in v24
+    pub raw_transaction: Option<Vec<u8>>,
 }
 
 impl TransactionMetadata {
@@ -940,6 +992,12 @@ impl TransactionMetadata {
     pub fn is_outgoing_transaction(&self) -> bool {
         (!self.outgoing_tx_data.is_empty()) || self.total_value_spent() != 0
     }
+    /// A Tx can't be mined above its expiry height, so once the chain has reached that height
+    /// without including it, it never will be.
+    pub fn is_expired_at(&self, chain_height: u64) -> bool {
+        self.expiry_height
+            .map_or(false, |expiry| chain_height >= u64::from(u32::from(expiry)))
+    }
     pub fn is_incoming_transaction(&self) -> bool {
         self.sapling_notes.iter().any(|note| !note.is_change())
             || self.orchard_notes.iter().any(|note| !note.is_change())
@@ -971,6 +1029,8 @@ impl TransactionMetadata {
             outgoing_tx_data: vec![],
             full_tx_scanned: false,
             price: None,
+            expiry_height: None,
+            raw_transaction: None,
         }
     }
     pub fn new_txid(txid: &[u8]) -> TxId {
@@ -1093,6 +1153,17 @@ impl TransactionMetadata {
             })?
         };
 
+        let (expiry_height, raw_transaction) = if version <= 23 {
+            (None, None)
+        } else {
+            (
+                Optional::read(&mut reader, |r| {
+                    r.read_u32::<LittleEndian>().map(BlockHeight::from_u32)
+                })?,
+                Optional::read(&mut reader, |r| Vector::read(r, |r| r.read_u8()))?,
+            )
+        };
+
         Ok(Self {
             block_height: block,
             unconfirmed,
@@ -1109,11 +1180,13 @@ impl TransactionMetadata {
             outgoing_tx_data: outgoing_metadata,
             full_tx_scanned,
             price: zec_price,
+            expiry_height,
+            raw_transaction,
         })
     }
 
     pub fn serialized_version() -> u64 {
-        23
+        24
     }
 
     pub fn total_change_returned(&self) -> u64 {
@@ -1182,6 +1255,13 @@ impl TransactionMetadata {
             w.write_all(&n.to_bytes())
         })?;
 
+        Optional::write(&mut writer, self.expiry_height, |w, h| {
+            w.write_u32::<LittleEndian>(h.into())
+        })?;
+        Optional::write(&mut writer, self.raw_transaction.as_ref(), |w, bytes| {
+            Vector::write(w, bytes, |w, b| w.write_u8(*b))
+        })?;
+
         Ok(())
     }
 }
@@ -1294,3 +1374,18 @@ fn read_write_empty_sapling_tree() {
         read_commitment_tree(&mut buffer.as_slice()).unwrap()
     )
 }
+
+#[test]
+fn pending_transaction_expires_at_its_expiry_height() {
+    let mut transaction_metadata = TransactionMetadata::new(
+        BlockHeight::from_u32(100),
+        0,
+        &TxId::from_bytes([1; 32]),
+        true,
+    );
+    assert!(!transaction_metadata.is_expired_at(1_000));
+
+    transaction_metadata.expiry_height = Some(BlockHeight::from_u32(140));
+    assert!(!transaction_metadata.is_expired_at(139));
+    assert!(transaction_metadata.is_expired_at(140));
+}
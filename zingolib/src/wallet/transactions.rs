@@ -283,6 +283,29 @@ impl TransactionMetadataSet {
         }
     }
 
+    /// Forgets everything above `height`, rolling back any spends made there, and cuts the
+    /// witness trees back to the given sizes. For reorgs deeper than the witness trees'
+    /// checkpoints, which `remove_txns_at_height` can't undo.
+    pub fn rewind_to_height(
+        &mut self,
+        height: u64,
+        sapling_tree_size: u64,
+        orchard_tree_size: u64,
+    ) {
+        let rewind_height = BlockHeight::from_u32(height as u32);
+
+        let txids_to_remove = self
+            .current
+            .values()
+            .filter(|transaction_metadata| transaction_metadata.block_height > rewind_height)
+            .map(|transaction_metadata| transaction_metadata.txid)
+            .collect::<Vec<_>>();
+        self.remove_txids(txids_to_remove);
+        if let Some(ref mut t) = self.witness_trees {
+            t.truncate_to_sizes(rewind_height, sapling_tree_size, orchard_tree_size);
+        }
+    }
+
     /// This returns an _arbitrary_ txid from the latest block the wallet is aware of.
     pub fn get_some_txid_from_highest_wallet_block(&self) -> &'_ Option<TxId> {
         &self.some_txid_from_highest_wallet_block
@@ -380,6 +403,8 @@ impl TransactionMetadataSet {
             .collect()
     }
 
+    /// Removes pending Txs that can no longer be mined, which unlocks the notes and UTXOs they
+    /// were spending. A Tx without an expiry height is given up on after MAX_REORG blocks.
     pub(crate) fn clear_expired_mempool(&mut self, latest_height: u64) {
         let cutoff = BlockHeight::from_u32((latest_height.saturating_sub(MAX_REORG as u64)) as u32);
 
@@ -387,7 +412,11 @@ impl TransactionMetadataSet {
             .current
             .iter()
             .filter(|(_, transaction_metadata)| {
-                transaction_metadata.unconfirmed && transaction_metadata.block_height < cutoff
+                transaction_metadata.unconfirmed
+                    && match transaction_metadata.expiry_height {
+                        Some(_) => transaction_metadata.is_expired_at(latest_height),
+                        None => transaction_metadata.block_height < cutoff,
+                    }
             })
             .map(|(_, transaction_metadata)| transaction_metadata.txid)
             .collect::<Vec<_>>();
@@ -487,6 +516,10 @@ impl TransactionMetadataSet {
             transaction_metadata.block_height = height;
             transaction_metadata.datetime = datetime;
         }
+        // A mined Tx will never need rebroadcasting
+        if !unconfirmed {
+            transaction_metadata.raw_transaction = None;
+        }
 
         transaction_metadata
     }
@@ -495,6 +528,36 @@ impl TransactionMetadataSet {
         price.map(|p| self.current.get_mut(txid).map(|tx| tx.price = Some(p)));
     }
 
+    /// Records the expiry height of a scanned Tx. Zero means the Tx never expires.
+    pub fn set_expiry_height(&mut self, txid: &TxId, expiry_height: BlockHeight) {
+        if let Some(transaction_metadata) = self.current.get_mut(txid) {
+            transaction_metadata.expiry_height = Some(expiry_height).filter(|h| u32::from(*h) != 0);
+        }
+    }
+
+    pub fn set_raw_transaction(&mut self, txid: &TxId, raw_transaction: Vec<u8>) {
+        if let Some(transaction_metadata) = self.current.get_mut(txid) {
+            transaction_metadata.raw_transaction = Some(raw_transaction);
+        }
+    }
+
+    /// Pending Txs that are still mineable above `latest_height`, with the bytes to rebroadcast
+    pub fn rebroadcastable(&self, latest_height: u64) -> Vec<(TxId, Vec<u8>)> {
+        self.current
+            .values()
+            .filter(|transaction_metadata| {
+                transaction_metadata.unconfirmed
+                    && !transaction_metadata.is_expired_at(latest_height)
+            })
+            .filter_map(|transaction_metadata| {
+                Some((
+                    transaction_metadata.txid,
+                    transaction_metadata.raw_transaction.clone()?,
+                ))
+            })
+            .collect()
+    }
+
     // Records a TxId as having spent some nullifiers from the wallet.
     #[allow(clippy::too_many_arguments)]
     pub async fn add_new_spent(
@@ -909,3 +972,64 @@ impl TransactionMetadataSet {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use incrementalmerkletree::{Hashable, Retention};
+    use zcash_primitives::sapling::Node;
+
+    #[test]
+    fn rewinds_below_the_oldest_checkpoint() {
+        let mut transactions = TransactionMetadataSet::new_with_witness_trees();
+        // A sapling output in every block, for more blocks than the trees keep checkpoints for
+        let tip = MAX_REORG as u32 + 50;
+        let trees = transactions.witness_trees.as_mut().unwrap();
+        for height in 1..=tip {
+            trees
+                .witness_tree_sapling
+                .append(Node::empty_leaf(), Retention::Marked)
+                .unwrap();
+            trees.add_checkpoint(BlockHeight::from_u32(height));
+        }
+        let fork_height = BlockHeight::from_u32(10);
+        // Block 10's checkpoint is long gone, so the trees can't simply be truncated to it
+        assert!(!trees
+            .witness_tree_sapling
+            .truncate_removing_checkpoint(&fork_height)
+            .unwrap());
+
+        for (height, txid) in [(5, [5; 32]), (10, [10; 32]), (tip, [11; 32])] {
+            let txid = TxId::from_bytes(txid);
+            transactions.current.insert(
+                txid,
+                TransactionMetadata::new(BlockHeight::from_u32(height), 0, &txid, false),
+            );
+        }
+
+        // By the end of block 10 there were 10 sapling outputs, and no orchard ones
+        transactions.rewind_to_height(10, 10, 0);
+
+        let mut kept = transactions
+            .current
+            .values()
+            .map(|transaction| u32::from(transaction.block_height))
+            .collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, vec![5, 10]);
+        let trees = transactions.witness_trees.as_mut().unwrap();
+        assert_eq!(
+            trees.witness_tree_sapling.max_leaf_position(0).unwrap(),
+            Some(Position::from(9))
+        );
+        assert_eq!(
+            trees.witness_tree_orchard.max_leaf_position(0).unwrap(),
+            None
+        );
+        // The rewound block is checkpointed, so a reorg right after the rewind can go back to it
+        assert!(trees
+            .witness_tree_sapling
+            .truncate_removing_checkpoint(&fork_height)
+            .unwrap());
+    }
+}